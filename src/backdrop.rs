@@ -0,0 +1,291 @@
+use crate::color::Color;
+use crate::math::{lerp, Rect, V2};
+use crate::pixel_buffer::PixelBuffer;
+
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ScaleMode {
+    // NOTE(Fermin): Whole image visible, the background shows around it
+    Fit,
+    // NOTE(Fermin): Covers the buffer keeping the aspect ratio, the parts
+    // that don't fit get cropped.
+    Fill,
+    Stretch,
+    // NOTE(Fermin): Repeats the image at its own size from the top left
+    Tile,
+    Center,
+}
+
+// NOTE(Fermin): Slow zoom and pan that goes back and forth between where the
+// scale mode puts the image and the zoomed in, panned position.
+#[derive(Copy, Clone, Debug)]
+pub struct KenBurns {
+    // NOTE(Fermin): 0.15 makes the image 15% bigger at the far end
+    pub zoom: f32,
+    // NOTE(Fermin): Fraction of the buffer size the image moves at the far end
+    pub pan: V2,
+    // NOTE(Fermin): Seconds to go there and back
+    pub period: f32,
+}
+
+pub struct Backdrop {
+    pub image: PixelBuffer,
+    pub scale_mode: ScaleMode,
+    pub ken_burns: Option<KenBurns>,
+}
+
+// NOTE(Fermin): Where the image lands in a width x height buffer. For tiles
+// it's the first tile, the rest repeat from it.
+pub fn image_rect(
+    image_width: i32,
+    image_height: i32,
+    width: i32,
+    height: i32,
+    scale_mode: ScaleMode,
+) -> Rect {
+    let centered = |rect_width: i32, rect_height: i32| {
+        Rect::from_pos_size(
+            (width - rect_width) / 2,
+            (height - rect_height) / 2,
+            rect_width,
+            rect_height,
+        )
+    };
+    let scaled = |scale: f32| {
+        centered(
+            (image_width as f32 * scale).round() as i32,
+            (image_height as f32 * scale).round() as i32,
+        )
+    };
+
+    let scale_x = width as f32 / image_width as f32;
+    let scale_y = height as f32 / image_height as f32;
+    match scale_mode {
+        ScaleMode::Fit => scaled(scale_x.min(scale_y)),
+        ScaleMode::Fill => scaled(scale_x.max(scale_y)),
+        ScaleMode::Stretch => Rect::from_pos_size(0, 0, width, height),
+        ScaleMode::Tile => Rect::from_pos_size(0, 0, image_width, image_height),
+        ScaleMode::Center => centered(image_width, image_height),
+    }
+}
+
+// NOTE(Fermin): rect moved along the Ken Burns path at time. Images that
+// covered the buffer keep covering it, pan stops at their edges.
+pub fn ken_burns_rect(
+    rect: &Rect,
+    ken_burns: &KenBurns,
+    width: i32,
+    height: i32,
+    time: f32,
+) -> Rect {
+    let phase = 0.5 - 0.5 * (std::f32::consts::TAU * time / ken_burns.period).cos();
+    let zoom = 1.0 + ken_burns.zoom * phase;
+
+    let axis = |min: i32, size: i32, buffer_size: i32, pan: f32| -> (i32, i32) {
+        let zoomed_size = (size as f32 * zoom).round() as i32;
+        let center = min as f32 + size as f32 * 0.5;
+        let mut zoomed_min =
+            (center - zoomed_size as f32 * 0.5 + pan * phase * buffer_size as f32).round() as i32;
+        if size >= buffer_size && min <= 0 {
+            zoomed_min = zoomed_min.clamp(buffer_size - zoomed_size, 0);
+        }
+        (zoomed_min, zoomed_size)
+    };
+    let (min_x, zoomed_width) = axis(rect.min_x, rect.width(), width, ken_burns.pan.x);
+    let (min_y, zoomed_height) = axis(rect.min_y, rect.height(), height, ken_burns.pan.y);
+    Rect::from_pos_size(min_x, min_y, zoomed_width, zoomed_height)
+}
+
+fn draw_image(image: &PixelBuffer, rect: &Rect, buffer: &mut PixelBuffer) {
+    let view = image.view(&image.bounds());
+    if rect.width() == image.width && rect.height() == image.height {
+        buffer.blit(&view, rect.min_x, rect.min_y);
+    } else {
+        buffer.blit_scaled(&view, rect);
+    }
+}
+
+// NOTE(Fermin): Images come in straight alpha, the background layer has to
+// be opaque.
+fn flatten_over(background: &Color, rect: &Rect, buffer: &mut PixelBuffer) {
+    let format = buffer.format;
+    let bytes_per_pixel = format.bytes_per_pixel();
+    let mut view = buffer.view_mut(rect);
+    for row in view.rows_mut() {
+        for pixel in row.chunks_exact_mut(bytes_per_pixel) {
+            let color = format.decode(pixel);
+            if color.a == 255 {
+                continue;
+            }
+            let t = color.a as f32 / 255.0;
+            let channel = |bg: u8, c: u8| lerp(bg as f32, t, c as f32).round() as u8;
+            let flattened = Color::rgba(
+                channel(background.r, color.r),
+                channel(background.g, color.g),
+                channel(background.b, color.b),
+                255,
+            );
+            pixel.copy_from_slice(&format.encode(&flattened));
+        }
+    }
+}
+
+// NOTE(Fermin): Where the image lands in a width x height buffer at time,
+// Ken Burns included. Renders only change when this does.
+pub fn backdrop_rect(backdrop: &Backdrop, width: i32, height: i32, time: f32) -> Rect {
+    let rect = image_rect(
+        backdrop.image.width,
+        backdrop.image.height,
+        width,
+        height,
+        backdrop.scale_mode,
+    );
+    match &backdrop.ken_burns {
+        Some(ken_burns) => ken_burns_rect(&rect, ken_burns, width, height, time),
+        None => rect,
+    }
+}
+
+// NOTE(Fermin): Redraws the whole buffer, placement is worked out from the
+// buffer size every time so it's always resampled for the current size.
+pub fn render_backdrop(
+    backdrop: &Backdrop,
+    background: &Color,
+    buffer: &mut PixelBuffer,
+    time: f32,
+) {
+    buffer.fill(background);
+    if backdrop.image.width == 0 || backdrop.image.height == 0 {
+        return;
+    }
+
+    let mut rect = backdrop_rect(backdrop, buffer.width, buffer.height, time);
+    if rect.is_empty() {
+        return;
+    }
+
+    if backdrop.scale_mode == ScaleMode::Tile {
+        let (tile_width, tile_height) = (rect.width(), rect.height());
+        let mut y = rect.min_y.rem_euclid(tile_height) - tile_height;
+        while y < buffer.height {
+            let mut x = rect.min_x.rem_euclid(tile_width) - tile_width;
+            while x < buffer.width {
+                draw_image(
+                    &backdrop.image,
+                    &Rect::from_pos_size(x, y, tile_width, tile_height),
+                    buffer,
+                );
+                x += tile_width;
+            }
+            y += tile_height;
+        }
+        rect = buffer.bounds();
+    } else {
+        draw_image(&backdrop.image, &rect, buffer);
+    }
+
+    flatten_over(background, &rect, buffer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel_buffer::PixelFormat;
+
+    const BACKGROUND: Color = Color::rgba(10, 20, 30, 255);
+    const RED: Color = Color::rgba(255, 0, 0, 255);
+    const BLUE: Color = Color::rgba(0, 0, 255, 255);
+
+    // NOTE(Fermin): Left half red, right half blue
+    fn two_color_image(width: i32, height: i32) -> PixelBuffer {
+        let mut image = PixelBuffer::new(width, height, PixelFormat::Bgra8);
+        image.fill(&RED);
+        image
+            .view_mut(&Rect::from_pos_size(width / 2, 0, width, height))
+            .fill(&BLUE);
+        image
+    }
+
+    #[test]
+    fn scale_modes_place_the_image() {
+        let place = |mode| image_rect(200, 100, 100, 100, mode);
+        assert_eq!(place(ScaleMode::Fit), Rect::from_pos_size(0, 25, 100, 50));
+        assert_eq!(
+            place(ScaleMode::Fill),
+            Rect::from_pos_size(-50, 0, 200, 100)
+        );
+        assert_eq!(
+            place(ScaleMode::Stretch),
+            Rect::from_pos_size(0, 0, 100, 100)
+        );
+        assert_eq!(place(ScaleMode::Tile), Rect::from_pos_size(0, 0, 200, 100));
+        assert_eq!(
+            place(ScaleMode::Center),
+            Rect::from_pos_size(-50, 0, 200, 100)
+        );
+    }
+
+    #[test]
+    fn resampled_for_every_buffer_size() {
+        let backdrop = Backdrop {
+            image: two_color_image(4, 2),
+            scale_mode: ScaleMode::Fit,
+            ken_burns: None,
+        };
+
+        for (width, height) in [(8, 8), (30, 10), (3, 50)] {
+            let mut buffer = PixelBuffer::new(width, height, PixelFormat::Bgra8);
+            render_backdrop(&backdrop, &BACKGROUND, &mut buffer, 0.0);
+
+            let rect = image_rect(4, 2, width, height, ScaleMode::Fit);
+            assert_eq!(buffer.get(rect.min_x, rect.min_y), Some(RED));
+            assert_eq!(buffer.get(rect.max_x - 1, rect.max_y - 1), Some(BLUE));
+            assert_eq!(
+                buffer.get(0, 0) == Some(BACKGROUND),
+                rect.min_x > 0 || rect.min_y > 0
+            );
+        }
+    }
+
+    #[test]
+    fn tiles_repeat_and_transparency_shows_background() {
+        let mut image = two_color_image(2, 2);
+        image.set(0, 1, &Color::rgba(0, 0, 0, 0));
+        let backdrop = Backdrop {
+            image,
+            scale_mode: ScaleMode::Tile,
+            ken_burns: None,
+        };
+        let mut buffer = PixelBuffer::new(5, 4, PixelFormat::Bgra8);
+        render_backdrop(&backdrop, &BACKGROUND, &mut buffer, 0.0);
+
+        for (x, y, expected) in [
+            (0, 0, RED),
+            (1, 0, BLUE),
+            (2, 2, RED),
+            (4, 0, RED),
+            (3, 3, BLUE),
+        ] {
+            assert_eq!(buffer.get(x, y), Some(expected), "{}, {}", x, y);
+        }
+        assert_eq!(buffer.get(2, 3), Some(BACKGROUND));
+    }
+
+    #[test]
+    fn ken_burns_keeps_covering_the_buffer() {
+        let ken_burns = KenBurns {
+            zoom: 0.2,
+            pan: V2 { x: 0.5, y: -0.5 },
+            period: 10.0,
+        };
+        let rect = image_rect(200, 100, 100, 100, ScaleMode::Fill);
+        assert_eq!(ken_burns_rect(&rect, &ken_burns, 100, 100, 0.0), rect);
+        assert_eq!(ken_burns_rect(&rect, &ken_burns, 100, 100, 10.0), rect);
+
+        let far = ken_burns_rect(&rect, &ken_burns, 100, 100, 5.0);
+        assert_eq!((far.width(), far.height()), (240, 120));
+        let buffer_rect = Rect::from_pos_size(0, 0, 100, 100);
+        assert_eq!(far.intersection(&buffer_rect), buffer_rect);
+        assert!(far.min_x > rect.min_x - 20);
+    }
+}
@@ -0,0 +1,252 @@
+use crate::input::{Input, Key, Modifiers};
+use std::fmt;
+use std::fs::read_to_string;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    Pause,
+    SpeedUp,
+    SpeedDown,
+    StepFrame,
+    ToggleOverlay,
+    Screenshot,
+    CyclePreset,
+}
+
+const ACTION_NAMES: [(Action, &str); 7] = [
+    (Action::Pause, "pause"),
+    (Action::SpeedUp, "speed_up"),
+    (Action::SpeedDown, "speed_down"),
+    (Action::StepFrame, "step_frame"),
+    (Action::ToggleOverlay, "toggle_overlay"),
+    (Action::Screenshot, "screenshot"),
+    (Action::CyclePreset, "cycle_preset"),
+];
+
+// NOTE(Fermin): One "action = key" per line, keys can have modifiers in
+// front like "Ctrl+Shift+S" and an action can be on several lines. Lines
+// starting with # are comments.
+pub const DEFAULT_BINDINGS: &str = "\
+pause = Space
+pause = P
+speed_up = Plus
+speed_down = Minus
+step_frame = Period
+toggle_overlay = F1
+screenshot = F12
+cycle_preset = Tab
+";
+
+#[derive(Debug)]
+pub enum BindingsError {
+    Io(std::io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for BindingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BindingsError::Io(error) => write!(f, "Couldn't read bindings: {}", error),
+            BindingsError::Parse { line, message } => {
+                write!(f, "Bad binding on line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for BindingsError {
+    fn from(error: std::io::Error) -> BindingsError {
+        BindingsError::Io(error)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Binding {
+    pub key: Key,
+    // NOTE(Fermin): Have to match exactly, Space doesn't fire on Ctrl+Space
+    pub modifiers: Modifiers,
+    pub action: Action,
+}
+
+#[derive(Clone, Debug)]
+pub struct Bindings {
+    pub bindings: Vec<Binding>,
+}
+
+impl Default for Bindings {
+    fn default() -> Bindings {
+        Bindings::parse(DEFAULT_BINDINGS).expect("The default bindings don't parse")
+    }
+}
+
+fn parse_combo(combo: &str) -> Result<(Key, Modifiers), String> {
+    let mut modifiers = Modifiers::default();
+    let mut parts = combo.split('+').map(str::trim).peekable();
+    while let Some(part) = parts.next() {
+        let key = Key::from_name(part).ok_or_else(|| format!("unknown key \"{}\"", part))?;
+        if parts.peek().is_none() {
+            return Ok((key, modifiers));
+        }
+        match key {
+            Key::Shift => modifiers.shift = true,
+            Key::Control => modifiers.control = true,
+            Key::Alt => modifiers.alt = true,
+            _ => return Err(format!("\"{}\" isn't a modifier", part)),
+        }
+    }
+    Err("no key".to_string())
+}
+
+// NOTE(Fermin): A modifier key is down while it is being pressed, so a
+// binding on Shift alone would otherwise have to be written as Shift+Shift.
+fn without_own_modifier(mut modifiers: Modifiers, key: Key) -> Modifiers {
+    match key {
+        Key::Shift => modifiers.shift = false,
+        Key::Control => modifiers.control = false,
+        Key::Alt => modifiers.alt = false,
+        _ => {}
+    }
+    modifiers
+}
+
+impl Bindings {
+    pub fn parse(text: &str) -> Result<Bindings, BindingsError> {
+        let mut bindings = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| BindingsError::Parse {
+                line: index + 1,
+                message,
+            };
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, combo) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected action = key".to_string()))?;
+            let name = name.trim();
+            let action = ACTION_NAMES
+                .iter()
+                .find(|(_, action_name)| *action_name == name)
+                .map(|&(action, _)| action)
+                .ok_or_else(|| error(format!("unknown action \"{}\"", name)))?;
+            let (key, modifiers) = parse_combo(combo).map_err(error)?;
+            bindings.push(Binding {
+                key,
+                modifiers,
+                action,
+            });
+        }
+        Ok(Bindings { bindings })
+    }
+
+    // NOTE(Fermin): Actions whose key went down this frame, in the order
+    // they are bound.
+    pub fn triggered(&self, input: &Input) -> Vec<Action> {
+        let mut actions = Vec::new();
+        for binding in &self.bindings {
+            if input.key(binding.key).pressed
+                && binding.modifiers == without_own_modifier(input.modifiers(), binding.key)
+                && !actions.contains(&binding.action)
+            {
+                actions.push(binding.action);
+            }
+        }
+        actions
+    }
+}
+
+pub fn load_bindings(path: &str) -> Result<Bindings, BindingsError> {
+    Bindings::parse(&read_to_string(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_bindings_parse() {
+        let bindings = Bindings::default();
+        for (action, _) in ACTION_NAMES {
+            assert!(bindings
+                .bindings
+                .iter()
+                .any(|binding| binding.action == action));
+        }
+    }
+
+    #[test]
+    fn parses_modifiers_and_reports_bad_lines() {
+        let bindings = Bindings::parse("# Comment\n\n screenshot = ctrl + Shift+S \n").unwrap();
+        assert_eq!(
+            bindings.bindings,
+            vec![Binding {
+                key: Key::S,
+                modifiers: Modifiers {
+                    shift: true,
+                    control: true,
+                    alt: false,
+                },
+                action: Action::Screenshot,
+            }]
+        );
+
+        for (text, line) in [
+            ("pause = Space\njump = Space", 2),
+            ("pause Space", 1),
+            ("\npause = Ctrl+", 2),
+            ("pause = A+B", 1),
+            ("pause = Hyper", 1),
+        ] {
+            match Bindings::parse(text) {
+                Err(BindingsError::Parse { line: got, .. }) => assert_eq!(got, line, "{}", text),
+                other => panic!("{:?} parsed into {:?}", text, other),
+            }
+        }
+    }
+
+    #[test]
+    fn triggers_on_presses_with_matching_modifiers() {
+        let bindings =
+            Bindings::parse("pause = Space\nscreenshot = Ctrl+Space\npause = P").unwrap();
+        let mut input = Input::default();
+
+        input.set_key(Key::Space, true);
+        input.set_key(Key::P, true);
+        assert_eq!(bindings.triggered(&input), vec![Action::Pause]);
+
+        // NOTE(Fermin): Held keys don't fire again
+        input.begin_frame();
+        input.set_key(Key::Control, true);
+        assert!(bindings.triggered(&input).is_empty());
+
+        input.begin_frame();
+        input.set_key(Key::Space, false);
+        input.set_key(Key::Space, true);
+        assert_eq!(bindings.triggered(&input), vec![Action::Screenshot]);
+    }
+
+    #[test]
+    fn modifiers_can_be_bound_alone() {
+        let bindings = Bindings::parse("pause = Shift\nscreenshot = Ctrl+Alt").unwrap();
+        let mut input = Input::default();
+
+        input.set_key(Key::Shift, true);
+        assert_eq!(bindings.triggered(&input), vec![Action::Pause]);
+
+        input.begin_frame();
+        input.set_key(Key::Shift, false);
+        input.set_key(Key::Control, true);
+        assert!(bindings.triggered(&input).is_empty());
+
+        input.begin_frame();
+        input.set_key(Key::Alt, true);
+        assert_eq!(bindings.triggered(&input), vec![Action::Screenshot]);
+
+        // NOTE(Fermin): Other modifiers still have to match
+        input.begin_frame();
+        input.set_key(Key::Shift, true);
+        assert!(bindings.triggered(&input).is_empty());
+    }
+}
@@ -0,0 +1,446 @@
+use crate::hdr::{HdrBuffer, HDR_CHANNELS};
+
+#[derive(Copy, Clone)]
+pub struct BloomSettings {
+    // NOTE(Fermin): Only the part of a pixel above threshold blooms
+    pub threshold: f32,
+    pub intensity: f32,
+    // NOTE(Fermin): Gaussian radius in pixels of each mip level, the
+    // effective radius doubles with every level.
+    pub radius: i32,
+    pub levels: usize,
+}
+
+pub struct Bloom {
+    pub settings: BloomSettings,
+    kernel: Vec<f32>,
+    // NOTE(Fermin): levels[0] is half the size of the hdr buffer, every
+    // following level halves the previous one.
+    levels: Vec<HdrBuffer>,
+    scratch: Vec<HdrBuffer>,
+    composite: HdrBuffer,
+    threads: RowThreads,
+}
+
+impl Bloom {
+    pub fn new(width: i32, height: i32, settings: BloomSettings) -> Bloom {
+        let mut levels = Vec::new();
+        let mut scratch = Vec::new();
+        let mut level_width = width;
+        let mut level_height = height;
+        for _level in 0..settings.levels.max(1) {
+            level_width = (level_width / 2).max(1);
+            level_height = (level_height / 2).max(1);
+            levels.push(HdrBuffer::new(level_width, level_height));
+            scratch.push(HdrBuffer::new(level_width, level_height));
+        }
+
+        Bloom {
+            kernel: gaussian_kernel(settings.radius),
+            settings,
+            levels,
+            scratch,
+            composite: HdrBuffer::new(width, height),
+            threads: RowThreads::new(std::thread::available_parallelism().map_or(1, |n| n.get())),
+        }
+    }
+}
+
+fn gaussian_kernel(radius: i32) -> Vec<f32> {
+    let radius = radius.max(1);
+    // NOTE(Fermin): Weights at the edge of the kernel are ~1% of the center
+    let sigma = radius as f32 / 3.0;
+    let mut kernel: Vec<f32> = (-radius..=radius)
+        .map(|x| (-(x * x) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    for weight in &mut kernel {
+        *weight /= sum;
+    }
+
+    kernel
+}
+
+// NOTE(Fermin): How many threads a pass splits its rows over. They are
+// scoped to the pass, the calling thread works the first band itself and
+// counts as one of them.
+struct RowThreads {
+    threads: usize,
+}
+
+impl RowThreads {
+    fn new(threads: usize) -> RowThreads {
+        RowThreads {
+            threads: threads.max(1),
+        }
+    }
+
+    // NOTE(Fermin): Splits dest into bands of whole rows and hands every band
+    // to its own thread. f gets the row index and the row.
+    fn for_each_row<F>(&self, dest: &mut [f32], row_len: usize, f: F)
+    where
+        F: Fn(usize, &mut [f32]) + Sync,
+    {
+        let rows = dest.len() / row_len;
+        let rows_per_band = rows.div_ceil(self.threads).max(1);
+        let run_rows = |first_row: usize, band: &mut [f32]| {
+            for (row, row_bits) in band.chunks_mut(row_len).enumerate() {
+                f(first_row + row, row_bits);
+            }
+        };
+        let mut bands = dest.chunks_mut(rows_per_band * row_len);
+        let first_band = bands.next();
+        std::thread::scope(|scope| {
+            for (band, band_bits) in bands.enumerate() {
+                let run_rows = &run_rows;
+                scope.spawn(move || run_rows((band + 1) * rows_per_band, band_bits));
+            }
+            if let Some(band_bits) = first_band {
+                run_rows(0, band_bits);
+            }
+        });
+    }
+}
+
+// NOTE(Fermin): Keeps only what is above threshold and box filters 2x2
+// blocks of src into dest.
+fn threshold_and_downsample(
+    threads: &RowThreads,
+    src: &HdrBuffer,
+    dest: &mut HdrBuffer,
+    threshold: f32,
+) {
+    let src_pitch = src.width as usize * HDR_CHANNELS;
+    let max_src_x = src.width as usize - 1;
+    let max_src_y = src.height as usize - 1;
+    let dest_pitch = dest.width as usize * HDR_CHANNELS;
+
+    threads.for_each_row(&mut dest.bits, dest_pitch, |y, dest_row| {
+        let src_y0 = (2 * y).min(max_src_y);
+        let src_y1 = (2 * y + 1).min(max_src_y);
+        let src_rows = [
+            &src.bits[src_y0 * src_pitch..(src_y0 + 1) * src_pitch],
+            &src.bits[src_y1 * src_pitch..(src_y1 + 1) * src_pitch],
+        ];
+        for (x, dest_pixel) in dest_row.chunks_exact_mut(HDR_CHANNELS).enumerate() {
+            let src_x0 = (2 * x).min(max_src_x) * HDR_CHANNELS;
+            let src_x1 = (2 * x + 1).min(max_src_x) * HDR_CHANNELS;
+
+            let mut sum = [0.0; HDR_CHANNELS];
+            for src_row in src_rows {
+                for src_index in [src_x0, src_x1] {
+                    let pixel = &src_row[src_index..src_index + HDR_CHANNELS];
+                    let brightness = pixel[0].max(pixel[1]).max(pixel[2]);
+                    if brightness > threshold {
+                        let contribution = if threshold > 0.0 {
+                            (brightness - threshold) / brightness
+                        } else {
+                            1.0
+                        };
+                        sum[0] += pixel[0] * contribution;
+                        sum[1] += pixel[1] * contribution;
+                        sum[2] += pixel[2] * contribution;
+                    }
+                }
+            }
+
+            dest_pixel[0] = sum[0] * 0.25;
+            dest_pixel[1] = sum[1] * 0.25;
+            dest_pixel[2] = sum[2] * 0.25;
+        }
+    });
+}
+
+fn downsample(threads: &RowThreads, src: &HdrBuffer, dest: &mut HdrBuffer) {
+    threshold_and_downsample(threads, src, dest, 0.0);
+}
+
+// NOTE(Fermin): Separable blur, src -> scratch horizontally and then
+// scratch -> src vertically. Edges are clamped.
+fn gaussian_blur(
+    threads: &RowThreads,
+    src: &mut HdrBuffer,
+    scratch: &mut HdrBuffer,
+    kernel: &[f32],
+) {
+    let width = src.width as usize;
+    let height = src.height as usize;
+    let radius = kernel.len() / 2;
+    let pitch = width * HDR_CHANNELS;
+
+    {
+        let src = &*src;
+        threads.for_each_row(&mut scratch.bits, pitch, |y, dest_row| {
+            let src_row = &src.bits[y * pitch..(y + 1) * pitch];
+            for (x, dest_pixel) in dest_row.chunks_exact_mut(HDR_CHANNELS).enumerate() {
+                let mut sum = [0.0; HDR_CHANNELS];
+                if x >= radius && x + radius < width {
+                    // NOTE(Fermin): Fast path, the whole kernel is inside the row
+                    let taps =
+                        &src_row[(x - radius) * HDR_CHANNELS..(x + radius + 1) * HDR_CHANNELS];
+                    for (sample, weight) in taps.chunks_exact(HDR_CHANNELS).zip(kernel) {
+                        sum[0] += sample[0] * weight;
+                        sum[1] += sample[1] * weight;
+                        sum[2] += sample[2] * weight;
+                    }
+                } else {
+                    for (k, weight) in kernel.iter().enumerate() {
+                        let sample_x = (x + k).saturating_sub(radius).min(width - 1);
+                        let sample =
+                            &src_row[sample_x * HDR_CHANNELS..(sample_x + 1) * HDR_CHANNELS];
+                        sum[0] += sample[0] * weight;
+                        sum[1] += sample[1] * weight;
+                        sum[2] += sample[2] * weight;
+                    }
+                }
+                dest_pixel.copy_from_slice(&sum);
+            }
+        });
+    }
+
+    // NOTE(Fermin): Vertical pass walks whole rows at a time so memory
+    // access stays linear.
+    let scratch = &*scratch;
+    threads.for_each_row(&mut src.bits, pitch, |y, dest_row| {
+        dest_row.fill(0.0);
+        for (k, weight) in kernel.iter().enumerate() {
+            let sample_y = (y + k).saturating_sub(radius).min(height - 1);
+            let sample_row = &scratch.bits[sample_y * pitch..(sample_y + 1) * pitch];
+            for (dest, sample) in dest_row.iter_mut().zip(sample_row) {
+                *dest += sample * weight;
+            }
+        }
+    });
+}
+
+// NOTE(Fermin): dest = base + scale * bilinear(src), src can be any size.
+// Without a base the upsampled src is added on top of dest.
+fn add_upsampled(
+    threads: &RowThreads,
+    src: &HdrBuffer,
+    base: Option<&HdrBuffer>,
+    dest: &mut HdrBuffer,
+    scale: f32,
+) {
+    let x_ratio = src.width as f32 / dest.width as f32;
+    let y_ratio = src.height as f32 / dest.height as f32;
+    let src_pitch = src.width as usize * HDR_CHANNELS;
+    let max_x = src.width as usize - 1;
+    let max_y = src.height as usize - 1;
+
+    // NOTE(Fermin): Every row samples the same columns, so the horizontal
+    // taps are computed once.
+    let columns: Vec<(usize, usize, f32)> = (0..dest.width as usize)
+        .map(|x| {
+            let src_x = ((x as f32 + 0.5) * x_ratio - 0.5).max(0.0);
+            let src_x0 = (src_x as usize).min(max_x);
+            let src_x1 = (src_x0 + 1).min(max_x);
+            (
+                src_x0 * HDR_CHANNELS,
+                src_x1 * HDR_CHANNELS,
+                src_x - src_x0 as f32,
+            )
+        })
+        .collect();
+
+    let dest_pitch = dest.width as usize * HDR_CHANNELS;
+    threads.for_each_row(&mut dest.bits, dest_pitch, |y, dest_row| {
+        let src_y = ((y as f32 + 0.5) * y_ratio - 0.5).max(0.0);
+        let src_y0 = (src_y as usize).min(max_y);
+        let src_y1 = (src_y0 + 1).min(max_y);
+        let t_y = src_y - src_y0 as f32;
+        let top_row = &src.bits[src_y0 * src_pitch..(src_y0 + 1) * src_pitch];
+        let bottom_row = &src.bits[src_y1 * src_pitch..(src_y1 + 1) * src_pitch];
+
+        // NOTE(Fermin): Filter vertically once per src row, then every dest
+        // pixel only needs two taps.
+        let src_row: Vec<f32> = top_row
+            .iter()
+            .zip(bottom_row)
+            .map(|(top, bottom)| scale * (top + t_y * (bottom - top)))
+            .collect();
+
+        if let Some(base) = base {
+            dest_row.copy_from_slice(&base.bits[y * dest_pitch..(y + 1) * dest_pitch]);
+        }
+        for (dest_pixel, &(x0, x1, t_x)) in dest_row.chunks_exact_mut(HDR_CHANNELS).zip(&columns) {
+            let left = &src_row[x0..x0 + HDR_CHANNELS];
+            let right = &src_row[x1..x1 + HDR_CHANNELS];
+            dest_pixel[0] += left[0] + t_x * (right[0] - left[0]);
+            dest_pixel[1] += left[1] + t_x * (right[1] - left[1]);
+            dest_pixel[2] += left[2] + t_x * (right[2] - left[2]);
+        }
+    });
+}
+
+// NOTE(Fermin): The hdr buffer is left untouched since it persists between
+// frames, the returned buffer is hdr + glow and is what should be tone mapped.
+pub fn apply_bloom<'a>(bloom: &'a mut Bloom, hdr: &HdrBuffer) -> &'a HdrBuffer {
+    assert!(hdr.width == bloom.composite.width && hdr.height == bloom.composite.height);
+
+    let threads = &bloom.threads;
+    threshold_and_downsample(threads, hdr, &mut bloom.levels[0], bloom.settings.threshold);
+    for level in 1..bloom.levels.len() {
+        let (larger, smaller) = bloom.levels.split_at_mut(level);
+        downsample(threads, &larger[level - 1], &mut smaller[0]);
+    }
+
+    for (level, scratch) in bloom.levels.iter_mut().zip(bloom.scratch.iter_mut()) {
+        gaussian_blur(threads, level, scratch, &bloom.kernel);
+    }
+
+    // NOTE(Fermin): Accumulate from the smallest level up so every level
+    // adds its wider glow on top of the sharper ones.
+    for level in (1..bloom.levels.len()).rev() {
+        let (larger, smaller) = bloom.levels.split_at_mut(level);
+        add_upsampled(threads, &smaller[0], None, &mut larger[level - 1], 1.0);
+    }
+
+    add_upsampled(
+        threads,
+        &bloom.levels[0],
+        Some(hdr),
+        &mut bloom.composite,
+        bloom.settings.intensity,
+    );
+
+    &bloom.composite
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(buffer: &mut HdrBuffer, value: [f32; 3]) {
+        for pixel in buffer.bits.chunks_exact_mut(HDR_CHANNELS) {
+            pixel[..3].copy_from_slice(&value);
+        }
+    }
+
+    fn set(buffer: &mut HdrBuffer, x: i32, y: i32, value: [f32; 3]) {
+        let index = (y * buffer.width + x) as usize * HDR_CHANNELS;
+        buffer.bits[index..index + 3].copy_from_slice(&value);
+    }
+
+    fn energy(buffer: &HdrBuffer) -> [f64; 3] {
+        let mut sum = [0.0; 3];
+        for pixel in buffer.bits.chunks_exact(HDR_CHANNELS) {
+            for (channel, value) in sum.iter_mut().zip(pixel) {
+                *channel += *value as f64;
+            }
+        }
+        sum
+    }
+
+    #[test]
+    fn rows_are_visited_once() {
+        let threads = RowThreads::new(4);
+        for rows in [0, 1, 3, 4, 5, 97] {
+            let mut bits = vec![0.0; rows * 4];
+            threads.for_each_row(&mut bits, 4, |y, row| {
+                for value in row.iter_mut() {
+                    *value += y as f32;
+                }
+            });
+            for (y, row) in bits.chunks_exact(4).enumerate() {
+                assert_eq!(row, [y as f32; 4]);
+            }
+        }
+    }
+
+    // NOTE(Fermin): Only the part above threshold of the brightest channel
+    // is kept, and it keeps the pixel's hue.
+    #[test]
+    fn only_what_is_above_threshold_blooms() {
+        let threads = RowThreads::new(4);
+        let mut src = HdrBuffer::new(4, 2);
+        fill(&mut src, [1.0, 0.5, 0.25]);
+        set(&mut src, 2, 0, [4.0, 2.0, 0.0]);
+        let mut dest = HdrBuffer::new(2, 1);
+        threshold_and_downsample(&threads, &src, &mut dest, 1.0);
+
+        assert_eq!(&dest.bits[..3], [0.0, 0.0, 0.0]);
+        assert_eq!(
+            &dest.bits[HDR_CHANNELS..HDR_CHANNELS + 3],
+            [0.25 * 3.0, 0.25 * 1.5, 0.0]
+        );
+
+        threshold_and_downsample(&threads, &src, &mut dest, 0.0);
+        assert_eq!(&dest.bits[..3], [1.0, 0.5, 0.25]);
+    }
+
+    #[test]
+    fn blur_conserves_energy() {
+        let threads = RowThreads::new(4);
+        let kernel = gaussian_kernel(4);
+        assert!((kernel.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+
+        // NOTE(Fermin): Away from the edges, clamping there repeats pixels
+        let mut buffer = HdrBuffer::new(32, 24);
+        let mut scratch = HdrBuffer::new(32, 24);
+        set(&mut buffer, 15, 11, [100.0, 10.0, 1.0]);
+        set(&mut buffer, 12, 14, [0.0, 50.0, 0.0]);
+        let before = energy(&buffer);
+        gaussian_blur(&threads, &mut buffer, &mut scratch, &kernel);
+        let after = energy(&buffer);
+        for (before, after) in before.iter().zip(after) {
+            assert!(
+                (before - after).abs() <= before * 1e-5,
+                "{} {}",
+                before,
+                after
+            );
+        }
+        assert!(buffer.bits[(11 * 32 + 15) * HDR_CHANNELS] < 100.0);
+
+        // NOTE(Fermin): Flat buffers stay flat, edges included
+        fill(&mut buffer, [2.0, 1.0, 0.5]);
+        gaussian_blur(&threads, &mut buffer, &mut scratch, &kernel);
+        for pixel in buffer.bits.chunks_exact(HDR_CHANNELS) {
+            for (value, expected) in pixel.iter().zip([2.0, 1.0, 0.5]) {
+                assert!((value - expected).abs() < 1e-5);
+            }
+        }
+    }
+
+    // NOTE(Fermin): The whole pass at 1080p has to fit in a 60 Hz frame on
+    // a mid-range CPU, with rows split over all of its cores.
+    // cargo test --release bloom_keeps_up -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bloom_keeps_up_with_1080p_at_60_hz() {
+        let mut bloom = Bloom::new(
+            1920,
+            1080,
+            BloomSettings {
+                threshold: crate::BLOOM_THRESHOLD,
+                intensity: crate::BLOOM_INTENSITY,
+                radius: crate::BLOOM_RADIUS,
+                levels: crate::BLOOM_LEVELS,
+            },
+        );
+        let mut hdr = HdrBuffer::new(1920, 1080);
+        fill(&mut hdr, [0.02, 0.02, 0.05]);
+        for star in 0..2000 {
+            set(
+                &mut hdr,
+                star * 37 % 1920,
+                star * 53 % 1080,
+                [3.0, 2.5, 2.0],
+            );
+        }
+        apply_bloom(&mut bloom, &hdr);
+
+        let frames = 30;
+        let start = std::time::Instant::now();
+        for _frame in 0..frames {
+            apply_bloom(&mut bloom, &hdr);
+        }
+        let milliseconds = start.elapsed().as_secs_f32() * 1000.0 / frames as f32;
+        println!(
+            "bloom at 1080p: {:.2} ms on {} threads",
+            milliseconds, bloom.threads.threads
+        );
+        assert!(milliseconds < 1000.0 / 60.0, "{} ms", milliseconds);
+    }
+}
@@ -0,0 +1,363 @@
+use crate::color::Color;
+use crate::image::{check_image_size, ImageError};
+use crate::pixel_buffer::{PixelBuffer, PixelFormat};
+
+const FILE_HEADER_SIZE: usize = 14;
+const CORE_HEADER_SIZE: usize = 12;
+const INFO_HEADER_SIZE: usize = 40;
+// NOTE(Fermin): V2 headers and up carry the color masks themselves, V3 and
+// up an alpha mask too.
+const V2_HEADER_SIZE: usize = 52;
+const V3_HEADER_SIZE: usize = 56;
+
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, ImageError> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or(ImageError::Corrupt("truncated bmp header"))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, ImageError> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or(ImageError::Corrupt("truncated bmp header"))
+}
+
+// NOTE(Fermin): Pulls one channel out of a packed pixel and stretches it to
+// 8 bits, whatever the mask width is.
+#[derive(Copy, Clone)]
+struct ChannelMask {
+    mask: u32,
+    shift: u32,
+    max: u32,
+}
+
+impl ChannelMask {
+    fn new(mask: u32) -> ChannelMask {
+        let shift = if mask == 0 { 0 } else { mask.trailing_zeros() };
+        ChannelMask {
+            mask,
+            shift,
+            max: mask >> shift,
+        }
+    }
+
+    fn extract(&self, pixel: u32, default: u8) -> u8 {
+        if self.max == 0 {
+            return default;
+        }
+        (((pixel & self.mask) >> self.shift) as u64 * 255 / self.max as u64) as u8
+    }
+}
+
+// NOTE(Fermin): Uncompressed bmps at 1, 4, 8, 16, 24 and 32 bits per pixel,
+// bottom-up or top-down, with or without bitfields. RLE is not supported.
+pub fn decode_bmp(bytes: &[u8]) -> Result<PixelBuffer, ImageError> {
+    if !bytes.starts_with(b"BM") {
+        return Err(ImageError::Corrupt("missing bmp signature"));
+    }
+    let data_offset = read_u32(bytes, 10)? as usize;
+    let header_size = read_u32(bytes, FILE_HEADER_SIZE)? as usize;
+
+    let (width, height, bits_per_pixel, compression, colors_used, palette_entry_size);
+    if header_size == CORE_HEADER_SIZE {
+        width = read_u16(bytes, 18)? as i32;
+        height = read_u16(bytes, 20)? as i32;
+        bits_per_pixel = read_u16(bytes, 24)? as u32;
+        compression = BI_RGB;
+        colors_used = 0;
+        palette_entry_size = 3;
+    } else if header_size >= INFO_HEADER_SIZE {
+        width = read_u32(bytes, 18)? as i32;
+        height = read_u32(bytes, 22)? as i32;
+        bits_per_pixel = read_u16(bytes, 28)? as u32;
+        compression = read_u32(bytes, 30)?;
+        colors_used = read_u32(bytes, 46)? as usize;
+        palette_entry_size = 4;
+    } else {
+        return Err(ImageError::Corrupt("unknown bmp header size"));
+    }
+
+    // NOTE(Fermin): Negative height means rows are stored top-down
+    let top_down = height < 0;
+    let height = height
+        .checked_abs()
+        .ok_or(ImageError::Corrupt("bad bmp height"))?;
+    if width <= 0 || height == 0 {
+        return Err(ImageError::Corrupt("bmp has no pixels"));
+    }
+    check_image_size(width as usize, height as usize)?;
+    // NOTE(Fermin): Before anything gets sized after it, 0 would make rows
+    // 0 bytes long.
+    if ![1, 4, 8, 16, 24, 32].contains(&bits_per_pixel) {
+        return Err(ImageError::Unsupported(format!(
+            "{} bits per pixel bmp",
+            bits_per_pixel
+        )));
+    }
+    if compression != BI_RGB && compression != BI_BITFIELDS && compression != BI_ALPHABITFIELDS {
+        return Err(ImageError::Unsupported(format!(
+            "bmp compression {}",
+            compression
+        )));
+    }
+
+    // --------------------------------------------------------------------
+    // NOTE(Fermin): Masks come right after a 40 byte header or inside the
+    // bigger ones, both end up at the same offset.
+    // --------------------------------------------------------------------
+    let masks_offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE;
+    let mut extra_masks_size = 0;
+    let (red, green, blue, alpha) = if compression == BI_RGB {
+        match bits_per_pixel {
+            16 => (0x7c00, 0x03e0, 0x001f, 0),
+            _ => (0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0),
+        }
+    } else {
+        let has_alpha = compression == BI_ALPHABITFIELDS || header_size >= V3_HEADER_SIZE;
+        if header_size < V2_HEADER_SIZE {
+            extra_masks_size = if has_alpha { 16 } else { 12 };
+        }
+        let alpha = if has_alpha {
+            read_u32(bytes, masks_offset + 12)?
+        } else {
+            0
+        };
+        (
+            read_u32(bytes, masks_offset)?,
+            read_u32(bytes, masks_offset + 4)?,
+            read_u32(bytes, masks_offset + 8)?,
+            alpha,
+        )
+    };
+    let masks = [
+        ChannelMask::new(red),
+        ChannelMask::new(green),
+        ChannelMask::new(blue),
+        ChannelMask::new(alpha),
+    ];
+
+    let mut palette: Vec<Color> = Vec::new();
+    if bits_per_pixel <= 8 {
+        let palette_offset = FILE_HEADER_SIZE + header_size + extra_masks_size;
+        let count = if colors_used > 0 {
+            colors_used.min(256)
+        } else {
+            1 << bits_per_pixel
+        };
+        for entry in 0..count {
+            let start = palette_offset + entry * palette_entry_size;
+            let bgr = bytes
+                .get(start..start + 3)
+                .ok_or(ImageError::Corrupt("truncated bmp palette"))?;
+            palette.push(Color::rgba(bgr[2], bgr[1], bgr[0], 255));
+        }
+    }
+
+    // --------------------------------------------------------------------
+    // NOTE(Fermin): Rows are padded to 4 bytes
+    // --------------------------------------------------------------------
+    let stride = (width as usize * bits_per_pixel as usize).div_ceil(32) * 4;
+    let data_end = data_offset + stride * height as usize;
+    let data = bytes
+        .get(data_offset..data_end)
+        .ok_or(ImageError::Corrupt("truncated bmp pixel data"))?;
+
+    let mut buffer = PixelBuffer::new(width, height, PixelFormat::Bgra8);
+    for (row_index, row) in data.chunks_exact(stride).enumerate() {
+        let y = if top_down {
+            row_index as i32
+        } else {
+            height - 1 - row_index as i32
+        };
+        for x in 0..width {
+            let color = match bits_per_pixel {
+                1 | 4 | 8 => {
+                    let bit = x as usize * bits_per_pixel as usize;
+                    let byte = row[bit / 8];
+                    let shift = 8 - bits_per_pixel as usize - bit % 8;
+                    let index = (byte >> shift) as usize & ((1 << bits_per_pixel) - 1);
+                    *palette
+                        .get(index)
+                        .ok_or(ImageError::Corrupt("bmp palette index out of range"))?
+                }
+                // NOTE(Fermin): 16, 24 or 32
+                _ => {
+                    let bytes_per_pixel = bits_per_pixel as usize / 8;
+                    let start = x as usize * bytes_per_pixel;
+                    let mut pixel = [0; 4];
+                    pixel[..bytes_per_pixel].copy_from_slice(&row[start..start + bytes_per_pixel]);
+                    let pixel = u32::from_le_bytes(pixel);
+                    Color::rgba(
+                        masks[0].extract(pixel, 0),
+                        masks[1].extract(pixel, 0),
+                        masks[2].extract(pixel, 0),
+                        masks[3].extract(pixel, 255),
+                    )
+                }
+            };
+            buffer.set(x, y, &color);
+        }
+    }
+
+    Ok(buffer)
+}
+
+// NOTE(Fermin): 32 bits per pixel, top-down, the same BGRA layout the
+// window buffer has. The fourth byte is padding in BI_RGB bmps, so the
+// image comes back opaque.
+pub fn encode_bmp(buffer: &PixelBuffer) -> Vec<u8> {
+    let data_offset = (FILE_HEADER_SIZE + INFO_HEADER_SIZE) as u32;
+    let data_size = (buffer.width * buffer.height * 4) as u32;
+    let mut bytes = Vec::with_capacity((data_offset + data_size) as usize);
+    bytes.extend_from_slice(b"BM");
+    bytes.extend_from_slice(&(data_offset + data_size).to_le_bytes());
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.extend_from_slice(&data_offset.to_le_bytes());
+    bytes.extend_from_slice(&(INFO_HEADER_SIZE as u32).to_le_bytes());
+    bytes.extend_from_slice(&buffer.width.to_le_bytes());
+    bytes.extend_from_slice(&(-buffer.height).to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&32u16.to_le_bytes());
+    bytes.extend_from_slice(&BI_RGB.to_le_bytes());
+    bytes.extend_from_slice(&data_size.to_le_bytes());
+    // NOTE(Fermin): Resolution, palette size and important colors
+    bytes.extend_from_slice(&[0; 16]);
+
+    for row in buffer.rows() {
+        for pixel in row.chunks_exact(buffer.format.bytes_per_pixel()) {
+            bytes.extend_from_slice(&buffer.format.decode(pixel).to_bgra());
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NOTE(Fermin): Builds a bmp with a 40 byte header around the given
+    // palette and rows, which must already be padded.
+    fn bmp(
+        width: i32,
+        height: i32,
+        bits_per_pixel: u16,
+        palette: &[[u8; 4]],
+        rows: &[u8],
+    ) -> Vec<u8> {
+        let data_offset = (FILE_HEADER_SIZE + INFO_HEADER_SIZE + palette.len() * 4) as u32;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"BM");
+        bytes.extend_from_slice(&(data_offset + rows.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&data_offset.to_le_bytes());
+        bytes.extend_from_slice(&(INFO_HEADER_SIZE as u32).to_le_bytes());
+        bytes.extend_from_slice(&width.to_le_bytes());
+        bytes.extend_from_slice(&height.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&bits_per_pixel.to_le_bytes());
+        bytes.extend_from_slice(&BI_RGB.to_le_bytes());
+        bytes.extend_from_slice(&(rows.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&(palette.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        for entry in palette {
+            bytes.extend_from_slice(entry);
+        }
+        bytes.extend_from_slice(rows);
+        bytes
+    }
+
+    #[test]
+    fn decodes_bottom_up_24_bit_with_padding() {
+        // NOTE(Fermin): 2 pixels * 3 bytes + 2 bytes of padding per row,
+        // bottom row first.
+        let rows = [
+            0, 0, 255, 0, 255, 0, 0, 0, //
+            255, 0, 0, 255, 255, 255, 0, 0,
+        ];
+        let image = decode_bmp(&bmp(2, 2, 24, &[], &rows)).unwrap();
+
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.get(0, 0), Some(Color::rgba(0, 0, 255, 255)));
+        assert_eq!(image.get(1, 0), Some(Color::rgba(255, 255, 255, 255)));
+        assert_eq!(image.get(0, 1), Some(Color::rgba(255, 0, 0, 255)));
+        assert_eq!(image.get(1, 1), Some(Color::rgba(0, 255, 0, 255)));
+    }
+
+    #[test]
+    fn decodes_top_down_paletted() {
+        let palette = [[0, 0, 0, 0], [255, 128, 0, 0]];
+        // NOTE(Fermin): 1 bit per pixel, 3 pixels per row: 101 and 010
+        let rows = [0b1010_0000, 0, 0, 0, 0b0100_0000, 0, 0, 0];
+        let image = decode_bmp(&bmp(3, -2, 1, &palette, &rows)).unwrap();
+
+        let blue = Color::rgba(0, 128, 255, 255);
+        let black = Color::rgba(0, 0, 0, 255);
+        assert_eq!(image.get(0, 0), Some(blue));
+        assert_eq!(image.get(1, 0), Some(black));
+        assert_eq!(image.get(2, 0), Some(blue));
+        assert_eq!(image.get(1, 1), Some(blue));
+    }
+
+    #[test]
+    fn decodes_art_star_with_alpha() {
+        let image = decode_bmp(include_bytes!("../art/star.bmp")).unwrap();
+        assert_eq!((image.width, image.height), (50, 50));
+
+        // NOTE(Fermin): Transparent corners, opaque middle
+        assert_eq!(image.get(0, 0).unwrap().a, 0);
+        assert_eq!(image.get(25, 25).unwrap().a, 255);
+    }
+
+    #[test]
+    fn encoded_buffers_decode_the_same() {
+        let mut buffer = PixelBuffer::new(3, 2, PixelFormat::Rgba8);
+        buffer.set(0, 0, &Color::rgba(255, 0, 0, 255));
+        buffer.set(2, 0, &Color::rgba(0, 128, 255, 255));
+        buffer.set(1, 1, &Color::rgba(10, 20, 30, 255));
+
+        let image = decode_bmp(&encode_bmp(&buffer)).unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        for y in 0..2 {
+            for x in 0..3 {
+                let expected = buffer.get(x, y).unwrap();
+                let expected = Color::rgba(expected.r, expected.g, expected.b, 255);
+                assert_eq!(image.get(x, y), Some(expected));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        let rows = [0; 8];
+        let valid = bmp(2, 2, 24, &[], &rows);
+
+        assert!(matches!(
+            decode_bmp(&valid[..valid.len() - 1]),
+            Err(ImageError::Corrupt(_))
+        ));
+        assert!(matches!(
+            decode_bmp(&valid[..20]),
+            Err(ImageError::Corrupt(_))
+        ));
+        assert!(matches!(decode_bmp(b"PNG"), Err(ImageError::Corrupt(_))));
+
+        let mut rle = valid.clone();
+        rle[30] = 1;
+        assert!(matches!(decode_bmp(&rle), Err(ImageError::Unsupported(_))));
+
+        for bits_per_pixel in [0, 2, 7, 64] {
+            let odd_depth = bmp(2, 2, bits_per_pixel, &[], &rows);
+            assert!(matches!(
+                decode_bmp(&odd_depth),
+                Err(ImageError::Unsupported(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,440 @@
+use crate::math::lerp;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+// NOTE(Fermin): h in degrees [0, 360), everything else in [0, 1]. HSV and
+// HSL are for picking colors by hue in tools, the game itself blends in
+// OkLab and doesn't need them.
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Hsv {
+    pub h: f32,
+    pub s: f32,
+    pub v: f32,
+}
+
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Hsl {
+    pub h: f32,
+    pub s: f32,
+    pub l: f32,
+}
+
+// NOTE(Fermin): Björn Ottosson's perceptual color space, l in [0, 1] and a, b
+// roughly in [-0.4, 0.4].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OkLab {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+fn channel_to_unit(channel: u8) -> f32 {
+    channel as f32 / 255.0
+}
+
+fn unit_to_channel(unit: f32) -> u8 {
+    (unit.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// NOTE(Fermin): Shared by hsv and hsl, builds rgb out of hue, chroma and the
+// amount every channel gets on top.
+#[allow(dead_code)]
+fn hue_to_rgb(h: f32, chroma: f32, m: f32) -> [f32; 3] {
+    let h = h.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+    let [r, g, b] = match h as i32 {
+        0 => [chroma, x, 0.0],
+        1 => [x, chroma, 0.0],
+        2 => [0.0, chroma, x],
+        3 => [0.0, x, chroma],
+        4 => [x, 0.0, chroma],
+        _ => [chroma, 0.0, x],
+    };
+    [r + m, g + m, b + m]
+}
+
+impl Color {
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color { r, g, b, a }
+    }
+
+    // NOTE(Fermin): Pixel -> BB GG RR AA, the memory order of the
+    // presentation buffer.
+    pub fn to_bgra(self) -> [u8; 4] {
+        [self.b, self.g, self.r, self.a]
+    }
+
+    pub fn from_bgra(bytes: &[u8]) -> Color {
+        Color::rgba(bytes[2], bytes[1], bytes[0], bytes[3])
+    }
+
+    // NOTE(Fermin): The packed values are little endian, the low byte of
+    // to_u32_bgra is blue. Pixel buffers are bytes, packing is only for
+    // whoever wants to hand colors around as one number.
+    #[allow(dead_code)]
+    pub fn to_u32_bgra(self) -> u32 {
+        u32::from_le_bytes(self.to_bgra())
+    }
+
+    #[allow(dead_code)]
+    pub fn from_u32_bgra(packed: u32) -> Color {
+        Color::from_bgra(&packed.to_le_bytes())
+    }
+
+    #[allow(dead_code)]
+    pub fn to_u32_rgba(self) -> u32 {
+        u32::from_le_bytes([self.r, self.g, self.b, self.a])
+    }
+
+    #[allow(dead_code)]
+    pub fn from_u32_rgba(packed: u32) -> Color {
+        let [r, g, b, a] = packed.to_le_bytes();
+        Color::rgba(r, g, b, a)
+    }
+
+    // NOTE(Fermin): Accepts #rrggbb and #rrggbbaa, the # is optional
+    pub fn from_hex(hex: &str) -> Option<Color> {
+        let digits = hex.strip_prefix('#').unwrap_or(hex);
+        if !(digits.len() == 6 || digits.len() == 8) || !digits.is_ascii() {
+            return None;
+        }
+
+        let channel = |index: usize| u8::from_str_radix(&digits[index..index + 2], 16).ok();
+        let a = if digits.len() == 8 { channel(6)? } else { 255 };
+        Some(Color::rgba(channel(0)?, channel(2)?, channel(4)?, a))
+    }
+
+    // NOTE(Fermin): Nothing writes settings back out yet
+    #[allow(dead_code)]
+    pub fn to_hex(self) -> String {
+        format!("#{:02x}{:02x}{:02x}{:02x}", self.r, self.g, self.b, self.a)
+    }
+
+    #[allow(dead_code)]
+    pub fn to_hsv(self) -> Hsv {
+        let (h, max, min) = self.hue_max_min();
+        let chroma = max - min;
+        Hsv {
+            h,
+            s: if max > 0.0 { chroma / max } else { 0.0 },
+            v: max,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn from_hsv(hsv: Hsv, a: u8) -> Color {
+        let chroma = hsv.v * hsv.s;
+        let [r, g, b] = hue_to_rgb(hsv.h, chroma, hsv.v - chroma);
+        Color::rgba(
+            unit_to_channel(r),
+            unit_to_channel(g),
+            unit_to_channel(b),
+            a,
+        )
+    }
+
+    #[allow(dead_code)]
+    pub fn to_hsl(self) -> Hsl {
+        let (h, max, min) = self.hue_max_min();
+        let l = (max + min) / 2.0;
+        let s = if max == min {
+            0.0
+        } else {
+            (max - min) / (1.0 - (2.0 * l - 1.0).abs())
+        };
+        Hsl { h, s, l }
+    }
+
+    #[allow(dead_code)]
+    pub fn from_hsl(hsl: Hsl, a: u8) -> Color {
+        let chroma = (1.0 - (2.0 * hsl.l - 1.0).abs()) * hsl.s;
+        let [r, g, b] = hue_to_rgb(hsl.h, chroma, hsl.l - chroma / 2.0);
+        Color::rgba(
+            unit_to_channel(r),
+            unit_to_channel(g),
+            unit_to_channel(b),
+            a,
+        )
+    }
+
+    #[allow(dead_code)]
+    fn hue_max_min(self) -> (f32, f32, f32) {
+        let r = channel_to_unit(self.r);
+        let g = channel_to_unit(self.g);
+        let b = channel_to_unit(self.b);
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let chroma = max - min;
+
+        let h = if chroma == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / chroma).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / chroma + 2.0)
+        } else {
+            60.0 * ((r - g) / chroma + 4.0)
+        };
+        (h, max, min)
+    }
+
+    // NOTE(Fermin): Constants are copied as is from the reference implementation
+    #[allow(clippy::excessive_precision)]
+    pub fn to_oklab(self) -> OkLab {
+        let r = srgb_to_linear(channel_to_unit(self.r));
+        let g = srgb_to_linear(channel_to_unit(self.g));
+        let b = srgb_to_linear(channel_to_unit(self.b));
+
+        let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+        let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+        let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+        OkLab {
+            l: 0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+            a: 1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+            b: 0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+        }
+    }
+
+    #[allow(clippy::excessive_precision)]
+    pub fn from_oklab(lab: OkLab, a: u8) -> Color {
+        let l = lab.l + 0.3963377774 * lab.a + 0.2158037573 * lab.b;
+        let m = lab.l - 0.1055613458 * lab.a - 0.0638541728 * lab.b;
+        let s = lab.l - 0.0894841775 * lab.a - 1.2914855480 * lab.b;
+        let (l, m, s) = (l * l * l, m * m * m, s * s * s);
+
+        let r = 4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s;
+        let g = -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s;
+        let b = -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s;
+
+        Color::rgba(
+            unit_to_channel(linear_to_srgb(r)),
+            unit_to_channel(linear_to_srgb(g)),
+            unit_to_channel(linear_to_srgb(b)),
+            a,
+        )
+    }
+}
+
+// NOTE(Fermin): Interpolates in OkLab so the midpoint between two colors
+// looks halfway between them instead of muddy.
+pub fn color_lerp(a: &Color, t: f32, b: &Color) -> Color {
+    let lab_a = a.to_oklab();
+    let lab_b = b.to_oklab();
+    Color::from_oklab(
+        OkLab {
+            l: lerp(lab_a.l, t, lab_b.l),
+            a: lerp(lab_a.a, t, lab_b.a),
+            b: lerp(lab_a.b, t, lab_b.b),
+        },
+        lerp(a.a as f32, t, b.a as f32).round() as u8,
+    )
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GradientStop {
+    pub t: f32,
+    pub color: Color,
+}
+
+// NOTE(Fermin): Colors placed along [0, 1], sampling between two stops
+// lerps them perceptually.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gradient {
+    stops: Vec<GradientStop>,
+}
+
+impl Gradient {
+    pub fn new(mut stops: Vec<GradientStop>) -> Gradient {
+        assert!(!stops.is_empty(), "Err: A gradient needs at least one stop");
+        stops.sort_by(|a, b| a.t.total_cmp(&b.t));
+        Gradient { stops }
+    }
+
+    pub fn from_hex(stops: &[(f32, &str)]) -> Gradient {
+        Gradient::new(
+            stops
+                .iter()
+                .map(|&(t, hex)| GradientStop {
+                    t,
+                    color: Color::from_hex(hex).expect("Err: Invalid gradient hex color"),
+                })
+                .collect(),
+        )
+    }
+
+    pub fn sample(&self, t: f32) -> Color {
+        let first = &self.stops[0];
+        if t <= first.t {
+            return first.color;
+        }
+
+        for pair in self.stops.windows(2) {
+            let (from, to) = (&pair[0], &pair[1]);
+            if t <= to.t {
+                let span = to.t - from.t;
+                let local_t = if span > 0.0 { (t - from.t) / span } else { 1.0 };
+                return color_lerp(&from.color, local_t, &to.color);
+            }
+        }
+
+        self.stops[self.stops.len() - 1].color
+    }
+}
+
+// NOTE(Fermin): Every color the scene is drawn with
+#[derive(Clone, Debug)]
+pub struct Palette {
+    pub background: Color,
+    pub stars: Gradient,
+    pub nebula: Gradient,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_color_close(a: Color, b: Color) {
+        let close = |x: u8, y: u8| (x as i32 - y as i32).abs() <= 1;
+        assert!(
+            close(a.r, b.r) && close(a.g, b.g) && close(a.b, b.b) && a.a == b.a,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn packing_round_trips() {
+        let color = Color::rgba(0x11, 0x22, 0x33, 0x44);
+        assert_eq!(color.to_bgra(), [0x33, 0x22, 0x11, 0x44]);
+        assert_eq!(color.to_u32_bgra(), 0x44112233);
+        assert_eq!(color.to_u32_rgba(), 0x44332211);
+        assert_eq!(Color::from_u32_bgra(color.to_u32_bgra()), color);
+        assert_eq!(Color::from_u32_rgba(color.to_u32_rgba()), color);
+        assert_eq!(Color::from_bgra(&color.to_bgra()), color);
+    }
+
+    #[test]
+    fn hex_parsing() {
+        assert_eq!(
+            Color::from_hex("#40128b"),
+            Some(Color::rgba(64, 18, 139, 255))
+        );
+        assert_eq!(
+            Color::from_hex("F9D94980"),
+            Some(Color::rgba(249, 217, 73, 128))
+        );
+        assert_eq!(Color::from_hex("#fff"), None);
+        assert_eq!(Color::from_hex("#gg0000"), None);
+        assert_eq!(Color::from_hex("#ééé"), None);
+        assert_eq!(Color::rgba(1, 2, 255, 16).to_hex(), "#0102ff10");
+    }
+
+    #[test]
+    fn hsv_and_hsl_round_trip() {
+        let red = Color::rgba(255, 0, 0, 255);
+        assert_eq!(
+            red.to_hsv(),
+            Hsv {
+                h: 0.0,
+                s: 1.0,
+                v: 1.0
+            }
+        );
+        assert_eq!(
+            red.to_hsl(),
+            Hsl {
+                h: 0.0,
+                s: 1.0,
+                l: 0.5
+            }
+        );
+        assert_eq!(
+            Color::from_hsv(
+                Hsv {
+                    h: 120.0,
+                    s: 1.0,
+                    v: 1.0
+                },
+                255
+            ),
+            Color::rgba(0, 255, 0, 255)
+        );
+        assert_eq!(
+            Color::from_hsl(
+                Hsl {
+                    h: 240.0,
+                    s: 1.0,
+                    l: 0.5
+                },
+                9
+            ),
+            Color::rgba(0, 0, 255, 9)
+        );
+
+        for color in [
+            Color::rgba(64, 18, 139, 255),
+            Color::rgba(249, 217, 73, 255),
+            Color::rgba(128, 128, 128, 255),
+            Color::rgba(10, 200, 150, 255),
+        ] {
+            assert_color_close(Color::from_hsv(color.to_hsv(), 255), color);
+            assert_color_close(Color::from_hsl(color.to_hsl(), 255), color);
+        }
+    }
+
+    #[test]
+    fn oklab_round_trips() {
+        let white = Color::rgba(255, 255, 255, 255).to_oklab();
+        assert!((white.l - 1.0).abs() < 1.0e-3 && white.a.abs() < 1.0e-3 && white.b.abs() < 1.0e-3);
+
+        for color in [
+            Color::rgba(0, 0, 0, 255),
+            Color::rgba(64, 18, 139, 255),
+            Color::rgba(249, 217, 73, 255),
+            Color::rgba(255, 0, 0, 255),
+        ] {
+            assert_color_close(Color::from_oklab(color.to_oklab(), 255), color);
+        }
+    }
+
+    #[test]
+    fn gradient_sampling() {
+        let gradient = Gradient::from_hex(&[(1.0, "#ffffff"), (0.0, "#000000")]);
+        assert_eq!(gradient.sample(-1.0), Color::rgba(0, 0, 0, 255));
+        assert_eq!(gradient.sample(0.0), Color::rgba(0, 0, 0, 255));
+        assert_eq!(gradient.sample(1.0), Color::rgba(255, 255, 255, 255));
+        assert_eq!(gradient.sample(2.0), Color::rgba(255, 255, 255, 255));
+
+        // NOTE(Fermin): Halfway in OkLab lightness, not the rgb average
+        let middle = gradient.sample(0.5);
+        assert!(middle.r > 90 && middle.r < 110);
+        assert!(middle.r == middle.g && middle.g == middle.b);
+        assert_eq!(color_lerp(&middle, 0.0, &middle), middle);
+    }
+}
@@ -0,0 +1,410 @@
+use crate::color::Color;
+use crate::math::Rect;
+use crate::pixel_buffer::{PixelBuffer, PixelFormat};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    // NOTE(Fermin): Layer goes over whatever is below it
+    Normal,
+    // NOTE(Fermin): Light adds up, what hdr stars and glows want
+    Add,
+    // NOTE(Fermin): For the overlays that come after background and stars,
+    // nothing blends like this yet.
+    #[allow(dead_code)]
+    Multiply,
+    #[allow(dead_code)]
+    Screen,
+}
+
+// NOTE(Fermin): Layer pixels are premultiplied by their alpha, a transparent
+// pixel is all zeros. Blending into a layer with RenderTarget::blend_pixel
+// keeps it that way.
+pub struct Layer {
+    pub name: String,
+    pub buffer: PixelBuffer,
+    pub blend_mode: BlendMode,
+    // NOTE(Fermin): Static layers keep their pixels between frames and are
+    // only redrawn after invalidate(). Everything else is redrawn by its owner
+    // every frame.
+    pub is_static: bool,
+    opacity: f32,
+    visible: bool,
+    needs_redraw: bool,
+    changed: bool,
+}
+
+impl Layer {
+    // NOTE(Fermin): Every layer is opaque and shown for now, fades and
+    // toggles only come with overlays.
+    #[allow(dead_code)]
+    pub fn opacity(&self) -> f32 {
+        self.opacity
+    }
+
+    #[allow(dead_code)]
+    pub fn set_opacity(&mut self, opacity: f32) {
+        let opacity = opacity.clamp(0.0, 1.0);
+        if opacity != self.opacity {
+            self.opacity = opacity;
+            self.changed = true;
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    #[allow(dead_code)]
+    pub fn set_visible(&mut self, visible: bool) {
+        if visible != self.visible {
+            self.visible = visible;
+            self.changed = true;
+        }
+    }
+
+    pub fn invalidate(&mut self) {
+        self.needs_redraw = true;
+        self.changed = true;
+    }
+
+    pub fn needs_redraw(&self) -> bool {
+        !self.is_static || self.needs_redraw
+    }
+
+    pub fn mark_redrawn(&mut self) {
+        self.needs_redraw = false;
+    }
+}
+
+// NOTE(Fermin): Stack of layers, the first one is at the bottom. Every layer
+// is the size of the output buffer.
+pub struct Compositor {
+    pub layers: Vec<Layer>,
+    pub width: i32,
+    pub height: i32,
+    format: PixelFormat,
+}
+
+impl Compositor {
+    pub fn new(width: i32, height: i32, format: PixelFormat) -> Compositor {
+        Compositor {
+            layers: Vec::new(),
+            width,
+            height,
+            format,
+        }
+    }
+
+    // NOTE(Fermin): New layers go on top and start out transparent, static
+    // ones ask to be drawn once.
+    pub fn push_layer(&mut self, name: &str, blend_mode: BlendMode, is_static: bool) -> &mut Layer {
+        assert!(self.layer(name).is_none(), "Layer {} already exists", name);
+        self.layers.push(Layer {
+            name: name.to_string(),
+            buffer: PixelBuffer::new(self.width, self.height, self.format),
+            blend_mode,
+            is_static,
+            opacity: 1.0,
+            visible: true,
+            needs_redraw: true,
+            changed: true,
+        });
+        self.layers.last_mut().unwrap()
+    }
+
+    // NOTE(Fermin): Layers lose their pixels, static ones get invalidated so
+    // they are drawn again at the new size.
+    pub fn resize(&mut self, width: i32, height: i32) {
+        self.width = width;
+        self.height = height;
+        for layer in &mut self.layers {
+            layer.buffer = PixelBuffer::new(width, height, self.format);
+            layer.invalidate();
+        }
+    }
+
+    pub fn layer(&self, name: &str) -> Option<&Layer> {
+        self.layers.iter().find(|layer| layer.name == name)
+    }
+
+    pub fn layer_mut(&mut self, name: &str) -> Option<&mut Layer> {
+        self.layers.iter_mut().find(|layer| layer.name == name)
+    }
+
+    // NOTE(Fermin): True when some layer changed as a whole (visibility,
+    // opacity, static content) since the last call, meaning the entire output
+    // has to be flattened again.
+    pub fn take_changed(&mut self) -> bool {
+        let mut changed = false;
+        for layer in &mut self.layers {
+            changed |= layer.changed;
+            layer.changed = false;
+        }
+        changed
+    }
+
+    // NOTE(Fermin): Only the pixels inside rects are flattened, everything
+    // else in output is left alone.
+    pub fn flatten(&self, output: &mut PixelBuffer, rects: &[Rect]) {
+        assert!(output.width == self.width && output.height == self.height);
+
+        let out_format = output.format;
+        let out_bytes_per_pixel = out_format.bytes_per_pixel();
+        let visible_layers: Vec<&Layer> = self
+            .layers
+            .iter()
+            .filter(|layer| layer.visible && layer.opacity > 0.0)
+            .collect();
+        for rect in rects {
+            let rect = rect.intersection(&output.bounds());
+            if rect.is_empty() {
+                continue;
+            }
+
+            for y in rect.min_y..rect.max_y {
+                let row_rect = Rect::from_pos_size(rect.min_x, y, rect.width(), 1);
+                let mut out_view = output.view_mut(&row_rect);
+                let out_row = out_view.rows_mut().next().unwrap();
+
+                // NOTE(Fermin): Output starts out transparent, unless the
+                // bottom layer covers it and can be copied as is.
+                let mut layers = visible_layers.iter().peekable();
+                match layers.peek() {
+                    Some(bottom)
+                        if bottom.blend_mode == BlendMode::Normal
+                            && bottom.opacity == 1.0
+                            && bottom.buffer.format == out_format =>
+                    {
+                        let view = bottom.buffer.view(&row_rect);
+                        out_row.copy_from_slice(view.rows().next().unwrap());
+                        layers.next();
+                    }
+                    _ => out_row.fill(0),
+                }
+
+                for layer in layers {
+                    let view = layer.buffer.view(&row_rect);
+                    let layer_format = view.format;
+                    let src_row = view.rows().next().unwrap();
+                    let add_in_place = layer.blend_mode == BlendMode::Add && layer.opacity == 1.0;
+                    for (dest, src) in out_row
+                        .chunks_exact_mut(out_bytes_per_pixel)
+                        .zip(src_row.chunks_exact(layer_format.bytes_per_pixel()))
+                    {
+                        if leaves_dest_alone(src, layer.blend_mode) {
+                            continue;
+                        }
+                        if add_in_place && layer_format == out_format {
+                            add_bytes(dest, src);
+                            continue;
+                        }
+                        let blended = blend(
+                            normalize(&out_format.decode(dest)),
+                            normalize(&layer_format.decode(src)),
+                            layer.opacity,
+                            layer.blend_mode,
+                        );
+                        dest.copy_from_slice(&out_format.encode(&denormalize(&blended)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn normalize(color: &Color) -> [f32; 4] {
+    [
+        color.r as f32 / 255.0,
+        color.g as f32 / 255.0,
+        color.b as f32 / 255.0,
+        color.a as f32 / 255.0,
+    ]
+}
+
+fn denormalize(pixel: &[f32; 4]) -> Color {
+    let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    Color::rgba(
+        channel(pixel[0]),
+        channel(pixel[1]),
+        channel(pixel[2]),
+        channel(pixel[3]),
+    )
+}
+
+// NOTE(Fermin): Most of a stars layer is empty, skipping those pixels is
+// what keeps flattening cheap. Every PixelFormat keeps alpha in the last
+// byte, so there's no need to decode.
+fn leaves_dest_alone(src: &[u8], blend_mode: BlendMode) -> bool {
+    let is_black = src[0] == 0 && src[1] == 0 && src[2] == 0;
+    match blend_mode {
+        BlendMode::Add | BlendMode::Screen => is_black,
+        BlendMode::Normal | BlendMode::Multiply => is_black && src[3] == 0,
+    }
+}
+
+// NOTE(Fermin): Same as blend() with BlendMode::Add at full opacity, without
+// going through floats. Both pixels must be in the same format.
+fn add_bytes(dest: &mut [u8], src: &[u8]) {
+    for channel in 0..3 {
+        dest[channel] = dest[channel].saturating_add(src[channel]);
+    }
+    let (src_a, dest_a) = (src[3] as u32, dest[3] as u32);
+    dest[3] = (src_a + dest_a - (src_a * dest_a + 127) / 255) as u8;
+}
+
+// NOTE(Fermin): Both pixels are premultiplied, channels are in 0..1. Opacity
+// scales the whole source pixel before blending.
+fn blend(dest: [f32; 4], src: [f32; 4], opacity: f32, blend_mode: BlendMode) -> [f32; 4] {
+    let src_a = src[3] * opacity;
+    let mut result = [0.0; 4];
+    for channel in 0..3 {
+        let s = src[channel] * opacity;
+        let d = dest[channel];
+        result[channel] = match blend_mode {
+            BlendMode::Normal => s + d * (1.0 - src_a),
+            BlendMode::Add => (s + d).min(1.0),
+            BlendMode::Multiply => s * d + d * (1.0 - src_a),
+            BlendMode::Screen => s + d - s * d,
+        };
+    }
+    result[3] = src_a + dest[3] * (1.0 - src_a);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKGROUND: Color = Color::rgba(40, 20, 140, 255);
+    const TRANSPARENT: Color = Color::rgba(0, 0, 0, 0);
+
+    fn compositor_with_background() -> Compositor {
+        let mut compositor = Compositor::new(4, 4, PixelFormat::Bgra8);
+        compositor
+            .push_layer("background", BlendMode::Normal, true)
+            .buffer
+            .fill(&BACKGROUND);
+        compositor
+    }
+
+    fn flatten_all(compositor: &Compositor) -> PixelBuffer {
+        let mut output = PixelBuffer::new(compositor.width, compositor.height, PixelFormat::Bgra8);
+        let bounds = output.bounds();
+        compositor.flatten(&mut output, &[bounds]);
+        output
+    }
+
+    #[test]
+    fn transparent_layers_show_what_is_below() {
+        let mut compositor = compositor_with_background();
+        compositor.push_layer("stars", BlendMode::Normal, false);
+        compositor
+            .layer_mut("stars")
+            .unwrap()
+            .buffer
+            .set(1, 1, &Color::rgba(255, 255, 255, 255));
+
+        let output = flatten_all(&compositor);
+        assert_eq!(output.get(0, 0), Some(BACKGROUND));
+        assert_eq!(output.get(1, 1), Some(Color::rgba(255, 255, 255, 255)));
+    }
+
+    #[test]
+    fn opacity_and_visibility_fade_layers() {
+        let mut compositor = compositor_with_background();
+        let top = compositor.push_layer("top", BlendMode::Normal, false);
+        top.buffer.fill(&Color::rgba(240, 240, 240, 255));
+        top.set_opacity(0.5);
+
+        let output = flatten_all(&compositor);
+        assert_eq!(output.get(2, 2), Some(Color::rgba(140, 130, 190, 255)));
+
+        compositor.layer_mut("top").unwrap().set_visible(false);
+        assert_eq!(flatten_all(&compositor).get(2, 2), Some(BACKGROUND));
+    }
+
+    #[test]
+    fn blend_modes() {
+        let dest = [0.5, 0.25, 1.0, 1.0];
+        let src = [0.5, 0.5, 0.0, 0.5];
+
+        assert_eq!(
+            blend(dest, src, 1.0, BlendMode::Normal),
+            [0.75, 0.625, 0.5, 1.0]
+        );
+        assert_eq!(blend(dest, src, 1.0, BlendMode::Add), [1.0, 0.75, 1.0, 1.0]);
+        assert_eq!(
+            blend(dest, src, 1.0, BlendMode::Multiply),
+            [0.5, 0.25, 0.5, 1.0]
+        );
+        assert_eq!(
+            blend(dest, src, 1.0, BlendMode::Screen),
+            [0.75, 0.625, 1.0, 1.0]
+        );
+        assert_eq!(blend(dest, src, 0.0, BlendMode::Screen), dest);
+    }
+
+    #[test]
+    fn byte_add_matches_blend() {
+        let dest = Color::rgba(200, 40, 0, 128);
+        let src = Color::rgba(100, 10, 0, 100);
+        let expected = denormalize(&blend(
+            normalize(&dest),
+            normalize(&src),
+            1.0,
+            BlendMode::Add,
+        ));
+
+        let mut bytes = dest.to_bgra();
+        add_bytes(&mut bytes, &src.to_bgra());
+        assert_eq!(Color::from_bgra(&bytes), expected);
+        assert_eq!(expected, Color::rgba(255, 50, 0, 178));
+    }
+
+    #[test]
+    fn only_rects_get_flattened() {
+        let compositor = compositor_with_background();
+        let mut output = PixelBuffer::new(4, 4, PixelFormat::Bgra8);
+        compositor.flatten(&mut output, &[Rect::from_pos_size(1, 1, 2, 1)]);
+
+        assert_eq!(output.get(1, 1), Some(BACKGROUND));
+        assert_eq!(output.get(2, 1), Some(BACKGROUND));
+        assert_eq!(output.get(0, 1), Some(TRANSPARENT));
+        assert_eq!(output.get(1, 2), Some(TRANSPARENT));
+    }
+
+    #[test]
+    fn resizing_invalidates_every_layer() {
+        let mut compositor = compositor_with_background();
+        compositor.layers[0].mark_redrawn();
+        compositor.take_changed();
+
+        compositor.resize(7, 3);
+        assert!(compositor.take_changed());
+        let background = compositor.layer("background").unwrap();
+        assert!(background.needs_redraw());
+        assert_eq!((background.buffer.width, background.buffer.height), (7, 3));
+        assert_eq!(flatten_all(&compositor).get(6, 2), Some(TRANSPARENT));
+    }
+
+    #[test]
+    fn static_layers_redraw_only_when_invalidated() {
+        let mut compositor = compositor_with_background();
+        compositor.push_layer("stars", BlendMode::Add, false);
+        assert!(compositor.take_changed());
+        assert!(!compositor.take_changed());
+
+        let background = compositor.layer_mut("background").unwrap();
+        assert!(background.needs_redraw());
+        background.mark_redrawn();
+        assert!(!background.needs_redraw());
+        background.invalidate();
+        assert!(background.needs_redraw());
+        assert!(compositor.take_changed());
+
+        let stars = compositor.layer_mut("stars").unwrap();
+        stars.mark_redrawn();
+        assert!(stars.needs_redraw());
+    }
+}
@@ -0,0 +1,337 @@
+use crate::color::Color;
+use crate::compositor::BlendMode;
+use crate::dirty_rects::DirtyRegion;
+use crate::lines::{draw_line, line_bounds, LineJoin, LineStyle, LineVertex};
+use crate::math::{v2_length, Rect, V2};
+use crate::render::RenderTarget;
+use crate::spatial_grid::SpatialGrid;
+use std::collections::BTreeMap;
+
+#[derive(Copy, Clone, Debug)]
+pub struct ConstellationSettings {
+    // NOTE(Fermin): Stars further apart than this many pixels aren't linked
+    pub link_distance: f32,
+    // NOTE(Fermin): Alpha of a link between stars right next to each other,
+    // it falls off to 0 at link_distance.
+    pub max_alpha: f32,
+    pub thickness: f32,
+    pub intensity: f32,
+    // NOTE(Fermin): Seconds a new link takes to fade in. Drifting apart
+    // already fades links out, this covers stars that respawn next to
+    // each other.
+    pub fade_seconds: f32,
+}
+
+struct Link {
+    fade: f32,
+    // NOTE(Fermin): Of the stars it joins, when either one changes the star
+    // respawned and the link fades in again.
+    generations: (u32, u32),
+    vertices: [LineVertex; 2],
+    bounds: Rect,
+}
+
+// NOTE(Fermin): Links are keyed by the indices of the stars they join,
+// lowest first. Ordered so they always draw in the same order, adding
+// floats in a different one doesn't give the same bits and replays would
+// drift.
+pub struct Constellation {
+    links: BTreeMap<(usize, usize), Link>,
+}
+
+impl Constellation {
+    pub fn new() -> Constellation {
+        Constellation {
+            links: BTreeMap::new(),
+        }
+    }
+}
+
+fn link_style(settings: &ConstellationSettings) -> LineStyle {
+    LineStyle {
+        thickness: settings.thickness,
+        join: LineJoin::Miter,
+        blend_mode: BlendMode::Add,
+    }
+}
+
+// NOTE(Fermin): Links stars that got close and drops the ones that drifted
+// apart. grid, positions, colors and generations are indexed like the stars.
+#[allow(clippy::too_many_arguments)]
+pub fn update_constellation(
+    constellation: &mut Constellation,
+    settings: &ConstellationSettings,
+    grid: &SpatialGrid,
+    positions: &[V2],
+    colors: &[Color],
+    generations: &[u32],
+    dirty_region: &mut DirtyRegion,
+    dt_for_frame: f32,
+) {
+    for link in constellation.links.values() {
+        dirty_region.add(link.bounds);
+    }
+
+    let mut pairs = Vec::new();
+    grid.pairs_within(settings.link_distance, &mut pairs);
+
+    let style = link_style(settings);
+    let mut links = BTreeMap::new();
+    for (a, b) in pairs {
+        let link_generations = (generations[a], generations[b]);
+        let previous_fade = constellation
+            .links
+            .get(&(a, b))
+            .filter(|link| link.generations == link_generations)
+            .map_or(0.0, |link| link.fade);
+        let fade = (previous_fade + dt_for_frame / settings.fade_seconds).min(1.0);
+
+        let closeness = 1.0 - v2_length(positions[b] - positions[a]) / settings.link_distance;
+        let alpha = settings.max_alpha * closeness * closeness * fade;
+        let vertex = |index: usize| LineVertex {
+            pos: positions[index],
+            width: 1.0,
+            color: colors[index],
+            alpha,
+            intensity: settings.intensity,
+        };
+        let vertices = [vertex(a), vertex(b)];
+        let bounds = line_bounds(&vertices, &style);
+        dirty_region.add(bounds);
+        links.insert(
+            (a, b),
+            Link {
+                fade,
+                generations: link_generations,
+                vertices,
+                bounds,
+            },
+        );
+    }
+    constellation.links = links;
+}
+
+pub fn draw_constellation<T: RenderTarget>(
+    constellation: &Constellation,
+    settings: &ConstellationSettings,
+    buffer: &mut T,
+) {
+    let style = link_style(settings);
+    for link in constellation.links.values() {
+        draw_line(&link.vertices[0], &link.vertices[1], &style, buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hdr::{HdrBuffer, HDR_CHANNELS};
+    use crate::math::v2_length_sq;
+    use crate::spatial_grid::random_positions;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    const SETTINGS: ConstellationSettings = ConstellationSettings {
+        link_distance: 40.0,
+        max_alpha: 0.5,
+        thickness: 1.0,
+        intensity: 1.0,
+        fade_seconds: 1.0,
+    };
+    const WHITE: Color = Color::rgba(255, 255, 255, 255);
+
+    fn update_with_generations(
+        constellation: &mut Constellation,
+        positions: &[V2],
+        colors: &[Color],
+        generations: &[u32],
+        dirty_region: &mut DirtyRegion,
+        dt_for_frame: f32,
+    ) {
+        let mut grid = SpatialGrid::new(SETTINGS.link_distance);
+        grid.rebuild(positions);
+        update_constellation(
+            constellation,
+            &SETTINGS,
+            &grid,
+            positions,
+            colors,
+            generations,
+            dirty_region,
+            dt_for_frame,
+        );
+    }
+
+    // NOTE(Fermin): Stars that never respawn
+    fn update(
+        constellation: &mut Constellation,
+        positions: &[V2],
+        colors: &[Color],
+        dirty_region: &mut DirtyRegion,
+        dt_for_frame: f32,
+    ) {
+        let generations = vec![0; positions.len()];
+        update_with_generations(
+            constellation,
+            positions,
+            colors,
+            &generations,
+            dirty_region,
+            dt_for_frame,
+        );
+    }
+
+    #[test]
+    fn links_the_same_pairs_as_brute_force() {
+        let mut rng = StdRng::seed_from_u64(39);
+        let mut constellation = Constellation::new();
+        let mut dirty_region = DirtyRegion::new(300, 200);
+        for count in [0, 1, 2, 50, 400] {
+            let positions = random_positions(&mut rng, count, 300.0, 200.0);
+            let colors = vec![WHITE; count];
+            update(
+                &mut constellation,
+                &positions,
+                &colors,
+                &mut dirty_region,
+                0.1,
+            );
+            let mut pairs: Vec<(usize, usize)> = constellation.links.keys().copied().collect();
+            pairs.sort_unstable();
+
+            let mut expected = Vec::new();
+            for a in 0..count {
+                for b in a + 1..count {
+                    if v2_length_sq(positions[b] - positions[a])
+                        <= SETTINGS.link_distance * SETTINGS.link_distance
+                    {
+                        expected.push((a, b));
+                    }
+                }
+            }
+            assert_eq!(pairs, expected);
+        }
+    }
+
+    #[test]
+    fn links_fade_in_and_fall_off_with_distance() {
+        let mut constellation = Constellation::new();
+        let mut dirty_region = DirtyRegion::new(100, 100);
+        let colors = [WHITE; 2];
+        let link_alpha = |constellation: &Constellation| {
+            constellation
+                .links
+                .get(&(0, 1))
+                .map(|link| link.vertices[0].alpha)
+        };
+
+        let mut positions = [V2 { x: 10.0, y: 10.0 }, V2 { x: 30.0, y: 10.0 }];
+        update(
+            &mut constellation,
+            &positions,
+            &colors,
+            &mut dirty_region,
+            0.5,
+        );
+        assert_eq!(link_alpha(&constellation), Some(0.0625));
+        for _ in 0..2 {
+            update(
+                &mut constellation,
+                &positions,
+                &colors,
+                &mut dirty_region,
+                0.5,
+            );
+        }
+        assert_eq!(link_alpha(&constellation), Some(0.125));
+
+        positions[1].x = 60.0;
+        update(
+            &mut constellation,
+            &positions,
+            &colors,
+            &mut dirty_region,
+            0.5,
+        );
+        assert!(constellation.links.is_empty());
+    }
+
+    #[test]
+    fn links_to_respawned_stars_fade_in_again() {
+        let mut constellation = Constellation::new();
+        let mut dirty_region = DirtyRegion::new(100, 100);
+        let colors = [WHITE; 3];
+        let positions = [
+            V2 { x: 10.0, y: 10.0 },
+            V2 { x: 30.0, y: 10.0 },
+            V2 { x: 10.0, y: 30.0 },
+        ];
+        let fade = |constellation: &Constellation, pair| constellation.links[&pair].fade;
+
+        let mut generations = [0, 0, 0];
+        for _ in 0..4 {
+            update_with_generations(
+                &mut constellation,
+                &positions,
+                &colors,
+                &generations,
+                &mut dirty_region,
+                0.5,
+            );
+        }
+        assert_eq!(fade(&constellation, (0, 1)), 1.0);
+        assert_eq!(fade(&constellation, (0, 2)), 1.0);
+
+        // NOTE(Fermin): Star 1 respawned right where it was
+        generations[1] += 1;
+        update_with_generations(
+            &mut constellation,
+            &positions,
+            &colors,
+            &generations,
+            &mut dirty_region,
+            0.5,
+        );
+        assert_eq!(fade(&constellation, (0, 1)), 0.5);
+        assert_eq!(fade(&constellation, (1, 2)), 0.5);
+        assert_eq!(fade(&constellation, (0, 2)), 1.0);
+    }
+
+    #[test]
+    fn links_stay_inside_their_dirty_rects() {
+        let mut rng = StdRng::seed_from_u64(39);
+        let mut constellation = Constellation::new();
+        let mut positions = random_positions(&mut rng, 40, 160.0, 120.0);
+        let colors = vec![WHITE; positions.len()];
+        let velocities = random_positions(&mut rng, positions.len(), 40.0, 40.0);
+        let mut dirty_region = DirtyRegion::new(160, 120);
+
+        let mut previous = HdrBuffer::new(160, 120);
+        for _frame in 0..60 {
+            dirty_region.clear();
+            for (position, velocity) in positions.iter_mut().zip(&velocities) {
+                *position = *position + (*velocity - V2 { x: 20.0, y: 20.0 }) * 0.1;
+            }
+            update(
+                &mut constellation,
+                &positions,
+                &colors,
+                &mut dirty_region,
+                0.1,
+            );
+
+            let mut buffer = HdrBuffer::new(160, 120);
+            draw_constellation(&constellation, &SETTINGS, &mut buffer);
+            let pixels = buffer.bits.chunks_exact(HDR_CHANNELS);
+            for (index, (pixel, before)) in pixels
+                .zip(previous.bits.chunks_exact(HDR_CHANNELS))
+                .enumerate()
+            {
+                if pixel != before {
+                    let (x, y) = (index as i32 % 160, index as i32 / 160);
+                    assert!(dirty_region.rects.iter().any(|rect| rect.contains(x, y)));
+                }
+            }
+            previous = buffer;
+        }
+    }
+}
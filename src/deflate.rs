@@ -0,0 +1,865 @@
+// --------------------------------------------------------------------
+// NOTE(Fermin): Deflate (RFC 1951) and the zlib wrapper around it (RFC 1950),
+// what png keeps its pixels in. Inflate handles every block type. Deflate
+// finds matches with hash chains and writes dynamic Huffman blocks, or
+// stored ones when those come out smaller.
+// --------------------------------------------------------------------
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// NOTE(Fermin): The order code length code lengths are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const END_OF_BLOCK: usize = 256;
+const LITERAL_LENGTH_CODES: usize = 286;
+const DISTANCE_CODES: usize = 30;
+const MAX_CODE_LENGTH: u8 = 15;
+const MAX_CODE_LENGTH_CODE_LENGTH: u8 = 7;
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_STORED_BLOCK: usize = 0xffff;
+
+// --------------------------------------------------------------------
+// NOTE(Fermin): Inflate
+// --------------------------------------------------------------------
+
+// NOTE(Fermin): Bits come out least significant first
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u32) -> Result<u32, &'static str> {
+        while self.bit_count < count {
+            let byte = *self.bytes.get(self.at).ok_or("truncated deflate stream")?;
+            self.at += 1;
+            self.bit_buffer |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buffer & ((1u64 << count) - 1) as u32;
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    // NOTE(Fermin): Stored blocks start on a byte boundary
+    fn align_to_byte(&mut self) {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+    }
+}
+
+// NOTE(Fermin): Canonical code, decoded a bit at a time by counting how
+// many codes there are of every length.
+struct Huffman {
+    counts: [u16; MAX_CODE_LENGTH as usize + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, &'static str> {
+        let mut counts = [0u16; MAX_CODE_LENGTH as usize + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // NOTE(Fermin): Incomplete codes are fine, oversubscribed ones can't
+        // be decoded.
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = left * 2 - count as i32;
+            if left < 0 {
+                return Err("oversubscribed huffman code");
+            }
+        }
+
+        let mut offsets = [0u16; MAX_CODE_LENGTH as usize + 2];
+        for length in 1..=MAX_CODE_LENGTH as usize {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; offsets[MAX_CODE_LENGTH as usize + 1] as usize];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<usize, &'static str> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= reader.bits(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("bad huffman code")
+    }
+}
+
+fn fixed_lengths() -> (Vec<u8>, Vec<u8>) {
+    let mut literal_length = vec![8; 288];
+    literal_length[144..256].fill(9);
+    literal_length[256..280].fill(7);
+    (literal_length, vec![5; 30])
+}
+
+fn read_dynamic_lengths(reader: &mut BitReader) -> Result<(Vec<u8>, Vec<u8>), &'static str> {
+    let literal_length_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    if literal_length_count > LITERAL_LENGTH_CODES || distance_count > DISTANCE_CODES {
+        return Err("too many deflate codes");
+    }
+
+    let mut code_length_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_length_lengths[symbol] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_length_lengths)?;
+
+    let total = literal_length_count + distance_count;
+    let mut lengths: Vec<u8> = Vec::with_capacity(total);
+    while lengths.len() < total {
+        let symbol = code_lengths.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths.last().ok_or("repeat with no code length")?;
+                (previous, 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if lengths.len() + repeat > total {
+            return Err("code lengths overflow");
+        }
+        lengths.extend(std::iter::repeat_n(value, repeat));
+    }
+    if lengths[END_OF_BLOCK] == 0 {
+        return Err("no end of block code");
+    }
+    let distance_lengths = lengths.split_off(literal_length_count);
+    Ok((lengths, distance_lengths))
+}
+
+// NOTE(Fermin): Returns early once out has limit bytes or more
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    limit: usize,
+    literal_length: &Huffman,
+    distance: &Huffman,
+) -> Result<(), &'static str> {
+    while out.len() < limit {
+        let symbol = literal_length.decode(reader)?;
+        if symbol < END_OF_BLOCK {
+            out.push(symbol as u8);
+        } else if symbol == END_OF_BLOCK {
+            return Ok(());
+        } else {
+            let index = symbol - 257;
+            if index >= LENGTH_BASE.len() {
+                return Err("bad length code");
+            }
+            let length =
+                LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+
+            let index = distance.decode(reader)?;
+            if index >= DISTANCE_BASE.len() {
+                return Err("bad distance code");
+            }
+            let distance =
+                DISTANCE_BASE[index] as usize + reader.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+            if distance > out.len() {
+                return Err("distance reaches before the start");
+            }
+
+            // NOTE(Fermin): Matches can overlap what they copy
+            let start = out.len() - distance;
+            for offset in 0..length {
+                out.push(out[start + offset]);
+            }
+        }
+    }
+    Ok(())
+}
+
+// NOTE(Fermin): Returns the data and how many bytes the stream took. It
+// stops at limit bytes of data, then it never got to the end of the stream
+// and there's no telling how long that is.
+fn inflate_stream(bytes: &[u8], limit: usize) -> Result<(Vec<u8>, Option<usize>), &'static str> {
+    let mut reader = BitReader {
+        bytes,
+        at: 0,
+        bit_buffer: 0,
+        bit_count: 0,
+    };
+    let mut out = Vec::new();
+    loop {
+        let is_final = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align_to_byte();
+                let header = bytes
+                    .get(reader.at..reader.at + 4)
+                    .ok_or("truncated deflate stream")?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+                let not_length = u16::from_le_bytes([header[2], header[3]]);
+                if length != !not_length {
+                    return Err("stored block length doesn't match");
+                }
+                let start = reader.at + 4;
+                let data = bytes
+                    .get(start..start + length as usize)
+                    .ok_or("truncated deflate stream")?;
+                out.extend_from_slice(&data[..data.len().min(limit - out.len())]);
+                reader.at = start + length as usize;
+            }
+            1 => {
+                let (literal_length, distance) = fixed_lengths();
+                inflate_block(
+                    &mut reader,
+                    &mut out,
+                    limit,
+                    &Huffman::new(&literal_length)?,
+                    &Huffman::new(&distance)?,
+                )?;
+            }
+            2 => {
+                let (literal_length, distance) = read_dynamic_lengths(&mut reader)?;
+                inflate_block(
+                    &mut reader,
+                    &mut out,
+                    limit,
+                    &Huffman::new(&literal_length)?,
+                    &Huffman::new(&distance)?,
+                )?;
+            }
+            _ => return Err("bad deflate block type"),
+        }
+        if out.len() >= limit {
+            out.truncate(limit);
+            return Ok((out, None));
+        }
+        if is_final {
+            // NOTE(Fermin): Whole bytes left in the bit buffer weren't used
+            return Ok((out, Some(reader.at - reader.bit_count as usize / 8)));
+        }
+    }
+}
+
+pub fn adler32(bytes: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // NOTE(Fermin): 5552 bytes is as many as fit before b can overflow
+    for chunk in bytes.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+// NOTE(Fermin): At most limit bytes come out, whatever the stream holds.
+// The checksum covers all of it, streams cut short don't get checked.
+pub fn zlib_decompress(bytes: &[u8], limit: usize) -> Result<Vec<u8>, &'static str> {
+    if bytes.len() < 2 {
+        return Err("truncated zlib stream");
+    }
+    let (method, flags) = (bytes[0], bytes[1]);
+    if method & 0x0f != 8 || method >> 4 > 7 {
+        return Err("not a deflate zlib stream");
+    }
+    if (method as u16 * 256 + flags as u16) % 31 != 0 {
+        return Err("bad zlib header check");
+    }
+    if flags & 0x20 != 0 {
+        return Err("zlib preset dictionaries aren't supported");
+    }
+
+    let (out, used) = inflate_stream(&bytes[2..], limit)?;
+    let Some(used) = used else {
+        return Ok(out);
+    };
+    let trailer = bytes
+        .get(2 + used..2 + used + 4)
+        .ok_or("missing zlib checksum")?;
+    if u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]) != adler32(&out) {
+        return Err("zlib checksum doesn't match");
+    }
+    Ok(out)
+}
+
+// --------------------------------------------------------------------
+// NOTE(Fermin): Deflate
+// --------------------------------------------------------------------
+const HASH_BITS: u32 = 15;
+// NOTE(Fermin): How many earlier positions get tried for every match, and a
+// match long enough to stop looking.
+const MAX_CHAIN: usize = 128;
+const NICE_MATCH: usize = 128;
+// NOTE(Fermin): Symbols per block, every block gets its own codes
+const BLOCK_SYMBOLS: usize = 1 << 15;
+
+struct BitWriter {
+    out: Vec<u8>,
+    bit_buffer: u64,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u32, count: u32) {
+        self.bit_buffer |= (value as u64) << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.out.push(self.bit_buffer as u8);
+            self.bit_buffer >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    fn align_to_byte(&mut self) {
+        if self.bit_count > 0 {
+            self.put(0, 8 - self.bit_count);
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+fn length_code(length: usize) -> usize {
+    LENGTH_BASE.partition_point(|&base| base as usize <= length) - 1
+}
+
+fn distance_code(distance: usize) -> usize {
+    DISTANCE_BASE.partition_point(|&base| base as usize <= distance) - 1
+}
+
+// NOTE(Fermin): Plain Huffman, then frequencies get flattened until no code
+// is longer than max_length. Always gives at least two codes, some
+// decoders don't like a lone one.
+fn huffman_lengths(frequencies: &[u32], max_length: u8) -> Vec<u8> {
+    let mut frequencies = frequencies.to_vec();
+    let mut used = frequencies
+        .iter()
+        .filter(|&&frequency| frequency > 0)
+        .count();
+    for frequency in frequencies.iter_mut() {
+        if used >= 2 {
+            break;
+        }
+        if *frequency == 0 {
+            *frequency = 1;
+            used += 1;
+        }
+    }
+
+    loop {
+        // NOTE(Fermin): Leaves first, then internal nodes as they get made
+        let mut weights: Vec<u64> = Vec::new();
+        let mut parents: Vec<usize> = Vec::new();
+        let mut leaves = Vec::new();
+        let mut queue = std::collections::BinaryHeap::new();
+        for (symbol, &frequency) in frequencies.iter().enumerate() {
+            if frequency > 0 {
+                leaves.push(symbol);
+                queue.push(std::cmp::Reverse((frequency as u64, weights.len())));
+                weights.push(frequency as u64);
+                parents.push(usize::MAX);
+            }
+        }
+        while queue.len() > 1 {
+            let std::cmp::Reverse((a_weight, a)) = queue.pop().unwrap();
+            let std::cmp::Reverse((b_weight, b)) = queue.pop().unwrap();
+            let node = weights.len();
+            weights.push(a_weight + b_weight);
+            parents.push(usize::MAX);
+            parents[a] = node;
+            parents[b] = node;
+            queue.push(std::cmp::Reverse((a_weight + b_weight, node)));
+        }
+
+        // NOTE(Fermin): Parents always come after their children
+        let mut depths = vec![0u8; weights.len()];
+        for node in (0..weights.len()).rev() {
+            if parents[node] != usize::MAX {
+                depths[node] = depths[parents[node]] + 1;
+            }
+        }
+
+        let mut lengths = vec![0u8; frequencies.len()];
+        for (leaf, &symbol) in leaves.iter().enumerate() {
+            lengths[symbol] = depths[leaf];
+        }
+        if lengths.iter().all(|&length| length <= max_length) {
+            return lengths;
+        }
+        for frequency in frequencies.iter_mut().filter(|frequency| **frequency > 0) {
+            *frequency = (*frequency).div_ceil(2);
+        }
+    }
+}
+
+// NOTE(Fermin): Codes come out bit reversed, ready to be written least
+// significant bit first.
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut counts = [0u16; MAX_CODE_LENGTH as usize + 1];
+    for &length in lengths {
+        counts[length as usize] += 1;
+    }
+    counts[0] = 0;
+    let mut next_code = [0u16; MAX_CODE_LENGTH as usize + 2];
+    for length in 1..=MAX_CODE_LENGTH as usize {
+        next_code[length + 1] = (next_code[length] + counts[length]) << 1;
+    }
+    lengths
+        .iter()
+        .map(|&length| {
+            if length == 0 {
+                return 0;
+            }
+            let code = next_code[length as usize];
+            next_code[length as usize] += 1;
+            code.reverse_bits() >> (16 - length)
+        })
+        .collect()
+}
+
+// NOTE(Fermin): Runs of code lengths as (symbol, extra bits value)
+fn run_length_encode(lengths: &[u8]) -> Vec<(usize, u32)> {
+    let mut symbols = Vec::new();
+    let mut at = 0;
+    while at < lengths.len() {
+        let value = lengths[at];
+        let run = lengths[at..]
+            .iter()
+            .take_while(|&&length| length == value)
+            .count();
+        if value == 0 && run >= 3 {
+            let run = run.min(138);
+            if run >= 11 {
+                symbols.push((18, (run - 11) as u32));
+            } else {
+                symbols.push((17, (run - 3) as u32));
+            }
+            at += run;
+        } else if value != 0 && run >= 4 {
+            symbols.push((value as usize, 0));
+            let run = (run - 1).min(6);
+            symbols.push((16, (run - 3) as u32));
+            at += 1 + run;
+        } else {
+            symbols.push((value as usize, 0));
+            at += 1;
+        }
+    }
+    symbols
+}
+
+fn code_length_extra_bits(symbol: usize) -> u32 {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+fn write_stored_blocks(writer: &mut BitWriter, data: &[u8], is_final: bool) {
+    let block_count = data.len().div_ceil(MAX_STORED_BLOCK).max(1);
+    for block in 0..block_count {
+        let start = block * MAX_STORED_BLOCK;
+        let end = (start + MAX_STORED_BLOCK).min(data.len());
+        let length = (end - start) as u16;
+        writer.put((is_final && block == block_count - 1) as u32, 1);
+        writer.put(0, 2);
+        writer.align_to_byte();
+        writer.out.extend_from_slice(&length.to_le_bytes());
+        writer.out.extend_from_slice(&(!length).to_le_bytes());
+        writer.out.extend_from_slice(&data[start..end]);
+    }
+}
+
+// NOTE(Fermin): data is the input the tokens came from, written as is if
+// that turns out smaller than the compressed block.
+fn write_block(writer: &mut BitWriter, tokens: &[Token], data: &[u8], is_final: bool) {
+    let mut literal_length_frequencies = [0u32; LITERAL_LENGTH_CODES];
+    let mut distance_frequencies = [0u32; DISTANCE_CODES];
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => literal_length_frequencies[byte as usize] += 1,
+            Token::Match { length, distance } => {
+                literal_length_frequencies[257 + length_code(length as usize)] += 1;
+                distance_frequencies[distance_code(distance as usize)] += 1;
+            }
+        }
+    }
+    literal_length_frequencies[END_OF_BLOCK] = 1;
+
+    let literal_length_lengths = huffman_lengths(&literal_length_frequencies, MAX_CODE_LENGTH);
+    let distance_lengths = huffman_lengths(&distance_frequencies, MAX_CODE_LENGTH);
+    let literal_length_count = 257.max(
+        literal_length_lengths
+            .iter()
+            .rposition(|&length| length > 0)
+            .unwrap_or(0)
+            + 1,
+    );
+    let distance_count = distance_lengths
+        .iter()
+        .rposition(|&length| length > 0)
+        .unwrap_or(0)
+        + 1;
+
+    let mut all_lengths = literal_length_lengths[..literal_length_count].to_vec();
+    all_lengths.extend_from_slice(&distance_lengths[..distance_count]);
+    let runs = run_length_encode(&all_lengths);
+    let mut code_length_frequencies = [0u32; 19];
+    for &(symbol, _) in &runs {
+        code_length_frequencies[symbol] += 1;
+    }
+    let code_length_lengths =
+        huffman_lengths(&code_length_frequencies, MAX_CODE_LENGTH_CODE_LENGTH);
+    let code_length_count = 4.max(
+        CODE_LENGTH_ORDER
+            .iter()
+            .rposition(|&symbol| code_length_lengths[symbol] > 0)
+            .unwrap_or(0)
+            + 1,
+    );
+
+    // NOTE(Fermin): Sizes in bits
+    let mut compressed_size = 3 + 5 + 5 + 4 + 3 * code_length_count as u64;
+    for &(symbol, _) in &runs {
+        compressed_size +=
+            (code_length_lengths[symbol] as u32 + code_length_extra_bits(symbol)) as u64;
+    }
+    for (symbol, &frequency) in literal_length_frequencies.iter().enumerate() {
+        let extra = if symbol > END_OF_BLOCK {
+            LENGTH_EXTRA[symbol - 257] as u64
+        } else {
+            0
+        };
+        compressed_size += frequency as u64 * (literal_length_lengths[symbol] as u64 + extra);
+    }
+    for (symbol, &frequency) in distance_frequencies.iter().enumerate() {
+        compressed_size +=
+            frequency as u64 * (distance_lengths[symbol] as u64 + DISTANCE_EXTRA[symbol] as u64);
+    }
+    let stored_size =
+        (data.len() as u64 + 5 * data.len().div_ceil(MAX_STORED_BLOCK).max(1) as u64) * 8;
+    if stored_size <= compressed_size {
+        write_stored_blocks(writer, data, is_final);
+        return;
+    }
+
+    writer.put(is_final as u32, 1);
+    writer.put(2, 2);
+    writer.put((literal_length_count - 257) as u32, 5);
+    writer.put((distance_count - 1) as u32, 5);
+    writer.put((code_length_count - 4) as u32, 4);
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        writer.put(code_length_lengths[symbol] as u32, 3);
+    }
+    let code_length_codes = canonical_codes(&code_length_lengths);
+    for &(symbol, extra) in &runs {
+        writer.put(
+            code_length_codes[symbol] as u32,
+            code_length_lengths[symbol] as u32,
+        );
+        writer.put(extra, code_length_extra_bits(symbol));
+    }
+
+    let literal_length_codes = canonical_codes(&literal_length_lengths);
+    let distance_codes = canonical_codes(&distance_lengths);
+    let put_literal_length = |writer: &mut BitWriter, symbol: usize| {
+        writer.put(
+            literal_length_codes[symbol] as u32,
+            literal_length_lengths[symbol] as u32,
+        );
+    };
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => put_literal_length(writer, byte as usize),
+            Token::Match { length, distance } => {
+                let (length, distance) = (length as usize, distance as usize);
+                let code = length_code(length);
+                put_literal_length(writer, 257 + code);
+                writer.put(
+                    (length - LENGTH_BASE[code] as usize) as u32,
+                    LENGTH_EXTRA[code] as u32,
+                );
+                let code = distance_code(distance);
+                writer.put(distance_codes[code] as u32, distance_lengths[code] as u32);
+                writer.put(
+                    (distance - DISTANCE_BASE[code] as usize) as u32,
+                    DISTANCE_EXTRA[code] as u32,
+                );
+            }
+        }
+    }
+    put_literal_length(writer, END_OF_BLOCK);
+}
+
+// NOTE(Fermin): Hash chains over the last WINDOW_SIZE positions. prev is a
+// ring, so entries older than the window can point anywhere and chains stop
+// as soon as they stop going back.
+struct MatchFinder<'a> {
+    data: &'a [u8],
+    head: Vec<i64>,
+    prev: Vec<i64>,
+    inserted: usize,
+}
+
+impl MatchFinder<'_> {
+    fn hash(&self, at: usize) -> usize {
+        let bytes = (self.data[at] as u32) << 16
+            | (self.data[at + 1] as u32) << 8
+            | self.data[at + 2] as u32;
+        (bytes.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+    }
+
+    // NOTE(Fermin): Adds every position before end that has 3 bytes to hash
+    fn insert_until(&mut self, end: usize) {
+        let end = end.min(self.data.len().saturating_sub(MIN_MATCH - 1));
+        while self.inserted < end {
+            let hash = self.hash(self.inserted);
+            self.prev[self.inserted % WINDOW_SIZE] = self.head[hash];
+            self.head[hash] = self.inserted as i64;
+            self.inserted += 1;
+        }
+    }
+
+    // NOTE(Fermin): Returns length and distance, length is 0 without a match
+    fn longest_match(&mut self, at: usize) -> (usize, usize) {
+        let max_length = MAX_MATCH.min(self.data.len() - at);
+        if max_length < MIN_MATCH {
+            return (0, 0);
+        }
+        self.insert_until(at);
+
+        let (mut best_length, mut best_distance) = (0, 0);
+        let mut candidate = self.head[self.hash(at)];
+        for _ in 0..MAX_CHAIN {
+            if candidate < 0 || at - candidate as usize > WINDOW_SIZE {
+                break;
+            }
+            let start = candidate as usize;
+            if self.data[start + best_length.min(max_length - 1)]
+                == self.data[at + best_length.min(max_length - 1)]
+            {
+                let length = self.data[start..start + max_length]
+                    .iter()
+                    .zip(&self.data[at..at + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = at - start;
+                    if length >= NICE_MATCH.min(max_length) {
+                        break;
+                    }
+                }
+            }
+            let next = self.prev[start % WINDOW_SIZE];
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+        if best_length >= MIN_MATCH {
+            (best_length, best_distance)
+        } else {
+            (0, 0)
+        }
+    }
+}
+
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter {
+        out: Vec::new(),
+        bit_buffer: 0,
+        bit_count: 0,
+    };
+    let mut finder = MatchFinder {
+        data,
+        head: vec![-1; 1 << HASH_BITS],
+        prev: vec![-1; WINDOW_SIZE],
+        inserted: 0,
+    };
+
+    let mut tokens = Vec::with_capacity(BLOCK_SYMBOLS);
+    let mut block_start = 0;
+    let mut at = 0;
+    while at < data.len() {
+        // NOTE(Fermin): Lazy matching, a longer match one byte later wins
+        // over the one here.
+        let (length, distance) = finder.longest_match(at);
+        let take_match = length > 0 && {
+            let (next_length, _) = finder.longest_match(at + 1);
+            next_length <= length
+        };
+        if take_match {
+            tokens.push(Token::Match {
+                length: length as u16,
+                distance: distance as u16,
+            });
+            at += length;
+        } else {
+            tokens.push(Token::Literal(data[at]));
+            at += 1;
+        }
+
+        if tokens.len() == BLOCK_SYMBOLS && at < data.len() {
+            write_block(&mut writer, &tokens, &data[block_start..at], false);
+            tokens.clear();
+            block_start = at;
+        }
+    }
+    write_block(&mut writer, &tokens, &data[block_start..], true);
+    writer.align_to_byte();
+    writer.out
+}
+
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    // NOTE(Fermin): 32K window, default compression level
+    let mut out = vec![0x78, 0x9c];
+    out.extend_from_slice(&deflate(data));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn test_inputs() -> Vec<Vec<u8>> {
+        let mut rng = StdRng::seed_from_u64(46);
+        let random: Vec<u8> = (0..100_000).map(|_| rng.gen()).collect();
+        let text = "the quick brown fox jumps over the lazy dog. "
+            .repeat(3000)
+            .into_bytes();
+        let mut mixed = Vec::new();
+        for _ in 0..2000 {
+            let run = rng.gen_range(1..300);
+            let byte = rng.gen_range(0..4u8);
+            mixed.extend(std::iter::repeat_n(byte, run));
+            mixed.extend((0..rng.gen_range(0..20)).map(|_| rng.gen::<u8>()));
+        }
+        vec![Vec::new(), vec![7], vec![0; 200_000], random, text, mixed]
+    }
+
+    #[test]
+    fn adler32_matches_known_values() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        let long = vec![0xff; 100_000];
+        let slow = long.iter().fold((1u64, 0u64), |(a, b), &byte| {
+            let a = (a + byte as u64) % 65521;
+            (a, (b + a) % 65521)
+        });
+        assert_eq!(adler32(&long), ((slow.1 << 16) | slow.0) as u32);
+    }
+
+    // NOTE(Fermin): zlib.compress(b"hello hello hello hello") with Python,
+    // a fixed Huffman block.
+    #[test]
+    fn inflates_streams_from_zlib() {
+        let stream = [
+            0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0x01, 0x68, 0x03,
+            0x08, 0xb1,
+        ];
+        assert_eq!(
+            zlib_decompress(&stream, usize::MAX).unwrap(),
+            b"hello hello hello hello".to_vec()
+        );
+    }
+
+    #[test]
+    fn round_trips_and_compresses() {
+        for input in test_inputs() {
+            let compressed = zlib_compress(&input);
+            assert_eq!(zlib_decompress(&compressed, usize::MAX).unwrap(), input);
+            let limit = input.len() / 3;
+            assert_eq!(zlib_decompress(&compressed, limit).unwrap(), input[..limit]);
+            // NOTE(Fermin): Random data only grows by the stored block headers
+            assert!(compressed.len() <= input.len() + input.len() / 1000 + 16);
+        }
+        assert!(zlib_compress(&vec![0; 200_000]).len() < 1000);
+        let text = "the quick brown fox jumps over the lazy dog. ".repeat(3000);
+        assert!(zlib_compress(text.as_bytes()).len() < 1000);
+    }
+
+    #[test]
+    fn lengths_are_limited() {
+        // NOTE(Fermin): Fibonacci frequencies make the deepest trees
+        let mut frequencies = vec![1u32, 1];
+        while frequencies.len() < 30 {
+            let next = frequencies[frequencies.len() - 1] + frequencies[frequencies.len() - 2];
+            frequencies.push(next);
+        }
+        let lengths = huffman_lengths(&frequencies, 7);
+        assert!(lengths.iter().all(|&length| (1..=7).contains(&length)));
+        let kraft: f64 = lengths
+            .iter()
+            .map(|&length| 0.5f64.powi(length as i32))
+            .sum();
+        assert!(kraft <= 1.0);
+
+        assert_eq!(huffman_lengths(&[0, 0, 5, 0], 15), [1, 0, 1, 0]);
+    }
+
+    #[test]
+    fn garbage_never_panics() {
+        let mut rng = StdRng::seed_from_u64(47);
+        let valid = zlib_compress(&test_inputs()[5][..4000]);
+        for _ in 0..2000 {
+            let mut bytes = valid.clone();
+            for _ in 0..rng.gen_range(1..4) {
+                let at = rng.gen_range(0..bytes.len());
+                bytes[at] = rng.gen();
+            }
+            bytes.truncate(rng.gen_range(0..bytes.len()));
+            let _ = zlib_decompress(&bytes, usize::MAX);
+        }
+        for _ in 0..2000 {
+            let garbage: Vec<u8> = (0..rng.gen_range(0..64)).map(|_| rng.gen()).collect();
+            let _ = inflate_stream(&garbage, usize::MAX);
+        }
+        assert!(zlib_decompress(&valid[..valid.len() - 1], usize::MAX).is_err());
+    }
+}
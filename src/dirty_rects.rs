@@ -0,0 +1,154 @@
+// NOTE(Fermin): Only the Win32 backend presents partial buffers for now
+#![cfg_attr(not(windows), allow(dead_code))]
+
+use crate::math::Rect;
+
+// NOTE(Fermin): Tracks which parts of the buffer changed since the last
+// present. Rects are kept clipped to the buffer and never overlap each other.
+pub struct DirtyRegion {
+    pub rects: Vec<Rect>,
+    // NOTE(Fermin): Every added rect grows by this much on each side, for
+    // effects like bloom that bleed outside of what was drawn.
+    pub margin: i32,
+    bounds: Rect,
+}
+
+impl DirtyRegion {
+    pub fn new(width: i32, height: i32) -> DirtyRegion {
+        DirtyRegion {
+            rects: Vec::new(),
+            margin: 0,
+            bounds: Rect::from_pos_size(0, 0, width, height),
+        }
+    }
+
+    pub fn add(&mut self, rect: Rect) {
+        let mut rect = Rect {
+            min_x: rect.min_x - self.margin,
+            min_y: rect.min_y - self.margin,
+            max_x: rect.max_x + self.margin,
+            max_y: rect.max_y + self.margin,
+        }
+        .intersection(&self.bounds);
+        if rect.is_empty() {
+            return;
+        }
+
+        // NOTE(Fermin): Merging can make the rect grow into rects that didn't
+        // overlap it before, so keep going until nothing overlaps.
+        while let Some(index) = self.rects.iter().position(|dirty| dirty.overlaps(&rect)) {
+            rect = rect.union(&self.rects.swap_remove(index));
+        }
+        self.rects.push(rect);
+    }
+
+    pub fn add_all(&mut self) {
+        self.rects.clear();
+        self.rects.push(self.bounds);
+    }
+
+    pub fn clear(&mut self) {
+        self.rects.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    pub fn dirty_area(&self) -> i64 {
+        self.rects.iter().map(|rect| rect.area()).sum()
+    }
+
+    // NOTE(Fermin): Past some point a single present of the whole buffer is
+    // cheaper than one present per rect.
+    pub fn should_present_all(&self, max_dirty_fraction: f32, max_rects: usize) -> bool {
+        self.rects.len() > max_rects
+            || self.dirty_area() as f32 > max_dirty_fraction * self.bounds.area() as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rects_are_clipped_to_bounds() {
+        let mut dirty = DirtyRegion::new(100, 50);
+        dirty.add(Rect::from_pos_size(-10, -10, 20, 20));
+        dirty.add(Rect::from_pos_size(90, 40, 20, 20));
+        dirty.add(Rect::from_pos_size(200, 200, 10, 10));
+
+        assert_eq!(dirty.rects.len(), 2);
+        assert!(dirty.rects.contains(&Rect::from_pos_size(0, 0, 10, 10)));
+        assert!(dirty.rects.contains(&Rect::from_pos_size(90, 40, 10, 10)));
+    }
+
+    #[test]
+    fn overlapping_rects_are_merged() {
+        let mut dirty = DirtyRegion::new(100, 100);
+        dirty.add(Rect::from_pos_size(0, 0, 10, 10));
+        dirty.add(Rect::from_pos_size(5, 5, 10, 10));
+
+        assert_eq!(dirty.rects, vec![Rect::from_pos_size(0, 0, 15, 15)]);
+    }
+
+    #[test]
+    fn merging_picks_up_rects_the_union_grew_into() {
+        let mut dirty = DirtyRegion::new(100, 100);
+        dirty.add(Rect::from_pos_size(0, 0, 10, 30));
+        dirty.add(Rect::from_pos_size(20, 20, 10, 10));
+        assert_eq!(dirty.rects.len(), 2);
+
+        // NOTE(Fermin): Only overlaps the first rect, but once merged with it
+        // the result reaches the second one.
+        dirty.add(Rect::from_pos_size(5, 0, 20, 5));
+        assert_eq!(dirty.rects, vec![Rect::from_pos_size(0, 0, 30, 30)]);
+    }
+
+    #[test]
+    fn margin_grows_added_rects() {
+        let mut dirty = DirtyRegion::new(100, 100);
+        dirty.margin = 4;
+        dirty.add(Rect::from_pos_size(10, 10, 2, 2));
+        dirty.add(Rect::from_pos_size(0, 0, 1, 1));
+
+        assert!(dirty.rects.contains(&Rect::from_pos_size(6, 6, 10, 10)));
+        assert!(dirty.rects.contains(&Rect::from_pos_size(0, 0, 5, 5)));
+    }
+
+    #[test]
+    fn disjoint_rects_are_kept_apart() {
+        let mut dirty = DirtyRegion::new(100, 100);
+        dirty.add(Rect::from_pos_size(0, 0, 10, 10));
+        dirty.add(Rect::from_pos_size(10, 0, 10, 10));
+
+        assert_eq!(dirty.rects.len(), 2);
+        assert_eq!(dirty.dirty_area(), 200);
+    }
+
+    #[test]
+    fn large_dirty_area_falls_back_to_full_present() {
+        let mut dirty = DirtyRegion::new(100, 100);
+        dirty.add(Rect::from_pos_size(0, 0, 10, 10));
+        assert!(!dirty.should_present_all(0.5, 16));
+
+        dirty.add(Rect::from_pos_size(0, 0, 100, 60));
+        assert!(dirty.should_present_all(0.5, 16));
+
+        dirty.clear();
+        assert!(dirty.is_empty());
+        dirty.add_all();
+        assert_eq!(dirty.dirty_area(), 100 * 100);
+    }
+
+    #[test]
+    fn many_rects_fall_back_to_full_present() {
+        let mut dirty = DirtyRegion::new(100, 100);
+        for i in 0..5 {
+            dirty.add(Rect::from_pos_size(i * 20, 0, 1, 1));
+        }
+        assert!(!dirty.should_present_all(0.5, 5));
+        dirty.add(Rect::from_pos_size(0, 50, 1, 1));
+        assert!(dirty.should_present_all(0.5, 5));
+    }
+}
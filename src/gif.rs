@@ -0,0 +1,434 @@
+use std::io::Write;
+
+// --------------------------------------------------------------------
+// NOTE(Fermin): Animated GIF89a that loops forever. Every frame uses the one
+// global palette, and only the rectangle that changed since the frame
+// before gets written, on top of what is already there. Pixels in it that
+// didn't change are transparent, runs of those pack well.
+// --------------------------------------------------------------------
+const MAX_CODE_SIZE: u32 = 12;
+const MAX_CODES: u16 = 1 << MAX_CODE_SIZE;
+const MAX_SUB_BLOCK: usize = 255;
+
+// NOTE(Fermin): Delays are in hundredths of a second and can't be split,
+// this spreads the rounding over the frames so the loop keeps its length.
+// Frames longer than the format allows (about 11 minutes) get clamped to
+// u16::MAX hundredths, the exporter never asks for anything near that.
+pub fn frame_delay(frame: usize, frames_per_second: f32) -> u16 {
+    let at = |frame: usize| (frame as f64 * 100.0 / frames_per_second as f64).round() as u64;
+    (at(frame + 1) - at(frame)).min(u16::MAX as u64) as u16
+}
+
+// NOTE(Fermin): Codes go out least significant bit first
+struct CodeWriter {
+    out: Vec<u8>,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl CodeWriter {
+    fn put(&mut self, code: u16, size: u32) {
+        self.bit_buffer |= (code as u32) << self.bit_count;
+        self.bit_count += size;
+        while self.bit_count >= 8 {
+            self.out.push(self.bit_buffer as u8);
+            self.bit_buffer >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.out.push(self.bit_buffer as u8);
+        }
+        self.out
+    }
+}
+
+// NOTE(Fermin): Strings in the dictionary are a code plus one more index,
+// found through open addressing. Twice as many slots as codes keeps probes
+// short, and clearing it is cheap enough to do whenever the codes run out.
+struct Dictionary {
+    keys: Vec<u32>,
+    codes: Vec<u16>,
+}
+
+impl Dictionary {
+    const SLOTS: usize = 2 * MAX_CODES as usize;
+    const EMPTY: u32 = u32::MAX;
+
+    fn new() -> Dictionary {
+        Dictionary {
+            keys: vec![Dictionary::EMPTY; Dictionary::SLOTS],
+            codes: vec![0; Dictionary::SLOTS],
+        }
+    }
+
+    fn slot(&self, key: u32) -> usize {
+        let mut slot = (key.wrapping_mul(2_654_435_761) >> 19) as usize % Dictionary::SLOTS;
+        while self.keys[slot] != Dictionary::EMPTY && self.keys[slot] != key {
+            slot = (slot + 1) % Dictionary::SLOTS;
+        }
+        slot
+    }
+
+    fn get(&self, prefix: u16, index: u8) -> Option<u16> {
+        let slot = self.slot((prefix as u32) << 8 | index as u32);
+        (self.keys[slot] != Dictionary::EMPTY).then(|| self.codes[slot])
+    }
+
+    fn insert(&mut self, prefix: u16, index: u8, code: u16) {
+        let key = (prefix as u32) << 8 | index as u32;
+        let slot = self.slot(key);
+        self.keys[slot] = key;
+        self.codes[slot] = code;
+    }
+
+    fn clear(&mut self) {
+        self.keys.fill(Dictionary::EMPTY);
+    }
+}
+
+// NOTE(Fermin): Variable length LZW the way GIF wants it, starting with a
+// clear code. Decoders add their entries one code behind the encoder, so
+// code sizes grow when the next free code reaches the size limit before
+// this one gets added.
+pub fn lzw_encode(indices: &[u8], min_code_size: u32) -> Vec<u8> {
+    let clear_code: u16 = 1 << min_code_size;
+    let end_code = clear_code + 1;
+    let mut writer = CodeWriter {
+        out: Vec::new(),
+        bit_buffer: 0,
+        bit_count: 0,
+    };
+    let mut dictionary = Dictionary::new();
+    let mut code_size = min_code_size + 1;
+    let mut next_code = end_code + 1;
+    writer.put(clear_code, code_size);
+
+    let Some((&first, rest)) = indices.split_first() else {
+        writer.put(end_code, code_size);
+        return writer.finish();
+    };
+    let mut prefix = first as u16;
+    for &index in rest {
+        if let Some(code) = dictionary.get(prefix, index) {
+            prefix = code;
+            continue;
+        }
+        writer.put(prefix, code_size);
+        if next_code == 1 << code_size && code_size < MAX_CODE_SIZE {
+            code_size += 1;
+        }
+        if next_code < MAX_CODES {
+            dictionary.insert(prefix, index, next_code);
+            next_code += 1;
+        } else {
+            writer.put(clear_code, code_size);
+            dictionary.clear();
+            code_size = min_code_size + 1;
+            next_code = end_code + 1;
+        }
+        prefix = index as u16;
+    }
+    writer.put(prefix, code_size);
+    if next_code == 1 << code_size && code_size < MAX_CODE_SIZE {
+        code_size += 1;
+    }
+    writer.put(end_code, code_size);
+    writer.finish()
+}
+
+fn write_sub_blocks<W: Write>(writer: &mut W, data: &[u8]) -> std::io::Result<()> {
+    for block in data.chunks(MAX_SUB_BLOCK) {
+        writer.write_all(&[block.len() as u8])?;
+        writer.write_all(block)?;
+    }
+    writer.write_all(&[0])
+}
+
+pub struct GifWriter<W: Write> {
+    writer: W,
+    width: usize,
+    height: usize,
+    min_code_size: u32,
+    // NOTE(Fermin): The entry right after the palette
+    transparent: u8,
+    // NOTE(Fermin): Indices of the last frame, what the next one is
+    // compared against.
+    previous: Option<Vec<u8>>,
+}
+
+impl<W: Write> GifWriter<W> {
+    // NOTE(Fermin): palette has at most 255 colors, the last entry is
+    // taken by transparency.
+    pub fn new(
+        mut writer: W,
+        width: u16,
+        height: u16,
+        palette: &[[u8; 3]],
+    ) -> std::io::Result<GifWriter<W>> {
+        assert!(!palette.is_empty() && palette.len() <= 255);
+        // NOTE(Fermin): Color tables hold a power of two entries, 2 or more
+        let table_bits = (palette.len() + 1)
+            .next_power_of_two()
+            .trailing_zeros()
+            .max(1);
+
+        writer.write_all(b"GIF89a")?;
+        writer.write_all(&width.to_le_bytes())?;
+        writer.write_all(&height.to_le_bytes())?;
+        // NOTE(Fermin): Global color table, 8 bits per channel, background
+        // color 0 and square pixels.
+        writer.write_all(&[0x80 | (7 << 4) | (table_bits as u8 - 1), 0, 0])?;
+        for entry in palette {
+            writer.write_all(entry)?;
+        }
+        for _padding in palette.len()..1 << table_bits {
+            writer.write_all(&[0, 0, 0])?;
+        }
+
+        // NOTE(Fermin): NETSCAPE2.0 extension, 0 loops means forever
+        writer.write_all(&[0x21, 0xff, 11])?;
+        writer.write_all(b"NETSCAPE2.0")?;
+        writer.write_all(&[3, 1, 0, 0, 0])?;
+
+        Ok(GifWriter {
+            writer,
+            width: width as usize,
+            height: height as usize,
+            min_code_size: table_bits.max(2),
+            transparent: palette.len() as u8,
+            previous: None,
+        })
+    }
+
+    // NOTE(Fermin): indices is a palette index per pixel, top row first
+    pub fn write_frame(&mut self, indices: &[u8], delay_centiseconds: u16) -> std::io::Result<()> {
+        assert_eq!(indices.len(), self.width * self.height);
+
+        // NOTE(Fermin): Frames can't be empty, one that didn't change
+        // rewrites its first pixel.
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (0, 0, self.width, self.height);
+        if let Some(previous) = &self.previous {
+            (min_x, min_y, max_x, max_y) = (self.width, self.height, 0, 0);
+            for y in 0..self.height {
+                let row = y * self.width..(y + 1) * self.width;
+                let (row, previous_row) = (&indices[row.clone()], &previous[row]);
+                if let Some(first) = (0..self.width).find(|&x| row[x] != previous_row[x]) {
+                    let last = (0..self.width)
+                        .rfind(|&x| row[x] != previous_row[x])
+                        .unwrap();
+                    min_x = min_x.min(first);
+                    max_x = max_x.max(last + 1);
+                    min_y = min_y.min(y);
+                    max_y = y + 1;
+                }
+            }
+            if min_x >= max_x {
+                (min_x, min_y, max_x, max_y) = (0, 0, 1, 1);
+            }
+        }
+
+        // NOTE(Fermin): Graphic control extension, frames are left in place
+        // for the next one to draw over and have a transparent index.
+        self.writer.write_all(&[0x21, 0xf9, 4, (1 << 2) | 1])?;
+        self.writer.write_all(&delay_centiseconds.to_le_bytes())?;
+        self.writer.write_all(&[self.transparent, 0])?;
+
+        self.writer.write_all(&[0x2c])?;
+        for value in [min_x, min_y, max_x - min_x, max_y - min_y] {
+            self.writer.write_all(&(value as u16).to_le_bytes())?;
+        }
+        self.writer.write_all(&[0])?;
+
+        let mut cropped = Vec::with_capacity((max_x - min_x) * (max_y - min_y));
+        for y in min_y..max_y {
+            let row = y * self.width + min_x..y * self.width + max_x;
+            match &self.previous {
+                Some(previous) => {
+                    cropped.extend(indices[row.clone()].iter().zip(&previous[row]).map(
+                        |(&index, &previous)| {
+                            if index == previous {
+                                self.transparent
+                            } else {
+                                index
+                            }
+                        },
+                    ))
+                }
+                None => cropped.extend_from_slice(&indices[row]),
+            }
+        }
+        self.writer.write_all(&[self.min_code_size as u8])?;
+        write_sub_blocks(&mut self.writer, &lzw_encode(&cropped, self.min_code_size))?;
+
+        self.previous = Some(indices.to_vec());
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.writer.write_all(&[0x3b])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    // NOTE(Fermin): A plain decoder written from the spec, not from the
+    // encoder above.
+    fn lzw_decode(bytes: &[u8], min_code_size: u32) -> Vec<u8> {
+        let clear_code = 1usize << min_code_size;
+        let end_code = clear_code + 1;
+        let reset = || -> Vec<Vec<u8>> {
+            let mut table: Vec<Vec<u8>> = (0..clear_code).map(|index| vec![index as u8]).collect();
+            table.push(Vec::new());
+            table.push(Vec::new());
+            table
+        };
+        let mut table = reset();
+        let mut code_size = min_code_size + 1;
+        let mut previous: Option<usize> = None;
+        let mut out = Vec::new();
+        let mut bit = 0;
+        loop {
+            let mut code = 0;
+            for i in 0..code_size as usize {
+                let byte = bytes[(bit + i) / 8];
+                code |= ((byte >> ((bit + i) % 8)) as usize & 1) << i;
+            }
+            bit += code_size as usize;
+
+            if code == clear_code {
+                table = reset();
+                code_size = min_code_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == end_code {
+                return out;
+            }
+            let entry = match previous {
+                None => table[code].clone(),
+                Some(previous) => {
+                    let entry = if code < table.len() {
+                        table[code].clone()
+                    } else {
+                        assert_eq!(code, table.len());
+                        let mut entry = table[previous].clone();
+                        entry.push(table[previous][0]);
+                        entry
+                    };
+                    if table.len() < MAX_CODES as usize {
+                        let mut added = table[previous].clone();
+                        added.push(entry[0]);
+                        table.push(added);
+                        if table.len() == 1 << code_size && code_size < MAX_CODE_SIZE {
+                            code_size += 1;
+                        }
+                    }
+                    entry
+                }
+            };
+            out.extend_from_slice(&entry);
+            previous = Some(code);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trips() {
+        let mut rng = StdRng::seed_from_u64(47);
+        for (min_code_size, colors) in [(2, 2), (2, 4), (4, 16), (8, 256), (8, 3)] {
+            let mut inputs = vec![Vec::new(), vec![1]];
+            // NOTE(Fermin): Random indices run out of codes quickly, runs
+            // grow long strings.
+            inputs.push(
+                (0..50_000)
+                    .map(|_| rng.gen_range(0..colors) as u8)
+                    .collect(),
+            );
+            let mut runs = Vec::new();
+            while runs.len() < 50_000 {
+                let index = rng.gen_range(0..colors) as u8;
+                runs.extend(std::iter::repeat_n(index, rng.gen_range(1..40)));
+            }
+            inputs.push(runs);
+            for input in inputs {
+                let encoded = lzw_encode(&input, min_code_size);
+                assert_eq!(lzw_decode(&encoded, min_code_size), input);
+            }
+        }
+    }
+
+    #[test]
+    fn frames_only_write_what_changed() {
+        let (width, height) = (20, 10);
+        let palette = [[0, 0, 0], [255, 0, 0], [0, 255, 0]];
+        let mut gif = GifWriter::new(Vec::new(), width, height, &palette).unwrap();
+        let mut frame = vec![0u8; 200];
+        gif.write_frame(&frame, 4).unwrap();
+        frame[3 * 20 + 5] = 1;
+        frame[6 * 20 + 12] = 2;
+        gif.write_frame(&frame, 4).unwrap();
+        gif.write_frame(&frame, 4).unwrap();
+        let bytes = gif.finish().unwrap();
+
+        assert_eq!(&bytes[..6], b"GIF89a");
+        assert_eq!(bytes[6..10], [20, 0, 10, 0]);
+        // NOTE(Fermin): 3 colors and transparency make a table of 4
+        assert_eq!(bytes[10] & 0x87, 0x81);
+        let after_table = 13 + 4 * 3;
+        assert_eq!(&bytes[after_table + 3..after_table + 14], b"NETSCAPE2.0");
+        assert_eq!(*bytes.last().unwrap(), 0x3b);
+
+        // NOTE(Fermin): Walks the frames and unpacks their pixels
+        let mut at = after_table + 19;
+        let mut frames = Vec::new();
+        while bytes[at] == 0x21 {
+            assert_eq!(bytes[at + 1..at + 4], [0xf9, 4, (1 << 2) | 1]);
+            assert_eq!(bytes[at + 4..at + 7], [4, 0, 3]);
+            at += 8;
+            assert_eq!(bytes[at], 0x2c);
+            let field =
+                |i: usize| u16::from_le_bytes([bytes[at + 1 + i * 2], bytes[at + 2 + i * 2]]);
+            let rect = (field(0), field(1), field(2), field(3));
+            let min_code_size = bytes[at + 10] as u32;
+            at += 11;
+            let mut data = Vec::new();
+            while bytes[at] != 0 {
+                let len = bytes[at] as usize;
+                data.extend_from_slice(&bytes[at + 1..at + 1 + len]);
+                at += 1 + len;
+            }
+            at += 1;
+            frames.push((rect, lzw_decode(&data, min_code_size)));
+        }
+        assert_eq!(bytes[at], 0x3b);
+
+        assert_eq!(frames[0], ((0, 0, 20, 10), vec![0; 200]));
+        assert_eq!(frames[1].0, (5, 3, 8, 4));
+        let mut changed = vec![3; 32];
+        changed[0] = 1;
+        changed[3 * 8 + 7] = 2;
+        assert_eq!(frames[1].1, changed);
+        assert_eq!(frames[2], ((0, 0, 1, 1), vec![3]));
+    }
+
+    #[test]
+    fn delays_add_up_to_the_loop() {
+        let total: u32 = (0..30).map(|frame| frame_delay(frame, 30.0) as u32).sum();
+        assert_eq!(total, 100);
+        assert_eq!(frame_delay(0, 25.0), 4);
+
+        // NOTE(Fermin): Late frames of a long loop still get their delay
+        let total: u32 = (0..30)
+            .map(|frame| frame_delay(frame + 100_000, 30.0) as u32)
+            .sum();
+        assert_eq!(total, 100);
+        assert_eq!(frame_delay(0, 0.001), u16::MAX);
+    }
+}
@@ -0,0 +1,223 @@
+use crate::color::Color;
+use crate::compositor::BlendMode;
+use crate::math::lerp;
+use crate::pixel_buffer::PixelBuffer;
+use crate::render::{blend_channel, RenderTarget};
+
+pub const HDR_CHANNELS: usize = 3;
+
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub enum ToneMapper {
+    Reinhard,
+    Aces,
+}
+
+// NOTE(Fermin): Linear RGB, 1.0 is the brightest value the presentation
+// buffer can show. Values above that are kept until tone mapping.
+pub struct HdrBuffer {
+    pub bits: Vec<f32>,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl HdrBuffer {
+    pub fn new(width: i32, height: i32) -> HdrBuffer {
+        let len = (width.max(0) as usize)
+            .checked_mul(height.max(0) as usize)
+            .and_then(|pixels| pixels.checked_mul(HDR_CHANNELS))
+            .expect("hdr buffer too big");
+        HdrBuffer {
+            bits: vec![0.0; len],
+            width,
+            height,
+        }
+    }
+}
+
+// NOTE(Fermin): Gamma 2 approximation, sqrt is cheap enough to run on every
+// pixel each frame.
+fn srgb_to_linear(channel: u8) -> f32 {
+    let c = channel as f32 / 255.0;
+    c * c
+}
+
+fn linear_to_srgb(channel: f32) -> u8 {
+    (channel.clamp(0.0, 1.0).sqrt() * 255.0).round() as u8
+}
+
+impl RenderTarget for HdrBuffer {
+    fn width(&self) -> i32 {
+        self.width
+    }
+
+    fn height(&self) -> i32 {
+        self.height
+    }
+
+    fn pitch(&self) -> i32 {
+        self.width
+    }
+
+    fn fill_pixel(&mut self, index: usize, color: &Color) {
+        // NOTE(Fermin): Pixel -> RR GG BB
+        let dest_index = index * HDR_CHANNELS;
+        self.bits[dest_index] = srgb_to_linear(color.r);
+        self.bits[dest_index + 1] = srgb_to_linear(color.g);
+        self.bits[dest_index + 2] = srgb_to_linear(color.b);
+    }
+
+    fn blend_pixel_with(
+        &mut self,
+        index: usize,
+        color: &Color,
+        intensity: f32,
+        t: f32,
+        blend_mode: BlendMode,
+    ) {
+        let dest_index = index * HDR_CHANNELS;
+        let src = [
+            srgb_to_linear(color.r) * intensity,
+            srgb_to_linear(color.g) * intensity,
+            srgb_to_linear(color.b) * intensity,
+        ];
+
+        for (dest, src) in self.bits[dest_index..dest_index + HDR_CHANNELS]
+            .iter_mut()
+            .zip(src)
+        {
+            *dest = lerp(*dest, t, blend_channel(*dest, src, 1.0, blend_mode));
+        }
+    }
+
+    fn decay_towards(&mut self, color: &Color, factor: f32) {
+        let target = [
+            srgb_to_linear(color.r),
+            srgb_to_linear(color.g),
+            srgb_to_linear(color.b),
+        ];
+        for pixel in self.bits.chunks_exact_mut(HDR_CHANNELS) {
+            for (channel, target) in pixel.iter_mut().zip(target) {
+                *channel = lerp(target, factor, *channel);
+            }
+        }
+    }
+}
+
+fn tone_map_channel(value: f32, tone_mapper: ToneMapper) -> f32 {
+    match tone_mapper {
+        ToneMapper::Reinhard => value / (1.0 + value),
+        ToneMapper::Aces => {
+            // NOTE(Fermin): Krzysztof Narkowicz's fit of the ACES filmic curve,
+            // it goes past 1.0 a bit above its white point of about 7.24
+            let a = 2.51;
+            let b = 0.03;
+            let c = 2.43;
+            let d = 0.59;
+            let e = 0.14;
+            ((value * (a * value + b)) / (value * (c * value + d) + e)).clamp(0.0, 1.0)
+        }
+    }
+}
+
+// NOTE(Fermin): Resolves the hdr buffer into the presentation buffer. Both
+// buffers must have the same dimensions.
+pub fn tone_map_hdr_buffer(
+    hdr: &HdrBuffer,
+    buffer: &mut PixelBuffer,
+    tone_mapper: ToneMapper,
+    exposure: f32,
+) {
+    assert!(hdr.width == buffer.width && hdr.height == buffer.height);
+
+    let format = buffer.format;
+    let bytes_per_pixel = format.bytes_per_pixel();
+    let hdr_pitch = hdr.width as usize * HDR_CHANNELS;
+    let black = format.encode(&Color::rgba(0, 0, 0, 255));
+    for (src_row, dest_row) in hdr.bits.chunks_exact(hdr_pitch).zip(buffer.rows_mut()) {
+        for (src, dest) in src_row
+            .chunks_exact(HDR_CHANNELS)
+            .zip(dest_row.chunks_exact_mut(bytes_per_pixel))
+        {
+            // NOTE(Fermin): Both tone mappers keep black at black, and most
+            // of a frame with only stars in it is black.
+            if src[0] <= 0.0 && src[1] <= 0.0 && src[2] <= 0.0 {
+                dest.copy_from_slice(&black);
+                continue;
+            }
+            let color = Color::rgba(
+                linear_to_srgb(tone_map_channel(src[0] * exposure, tone_mapper)),
+                linear_to_srgb(tone_map_channel(src[1] * exposure, tone_mapper)),
+                linear_to_srgb(tone_map_channel(src[2] * exposure, tone_mapper)),
+                255,
+            );
+            dest.copy_from_slice(&format.encode(&color));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel_buffer::PixelFormat;
+
+    #[test]
+    fn tone_mappers_keep_values_in_range() {
+        for tone_mapper in [ToneMapper::Reinhard, ToneMapper::Aces] {
+            assert_eq!(tone_map_channel(0.0, tone_mapper), 0.0);
+            let mut last = 0.0;
+            for step in 1..=400 {
+                let mapped = tone_map_channel(step as f32 * 0.05, tone_mapper);
+                assert!((0.0..=1.0).contains(&mapped), "{}", mapped);
+                assert!(mapped >= last);
+                last = mapped;
+            }
+        }
+
+        assert_eq!(tone_map_channel(1.0, ToneMapper::Reinhard), 0.5);
+        assert!(tone_map_channel(1000.0, ToneMapper::Reinhard) < 1.0);
+        assert!(tone_map_channel(7.0, ToneMapper::Aces) < 1.0);
+        assert_eq!(tone_map_channel(7.5, ToneMapper::Aces), 1.0);
+    }
+
+    #[test]
+    fn tone_mapping_resolves_into_the_presentation_buffer() {
+        for channel in 0..=255 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(channel)), channel);
+        }
+
+        let mut hdr = HdrBuffer::new(2, 2);
+        hdr.fill_pixel(0, &Color::rgba(255, 255, 255, 255));
+        hdr.fill_pixel(1, &Color::rgba(0, 128, 255, 255));
+        hdr.fill_pixel(2, &Color::rgba(40, 0, 0, 255));
+        let mut buffer = PixelBuffer::new(2, 2, PixelFormat::Bgra8);
+        tone_map_hdr_buffer(&hdr, &mut buffer, ToneMapper::Reinhard, 1.0);
+        assert_eq!(buffer.get(0, 0), Some(Color::rgba(180, 180, 180, 255)));
+        assert_eq!(buffer.get(1, 0), Some(Color::rgba(0, 114, 180, 255)));
+        assert_eq!(buffer.get(0, 1), Some(Color::rgba(40, 0, 0, 255)));
+        assert_eq!(buffer.get(1, 1), Some(Color::rgba(0, 0, 0, 255)));
+
+        tone_map_hdr_buffer(&hdr, &mut buffer, ToneMapper::Reinhard, 2.0);
+        assert_eq!(buffer.get(0, 0), Some(Color::rgba(208, 208, 208, 255)));
+    }
+
+    #[test]
+    fn adding_goes_past_one_and_decay_comes_back() {
+        let white = Color::rgba(255, 255, 255, 255);
+        let mut hdr = HdrBuffer::new(1, 1);
+        hdr.blend_pixel_with(0, &white, 2.5, 1.0, BlendMode::Add);
+        hdr.blend_pixel_with(0, &white, 1.0, 1.0, BlendMode::Add);
+        assert_eq!(hdr.bits, [3.5, 3.5, 3.5]);
+
+        // NOTE(Fermin): Half the coverage adds half as much
+        hdr.blend_pixel_with(0, &white, 1.0, 0.5, BlendMode::Add);
+        assert_eq!(hdr.bits, [4.0, 4.0, 4.0]);
+
+        hdr.decay_towards(&Color::rgba(0, 0, 0, 255), 0.25);
+        assert_eq!(hdr.bits, [1.0, 1.0, 1.0]);
+        hdr.decay_towards(&white, 0.5);
+        assert_eq!(hdr.bits, [1.0, 1.0, 1.0]);
+        hdr.decay_towards(&Color::rgba(0, 0, 0, 255), 0.0);
+        assert_eq!(hdr.bits, [0.0, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,62 @@
+use crate::bmp::decode_bmp;
+use crate::pixel_buffer::PixelBuffer;
+use crate::png::{decode_png, SIGNATURE as PNG_SIGNATURE};
+use std::fmt;
+use std::fs::read;
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(std::io::Error),
+    // NOTE(Fermin): Valid file, but uses something we don't decode
+    Unsupported(String),
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::Io(error) => write!(f, "Couldn't read image: {}", error),
+            ImageError::Unsupported(what) => write!(f, "Unsupported image: {}", what),
+            ImageError::Corrupt(what) => write!(f, "Corrupt image: {}", what),
+        }
+    }
+}
+
+impl From<std::io::Error> for ImageError {
+    fn from(error: std::io::Error) -> ImageError {
+        ImageError::Io(error)
+    }
+}
+
+// NOTE(Fermin): Headers can claim any size, this keeps what gets allocated
+// to a gigabyte of Bgra8. PixelBuffer sizes are i32 and it fits in those.
+pub const MAX_IMAGE_PIXELS: usize = 1 << 28;
+
+pub fn check_image_size(width: usize, height: usize) -> Result<(), ImageError> {
+    let pixels = width
+        .checked_mul(height)
+        .filter(|&pixels| pixels <= MAX_IMAGE_PIXELS);
+    match pixels.and_then(|pixels| pixels.checked_mul(4)) {
+        Some(bytes) if bytes <= i32::MAX as usize => Ok(()),
+        _ => Err(ImageError::Unsupported(format!(
+            "{}x{} is too big",
+            width, height
+        ))),
+    }
+}
+
+// NOTE(Fermin): The format is picked from the first bytes, not the file
+// extension. Images come out top-down in straight (not premultiplied) alpha.
+pub fn decode_image(bytes: &[u8]) -> Result<PixelBuffer, ImageError> {
+    if bytes.starts_with(b"BM") {
+        decode_bmp(bytes)
+    } else if bytes.starts_with(&PNG_SIGNATURE) {
+        decode_png(bytes)
+    } else {
+        Err(ImageError::Unsupported("unknown file format".to_string()))
+    }
+}
+
+pub fn load_image(path: &str) -> Result<PixelBuffer, ImageError> {
+    decode_image(&read(path)?)
+}
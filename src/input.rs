@@ -0,0 +1,385 @@
+use crate::math::V2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    Digit0,
+    Digit1,
+    Digit2,
+    Digit3,
+    Digit4,
+    Digit5,
+    Digit6,
+    Digit7,
+    Digit8,
+    Digit9,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    Space,
+    Enter,
+    Escape,
+    Tab,
+    Backspace,
+    Left,
+    Right,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Insert,
+    Delete,
+    // NOTE(Fermin): The -/_ and =/+ keys, not the ones on the numpad
+    Minus,
+    Plus,
+    Comma,
+    Period,
+    Shift,
+    Control,
+    Alt,
+}
+
+// NOTE(Fermin): What keys are called in binding files, every key is in here
+// once.
+const KEY_NAMES: [(Key, &str); KEY_COUNT] = [
+    (Key::A, "A"),
+    (Key::B, "B"),
+    (Key::C, "C"),
+    (Key::D, "D"),
+    (Key::E, "E"),
+    (Key::F, "F"),
+    (Key::G, "G"),
+    (Key::H, "H"),
+    (Key::I, "I"),
+    (Key::J, "J"),
+    (Key::K, "K"),
+    (Key::L, "L"),
+    (Key::M, "M"),
+    (Key::N, "N"),
+    (Key::O, "O"),
+    (Key::P, "P"),
+    (Key::Q, "Q"),
+    (Key::R, "R"),
+    (Key::S, "S"),
+    (Key::T, "T"),
+    (Key::U, "U"),
+    (Key::V, "V"),
+    (Key::W, "W"),
+    (Key::X, "X"),
+    (Key::Y, "Y"),
+    (Key::Z, "Z"),
+    (Key::Digit0, "0"),
+    (Key::Digit1, "1"),
+    (Key::Digit2, "2"),
+    (Key::Digit3, "3"),
+    (Key::Digit4, "4"),
+    (Key::Digit5, "5"),
+    (Key::Digit6, "6"),
+    (Key::Digit7, "7"),
+    (Key::Digit8, "8"),
+    (Key::Digit9, "9"),
+    (Key::F1, "F1"),
+    (Key::F2, "F2"),
+    (Key::F3, "F3"),
+    (Key::F4, "F4"),
+    (Key::F5, "F5"),
+    (Key::F6, "F6"),
+    (Key::F7, "F7"),
+    (Key::F8, "F8"),
+    (Key::F9, "F9"),
+    (Key::F10, "F10"),
+    (Key::F11, "F11"),
+    (Key::F12, "F12"),
+    (Key::Space, "Space"),
+    (Key::Enter, "Enter"),
+    (Key::Escape, "Escape"),
+    (Key::Tab, "Tab"),
+    (Key::Backspace, "Backspace"),
+    (Key::Left, "Left"),
+    (Key::Right, "Right"),
+    (Key::Up, "Up"),
+    (Key::Down, "Down"),
+    (Key::PageUp, "PageUp"),
+    (Key::PageDown, "PageDown"),
+    (Key::Home, "Home"),
+    (Key::End, "End"),
+    (Key::Insert, "Insert"),
+    (Key::Delete, "Delete"),
+    (Key::Minus, "Minus"),
+    (Key::Plus, "Plus"),
+    (Key::Comma, "Comma"),
+    (Key::Period, "Period"),
+    (Key::Shift, "Shift"),
+    (Key::Control, "Control"),
+    (Key::Alt, "Alt"),
+];
+pub const KEY_COUNT: usize = Key::Alt as usize + 1;
+// NOTE(Fermin): Keys then mouse buttons, for code that stores input
+pub const BUTTON_COUNT: usize = KEY_COUNT + 3;
+
+impl Key {
+    // NOTE(Fermin): Case doesn't matter, "Ctrl" works for Control too
+    pub fn from_name(name: &str) -> Option<Key> {
+        if name.eq_ignore_ascii_case("ctrl") {
+            return Some(Key::Control);
+        }
+        KEY_NAMES
+            .iter()
+            .find(|(_, key_name)| key_name.eq_ignore_ascii_case(name))
+            .map(|&(key, _)| key)
+    }
+
+    // NOTE(Fermin): Letters, digits and function keys are declared in order,
+    // so platform layers can count from the first one.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn offset_by(self, offset: usize) -> Option<Key> {
+        KEY_NAMES.get(self as usize + offset).map(|&(key, _)| key)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub control: bool,
+    pub alt: bool,
+}
+
+// NOTE(Fermin): pressed and released only cover the current frame, both can
+// be set if the key went down and up again before it started.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ButtonState {
+    pub is_down: bool,
+    pub pressed: bool,
+    pub released: bool,
+}
+
+impl ButtonState {
+    // NOTE(Fermin): Key repeats come in as downs on a key that is already
+    // down, they don't count as presses.
+    fn set_down(&mut self, is_down: bool) {
+        if is_down && !self.is_down {
+            self.pressed = true;
+        }
+        if !is_down && self.is_down {
+            self.released = true;
+        }
+        self.is_down = is_down;
+    }
+
+    fn begin_frame(&mut self) {
+        self.pressed = false;
+        self.released = false;
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Mouse {
+    // NOTE(Fermin): In buffer pixels, None while the cursor is outside of
+    // the window. Dragging keeps it following the cursor outside of it.
+    pub pos: Option<V2>,
+    buttons: [ButtonState; 3],
+}
+
+impl Mouse {
+    pub fn button(&self, button: MouseButton) -> ButtonState {
+        self.buttons[button as usize]
+    }
+
+    pub fn is_down(&self, button: MouseButton) -> bool {
+        self.button(button).is_down
+    }
+
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn any_down(&self) -> bool {
+        self.buttons.iter().any(|button| button.is_down)
+    }
+
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn set_down(&mut self, button: MouseButton, is_down: bool) {
+        self.buttons[button as usize].set_down(is_down);
+    }
+}
+
+// NOTE(Fermin): Everything the player is doing, platform layers fill it in
+// before every frame. Nothing in here depends on the platform.
+#[derive(Clone, Debug)]
+pub struct Input {
+    pub mouse: Mouse,
+    keys: [ButtonState; KEY_COUNT],
+}
+
+impl Default for Input {
+    fn default() -> Input {
+        Input {
+            mouse: Mouse::default(),
+            keys: [ButtonState::default(); KEY_COUNT],
+        }
+    }
+}
+
+impl Input {
+    // NOTE(Fermin): Platform layers call this before handing over the
+    // events of a new frame.
+    pub fn begin_frame(&mut self) {
+        for key in &mut self.keys {
+            key.begin_frame();
+        }
+        for button in &mut self.mouse.buttons {
+            button.begin_frame();
+        }
+    }
+
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn set_key(&mut self, key: Key, is_down: bool) {
+        self.keys[key as usize].set_down(is_down);
+    }
+
+    // NOTE(Fermin): For when the window stops getting events, like losing
+    // focus, so nothing stays held down.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn release_all(&mut self) {
+        for key in &mut self.keys {
+            key.set_down(false);
+        }
+        for button in &mut self.mouse.buttons {
+            button.set_down(false);
+        }
+    }
+
+    pub fn button_state(&self, index: usize) -> ButtonState {
+        if index < KEY_COUNT {
+            self.keys[index]
+        } else {
+            self.mouse.buttons[index - KEY_COUNT]
+        }
+    }
+
+    pub fn set_button_state(&mut self, index: usize, state: ButtonState) {
+        if index < KEY_COUNT {
+            self.keys[index] = state;
+        } else {
+            self.mouse.buttons[index - KEY_COUNT] = state;
+        }
+    }
+
+    pub fn key(&self, key: Key) -> ButtonState {
+        self.keys[key as usize]
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            shift: self.key(Key::Shift).is_down,
+            control: self.key(Key::Control).is_down,
+            alt: self.key(Key::Alt).is_down,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_key_has_a_name() {
+        for (index, &(key, name)) in KEY_NAMES.iter().enumerate() {
+            assert_eq!(key as usize, index);
+            assert_eq!(Key::from_name(&name.to_lowercase()), Some(key));
+        }
+        assert_eq!(Key::from_name("Ctrl"), Some(Key::Control));
+        assert_eq!(Key::from_name("Hyper"), None);
+    }
+
+    #[test]
+    fn presses_and_releases_last_one_frame() {
+        let mut input = Input::default();
+        input.set_key(Key::Space, true);
+        // NOTE(Fermin): A repeat
+        input.set_key(Key::Space, true);
+        let space = input.key(Key::Space);
+        assert!(space.is_down && space.pressed && !space.released);
+
+        input.begin_frame();
+        assert_eq!(
+            input.key(Key::Space),
+            ButtonState {
+                is_down: true,
+                pressed: false,
+                released: false,
+            }
+        );
+
+        input.begin_frame();
+        input.set_key(Key::Space, false);
+        input.set_key(Key::Space, true);
+        input.set_key(Key::Space, false);
+        let space = input.key(Key::Space);
+        assert!(!space.is_down && space.pressed && space.released);
+    }
+
+    #[test]
+    fn modifiers_and_release_all() {
+        let mut input = Input::default();
+        input.set_key(Key::Control, true);
+        input.set_key(Key::Shift, true);
+        input.mouse.set_down(MouseButton::Right, true);
+        assert_eq!(
+            input.modifiers(),
+            Modifiers {
+                shift: true,
+                control: true,
+                alt: false,
+            }
+        );
+
+        input.begin_frame();
+        input.release_all();
+        assert_eq!(input.modifiers(), Modifiers::default());
+        assert!(input.key(Key::Shift).released);
+        assert!(input.mouse.button(MouseButton::Right).released);
+        assert!(!input.mouse.any_down());
+    }
+}
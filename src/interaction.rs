@@ -0,0 +1,140 @@
+use crate::input::{Mouse, MouseButton};
+use crate::math::{v2_length, v2_perp, V2};
+
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InteractionMode {
+    Repel,
+    Attract,
+    // NOTE(Fermin): Mostly around the cursor, a bit towards it so stars
+    // orbit instead of getting flung out.
+    Swirl,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct InteractionSettings {
+    pub mode: InteractionMode,
+    // NOTE(Fermin): Pixels around the cursor that stars feel it from
+    pub radius: f32,
+    // NOTE(Fermin): Pixels per second squared right under the cursor, it
+    // falls off to 0 at radius.
+    pub strength: f32,
+    // NOTE(Fermin): Fraction of the push a star still has after one
+    // second, the rest of the time they drift like usual.
+    pub push_left_per_second: f32,
+    // NOTE(Fermin): None works whenever the cursor is over the window
+    pub button: Option<MouseButton>,
+}
+
+// NOTE(Fermin): How much of the swirl's push goes towards the cursor
+const SWIRL_PULL: f32 = 0.3;
+
+// NOTE(Fermin): Where stars should feel the cursor from this frame, if at all
+pub fn interaction_cursor(settings: &InteractionSettings, mouse: &Mouse) -> Option<V2> {
+    match settings.button {
+        Some(button) if !mouse.is_down(button) => None,
+        _ => mouse.pos,
+    }
+}
+
+// NOTE(Fermin): Acceleration the cursor puts on something at pos
+pub fn cursor_force(settings: &InteractionSettings, cursor: V2, pos: V2) -> V2 {
+    let offset = pos - cursor;
+    let distance = v2_length(offset);
+    // NOTE(Fermin): Right on the cursor there's no direction to push in
+    if distance >= settings.radius || distance < 1e-3 {
+        return V2 { x: 0.0, y: 0.0 };
+    }
+
+    let away = offset / distance;
+    let falloff = 1.0 - distance / settings.radius;
+    let direction = match settings.mode {
+        InteractionMode::Repel => away,
+        InteractionMode::Attract => -away,
+        InteractionMode::Swirl => v2_perp(away) - away * SWIRL_PULL,
+    };
+    direction * (settings.strength * falloff * falloff)
+}
+
+// NOTE(Fermin): What is left of push after a frame of relaxing
+pub fn relax_push(settings: &InteractionSettings, push: V2, dt_for_frame: f32) -> V2 {
+    push * settings.push_left_per_second.powf(dt_for_frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::v2_dot;
+
+    fn settings(mode: InteractionMode) -> InteractionSettings {
+        InteractionSettings {
+            mode,
+            radius: 100.0,
+            strength: 400.0,
+            push_left_per_second: 0.1,
+            button: None,
+        }
+    }
+
+    #[test]
+    fn modes_push_the_right_way() {
+        let cursor = V2 { x: 50.0, y: 50.0 };
+        let pos = V2 { x: 80.0, y: 50.0 };
+        let away = V2 { x: 1.0, y: 0.0 };
+
+        let repel = cursor_force(&settings(InteractionMode::Repel), cursor, pos);
+        let attract = cursor_force(&settings(InteractionMode::Attract), cursor, pos);
+        let swirl = cursor_force(&settings(InteractionMode::Swirl), cursor, pos);
+        assert_eq!(repel, V2 { x: 196.0, y: 0.0 });
+        assert_eq!(attract, -repel);
+        assert!(v2_dot(swirl, away) < 0.0);
+        assert!(swirl.y.abs() > swirl.x.abs());
+    }
+
+    #[test]
+    fn nothing_outside_the_radius_or_on_the_cursor() {
+        let repel = settings(InteractionMode::Repel);
+        let cursor = V2 { x: 0.0, y: 0.0 };
+        let zero = V2 { x: 0.0, y: 0.0 };
+        assert_eq!(cursor_force(&repel, cursor, V2 { x: 0.0, y: 100.0 }), zero);
+        assert_eq!(cursor_force(&repel, cursor, V2 { x: -300.0, y: 5.0 }), zero);
+        assert_eq!(cursor_force(&repel, cursor, cursor), zero);
+    }
+
+    #[test]
+    fn buttons_gate_the_cursor() {
+        let mut mouse = Mouse::default();
+        let held = InteractionSettings {
+            button: Some(MouseButton::Left),
+            ..settings(InteractionMode::Swirl)
+        };
+        assert_eq!(interaction_cursor(&held, &mouse), None);
+
+        let pos = V2 { x: 4.0, y: 2.0 };
+        mouse.pos = Some(pos);
+        assert_eq!(
+            interaction_cursor(&settings(InteractionMode::Swirl), &mouse),
+            Some(pos)
+        );
+        assert_eq!(interaction_cursor(&held, &mouse), None);
+        mouse.set_down(MouseButton::Left, true);
+        assert_eq!(interaction_cursor(&held, &mouse), Some(pos));
+    }
+
+    #[test]
+    fn pushes_relax_back_to_nothing() {
+        let repel = settings(InteractionMode::Repel);
+        let mut push = V2 {
+            x: 300.0,
+            y: -120.0,
+        };
+        for _frame in 0..60 {
+            push = relax_push(&repel, push, 1.0 / 60.0);
+        }
+        assert!((push.x - 30.0).abs() < 1e-2);
+        for _frame in 0..240 {
+            push = relax_push(&repel, push, 1.0 / 60.0);
+        }
+        assert!(v2_length(push) < 0.1);
+    }
+}
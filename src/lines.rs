@@ -0,0 +1,696 @@
+use crate::color::Color;
+use crate::compositor::BlendMode;
+use crate::math::{lerp, v2_dot, v2_length, v2_length_sq, v2_normalize, v2_perp, Rect, V2};
+use crate::render::{clip_to_target, RenderTarget};
+
+// NOTE(Fermin): Per channel, trails go through too many pixels for OkLab
+fn lerp_color(a: &Color, t: f32, b: &Color) -> Color {
+    let channel = |a: u8, b: u8| lerp(a as f32, t, b as f32).round() as u8;
+    Color::rgba(
+        channel(a.r, b.r),
+        channel(a.g, b.g),
+        channel(a.b, b.b),
+        channel(a.a, b.a),
+    )
+}
+
+// NOTE(Fermin): A corner of a line or polyline. Width, color, alpha and
+// intensity lerp towards the next vertex. Width scales the style's thickness,
+// 1.0 draws it as is.
+#[derive(Copy, Clone, Debug)]
+pub struct LineVertex {
+    pub pos: V2,
+    pub width: f32,
+    pub color: Color,
+    pub alpha: f32,
+    pub intensity: f32,
+}
+
+// NOTE(Fermin): How thick polylines fill the gap on the outside of a bend
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LineJoin {
+    Miter,
+    Bevel,
+    Round,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct LineStyle {
+    // NOTE(Fermin): In pixels. Up to 1.0 lines are drawn with Xiaolin Wu's
+    // algorithm and thinner ones just get fainter.
+    pub thickness: f32,
+    pub join: LineJoin,
+    pub blend_mode: BlendMode,
+}
+
+// NOTE(Fermin): Miters that would stick out further than this many half
+// thicknesses from their vertex are beveled instead.
+const MITER_LIMIT: f32 = 4.0;
+
+#[derive(Copy, Clone)]
+struct LineSample {
+    index: usize,
+    alpha: f32,
+    color: Color,
+    intensity: f32,
+}
+
+// NOTE(Fermin): Every pixel a line touches, kept until the whole polyline is
+// rasterized so overlapping segments and joins only blend once.
+struct LineSamples {
+    width: i32,
+    height: i32,
+    pitch: i32,
+    samples: Vec<LineSample>,
+}
+
+impl LineSamples {
+    fn new<T: RenderTarget>(buffer: &T) -> LineSamples {
+        LineSamples {
+            width: buffer.width(),
+            height: buffer.height(),
+            pitch: buffer.pitch(),
+            samples: Vec::new(),
+        }
+    }
+
+    fn plot(&mut self, x: i32, y: i32, coverage: f32, from: &LineVertex, t: f32, to: &LineVertex) {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return;
+        }
+        let alpha = coverage * lerp(from.alpha, t, to.alpha);
+        if alpha <= 0.0 {
+            return;
+        }
+        self.samples.push(LineSample {
+            index: (y * self.pitch + x) as usize,
+            alpha: alpha.min(1.0),
+            color: lerp_color(&from.color, t, &to.color),
+            intensity: lerp(from.intensity, t, to.intensity),
+        });
+    }
+
+    // NOTE(Fermin): Calls coverage for every pixel whose center is inside the
+    // convex polygon corners, clipped to the buffer. It returns how much of
+    // the pixel is covered and how far from `from` to `to` it is.
+    fn scan(
+        &mut self,
+        corners: &[V2],
+        from: &LineVertex,
+        to: &LineVertex,
+        coverage: impl Fn(V2) -> (f32, f32),
+    ) {
+        let min_y = corners
+            .iter()
+            .fold(f32::MAX, |min, corner| min.min(corner.y));
+        let max_y = corners
+            .iter()
+            .fold(f32::MIN, |max, corner| max.max(corner.y));
+        let first_row = ((min_y - 0.5).ceil() as i32).max(0);
+        let last_row = ((max_y - 0.5).floor() as i32).min(self.height - 1);
+        for y in first_row..=last_row {
+            let center_y = y as f32 + 0.5;
+            let Some((min_x, max_x)) = convex_span(corners, center_y) else {
+                continue;
+            };
+            let first_column = ((min_x - 0.5).ceil() as i32).max(0);
+            let last_column = ((max_x - 0.5).floor() as i32).min(self.width - 1);
+            for x in first_column..=last_column {
+                let (covered, t) = coverage(V2 {
+                    x: x as f32 + 0.5,
+                    y: center_y,
+                });
+                if covered > 0.0 {
+                    self.plot(x, y, covered, from, t, to);
+                }
+            }
+        }
+    }
+
+    fn blend_into<T: RenderTarget>(mut self, buffer: &mut T, blend_mode: BlendMode) {
+        // NOTE(Fermin): Strongest sample first for every pixel, the rest get
+        // skipped.
+        self.samples
+            .sort_unstable_by(|a, b| a.index.cmp(&b.index).then(b.alpha.total_cmp(&a.alpha)));
+        let mut last_index = None;
+        for sample in &self.samples {
+            if last_index == Some(sample.index) {
+                continue;
+            }
+            last_index = Some(sample.index);
+            buffer.blend_pixel_with(
+                sample.index,
+                &sample.color,
+                sample.intensity,
+                sample.alpha,
+                blend_mode,
+            );
+        }
+    }
+}
+
+// NOTE(Fermin): Where a horizontal line at y enters and leaves a convex
+// polygon, if it crosses it at all.
+fn convex_span(corners: &[V2], y: f32) -> Option<(f32, f32)> {
+    let mut span: Option<(f32, f32)> = None;
+    for (index, a) in corners.iter().enumerate() {
+        let b = corners[(index + 1) % corners.len()];
+        if (a.y > y && b.y > y) || (a.y < y && b.y < y) {
+            continue;
+        }
+        let xs = if a.y == b.y {
+            (a.x.min(b.x), a.x.max(b.x))
+        } else {
+            let x = a.x + (y - a.y) * (b.x - a.x) / (b.y - a.y);
+            (x, x)
+        };
+        span = Some(match span {
+            Some((min_x, max_x)) => (min_x.min(xs.0), max_x.max(xs.1)),
+            None => xs,
+        });
+    }
+    span
+}
+
+// NOTE(Fermin): Coverage of a convex polygon where only the soft edges are
+// anti-aliased. Hard edges are the ones shared with a segment, a pixel on
+// them is covered by one or the other, never half by both.
+fn polygon_coverage(pixel: V2, corners: &[V2], soft: impl Fn(usize) -> bool) -> f32 {
+    let mut area = 0.0;
+    for (index, a) in corners.iter().enumerate() {
+        let b = corners[(index + 1) % corners.len()];
+        area += a.x * b.y - b.x * a.y;
+    }
+    // NOTE(Fermin): Outward normals depend on which way the corners wind
+    let outward = if area > 0.0 { -1.0 } else { 1.0 };
+
+    let mut coverage: f32 = 1.0;
+    for (index, a) in corners.iter().enumerate() {
+        let b = corners[(index + 1) % corners.len()];
+        let normal = v2_normalize(v2_perp(b - *a)) * outward;
+        let distance = v2_dot(pixel - *a, normal);
+        if soft(index) {
+            coverage = coverage.min((0.5 - distance).clamp(0.0, 1.0));
+        } else if distance > 0.0 {
+            return 0.0;
+        }
+    }
+    coverage
+}
+
+// NOTE(Fermin): Every pixel a line or polyline can touch, before clipping
+pub fn line_bounds(vertices: &[LineVertex], style: &LineStyle) -> Rect {
+    let mut bounds = Rect::from_pos_size(0, 0, 0, 0);
+    for (index, vertex) in vertices.iter().enumerate() {
+        let mut reach = (style.thickness * vertex.width * 0.5).max(1.0);
+        if style.join == LineJoin::Miter && vertices.len() > 2 {
+            reach *= MITER_LIMIT;
+        }
+        // NOTE(Fermin): One extra pixel for the anti-aliased edge
+        reach += 1.0;
+        let vertex_bounds = Rect {
+            min_x: (vertex.pos.x - reach).floor() as i32,
+            min_y: (vertex.pos.y - reach).floor() as i32,
+            max_x: (vertex.pos.x + reach).ceil() as i32 + 1,
+            max_y: (vertex.pos.y + reach).ceil() as i32 + 1,
+        };
+        bounds = if index == 0 {
+            vertex_bounds
+        } else {
+            bounds.union(&vertex_bounds)
+        };
+    }
+    bounds
+}
+
+pub fn draw_line<T: RenderTarget>(
+    from: &LineVertex,
+    to: &LineVertex,
+    style: &LineStyle,
+    buffer: &mut T,
+) {
+    draw_polyline(&[*from, *to], style, buffer);
+}
+
+// NOTE(Fermin): Clipped to the buffer, a pixel covered by more than one
+// segment or join is only blended once with the strongest of them. Butt
+// ends, vertices on top of the previous one are skipped.
+pub fn draw_polyline<T: RenderTarget>(vertices: &[LineVertex], style: &LineStyle, buffer: &mut T) {
+    if style.thickness <= 0.0 || clip_to_target(&line_bounds(vertices, style), buffer).is_empty() {
+        return;
+    }
+
+    let mut distinct: Vec<LineVertex> = Vec::with_capacity(vertices.len());
+    for vertex in vertices {
+        if distinct
+            .last()
+            .is_none_or(|last| v2_length_sq(vertex.pos - last.pos) > 1e-6)
+        {
+            distinct.push(*vertex);
+        }
+    }
+    if distinct.len() < 2 {
+        return;
+    }
+
+    let mut samples = LineSamples::new(buffer);
+    let segment_count = distinct.len() - 1;
+    for segment in 0..segment_count {
+        let (from, to) = (&distinct[segment], &distinct[segment + 1]);
+        if style.thickness * from.width.max(to.width) <= 1.0 {
+            wu_segment(from, to, style.thickness, &mut samples);
+        } else {
+            // NOTE(Fermin): Only the ends of the polyline get anti-aliased,
+            // inner ends meet their join.
+            let soft_start = segment == 0;
+            let soft_end = segment + 1 == segment_count;
+            thick_segment(
+                from,
+                to,
+                style.thickness,
+                soft_start,
+                soft_end,
+                &mut samples,
+            );
+        }
+    }
+    for corner in distinct.windows(3) {
+        let half = style.thickness * corner[1].width * 0.5;
+        if half > 0.5 {
+            draw_join(
+                &corner[0],
+                &corner[1],
+                &corner[2],
+                half,
+                style.join,
+                &mut samples,
+            );
+        }
+    }
+
+    samples.blend_into(buffer, style.blend_mode);
+}
+
+// NOTE(Fermin): Xiaolin Wu's line, two pixels per step along the major axis
+// split by how far the line is from each of them. Steps outside the buffer
+// are skipped, so lines far away cost nothing.
+fn wu_segment(from: &LineVertex, to: &LineVertex, thickness: f32, samples: &mut LineSamples) {
+    // NOTE(Fermin): Wu puts pixel centers on whole coordinates
+    let center = V2 { x: 0.5, y: 0.5 };
+    let (mut a, mut b) = (from.pos - center, to.pos - center);
+    let steep = (b.y - a.y).abs() > (b.x - a.x).abs();
+    if steep {
+        a = V2 { x: a.y, y: a.x };
+        b = V2 { x: b.y, y: b.x };
+    }
+    let reversed = a.x > b.x;
+    if reversed {
+        std::mem::swap(&mut a, &mut b);
+    }
+    let dx = b.x - a.x;
+    if dx <= 0.0 {
+        return;
+    }
+    let gradient = (b.y - a.y) / dx;
+
+    let (start_x, end_x) = (a.x.round(), b.x.round());
+    let major_limit = if steep { samples.height } else { samples.width };
+    let first = start_x.max(-1.0) as i32;
+    let last = end_x.min(major_limit as f32) as i32;
+    for x in first..=last {
+        let xf = x as f32;
+        // NOTE(Fermin): End pixels only get the part of them the line reaches
+        let gap = if xf == start_x && xf == end_x {
+            dx
+        } else if xf == start_x {
+            1.0 - (a.x + 0.5).fract()
+        } else if xf == end_x {
+            (b.x + 0.5).fract()
+        } else {
+            1.0
+        };
+        let y = a.y + gradient * (xf - a.x);
+        let (y_floor, y_fract) = (y.floor(), y - y.floor());
+        let mut t = ((xf - a.x) / dx).clamp(0.0, 1.0);
+        if reversed {
+            t = 1.0 - t;
+        }
+
+        let width = thickness * lerp(from.width, t, to.width);
+        for (minor, coverage) in [
+            (y_floor as i32, 1.0 - y_fract),
+            (y_floor as i32 + 1, y_fract),
+        ] {
+            let (px, py) = if steep { (minor, x) } else { (x, minor) };
+            samples.plot(px, py, coverage * gap * width, from, t, to);
+        }
+    }
+}
+
+fn thick_segment(
+    from: &LineVertex,
+    to: &LineVertex,
+    thickness: f32,
+    soft_start: bool,
+    soft_end: bool,
+    samples: &mut LineSamples,
+) {
+    let direction = to.pos - from.pos;
+    let length = v2_length(direction);
+    let axis = direction / length;
+    let normal = v2_perp(axis);
+    let (from_half, to_half) = (thickness * from.width * 0.5, thickness * to.width * 0.5);
+
+    // NOTE(Fermin): One pixel bigger all around for the anti-aliased edges
+    let along = axis;
+    let from_across = normal * (from_half + 1.0);
+    let to_across = normal * (to_half + 1.0);
+    let corners = [
+        from.pos - along - from_across,
+        from.pos - along + from_across,
+        to.pos + along + to_across,
+        to.pos + along - to_across,
+    ];
+    samples.scan(&corners, from, to, |pixel| {
+        let offset = pixel - from.pos;
+        let distance_along = v2_dot(offset, axis);
+        let distance_across = v2_dot(offset, normal).abs();
+        let start = if soft_start {
+            (distance_along + 0.5).clamp(0.0, 1.0)
+        } else if distance_along >= 0.0 {
+            1.0
+        } else {
+            0.0
+        };
+        let end = if soft_end {
+            (length - distance_along + 0.5).clamp(0.0, 1.0)
+        } else if distance_along < length {
+            1.0
+        } else {
+            0.0
+        };
+        let t = (distance_along / length).clamp(0.0, 1.0);
+        let half = lerp(from_half, t, to_half);
+        let side = (half + 0.5 - distance_across).clamp(0.0, 1.0);
+        (side * start * end, t)
+    });
+}
+
+// NOTE(Fermin): Fills the wedge between two thick segments meeting at vertex
+fn draw_join(
+    previous: &LineVertex,
+    vertex: &LineVertex,
+    next: &LineVertex,
+    half: f32,
+    join: LineJoin,
+    samples: &mut LineSamples,
+) {
+    let in_axis = v2_normalize(vertex.pos - previous.pos);
+    let out_axis = v2_normalize(next.pos - vertex.pos);
+    let turn = in_axis.x * out_axis.y - in_axis.y * out_axis.x;
+    if turn.abs() < 1e-6 && v2_dot(in_axis, out_axis) > 0.0 {
+        return;
+    }
+
+    let reach =
+        half * if join == LineJoin::Miter {
+            MITER_LIMIT
+        } else {
+            1.0
+        } + 1.0;
+    let box_corners = [
+        vertex.pos
+            + V2 {
+                x: -reach,
+                y: -reach,
+            },
+        vertex.pos
+            + V2 {
+                x: reach,
+                y: -reach,
+            },
+        vertex.pos + V2 { x: reach, y: reach },
+        vertex.pos
+            + V2 {
+                x: -reach,
+                y: reach,
+            },
+    ];
+    if join == LineJoin::Round {
+        samples.scan(&box_corners, vertex, vertex, |pixel| {
+            let distance = v2_length(pixel - vertex.pos);
+            ((half + 0.5 - distance).clamp(0.0, 1.0), 0.0)
+        });
+        return;
+    }
+
+    // NOTE(Fermin): The outside of the bend is opposite to where it turns
+    let side = if turn > 0.0 { -1.0 } else { 1.0 };
+    let in_normal = v2_perp(in_axis) * side;
+    let out_normal = v2_perp(out_axis) * side;
+    let outer_in = vertex.pos + in_normal * half;
+    let outer_out = vertex.pos + out_normal * half;
+
+    let mut corners = vec![vertex.pos, outer_in];
+    if join == LineJoin::Miter {
+        let miter = v2_normalize(in_normal + out_normal);
+        let cos_half_angle = v2_dot(miter, in_normal);
+        if cos_half_angle > 1.0 / MITER_LIMIT {
+            corners.push(vertex.pos + miter * (half / cos_half_angle));
+        }
+    }
+    corners.push(outer_out);
+
+    // NOTE(Fermin): The first and last edges lie on the segments' ends
+    let last_edge = corners.len() - 1;
+    samples.scan(&box_corners, vertex, vertex, |pixel| {
+        let coverage = polygon_coverage(pixel, &corners, |edge| edge != 0 && edge != last_edge);
+        (coverage, 0.0)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hdr::{HdrBuffer, HDR_CHANNELS};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    const WHITE: Color = Color::rgba(255, 255, 255, 255);
+
+    fn red_at(buffer: &HdrBuffer, x: i32, y: i32) -> f32 {
+        buffer.bits[(x + y * buffer.width) as usize * HDR_CHANNELS]
+    }
+
+    #[test]
+    fn widths_taper_and_stay_in_bounds() {
+        let mut buffer = HdrBuffer::new(64, 32);
+        let vertices = [
+            LineVertex {
+                width: 3.0,
+                ..vertex(8.0, 16.0, WHITE, 1.0)
+            },
+            LineVertex {
+                width: 0.0,
+                ..vertex(56.0, 16.0, WHITE, 1.0)
+            },
+        ];
+        let tapered = style(4.0, LineJoin::Round);
+        draw_polyline(&vertices, &tapered, &mut buffer);
+
+        // NOTE(Fermin): Wide where it starts, gone where it ends
+        assert_eq!(red_at(&buffer, 10, 20), 1.0);
+        assert_eq!(red_at(&buffer, 50, 20), 0.0);
+        assert!(red_at(&buffer, 50, 16) > 0.0);
+
+        let bounds = line_bounds(&vertices, &tapered);
+        for y in 0..buffer.height {
+            for x in 0..buffer.width {
+                if red_at(&buffer, x, y) > 0.0 {
+                    assert!(bounds.contains(x, y), "{}, {}", x, y);
+                }
+            }
+        }
+    }
+
+    fn vertex(x: f32, y: f32, color: Color, alpha: f32) -> LineVertex {
+        LineVertex {
+            pos: V2 { x, y },
+            width: 1.0,
+            color,
+            alpha,
+            intensity: 1.0,
+        }
+    }
+
+    fn style(thickness: f32, join: LineJoin) -> LineStyle {
+        LineStyle {
+            thickness,
+            join,
+            blend_mode: BlendMode::Normal,
+        }
+    }
+
+    #[test]
+    fn wu_lines_cover_one_pixel_per_column() {
+        let mut buffer = HdrBuffer::new(32, 32);
+        let thin = style(1.0, LineJoin::Miter);
+        draw_line(
+            &vertex(2.0, 10.5, WHITE, 1.0),
+            &vertex(30.0, 10.5, WHITE, 1.0),
+            &thin,
+            &mut buffer,
+        );
+        for x in 3..29 {
+            assert_eq!(red_at(&buffer, x, 10), 1.0);
+            assert_eq!(red_at(&buffer, x, 9), 0.0);
+            assert_eq!(red_at(&buffer, x, 11), 0.0);
+        }
+
+        let mut buffer = HdrBuffer::new(32, 32);
+        draw_line(
+            &vertex(1.5, 2.5, WHITE, 1.0),
+            &vertex(29.5, 16.5, WHITE, 1.0),
+            &thin,
+            &mut buffer,
+        );
+        for x in 2..29 {
+            let column: f32 = (0..32).map(|y| red_at(&buffer, x, y)).sum();
+            assert!((column - 1.0).abs() < 1e-4, "{}: {}", x, column);
+        }
+    }
+
+    #[test]
+    fn joins_fill_the_outside_of_bends() {
+        let corner = |join| {
+            let mut buffer = HdrBuffer::new(64, 64);
+            draw_polyline(
+                &[
+                    vertex(10.0, 40.0, WHITE, 1.0),
+                    vertex(40.0, 40.0, WHITE, 1.0),
+                    vertex(40.0, 10.0, WHITE, 1.0),
+                ],
+                &style(8.0, join),
+                &mut buffer,
+            );
+            (red_at(&buffer, 42, 42), red_at(&buffer, 43, 43))
+        };
+
+        assert_eq!(corner(LineJoin::Miter), (1.0, 1.0));
+        let (inside, outside) = corner(LineJoin::Round);
+        assert!(inside > 0.9 && outside == 0.0);
+        assert_eq!(corner(LineJoin::Bevel), (0.0, 0.0));
+    }
+
+    #[test]
+    fn polylines_blend_every_pixel_once() {
+        for join in [LineJoin::Miter, LineJoin::Bevel, LineJoin::Round] {
+            let mut buffer = HdrBuffer::new(64, 64);
+            draw_polyline(
+                &[
+                    vertex(4.0, 20.0, WHITE, 0.5),
+                    vertex(30.0, 20.0, WHITE, 0.5),
+                    vertex(60.0, 20.0, WHITE, 0.5),
+                    vertex(40.0, 50.0, WHITE, 0.5),
+                    vertex(10.0, 30.0, WHITE, 0.5),
+                ],
+                &style(6.0, join),
+                &mut buffer,
+            );
+
+            // NOTE(Fermin): No seam where straight segments meet
+            for x in 26..34 {
+                assert_eq!(red_at(&buffer, x, 19), 0.5);
+            }
+            assert!(buffer.bits.iter().all(|&channel| channel <= 0.5));
+        }
+    }
+
+    #[test]
+    fn colors_lerp_between_vertices() {
+        let mut buffer = HdrBuffer::new(64, 16);
+        let red = Color::rgba(255, 0, 0, 255);
+        let blue = Color::rgba(0, 0, 255, 255);
+        draw_line(
+            &vertex(2.0, 8.0, red, 1.0),
+            &vertex(62.0, 8.0, blue, 1.0),
+            &style(4.0, LineJoin::Miter),
+            &mut buffer,
+        );
+
+        let pixel = |x: i32| {
+            let index = (x + 8 * buffer.width) as usize * HDR_CHANNELS;
+            (buffer.bits[index], buffer.bits[index + 2])
+        };
+        let (start_red, start_blue) = pixel(4);
+        let (end_red, end_blue) = pixel(60);
+        assert!(start_red > start_blue);
+        assert!(end_blue > end_red);
+    }
+
+    #[test]
+    fn blend_modes_combine_with_the_buffer() {
+        let draw = |blend_mode| {
+            let mut buffer = HdrBuffer::new(16, 16);
+            buffer.bits.fill(0.25);
+            draw_line(
+                &vertex(0.0, 8.0, WHITE, 1.0),
+                &vertex(16.0, 8.0, WHITE, 1.0),
+                &LineStyle {
+                    thickness: 4.0,
+                    join: LineJoin::Miter,
+                    blend_mode,
+                },
+                &mut buffer,
+            );
+            red_at(&buffer, 8, 8)
+        };
+
+        assert_eq!(draw(BlendMode::Normal), 1.0);
+        assert_eq!(draw(BlendMode::Add), 1.25);
+        assert_eq!(draw(BlendMode::Multiply), 0.25);
+        assert_eq!(draw(BlendMode::Screen), 1.0);
+    }
+
+    #[test]
+    fn lines_fuzzed_far_outside_the_buffer() {
+        let mut rng = StdRng::seed_from_u64(38);
+        let random_position = |rng: &mut StdRng| {
+            if rng.gen_bool(0.5) {
+                V2 {
+                    x: rng.gen_range(-100.0..164.0),
+                    y: rng.gen_range(-100.0..148.0),
+                }
+            } else {
+                V2 {
+                    x: rng.gen_range(-1.0e6..1.0e6),
+                    y: rng.gen_range(-1.0e6..1.0e6),
+                }
+            }
+        };
+
+        for _ in 0..500 {
+            let mut buffer = HdrBuffer::new(64, 48);
+            let vertices: Vec<LineVertex> = (0..rng.gen_range(1..5))
+                .map(|_| {
+                    let pos = random_position(&mut rng);
+                    vertex(pos.x, pos.y, WHITE, 0.5)
+                })
+                .collect();
+            let join = [LineJoin::Miter, LineJoin::Bevel, LineJoin::Round][rng.gen_range(0..3)];
+            let line_style = style(rng.gen_range(0.2..12.0), join);
+            draw_polyline(&vertices, &line_style, &mut buffer);
+
+            let bounds = line_bounds(&vertices, &line_style);
+            for y in 0..buffer.height {
+                for x in 0..buffer.width {
+                    let value = red_at(&buffer, x, y);
+                    assert!(value <= 0.5);
+                    if value > 0.0 {
+                        assert!(bounds.contains(x, y), "{}, {}", x, y);
+                    }
+                }
+            }
+        }
+    }
+}
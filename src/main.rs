@@ -1,414 +1,651 @@
 #![windows_subsystem = "windows"]
 
+mod backdrop;
+mod bindings;
+mod bloom;
+mod bmp;
+mod color;
+mod compositor;
+mod constellations;
+mod deflate;
+mod dirty_rects;
+mod gif;
+mod hdr;
+mod image;
+mod input;
+mod interaction;
+mod lines;
+mod math;
+mod meteors;
+mod nebula;
+mod pixel_buffer;
+mod png;
+mod quantize;
+mod render;
+mod replay;
+mod screenshot;
+mod sim_clock;
+mod spatial_grid;
+mod terminal;
+mod trails;
+mod video;
+#[cfg(windows)]
 mod window;
 
+use crate::backdrop::*;
+use crate::bindings::*;
+use crate::bloom::*;
+use crate::color::*;
+use crate::compositor::*;
+use crate::constellations::*;
+use crate::dirty_rects::*;
+use crate::gif::*;
+use crate::hdr::*;
+use crate::image::*;
+use crate::input::*;
+use crate::interaction::*;
+use crate::lines::*;
+use crate::math::*;
+use crate::meteors::*;
+use crate::nebula::*;
+use crate::pixel_buffer::*;
+use crate::quantize::*;
+use crate::render::*;
+use crate::replay::*;
+use crate::screenshot::*;
+use crate::sim_clock::*;
+use crate::spatial_grid::*;
+use crate::terminal::*;
+use crate::trails::*;
+use crate::video::*;
+#[cfg(windows)]
 use crate::window::*;
-use rand::Rng;
-//use std::fs::read;
-use std::time::{Duration, Instant};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs::{create_dir_all, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+#[cfg(windows)]
 use windows::{ core::Result, s };
 
-pub const BYTES_PER_PIXEL: i32 = 4;
 const NUMBER_OF_STARS: i32 = 60;
-const BACKGROUND_COLOR: Color = Color {
-    r: 64,
-    g: 18,
-    b: 139,
-    a: 255,
-};
-const STAR_COLOR: Color = Color {
-    r: 249,
-    g: 217,
-    b: 73,
-    a: 255,
-};
+const BACKGROUND_COLOR: Color = Color::rgba(64, 18, 139, 255);
+// NOTE(Fermin): Stars draw into their own layer, anything they don't cover
+// stays transparent (or black in the hdr buffer) and shows the background.
+const CLEAR_COLOR: Color = Color::rgba(0, 0, 0, 0);
+const BACKGROUND_LAYER: &str = "background";
+const STARS_LAYER: &str = "stars";
 const MAX_STAR_RADIUS: i32 = 12;
 const MIN_STAR_RADIUS: i32 = 2;
+// NOTE(Fermin): Pixels per second a star falls for each pixel of radius
+const STAR_SPEED_PER_RADIUS: f32 = 4.0;
+// NOTE(Fermin): Instead of erasing stars every frame the whole stars layer
+// fades out, leaving trails behind moving stars. --long-exposure turns it on.
+const LONG_EXPOSURE: bool = false;
+// NOTE(Fermin): Fraction of a trail that is still there after one second
+const TRAIL_DECAY_PER_SECOND: f32 = 0.1;
+// NOTE(Fermin): Set to false to draw straight into the window buffer
+const USE_HDR_BUFFER: bool = true;
+const STAR_INTENSITY: f32 = 2.5;
+const TONE_MAPPER: ToneMapper = ToneMapper::Aces;
+const EXPOSURE: f32 = 1.0;
+// NOTE(Fermin): Bloom only runs when drawing into the hdr buffer
+const USE_BLOOM: bool = true;
+const BLOOM_THRESHOLD: f32 = 1.0;
+const BLOOM_INTENSITY: f32 = 0.6;
+const BLOOM_RADIUS: i32 = 4;
+const BLOOM_LEVELS: usize = 4;
+// NOTE(Fermin): How far the glow can reach outside of a star, in pixels
+const BLOOM_REACH: i32 = (BLOOM_RADIUS + 1) << BLOOM_LEVELS;
+// NOTE(Fermin): Debug, a line from every star to where it will be in
+// VELOCITY_VECTOR_SECONDS. The toggle_overlay binding flips it.
+const DRAW_VELOCITY_VECTORS: bool = false;
+const VELOCITY_VECTOR_SECONDS: f32 = 1.0;
+const VELOCITY_VECTOR_COLOR: Color = Color::rgba(80, 255, 120, 255);
+const VELOCITY_VECTOR_STYLE: LineStyle = LineStyle {
+    thickness: 1.0,
+    join: LineJoin::Miter,
+    blend_mode: BlendMode::Add,
+};
+const METEOR_SETTINGS: MeteorSettings = MeteorSettings {
+    shooting_stars_per_second: 0.25,
+    comets_per_second: 0.01,
+    shooting_star_color: Color::rgba(236, 242, 255, 255),
+    comet_color: Color::rgba(170, 228, 255, 255),
+    head_intensity: 3.0,
+};
+// NOTE(Fermin): Faint lines between nearby stars, None turns them off
+const CONSTELLATIONS: Option<ConstellationSettings> = Some(ConstellationSettings {
+    link_distance: 160.0,
+    max_alpha: 0.35,
+    thickness: 1.0,
+    intensity: 1.0,
+    fade_seconds: 1.0,
+});
+// NOTE(Fermin): What stars do around the cursor, None leaves them alone
+const MOUSE_INTERACTION: Option<InteractionSettings> = Some(InteractionSettings {
+    mode: InteractionMode::Repel,
+    radius: 180.0,
+    strength: 1500.0,
+    push_left_per_second: 0.05,
+    button: None,
+});
+// NOTE(Fermin): Stars are looked up by position for constellations and the
+// mouse, about the distances they get queried at works best.
+const STAR_GRID_CELL_SIZE: f32 = 160.0;
+// NOTE(Fermin): Clouds over BACKGROUND_COLOR, density 0 leaves it flat
+const NEBULA_SEED: u64 = 1987;
+const NEBULA_DENSITY: f32 = 0.45;
+const NEBULA_SCALE: f32 = 600.0;
+const NEBULA_OCTAVES: u32 = 5;
+const NEBULA_WARP: f32 = 0.8;
+// NOTE(Fermin): Above 0 the clouds drift
+const NEBULA_DRIFT_SPEED: f32 = 0.0;
+const NEBULA_DOWNSCALE: i32 = 4;
+// NOTE(Fermin): How --backdrop images cover the window
+const BACKGROUND_SCALE_MODE: ScaleMode = ScaleMode::Fill;
+const BACKGROUND_KEN_BURNS: Option<KenBurns> = Some(KenBurns {
+    zoom: 0.1,
+    pan: V2 { x: 0.03, y: 0.02 },
+    period: 60.0,
+});
+// NOTE(Fermin): Overrides DEFAULT_BINDINGS when it's there
+const BINDINGS_PATH: &str = "bindings.cfg";
+// NOTE(Fermin): Where the screenshot binding saves to. Bigger scales blow
+// every pixel up into a square, --screenshot-scale overrides it.
+const SCREENSHOT_DIRECTORY: &str = "screenshots";
+const SCREENSHOT_FORMAT: ScreenshotFormat = ScreenshotFormat::Png;
+const SCREENSHOT_SCALE: i32 = 1;
+// NOTE(Fermin): How fast the simulation runs, speed_up and speed_down go
+// through these. step_frame moves a paused one FRAME_STEP_SECONDS at a time.
+const TIME_SCALES: &[f32] = &[0.25, 0.5, 1.0, 2.0];
+const FRAME_STEP_SECONDS: f32 = 1.0 / 60.0;
+const HEADLESS_FRAMES: i32 = 600;
+const HEADLESS_FRAMES_PER_SECOND: i32 = 60;
+// NOTE(Fermin): --terminal renders this wide, as tall as the terminal's
+// shape asks for, and shrinks it down to the cells.
+const TERMINAL_BUFFER_WIDTH: i32 = 640;
+const TERMINAL_FRAMES_PER_SECOND: f32 = 30.0;
+// NOTE(Fermin): What --gif exports unless told otherwise, one loop of the
+// sky. Short loops make slow stars faster, they all have to make it
+// through at least once.
+const GIF_LOOP_SECONDS: f32 = 6.0;
+const GIF_FRAMES_PER_SECOND: f32 = 25.0;
+const GIF_SIZE: (i32, i32) = (480, 270);
+const GIF_DITHER: bool = true;
+// NOTE(Fermin): Sums of dt never land right on the end of a loop, anything
+// this close to it counts.
+const LOOP_SNAP_SECONDS: f32 = 1e-4;
 
-#[derive(Copy, Clone)]
-struct V2 {
-    x: f32,
-    y: f32,
+struct Star {
+    origin: V2,
+    radius: i32,
+    color: Color,
+    // NOTE(Fermin): Pixels per second it falls, see star_speed()
+    speed: f32,
+    // NOTE(Fermin): Velocity on top of the normal drift, from the mouse.
+    // It relaxes back to nothing over time.
+    push: V2,
+    // NOTE(Fermin): Goes up every time the star respawns or wraps around, so
+    // links to where it was don't carry over to where it comes back.
+    generation: u32,
 }
-impl std::ops::Add<V2> for V2 {
-    type Output = V2;
 
-    fn add(self, a: V2) -> V2 {
-        V2 {
-            x: self.x + a.x,
-            y: self.y + a.y,
-        }
+// NOTE(Fermin): Small stars are pale and cold, big ones warm
+fn default_palette() -> Palette {
+    Palette {
+        background: BACKGROUND_COLOR,
+        stars: Gradient::from_hex(&[(0.0, "#dbe8ff"), (0.6, "#f9d949"), (1.0, "#ffb066")]),
+        nebula: Gradient::from_hex(&[
+            (0.0, "#2a0f5c"),
+            (0.5, "#7b2fa8"),
+            (0.8, "#d14d8b"),
+            (1.0, "#ffb3c7"),
+        ]),
     }
 }
-impl std::ops::Sub<V2> for V2 {
-    type Output = V2;
 
-    fn sub(self, a: V2) -> V2 {
-        V2 {
-            x: self.x - a.x,
-            y: self.y - a.y,
-        }
-    }
-}
-fn v2_length(a: V2) -> f32 {
-    (a.x * a.x + a.y * a.y).sqrt()
+// NOTE(Fermin): What cycle_preset goes through, the default comes first
+fn palette_presets() -> Vec<Palette> {
+    vec![
+        default_palette(),
+        // NOTE(Fermin): Ice
+        Palette {
+            background: Color::rgba(8, 22, 48, 255),
+            stars: Gradient::from_hex(&[(0.0, "#9fc4ff"), (0.7, "#e6f4ff"), (1.0, "#ffffff")]),
+            nebula: Gradient::from_hex(&[
+                (0.0, "#06203a"),
+                (0.5, "#125a7a"),
+                (0.8, "#3fa6b8"),
+                (1.0, "#c8f4ff"),
+            ]),
+        },
+        // NOTE(Fermin): Embers
+        Palette {
+            background: Color::rgba(36, 8, 10, 255),
+            stars: Gradient::from_hex(&[(0.0, "#ffd9a0"), (0.6, "#ff8a3d"), (1.0, "#ff4a2e")]),
+            nebula: Gradient::from_hex(&[
+                (0.0, "#2b0a0c"),
+                (0.5, "#7a1e1a"),
+                (0.8, "#c2502a"),
+                (1.0, "#ffc07a"),
+            ]),
+        },
+    ]
 }
 
-struct Color {
-    r: u8,
-    g: u8,
-    b: u8,
-    a: u8,
+fn star_speed(radius: i32) -> f32 {
+    STAR_SPEED_PER_RADIUS * radius as f32
 }
 
-struct Star {
-    origin: V2,
-    radius: i32,
+fn star_color(palette: &Palette, radius: i32) -> Color {
+    let t = (radius - MIN_STAR_RADIUS) as f32 / (MAX_STAR_RADIUS - MIN_STAR_RADIUS) as f32;
+    palette.stars.sample(t)
 }
 
-fn draw_rectangle(
+fn draw_rectangle<T: RenderTarget>(
     pos: &V2,
     width: i32,
     height: i32,
     color: &Color,
-    buffer: &mut Win32OffscreenBuffer,
+    buffer: &mut T,
 ) {
-    let row_x_index = pos.x.clamp(0.0, buffer.width as f32 - 1.0) as i32;
-    let row_y_index = pos.y.clamp(0.0, buffer.height as f32 - 1.0) as i32;
-    let mut row: usize = (row_x_index * BYTES_PER_PIXEL + row_y_index * buffer.pitch) as usize;
-
-    for y in 0..height {
-        let mut drawn = false;
-        let mut dest_index = row;
-        for x in 0..width {
-            let pixel_x = x + pos.x as i32;
-            let pixel_y = y + pos.y as i32;
-            if pixel_y >= 0 && pixel_y < buffer.height && pixel_x >= 0 && pixel_x < buffer.width {
-                // NOTE(Fermin): Pixel -> BB GG RR AA
-                buffer.bits[dest_index] = color.b;
-                buffer.bits[dest_index + 1] = color.g;
-                buffer.bits[dest_index + 2] = color.r;
-                buffer.bits[dest_index + 3] = color.a;
-
-                drawn = true;
-                dest_index += BYTES_PER_PIXEL as usize;
-            }
-        }
-        if drawn {
-            row += (buffer.pitch) as usize;
+    let bounds = Rect::from_pos_size(pos.x.floor() as i32, pos.y.floor() as i32, width, height);
+    let clipped = clip_to_target(&bounds, buffer);
+    if clipped.is_empty() {
+        return;
+    }
+
+    let buffer_pitch = buffer.pitch();
+    for y in clipped.min_y..clipped.max_y {
+        let row = (y * buffer_pitch) as usize;
+        for x in clipped.min_x..clipped.max_x {
+            buffer.fill_pixel(row + x as usize, color);
         }
     }
 }
 
-fn lerp(a: f32, t: f32, b: f32) -> f32 {
-    // TODO(Fermin): Deal with multiple types
-    (1.0 - t) * a + t * b
-}
+fn draw_star<T: RenderTarget>(star: &Star, buffer: &mut T) {
+    let clipped = clip_to_target(&star_bounds(star), buffer);
+    if clipped.is_empty() {
+        return;
+    }
 
-/*
-fn render_bmp(
-    origin: &V2,
-    x_axis: V2,
-    y_axis: V2,
-    bmp: &LoadedBitmap,
-    buffer: &mut Win32OffscreenBuffer,
-) {
-    let max_width = buffer.width - 1;
-    let max_height = buffer.height - 1;
-    let mut x_min = max_width;
-    let mut x_max = 0;
-    let mut y_min = max_height;
-    let mut y_max = 0;
-
-    let bmp_corners = [*origin, x_axis, x_axis + y_axis - *origin, y_axis];
-    for corner in bmp_corners {
-        let floor_x = corner.x.floor() as i32;
-        let ceil_x = corner.x.ceil() as i32;
-        let floor_y = corner.y.floor() as i32;
-        let ceil_y = corner.y.ceil() as i32;
-
-        if x_min > floor_x {
-            x_min = floor_x;
-        }
-        if x_max < ceil_x {
-            x_max = ceil_x;
-        }
-        if y_min > floor_y {
-            y_min = floor_y;
-        }
-        if y_max < ceil_y {
-            y_max = ceil_y;
-        }
-    }
-
-    let mut dest_row: usize =
-        (x_min as i32 * BYTES_PER_PIXEL + y_min as i32 * buffer.pitch) as usize;
-    for y in y_min..=y_max {
-        let mut dest_index = dest_row;
-        let mut drawn = false;
-        for x in x_min..=x_max {
-            if y >= 0 && y < max_height && x >= 0 && x < max_width {
-                let u = (x_max - x) as f32 / (x_max - x_min) as f32;
-                let v = (y_max - y) as f32 / (y_max - y_min) as f32;
-                assert!(u >= 0.0 && u <= 1.0);
-                assert!(v >= 0.0 && v <= 1.0);
-
-                let texel_x = u * (bmp.width - 2) as f32;
-                let texel_y = v * (bmp.height - 2) as f32;
-
-                let texel_dx = texel_x - texel_x.floor();
-                let texel_dy = texel_y - texel_y.floor();
-
-                // NOTE(Fermin): Sub-pixel precision
-                let texel_index =
-                    bmp.data_offset + texel_x as i32 * BYTES_PER_PIXEL + texel_y as i32 * bmp.pitch;
-                let src_index_00 = texel_index as usize;
-                let src_index_01 = (texel_index + BYTES_PER_PIXEL) as usize;
-                let src_index_10 = (texel_index + bmp.pitch) as usize;
-                let src_index_11 = (texel_index + bmp.pitch + BYTES_PER_PIXEL) as usize;
-
-                let src_00_01_b = lerp(
-                    bmp.bits[src_index_00] as f32,
-                    texel_dx,
-                    bmp.bits[src_index_01] as f32,
-                );
-                let src_00_01_g = lerp(
-                    bmp.bits[src_index_00 + 1] as f32,
-                    texel_dx,
-                    bmp.bits[src_index_01 + 1] as f32,
-                );
-                let src_00_01_r = lerp(
-                    bmp.bits[src_index_00 + 2] as f32,
-                    texel_dx,
-                    bmp.bits[src_index_01 + 2] as f32,
-                );
-                let src_00_01_a = lerp(
-                    bmp.bits[src_index_00 + 3] as f32,
-                    texel_dx,
-                    bmp.bits[src_index_01 + 3] as f32,
-                );
-
-                let src_10_11_b = lerp(
-                    bmp.bits[src_index_10] as f32,
-                    texel_dx,
-                    bmp.bits[src_index_11] as f32,
-                );
-                let src_10_11_g = lerp(
-                    bmp.bits[src_index_10 + 1] as f32,
-                    texel_dx,
-                    bmp.bits[src_index_11 + 1] as f32,
-                );
-                let src_10_11_r = lerp(
-                    bmp.bits[src_index_10 + 2] as f32,
-                    texel_dx,
-                    bmp.bits[src_index_11 + 2] as f32,
-                );
-                let src_10_11_a = lerp(
-                    bmp.bits[src_index_10 + 3] as f32,
-                    texel_dx,
-                    bmp.bits[src_index_11 + 3] as f32,
-                );
-
-                let src_b = lerp(src_00_01_b, texel_dy, src_10_11_b);
-                let src_g = lerp(src_00_01_g, texel_dy, src_10_11_g);
-                let src_r = lerp(src_00_01_r, texel_dy, src_10_11_r);
-                let src_a = lerp(src_00_01_a, texel_dy, src_10_11_a);
-
-                let alpha_ratio: f32 = src_a as f32 / 255.0;
-
-                let dest_b = &mut buffer.bits[dest_index];
-                *dest_b = lerp(*dest_b as f32, alpha_ratio, src_b as f32) as u8;
-
-                let dest_g = &mut buffer.bits[dest_index + 1];
-                *dest_g = lerp(*dest_g as f32, alpha_ratio, src_g as f32) as u8;
-
-                let dest_r = &mut buffer.bits[dest_index + 2];
-                *dest_r = lerp(*dest_r as f32, alpha_ratio, src_r as f32) as u8;
-
-                let dest_a = &mut buffer.bits[dest_index + 3];
-                *dest_a = src_a as u8;
-
-                dest_index += BYTES_PER_PIXEL as usize;
-                drawn = true;
+    let buffer_pitch = buffer.pitch();
+    for y in clipped.min_y..clipped.max_y {
+        let row = (y * buffer_pitch) as usize;
+        for x in clipped.min_x..clipped.max_x {
+            let pixel_radius = v2_length(V2 { x: x as f32, y: y as f32 } - star.origin);
+            // NOTE(Fermin): We need to clamp because we are iterating on a square,
+            // so some pixels(corners) will be further away than radius of the star
+            // resulting in a negative opacity
+            let mut pixel_opacity = (1.0 - (pixel_radius / star.radius as f32)).clamp(0.0, 1.0);
+            if pixel_opacity >= 0.85 {
+                pixel_opacity = 1.0;
             }
-        }
-        if drawn {
-            dest_row += (buffer.pitch) as usize;
+
+            let src_a = (star.color.a as f32 * pixel_opacity).round();
+            let color_t = src_a / 255.0;
+
+            // NOTE(Fermin): The core of the star goes above 1.0 when
+            // drawing into an hdr target.
+            let intensity = lerp(1.0, pixel_opacity, STAR_INTENSITY);
+            buffer.blend_pixel(row + x as usize, &star.color, intensity, color_t);
         }
     }
 }
 
-struct LoadedBitmap {
-    bits: Vec<u8>,
-    height: i32,
-    width: i32,
-    pitch: i32,
-    data_offset: i32,
-}
-fn load_bitmap(file: &str) -> LoadedBitmap {
-    let bits = read(file).expect("Err: Couldnt load bitmap");
-
-    let data_offset_index = 10;
-    let data_offset: i32 = ((bits[data_offset_index + 3] as i32) << 24)
-        | ((bits[data_offset_index + 2] as i32) << 16)
-        | ((bits[data_offset_index + 1] as i32) << 8)
-        | (bits[data_offset_index] as i32);
-
-    let width_index = 18;
-    let width: i32 = ((bits[width_index + 3] as i32) << 24)
-        | ((bits[width_index + 2] as i32) << 16)
-        | ((bits[width_index + 1] as i32) << 8)
-        | (bits[width_index] as i32);
-
-    let height_index = 22;
-    let height: i32 = ((bits[height_index + 3] as i32) << 24)
-        | ((bits[height_index + 2] as i32) << 16)
-        | ((bits[height_index + 1] as i32) << 8)
-        | (bits[height_index] as i32);
-
-    let pitch = BYTES_PER_PIXEL * width;
-
-    LoadedBitmap {
-        bits,
-        height,
-        width,
-        pitch,
-        data_offset,
+fn star_bounds(star: &Star) -> Rect {
+    Rect::from_pos_size(
+        (star.origin.x - star.radius as f32).floor() as i32,
+        (star.origin.y - star.radius as f32).floor() as i32,
+        star.radius * 2,
+        star.radius * 2,
+    )
+}
+
+fn star_velocity(star: &Star) -> V2 {
+    let drift = V2 {
+        x: 0.0,
+        y: star.speed,
+    };
+    drift + star.push
+}
+
+// NOTE(Fermin): Fades out towards the tip
+fn velocity_vector(star: &Star) -> [LineVertex; 2] {
+    let vertex = |pos: V2, alpha: f32| LineVertex {
+        pos,
+        width: 1.0,
+        color: VELOCITY_VECTOR_COLOR,
+        alpha,
+        intensity: 1.0,
+    };
+    let tip = star.origin + star_velocity(star) * VELOCITY_VECTOR_SECONDS;
+    [vertex(star.origin, 1.0), vertex(tip, 0.0)]
+}
+
+// NOTE(Fermin): Everything drawn for a star, debug vectors included
+fn star_dirty_bounds(star: &Star, draw_velocity_vectors: bool) -> Rect {
+    let bounds = star_bounds(star);
+    if draw_velocity_vectors {
+        bounds.union(&line_bounds(&velocity_vector(star), &VELOCITY_VECTOR_STYLE))
+    } else {
+        bounds
     }
 }
-*/
 
-fn draw_star(star: &Star, buffer: &mut Win32OffscreenBuffer) {
-    let top_left = star.origin
-        - V2 {
-            x: star.radius as f32,
-            y: star.radius as f32,
-        };
-    let row_x_index = top_left.x.clamp(0.0, buffer.width as f32 - 1.0) as i32;
-    let row_y_index = top_left.y.clamp(0.0, buffer.height as f32 - 1.0) as i32;
-    let mut row: usize = (row_x_index * BYTES_PER_PIXEL + row_y_index * buffer.pitch) as usize;
-
-    for y in 0..star.radius * 2 {
-        let mut drawn = false;
-        let mut dest_index: usize = row;
-        for x in 0..star.radius * 2 {
-            let pixel_x = top_left.x as i32 + x;
-            let pixel_y = top_left.y as i32 + y;
-            if pixel_y >= 0 && pixel_y < buffer.height && pixel_x >= 0 && pixel_x < buffer.width {
-                let pixel_radius = v2_length( V2 { x: pixel_x as f32, y: pixel_y as f32, } - star.origin);
-                // NOTE(Fermin): We need to clamp because we are iterating on a square,
-                // so some pixels(corners) will be further away than radius of the star
-                // resulting in a negative opacity
-                let mut pixel_opacity = (1.0 - (pixel_radius / star.radius as f32)).clamp(0.0, 1.0);
-                if pixel_opacity >= 0.85 {
-                    pixel_opacity = 1.0;
-                }
+// NOTE(Fermin): Everything that moves, kept apart from GameState so it can
+// be updated while the buffers it draws into are borrowed.
+struct Sky {
+    stars: Vec<Star>,
+    meteors: Vec<Meteor>,
+    constellation: Constellation,
+    // NOTE(Fermin): Indexed like stars, rebuilt after they move every frame
+    star_grid: SpatialGrid,
+    // NOTE(Fermin): Seeded so recordings can replay the same sky
+    rng: StdRng,
+    looping: Option<SkyLoop>,
+    long_exposure: bool,
+}
 
-                // TODO(Fermin): Define star color
-                let src_b = STAR_COLOR.b as f32;
-                let src_g = STAR_COLOR.g as f32 ;
-                let src_r = STAR_COLOR.r as f32;
-                let src_a = (STAR_COLOR.a as f32 * pixel_opacity).round();
-                let color_t = src_a / 255.0;
-
-                buffer.bits[dest_index] =
-                    lerp(buffer.bits[dest_index] as f32, color_t, src_b) as u8;
-                buffer.bits[dest_index + 1] =
-                    lerp(buffer.bits[dest_index + 1] as f32, color_t, src_g) as u8;
-                buffer.bits[dest_index + 2] =
-                    lerp(buffer.bits[dest_index + 2] as f32, color_t, src_r) as u8;
-                buffer.bits[dest_index + 3] = src_a as u8;
-
-                drawn = true;
-                dest_index += BYTES_PER_PIXEL as usize;
-            }
+// NOTE(Fermin): A sky that is back where it started every seconds. Stars
+// wrap around instead of respawning, every one of them after falling its
+// own span, which can be longer than the buffer is tall. Meteors come at
+// the same times every loop.
+struct SkyLoop {
+    seconds: f32,
+    // NOTE(Fermin): Into the current loop
+    time: f32,
+    start: Vec<V2>,
+    spans: Vec<f32>,
+    meteors: Vec<(f32, Meteor)>,
+}
+
+impl SkyLoop {
+    // NOTE(Fermin): Into the loop dt_for_frame from now, and whether it
+    // ends before then.
+    fn time_after(&self, dt_for_frame: f32) -> (f32, bool) {
+        let time = self.time + dt_for_frame;
+        if time >= self.seconds - LOOP_SNAP_SECONDS {
+            (0.0, true)
+        } else {
+            (time, false)
         }
-        if drawn {
-            row += (buffer.pitch) as usize;
+    }
+
+    // NOTE(Fermin): True when a loop just ended
+    fn advance(&mut self, dt_for_frame: f32) -> bool {
+        let (time, lapped) = self.time_after(dt_for_frame);
+        self.time = time;
+        lapped
+    }
+}
+
+// NOTE(Fermin): Stars keep their speed and fall a whole number of spans in
+// seconds, where a span is at least the buffer height plus the star. Stars
+// too slow for even one get sped up. Mouse pushes don't come back, looping
+// skies go without them. Starting over, after a resize, loops from where
+// stars are now.
+fn start_sky_loop(sky: &mut Sky, width: i32, height: i32, seconds: f32) {
+    let mut spans = Vec::new();
+    for star in &mut sky.stars {
+        let shortest_span = (height + 2 * star.radius) as f32;
+        let laps = (star.speed * seconds / shortest_span).floor();
+        if laps < 1.0 {
+            star.speed = shortest_span / seconds;
+            spans.push(shortest_span);
+        } else {
+            spans.push(star.speed * seconds / laps);
         }
+        star.push = V2 { x: 0.0, y: 0.0 };
     }
+    sky.meteors.clear();
+    sky.looping = Some(SkyLoop {
+        seconds,
+        time: 0.0,
+        start: sky.stars.iter().map(|star| star.origin).collect(),
+        spans,
+        meteors: schedule_meteors(&METEOR_SETTINGS, width, height, seconds, &mut sky.rng),
+    });
 }
 
-fn update_and_render(
-    buffer: &mut Win32OffscreenBuffer,
+fn update_and_render<T: RenderTarget>(
+    buffer: &mut T,
+    dirty_region: &mut DirtyRegion,
+    palette: &Palette,
     dt_for_frame: f32,
-    stars: &mut [Star],
-    rng: &mut rand::rngs::ThreadRng,
+    input: &Input,
+    draw_velocity_vectors: bool,
+    sky: &mut Sky,
 ) {
-    for star in &mut *stars {
-        // NOTE(Fermin): Erase previouse frame's star
-        draw_rectangle(
-            &(star.origin
-                - V2 {
-                    x: star.radius as f32,
-                    y: star.radius as f32,
-                }),
-            star.radius * 2,
-            star.radius * 2,
-            &BACKGROUND_COLOR,
-            buffer,
-        );
+    let Sky {
+        stars,
+        meteors,
+        constellation,
+        star_grid,
+        rng,
+        looping,
+        long_exposure,
+    } = sky;
+
+    if *long_exposure {
+        let decay = trail_decay_for_frame(TRAIL_DECAY_PER_SECOND, dt_for_frame);
+        buffer.decay_towards(&CLEAR_COLOR, decay);
+        dirty_region.add_all();
+    }
 
-        let speed = 4.0 * star.radius as f32 * dt_for_frame;
-        star.origin.y += speed;
+    // NOTE(Fermin): Once a loop is over stars go back to exactly where they
+    // started, whatever the floats added up to.
+    let (lapped, loop_from, loop_to) = match looping {
+        Some(sky_loop) => {
+            let from = sky_loop.time;
+            let lapped = sky_loop.advance(dt_for_frame);
+            let to = if lapped { sky_loop.seconds } else { sky_loop.time };
+            (lapped, from, to)
+        }
+        None => (false, 0.0, 0.0),
+    };
+
+    for (index, star) in stars.iter_mut().enumerate() {
+        let old_bounds = star_dirty_bounds(star, draw_velocity_vectors);
+        star.origin = star.origin + star_velocity(star) * dt_for_frame;
+        if let Some(settings) = &MOUSE_INTERACTION {
+            star.push = relax_push(settings, star.push, dt_for_frame);
+        }
 
         let half_radius = (star.radius / 2) as f32;
-        if star.origin.y.round() as i32 - star.radius >= buffer.height {
-            star.radius = rng.gen_range(MIN_STAR_RADIUS..MAX_STAR_RADIUS);
-            star.origin.x = rng.gen_range(-half_radius..buffer.width as f32 -half_radius);
-            star.origin.y = -star.radius as f32;
-        }
-    }
-
-    // NOTE(Fermin): We erase in the first loop and draw in this one to avoid
-    // erasing stars that overlap
-    for star in stars {
-        /*
-        render_bmp(
-            &star.pos,
-            V2{x: star.pos.x + star.width as f32, y: star.pos.y},
-            V2{x: star.pos.x, y: star.pos.y + star.height as f32},
-            bmp,
-            buffer
+        match looping {
+            Some(sky_loop) => {
+                if lapped {
+                    star.origin = sky_loop.start[index];
+                } else if star.origin.y - star.radius as f32 >= buffer.height() as f32 {
+                    star.origin.y -= sky_loop.spans[index];
+                    star.generation = star.generation.wrapping_add(1);
+                }
+            }
+            None => {
+                if star.origin.y.round() as i32 - star.radius >= buffer.height() {
+                    star.radius = rng.gen_range(MIN_STAR_RADIUS..MAX_STAR_RADIUS);
+                    star.color = star_color(palette, star.radius);
+                    star.speed = star_speed(star.radius);
+                    star.origin.x = rng.gen_range(-half_radius..buffer.width() as f32 -half_radius);
+                    star.origin.y = -star.radius as f32;
+                    star.push = V2 { x: 0.0, y: 0.0 };
+                    star.generation = star.generation.wrapping_add(1);
+                }
+            }
+        }
+
+        // NOTE(Fermin): A respawned star is far from where it was, adding the
+        // rects separately avoids dirtying everything in between.
+        let new_bounds = star_dirty_bounds(star, draw_velocity_vectors);
+        if old_bounds.overlaps(&new_bounds) {
+            dirty_region.add(old_bounds.union(&new_bounds));
+        } else {
+            dirty_region.add(old_bounds);
+            dirty_region.add(new_bounds);
+        }
+    }
+
+    let positions: Vec<V2> = stars.iter().map(|star| star.origin).collect();
+    star_grid.update(&positions);
+
+    // NOTE(Fermin): Pushes show up in how stars move next frame
+    if let (Some(settings), None) = (&MOUSE_INTERACTION, &looping) {
+        if let Some(cursor) = interaction_cursor(settings, &input.mouse) {
+            let mut nearby = Vec::new();
+            star_grid.query_radius(cursor, settings.radius, &mut nearby);
+            for index in nearby {
+                let star = &mut stars[index];
+                star.push = star.push + cursor_force(settings, cursor, star.origin) * dt_for_frame;
+            }
+        }
+    }
+
+    if let Some(settings) = &CONSTELLATIONS {
+        let colors: Vec<Color> = stars.iter().map(|star| star.color).collect();
+        let generations: Vec<u32> = stars.iter().map(|star| star.generation).collect();
+        update_constellation(
+            constellation,
+            settings,
+            star_grid,
+            &positions,
+            &colors,
+            &generations,
+            dirty_region,
+            dt_for_frame,
         );
-        */
-        draw_star(&star, buffer);
+    }
+
+    match looping {
+        Some(sky_loop) => update_scheduled_meteors(
+            meteors,
+            &METEOR_SETTINGS,
+            &sky_loop.meteors,
+            dirty_region,
+            loop_from,
+            loop_to,
+            dt_for_frame,
+        ),
+        None => {
+            let (width, height) = (buffer.width(), buffer.height());
+            update_meteors(meteors, &METEOR_SETTINGS, dirty_region, width, height, dt_for_frame, rng);
+        }
+    }
+
+    // NOTE(Fermin): Every star and meteor gets drawn again, so clearing
+    // whatever changed can't leave holes in the ones that didn't move.
+    if !*long_exposure {
+        for rect in &dirty_region.rects {
+            draw_rectangle(
+                &V2 {
+                    x: rect.min_x as f32,
+                    y: rect.min_y as f32,
+                },
+                rect.width(),
+                rect.height(),
+                &CLEAR_COLOR,
+                buffer,
+            );
+        }
+    }
+
+    // NOTE(Fermin): Under the stars, so links look like they start at their edges
+    if let Some(settings) = &CONSTELLATIONS {
+        draw_constellation(constellation, settings, buffer);
+    }
+
+    for star in stars.iter() {
+        draw_star(star, buffer);
+        if draw_velocity_vectors {
+            let [from, to] = velocity_vector(star);
+            draw_line(&from, &to, &VELOCITY_VECTOR_STYLE, buffer);
+        }
+    }
+
+    for meteor in meteors.iter() {
+        draw_meteor(meteor, METEOR_SETTINGS.head_intensity, buffer);
     }
 }
 
-fn main() -> Result<()> {
-    // TODO(Fermin): Make buffer the same size as the window instead of 
-    // fixed values.
-    let mut window = get_window(1920, 1080, &s!("Space Drift"))
-        .ok()
-        .expect("Err: at fn call init_window");
-    
+// NOTE(Fermin): Everything the game keeps between frames. Platform layers
+// only own the buffer that gets presented.
+struct GameState {
+    palette: Palette,
+    // NOTE(Fermin): Into palette_presets()
+    preset: usize,
+    bindings: Bindings,
+    // NOTE(Fermin): Simulation time, frames come in with wall clock time
+    clock: SimClock,
+    draw_velocity_vectors: bool,
+    screenshot_scale: i32,
+    sky: Sky,
+    nebula: Nebula,
+    // NOTE(Fermin): Drawn instead of the nebula when there is one
+    backdrop: Option<Backdrop>,
+    // NOTE(Fermin): backdrop_rect() or nebula_rect() as of the last render
+    background_rect: Rect,
+    compositor: Compositor,
+    hdr_buffer: Option<HdrBuffer>,
+    bloom: Option<Bloom>,
+    dirty_region: DirtyRegion,
+}
+
+fn game_init(width: i32, height: i32, seed: u64) -> GameState {
+    let palette = default_palette();
+
     // --------------------------------------------------------------------
-    // NOTE(Fermin): Fill buffer with background color
+    // NOTE(Fermin): The background only gets drawn when it changes, stars
+    // are redrawn every frame on top of it.
     // --------------------------------------------------------------------
-    let mut dest_index: usize = 0;
-    for _y in 0..window.buffer.height {
-        for _x in 0..window.buffer.width {
-            // NOTE(Fermin): Pixel -> BB GG RR AA
-            window.buffer.bits[dest_index] = BACKGROUND_COLOR.b;
-            window.buffer.bits[dest_index + 1] = BACKGROUND_COLOR.g;
-            window.buffer.bits[dest_index + 2] = BACKGROUND_COLOR.r;
-            window.buffer.bits[dest_index + 3] = BACKGROUND_COLOR.a;
-
-            dest_index += BYTES_PER_PIXEL as usize;
-        }
+    let mut compositor = Compositor::new(width, height, PixelFormat::Bgra8);
+    compositor.push_layer(BACKGROUND_LAYER, BlendMode::Normal, true);
+    if USE_HDR_BUFFER {
+        // NOTE(Fermin): Tone mapped stars come out on black, adding them
+        // lets the background show through.
+        compositor.push_layer(STARS_LAYER, BlendMode::Add, false);
+    } else {
+        compositor.push_layer(STARS_LAYER, BlendMode::Normal, false);
     }
 
+    let nebula = Nebula::new(NebulaSettings {
+        seed: NEBULA_SEED,
+        density: NEBULA_DENSITY,
+        scale: NEBULA_SCALE,
+        octaves: NEBULA_OCTAVES,
+        warp: NEBULA_WARP,
+        drift_speed: NEBULA_DRIFT_SPEED,
+        downscale: NEBULA_DOWNSCALE,
+    });
+
     // --------------------------------------------------------------------
-    // NOTE(Fermin): Load test bitmap. This bitmap is not used since we draw 
-    // stars now. I'll leave it for now in case we load something later.
+    // NOTE(Fermin): Stars are drawn into an hdr buffer that gets tone mapped
+    // into the stars layer at the end of every frame.
     // --------------------------------------------------------------------
-    //let bmp = load_bitmap("art/star.bmp");
+    let mut hdr_buffer: Option<HdrBuffer> = None;
+    if USE_HDR_BUFFER {
+        hdr_buffer = Some(HdrBuffer::new(width, height));
+    }
+
+    let mut bloom: Option<Bloom> = None;
+    if USE_HDR_BUFFER && USE_BLOOM {
+        bloom = Some(Bloom::new(
+            width,
+            height,
+            BloomSettings {
+                threshold: BLOOM_THRESHOLD,
+                intensity: BLOOM_INTENSITY,
+                radius: BLOOM_RADIUS,
+                levels: BLOOM_LEVELS,
+            },
+        ));
+    }
 
     // --------------------------------------------------------------------
     // NOTE(Fermin): Create collection of stars
     // --------------------------------------------------------------------
-    let mut rng: rand::rngs::ThreadRng = rand::thread_rng();
+    let mut rng = StdRng::seed_from_u64(seed);
 
     let mut stars: Vec<Star> = Vec::new();
     for _star in 0..NUMBER_OF_STARS {
@@ -416,13 +653,573 @@ fn main() -> Result<()> {
         let half_radius = (radius / 2) as f32;
         stars.push(Star {
             origin: V2 {
-                x: rng.gen_range(-half_radius..window.buffer.width as f32 - half_radius),
-                y: rng.gen_range(-radius as f32..(window.buffer.height - radius) as f32),
+                x: rng.gen_range(-half_radius..width as f32 - half_radius),
+                y: rng.gen_range(-radius as f32..(height - radius) as f32),
             },
             radius,
+            color: star_color(&palette, radius),
+            speed: star_speed(radius),
+            push: V2 { x: 0.0, y: 0.0 },
+            generation: 0,
         })
     }
 
+    // --------------------------------------------------------------------
+    // NOTE(Fermin): Only what changed gets flattened and presented, new
+    // layers mark everything as changed for the first frame.
+    // --------------------------------------------------------------------
+    let mut dirty_region = DirtyRegion::new(width, height);
+    if bloom.is_some() {
+        dirty_region.margin = BLOOM_REACH;
+    }
+
+    let bindings = match load_bindings(BINDINGS_PATH) {
+        Ok(bindings) => bindings,
+        Err(BindingsError::Io(error)) if error.kind() == std::io::ErrorKind::NotFound => {
+            Bindings::default()
+        }
+        Err(error) => {
+            eprintln!("{}, using the default bindings", error);
+            Bindings::default()
+        }
+    };
+
+    GameState {
+        palette,
+        preset: 0,
+        bindings,
+        clock: SimClock::new(TIME_SCALES, FRAME_STEP_SECONDS),
+        draw_velocity_vectors: DRAW_VELOCITY_VECTORS,
+        screenshot_scale: SCREENSHOT_SCALE,
+        sky: Sky {
+            stars,
+            meteors: Vec::new(),
+            constellation: Constellation::new(),
+            star_grid: SpatialGrid::new(STAR_GRID_CELL_SIZE),
+            rng,
+            looping: None,
+            long_exposure: LONG_EXPOSURE,
+        },
+        nebula,
+        backdrop: None,
+        background_rect: Rect::from_pos_size(0, 0, 0, 0),
+        compositor,
+        hdr_buffer,
+        bloom,
+        dirty_region,
+    }
+}
+
+// NOTE(Fermin): Everything sized after the buffer starts over, the static
+// layers get drawn again on the next frame.
+fn game_resize(game: &mut GameState, width: i32, height: i32) {
+    game.compositor.resize(width, height);
+    if game.hdr_buffer.is_some() {
+        game.hdr_buffer = Some(HdrBuffer::new(width, height));
+    }
+    if let Some(bloom) = &game.bloom {
+        game.bloom = Some(Bloom::new(width, height, bloom.settings));
+    }
+
+    let margin = game.dirty_region.margin;
+    game.dirty_region = DirtyRegion::new(width, height);
+    game.dirty_region.margin = margin;
+
+    if let Some(sky_loop) = &game.sky.looping {
+        let seconds = sky_loop.seconds;
+        start_sky_loop(&mut game.sky, width, height, seconds);
+    }
+}
+
+// NOTE(Fermin): What the background gets drawn at this frame. Looping skies
+// hold the nebula still, its drift never comes back, and pan ken burns a
+// whole number of times per loop.
+fn background_time(game: &GameState, dt_for_frame: f32) -> f32 {
+    let Some(sky_loop) = &game.sky.looping else {
+        return game.clock.time;
+    };
+    let (time, _) = sky_loop.time_after(dt_for_frame);
+    match game.backdrop.as_ref().and_then(|backdrop| backdrop.ken_burns.as_ref()) {
+        Some(ken_burns) => {
+            let periods = (sky_loop.seconds / ken_burns.period).round().max(1.0);
+            time * periods * ken_burns.period / sky_loop.seconds
+        }
+        None => 0.0,
+    }
+}
+
+// NOTE(Fermin): Leaves what changed in game.dirty_region, the platform layer
+// clears it once it has been presented. wall_seconds is how long the last
+// frame really took, the clock decides how much of it the sky gets.
+fn game_update_and_render(
+    game: &mut GameState,
+    buffer: &mut PixelBuffer,
+    input: &Input,
+    wall_seconds: f32,
+) {
+    let mut take_screenshot = false;
+    for action in game.bindings.triggered(input) {
+        match action {
+            Action::Pause => {
+                game.clock.toggle_pause();
+                if game.clock.is_paused() {
+                    eprintln!("Paused");
+                } else {
+                    eprintln!("Simulation speed {}x", game.clock.scale());
+                }
+            }
+            Action::SpeedUp => {
+                game.clock.speed_up();
+                eprintln!("Simulation speed {}x", game.clock.scale());
+            }
+            Action::SpeedDown => {
+                game.clock.speed_down();
+                eprintln!("Simulation speed {}x", game.clock.scale());
+            }
+            Action::StepFrame => game.clock.step(),
+            Action::ToggleOverlay => {
+                // NOTE(Fermin): Vectors that go away aren't in any star's
+                // dirty rect anymore.
+                game.draw_velocity_vectors = !game.draw_velocity_vectors;
+                game.dirty_region.add_all();
+            }
+            Action::Screenshot => take_screenshot = true,
+            Action::CyclePreset => {
+                let presets = palette_presets();
+                game.preset = (game.preset + 1) % presets.len();
+                game.palette = presets[game.preset].clone();
+                for star in &mut game.sky.stars {
+                    star.color = star_color(&game.palette, star.radius);
+                }
+                game.compositor.layer_mut(BACKGROUND_LAYER).unwrap().invalidate();
+            }
+        }
+    }
+    // NOTE(Fermin): Paused frames still go through everything below with a
+    // dt of 0, so the overlay, palette changes and presenting keep working.
+    let dt_for_frame = game.clock.advance(wall_seconds);
+    let background_time = background_time(game, dt_for_frame);
+
+    let background = game.compositor.layer_mut(BACKGROUND_LAYER).unwrap();
+    // NOTE(Fermin): Animated backgrounds only get rendered again when they
+    // moved by at least a pixel, but then right away so they don't step.
+    let (width, height) = (background.buffer.width, background.buffer.height);
+    let background_rect = match &game.backdrop {
+        Some(backdrop) => backdrop_rect(backdrop, width, height, background_time),
+        None => nebula_rect(&game.nebula.settings, width, height, background_time),
+    };
+    if background_rect != game.background_rect {
+        background.invalidate();
+    }
+    if background.needs_redraw() {
+        match &game.backdrop {
+            Some(backdrop) => render_backdrop(
+                backdrop,
+                &game.palette.background,
+                &mut background.buffer,
+                background_time,
+            ),
+            None => render_nebula(
+                &mut game.nebula,
+                &game.palette.nebula,
+                &game.palette.background,
+                &mut background.buffer,
+                background_time,
+            ),
+        }
+        background.mark_redrawn();
+        game.background_rect = background_rect;
+    }
+    if game.compositor.take_changed() {
+        game.dirty_region.add_all();
+    }
+
+    let stars_layer = game.compositor.layer_mut(STARS_LAYER).unwrap();
+    match &mut game.hdr_buffer {
+        Some(hdr) => {
+            update_and_render(
+                hdr,
+                &mut game.dirty_region,
+                &game.palette,
+                dt_for_frame,
+                input,
+                game.draw_velocity_vectors,
+                &mut game.sky,
+            );
+            let resolved = match &mut game.bloom {
+                Some(bloom) => apply_bloom(bloom, hdr),
+                None => hdr,
+            };
+            tone_map_hdr_buffer(resolved, &mut stars_layer.buffer, TONE_MAPPER, EXPOSURE);
+        }
+        None => update_and_render(
+            &mut stars_layer.buffer,
+            &mut game.dirty_region,
+            &game.palette,
+            dt_for_frame,
+            input,
+            game.draw_velocity_vectors,
+            &mut game.sky,
+        ),
+    }
+
+    game.compositor.flatten(buffer, &game.dirty_region.rects);
+
+    if take_screenshot {
+        save_timestamped_screenshot(buffer, game.screenshot_scale);
+    }
+}
+
+fn save_timestamped_screenshot(buffer: &PixelBuffer, scale: i32) {
+    let directory = Path::new(SCREENSHOT_DIRECTORY);
+    let path = directory.join(screenshot_file_name(SystemTime::now(), SCREENSHOT_FORMAT));
+    match create_dir_all(directory).and_then(|_| save_screenshot(buffer, &path, scale)) {
+        Ok(()) => eprintln!("Saved screenshot to {}", path.display()),
+        Err(error) => eprintln!("Couldn't save screenshot to {}: {}", path.display(), error),
+    }
+}
+
+// NOTE(Fermin): What can be set from the command line, everything else is
+// a const up top.
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    // NOTE(Fermin): Files to record input into and to play it back from
+    record: Option<String>,
+    replay: Option<String>,
+    // NOTE(Fermin): Where the last frame goes when the app exits, .png or .bmp
+    screenshot: Option<String>,
+    screenshot_scale: Option<i32>,
+    // NOTE(Fermin): Exports a looping gif there instead of running
+    gif: Option<String>,
+    gif_size: Option<(i32, i32)>,
+    gif_seconds: Option<f32>,
+    gif_dither: Option<bool>,
+    // NOTE(Fermin): Runs a sky that repeats every this many seconds
+    loop_seconds: Option<f32>,
+    // NOTE(Fermin): Runs headless and streams every frame there, - is stdout.
+    // Y4m is BT.709, video.rs has the ffmpeg flags that keep it that way.
+    video: Option<(VideoFormat, String)>,
+    // NOTE(Fermin): How many frames the stream gets, replays go on for as
+    // long as they were recorded
+    frames: Option<i32>,
+    // NOTE(Fermin): Draws in the terminal instead of a window
+    terminal: bool,
+    long_exposure: bool,
+    // NOTE(Fermin): Image drawn instead of the nebula, .png or .bmp
+    backdrop: Option<String>,
+}
+
+const USAGE: &str = "usage: space_drift [--record <file>] [--replay <file>] \
+                     [--screenshot <file>] [--screenshot-scale <n>] \
+                     [--loop <seconds>] [--long-exposure] [--backdrop <file>]\n       \
+                     [--y4m <file>|-] [--raw <file>|-] [--frames <n>] [--terminal]\n       \
+                     space_drift --gif <file> [--gif-size <width>x<height>] \
+                     [--gif-seconds <seconds>] [--gif-dither on|off]";
+
+// NOTE(Fermin): Gifs can't be any bigger than 65535 pixels a side, and the
+// frames get rendered into a pixel buffer that has to fit too.
+fn parse_size(size: &str) -> Option<(i32, i32)> {
+    let (width, height) = size.split_once('x')?;
+    let (width, height): (i32, i32) = (width.parse().ok()?, height.parse().ok()?);
+    let fits = |side: i32| (1..=u16::MAX as i32).contains(&side);
+    let buffer_fits = PixelBuffer::byte_len(width, height, PixelFormat::Bgra8).is_some();
+    (fits(width) && fits(height) && buffer_fits).then_some((width, height))
+}
+
+fn parse_options(args: impl IntoIterator<Item = String>) -> std::result::Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--record" => options.record = Some(value()?),
+            "--replay" => options.replay = Some(value()?),
+            "--screenshot" => options.screenshot = Some(value()?),
+            "--screenshot-scale" => {
+                let scale = value()?;
+                match scale.parse() {
+                    Ok(scale) if (1..=MAX_SCREENSHOT_SCALE).contains(&scale) => {
+                        options.screenshot_scale = Some(scale)
+                    }
+                    _ => return Err(format!("Bad screenshot scale {}", scale)),
+                }
+            }
+            "--gif" => options.gif = Some(value()?),
+            "--gif-size" => {
+                let size = value()?;
+                match parse_size(&size) {
+                    Some(size) => options.gif_size = Some(size),
+                    None => return Err(format!("Bad gif size {}", size)),
+                }
+            }
+            "--gif-seconds" => {
+                let seconds = value()?;
+                match seconds.parse::<f32>() {
+                    Ok(seconds) if seconds > 0.0 && seconds.is_finite() => {
+                        options.gif_seconds = Some(seconds)
+                    }
+                    _ => return Err(format!("Bad gif length {}", seconds)),
+                }
+            }
+            "--gif-dither" => {
+                let dither = value()?;
+                match dither.as_str() {
+                    "on" => options.gif_dither = Some(true),
+                    "off" => options.gif_dither = Some(false),
+                    _ => return Err(format!("--gif-dither is on or off, not {}", dither)),
+                }
+            }
+            "--loop" => {
+                let seconds = value()?;
+                match seconds.parse::<f32>() {
+                    Ok(seconds) if seconds > 0.0 && seconds.is_finite() => {
+                        options.loop_seconds = Some(seconds)
+                    }
+                    _ => return Err(format!("Bad loop length {}", seconds)),
+                }
+            }
+            "--y4m" => options.video = Some((VideoFormat::Y4m, value()?)),
+            "--raw" => options.video = Some((VideoFormat::RawBgra, value()?)),
+            "--frames" => {
+                let frames = value()?;
+                match frames.parse() {
+                    Ok(frames) if frames > 0 => options.frames = Some(frames),
+                    _ => return Err(format!("Bad frame count {}", frames)),
+                }
+            }
+            "--terminal" => options.terminal = true,
+            "--long-exposure" => options.long_exposure = true,
+            "--backdrop" => options.backdrop = Some(value()?),
+            _ => return Err(format!("Unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn options_or_exit() -> Options {
+    match parse_options(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n{}", error, USAGE);
+            std::process::exit(1);
+        }
+    }
+}
+
+// NOTE(Fermin): What --gif renders
+struct GifExport {
+    width: i32,
+    height: i32,
+    seconds: f32,
+    dither: bool,
+}
+
+// NOTE(Fermin): The loop gets rendered twice. The first time around settles
+// what depends on earlier frames, like links fading in and meteors from the
+// loop before, and collects the
+// colors the palette is picked from. The second one gets written, its last
+// frame leads right back into its first.
+fn export_gif<W: Write>(writer: W, export: &GifExport, seed: u64) -> std::io::Result<W> {
+    let mut game = game_init(export.width, export.height, seed);
+    start_sky_loop(&mut game.sky, export.width, export.height, export.seconds);
+    let frames = ((export.seconds * GIF_FRAMES_PER_SECOND).round() as usize).max(1);
+    let frames_per_second = frames as f32 / export.seconds;
+    let dt_for_frame = export.seconds / frames as f32;
+    let mut buffer = PixelBuffer::new(export.width, export.height, PixelFormat::Bgra8);
+    let input = Input::default();
+
+    let mut histogram = ColorHistogram::new();
+    for _frame in 0..frames {
+        game_update_and_render(&mut game, &mut buffer, &input, dt_for_frame);
+        game.dirty_region.clear();
+        histogram.add_buffer(&buffer);
+    }
+
+    let mut mapper = PaletteMapper::new(median_cut(&histogram, 255));
+    let mut gif = GifWriter::new(
+        writer,
+        export.width as u16,
+        export.height as u16,
+        &mapper.palette,
+    )?;
+    for frame in 0..frames {
+        game_update_and_render(&mut game, &mut buffer, &input, dt_for_frame);
+        game.dirty_region.clear();
+        let indices = mapper.map_buffer(&buffer, export.dither);
+        gif.write_frame(&indices, frame_delay(frame, frames_per_second))?;
+    }
+    gif.finish()
+}
+
+fn export_gif_or_exit(options: &Options, path: &str) {
+    let (width, height) = options.gif_size.unwrap_or(GIF_SIZE);
+    let export = GifExport {
+        width,
+        height,
+        seconds: options.gif_seconds.unwrap_or(GIF_LOOP_SECONDS),
+        dither: options.gif_dither.unwrap_or(GIF_DITHER),
+    };
+    let exported = File::create(path)
+        .and_then(|file| export_gif(BufWriter::new(file), &export, rand::random()));
+    match exported {
+        Ok(_) => eprintln!("Saved a {} second loop to {}", export.seconds, path),
+        Err(error) => {
+            eprintln!("Couldn't export to {}: {}", path, error);
+            std::process::exit(1);
+        }
+    }
+}
+
+// NOTE(Fermin): What the command line changes about a game that just started
+fn apply_options(game: &mut GameState, options: &Options) {
+    if let Some(path) = &options.backdrop {
+        match load_image(path) {
+            Ok(image) => {
+                game.backdrop = Some(Backdrop {
+                    image,
+                    scale_mode: BACKGROUND_SCALE_MODE,
+                    ken_burns: BACKGROUND_KEN_BURNS,
+                })
+            }
+            Err(error) => eprintln!("{}, drawing the nebula instead", error),
+        }
+    }
+    if let Some(scale) = options.screenshot_scale {
+        game.screenshot_scale = scale;
+    }
+    if let Some(seconds) = options.loop_seconds {
+        let (width, height) = (game.compositor.width, game.compositor.height);
+        start_sky_loop(&mut game.sky, width, height, seconds);
+    }
+    if options.long_exposure {
+        game.sky.long_exposure = true;
+    }
+    // NOTE(Fermin): stderr, stdout might be a video
+    if game.sky.long_exposure {
+        eprintln!(
+            "{}",
+            trail_preview(
+                TRAIL_DECAY_PER_SECOND,
+                STAR_SPEED_PER_RADIUS * MIN_STAR_RADIUS as f32,
+                STAR_SPEED_PER_RADIUS * MAX_STAR_RADIUS as f32,
+            )
+        );
+    }
+}
+
+// NOTE(Fermin): Where frames get their input from besides the platform and
+// where it gets recorded. While replaying the seed and size come from the
+// recording.
+struct Session {
+    seed: u64,
+    width: i32,
+    height: i32,
+    recorder: Option<InputRecorder<BufWriter<File>>>,
+    playback: Option<InputPlayback>,
+    screenshot: Option<String>,
+}
+
+fn start_session(options: &Options, width: i32, height: i32) -> Session {
+    let mut session = Session {
+        seed: rand::random(),
+        width,
+        height,
+        recorder: None,
+        playback: None,
+        screenshot: options.screenshot.clone(),
+    };
+    if let Some(path) = &options.replay {
+        match load_recording(path) {
+            Ok(playback) => {
+                session.seed = playback.seed;
+                session.width = playback.width;
+                session.height = playback.height;
+                session.playback = Some(playback);
+            }
+            Err(error) => {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = &options.record {
+        match create_recording(path, session.seed, session.width, session.height) {
+            Ok(recorder) => session.recorder = Some(recorder),
+            Err(error) => eprintln!("Couldn't record to {}: {}", path, error),
+        }
+    }
+    session
+}
+
+// NOTE(Fermin): The input and dt a frame runs with, the recorded ones while
+// replaying. None once the recording is over.
+fn session_frame(session: &mut Session, input: &Input, dt_for_frame: f32) -> Option<(Input, f32)> {
+    match &mut session.playback {
+        Some(playback) => match playback.next_frame() {
+            Ok(Some(dt_for_frame)) => Some((playback.input.clone(), dt_for_frame)),
+            Ok(None) => None,
+            Err(error) => {
+                eprintln!("{}, stopping the replay", error);
+                None
+            }
+        },
+        None => Some((input.clone(), dt_for_frame)),
+    }
+}
+
+fn session_record(session: &mut Session, width: i32, height: i32, dt_for_frame: f32, input: &Input) {
+    if let Some(recorder) = &mut session.recorder {
+        if let Err(error) = recorder.record_frame(width, height, dt_for_frame, input) {
+            eprintln!("{}, stopping the recording", error);
+            session.recorder = None;
+        }
+    }
+}
+
+// NOTE(Fermin): buffer holds the last frame. BufWriter would drop write
+// errors on the floor, finishing the recording here reports them.
+fn end_session(session: Session, buffer: &PixelBuffer, screenshot_scale: i32) {
+    if let Some(recorder) = session.recorder {
+        if let Err(error) = recorder.finish() {
+            eprintln!("Couldn't finish the recording: {}", error);
+        }
+    }
+    if let Some(path) = session.screenshot {
+        match save_screenshot(buffer, Path::new(&path), screenshot_scale) {
+            Ok(()) => eprintln!("Saved screenshot to {}", path),
+            Err(error) => eprintln!("Couldn't save screenshot to {}: {}", path, error),
+        }
+    }
+}
+
+// NOTE(Fermin): Replays only match the recording as long as the window has
+// the same size it had, the headless one always does.
+#[cfg(windows)]
+fn main() -> Result<()> {
+    let options = options_or_exit();
+    if let Some(path) = &options.gif {
+        export_gif_or_exit(&options, path);
+        return Ok(());
+    }
+    if options.terminal {
+        run_in_terminal(&options);
+        return Ok(());
+    }
+    if options.video.is_some() {
+        run_headless(&options);
+        return Ok(());
+    }
+    let mut session = start_session(&options, 1920, 1080);
+    let mut window = get_window(session.width, session.height, &s!("Space Drift"))
+        .expect("Err: at fn call init_window");
+
+    let mut game = game_init(
+        window.buffer.pixels.width,
+        window.buffer.pixels.height,
+        session.seed,
+    );
+    apply_options(&mut game, &options);
+
     // --------------------------------------------------------------------
     // NOTE(Fermin): Main loop
     // --------------------------------------------------------------------
@@ -433,12 +1230,26 @@ fn main() -> Result<()> {
         let frame_start_instant = Instant::now();
 
         win32_process_pending_messages(window.as_mut());
-        update_and_render(
-            &mut window.buffer,
-            last_frame_dur / 1000.0,
-            &mut stars,
-            &mut rng,
+        if window.buffer.pixels.width != game.compositor.width
+            || window.buffer.pixels.height != game.compositor.height
+        {
+            game_resize(&mut game, window.buffer.pixels.width, window.buffer.pixels.height);
+        }
+        let (input, dt_for_frame) =
+            match session_frame(&mut session, &window.input, last_frame_dur / 1000.0) {
+                Some(frame) => frame,
+                None => break,
+            };
+        game_update_and_render(&mut game, &mut window.buffer.pixels, &input, dt_for_frame);
+        session_record(
+            &mut session,
+            window.buffer.pixels.width,
+            window.buffer.pixels.height,
+            dt_for_frame,
+            &input,
         );
+        win32_present_buffer(window.as_mut(), &game.dirty_region);
+        game.dirty_region.clear();
 
         // --------------------------------------------------------------------
         // NOTE(Fermin): Sleep thread if necessary to sync with monitor refresh rate.
@@ -459,6 +1270,545 @@ fn main() -> Result<()> {
             last_frame_dur
         );
     }
+    end_session(session, &window.buffer.pixels, game.screenshot_scale);
 
     Ok(())
 }
+
+// NOTE(Fermin): Streams come out at HEADLESS_FRAMES_PER_SECOND, replays
+// recorded at some other rate play faster or slower in them.
+fn open_video(
+    format: VideoFormat,
+    path: &str,
+    width: i32,
+    height: i32,
+) -> std::io::Result<VideoWriter<Box<dyn Write>>> {
+    let writer: Box<dyn Write> = if path == "-" {
+        Box::new(BufWriter::new(std::io::stdout().lock()))
+    } else {
+        Box::new(BufWriter::new(File::create(path)?))
+    };
+    VideoWriter::new(writer, format, width, height, HEADLESS_FRAMES_PER_SECOND as u32)
+}
+
+// NOTE(Fermin): Runs the simulation without a window at a fixed step and
+// streams the frames out. Replays run for as long as the recording instead.
+// How long frames took on average goes to stderr at the end, stdout might
+// be the video.
+fn run_headless(options: &Options) {
+    let mut session = start_session(options, 1920, 1080);
+    let mut buffer = PixelBuffer::new(session.width, session.height, PixelFormat::Bgra8);
+    let mut game = game_init(buffer.width, buffer.height, session.seed);
+    apply_options(&mut game, options);
+
+    let mut video = None;
+    if let Some((format, path)) = &options.video {
+        match open_video(*format, path, buffer.width, buffer.height) {
+            Ok(writer) => video = Some(writer),
+            Err(error) => {
+                eprintln!("Couldn't stream video to {}: {}", path, error);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // NOTE(Fermin): Nobody to move a mouse around or press keys
+    let mut input = Input::default();
+    let dt_for_frame = 1.0 / HEADLESS_FRAMES_PER_SECOND as f32;
+    let mut frame = 0;
+    let frames = options.frames.unwrap_or(HEADLESS_FRAMES);
+    let run_start_instant = Instant::now();
+    while session.playback.is_some() || frame < frames {
+        input.begin_frame();
+        let (input, dt_for_frame) = match session_frame(&mut session, &input, dt_for_frame) {
+            Some(frame) => frame,
+            None => break,
+        };
+        frame += 1;
+        if let Some(playback) = &session.playback {
+            if playback.width != buffer.width || playback.height != buffer.height {
+                buffer = PixelBuffer::new(playback.width, playback.height, PixelFormat::Bgra8);
+                game_resize(&mut game, buffer.width, buffer.height);
+            }
+        }
+        game_update_and_render(&mut game, &mut buffer, &input, dt_for_frame);
+        session_record(&mut session, buffer.width, buffer.height, dt_for_frame, &input);
+        game.dirty_region.clear();
+
+        // NOTE(Fermin): Whatever reads the stream going away ends the run
+        if let Some(writer) = &mut video {
+            if let Err(error) = writer.write_frame(&buffer) {
+                eprintln!("Couldn't write frame {}: {}", frame, error);
+                break;
+            }
+        }
+    }
+    let frame_dur = run_start_instant.elapsed().as_secs_f32() * 1000.0 / frame.max(1) as f32;
+    eprintln!("{} frames, {} fps, {} ms/f", frame, 1000.0 / frame_dur, frame_dur);
+    if let Some(writer) = video {
+        if let Err(error) = writer.finish() {
+            eprintln!("Couldn't finish the video: {}", error);
+        }
+    }
+    end_session(session, &buffer, game.screenshot_scale);
+}
+
+// NOTE(Fermin): Cells are about half as wide as they are tall, with two
+// pixels in each the buffer has the terminal's shape. It stops at four
+// times as tall as wide and gets stretched past that, a one column terminal
+// would otherwise render a buffer thousands of pixels tall.
+fn terminal_buffer_size(columns: i32, rows: i32) -> (i32, i32) {
+    let height = (TERMINAL_BUFFER_WIDTH * rows * 2 / columns.max(1))
+        .clamp(1, TERMINAL_BUFFER_WIDTH * 4);
+    (TERMINAL_BUFFER_WIDTH, height)
+}
+
+// NOTE(Fermin): Runs until Ctrl+C, drawing into the terminal stdout goes to
+// and keeping up with its size. Replays keep the size they were recorded
+// at and get squeezed into whatever the terminal is.
+fn run_in_terminal(options: &Options) {
+    let (mut columns, mut rows) = terminal_size().unwrap_or((80, 24));
+    let (width, height) = terminal_buffer_size(columns, rows);
+    let mut session = start_session(options, width, height);
+    let mut buffer = PixelBuffer::new(session.width, session.height, PixelFormat::Bgra8);
+    let mut game = game_init(buffer.width, buffer.height, session.seed);
+    apply_options(&mut game, options);
+
+    catch_interrupts();
+    let mut stdout = std::io::stdout().lock();
+    let mut renderer = TerminalRenderer::new();
+    let mut written = stdout.write_all(TERMINAL_ENTER.as_bytes());
+
+    let mut input = Input::default();
+    let target_seconds_per_frame = 1.0 / TERMINAL_FRAMES_PER_SECOND;
+    let mut last_frame_seconds = target_seconds_per_frame;
+    while written.is_ok() && !interrupted() {
+        let frame_start_instant = Instant::now();
+        if let Some(size) = terminal_size() {
+            (columns, rows) = size;
+        }
+        let wanted = match &session.playback {
+            Some(playback) => (playback.width, playback.height),
+            None => terminal_buffer_size(columns, rows),
+        };
+        if wanted != (buffer.width, buffer.height) {
+            buffer = PixelBuffer::new(wanted.0, wanted.1, PixelFormat::Bgra8);
+            game_resize(&mut game, buffer.width, buffer.height);
+        }
+
+        input.begin_frame();
+        let (input, dt_for_frame) = match session_frame(&mut session, &input, last_frame_seconds) {
+            Some(frame) => frame,
+            None => break,
+        };
+        game_update_and_render(&mut game, &mut buffer, &input, dt_for_frame);
+        session_record(&mut session, buffer.width, buffer.height, dt_for_frame, &input);
+        game.dirty_region.clear();
+
+        written = stdout
+            .write_all(renderer.render(&buffer, columns, rows))
+            .and_then(|_| stdout.flush());
+
+        let elapsed = frame_start_instant.elapsed().as_secs_f32();
+        if elapsed < target_seconds_per_frame {
+            std::thread::sleep(Duration::from_secs_f32(target_seconds_per_frame - elapsed));
+        }
+        last_frame_seconds = frame_start_instant.elapsed().as_secs_f32();
+    }
+
+    let left = stdout
+        .write_all(TERMINAL_LEAVE.as_bytes())
+        .and_then(|_| stdout.flush());
+    if let Err(error) = written.and(left) {
+        eprintln!("Couldn't draw in the terminal: {}", error);
+    }
+    end_session(session, &buffer, game.screenshot_scale);
+}
+
+// NOTE(Fermin): There is no window backend for other platforms yet
+#[cfg(not(windows))]
+fn main() {
+    let options = options_or_exit();
+    if let Some(path) = &options.gif {
+        export_gif_or_exit(&options, path);
+        return;
+    }
+    if options.terminal {
+        run_in_terminal(&options);
+        return;
+    }
+    run_headless(&options);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // NOTE(Fermin): Counts how many times every pixel gets written to, and
+    // panics on any index outside of the target.
+    struct CountingTarget {
+        width: i32,
+        height: i32,
+        writes: Vec<u32>,
+    }
+
+    impl CountingTarget {
+        fn new(width: i32, height: i32) -> CountingTarget {
+            CountingTarget {
+                width,
+                height,
+                writes: vec![0; (width * height) as usize],
+            }
+        }
+
+        fn written_rect(&self) -> Rect {
+            let mut written = Rect::from_pos_size(self.width, self.height, -self.width, -self.height);
+            for (index, &count) in self.writes.iter().enumerate() {
+                if count > 0 {
+                    let x = index as i32 % self.width;
+                    let y = index as i32 / self.width;
+                    written = written.union(&Rect::from_pos_size(x, y, 1, 1));
+                }
+            }
+            written
+        }
+    }
+
+    impl RenderTarget for CountingTarget {
+        fn width(&self) -> i32 {
+            self.width
+        }
+
+        fn height(&self) -> i32 {
+            self.height
+        }
+
+        fn pitch(&self) -> i32 {
+            self.width
+        }
+
+        fn fill_pixel(&mut self, index: usize, _color: &Color) {
+            self.writes[index] += 1;
+        }
+
+        fn blend_pixel_with(
+            &mut self,
+            index: usize,
+            _color: &Color,
+            _intensity: f32,
+            _t: f32,
+            _blend_mode: BlendMode,
+        ) {
+            self.writes[index] += 1;
+        }
+
+        fn decay_towards(&mut self, _color: &Color, _factor: f32) {}
+    }
+
+    // NOTE(Fermin): Half of the positions are around the buffer's edges, the
+    // other half anywhere far outside of it.
+    fn fuzz_position(rng: &mut StdRng) -> V2 {
+        if rng.gen_bool(0.5) {
+            V2 {
+                x: rng.gen_range(-100.0..164.0),
+                y: rng.gen_range(-100.0..148.0),
+            }
+        } else {
+            V2 {
+                x: rng.gen_range(-1.0e6..1.0e6),
+                y: rng.gen_range(-1.0e6..1.0e6),
+            }
+        }
+    }
+
+    #[test]
+    fn rectangle_partially_off_the_top_left_lands_in_place() {
+        let mut target = CountingTarget::new(16, 16);
+        draw_rectangle(&V2 { x: -3.0, y: -5.0 }, 8, 8, &BACKGROUND_COLOR, &mut target);
+
+        assert_eq!(target.written_rect(), Rect::from_pos_size(0, 0, 5, 3));
+        assert_eq!(target.writes.iter().sum::<u32>(), 5 * 3);
+    }
+
+    #[test]
+    fn rectangle_fuzzed_far_outside_the_buffer() {
+        let mut rng = StdRng::seed_from_u64(30);
+        for _ in 0..2000 {
+            let mut target = CountingTarget::new(64, 48);
+            let pos = fuzz_position(&mut rng);
+            let width = rng.gen_range(0..200);
+            let height = rng.gen_range(0..200);
+            draw_rectangle(&pos, width, height, &BACKGROUND_COLOR, &mut target);
+
+            let expected = clip_to_target(
+                &Rect::from_pos_size(pos.x.floor() as i32, pos.y.floor() as i32, width, height),
+                &target,
+            );
+            assert!(target.writes.iter().all(|&count| count <= 1));
+            assert_eq!(target.writes.iter().sum::<u32>() as i64, expected.area());
+            if !expected.is_empty() {
+                assert_eq!(target.written_rect(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn star_fuzzed_far_outside_the_buffer() {
+        let mut rng = StdRng::seed_from_u64(30);
+        for _ in 0..2000 {
+            let mut target = CountingTarget::new(64, 48);
+            let star = Star {
+                origin: fuzz_position(&mut rng),
+                radius: rng.gen_range(1..100),
+                color: BACKGROUND_COLOR,
+                speed: 0.0,
+                push: V2 { x: 0.0, y: 0.0 },
+                generation: 0,
+            };
+            draw_star(&star, &mut target);
+
+            let expected = clip_to_target(&star_bounds(&star), &target);
+            assert!(target.writes.iter().all(|&count| count <= 1));
+            assert_eq!(target.writes.iter().sum::<u32>() as i64, expected.area());
+            if !expected.is_empty() {
+                assert_eq!(target.written_rect(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn options_parse() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(parse_options(args(&[])), Ok(Options::default()));
+        assert_eq!(
+            parse_options(args(&["--replay", "a.sdrp", "--record", "b.sdrp"])),
+            Ok(Options {
+                record: Some("b.sdrp".to_string()),
+                replay: Some("a.sdrp".to_string()),
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            parse_options(args(&["--backdrop", "art/sky.png"])),
+            Ok(Options {
+                backdrop: Some("art/sky.png".to_string()),
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            parse_options(args(&["--screenshot", "a.bmp", "--screenshot-scale", "2"])),
+            Ok(Options {
+                screenshot: Some("a.bmp".to_string()),
+                screenshot_scale: Some(2),
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            parse_options(args(&["--gif", "a.gif", "--gif-size", "320x200"])),
+            Ok(Options {
+                gif: Some("a.gif".to_string()),
+                gif_size: Some((320, 200)),
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            parse_options(args(&["--gif-seconds", "2.5", "--gif-dither", "off"])),
+            Ok(Options {
+                gif_seconds: Some(2.5),
+                gif_dither: Some(false),
+                ..Options::default()
+            })
+        );
+        assert!(parse_options(args(&["--gif-size", "320"])).is_err());
+        assert!(parse_options(args(&["--gif-size", "0x10"])).is_err());
+        assert!(parse_options(args(&["--gif-size", "70000x10"])).is_err());
+        assert!(parse_options(args(&["--gif-size", "65535x65535"])).is_err());
+        assert!(parse_options(args(&["--gif-size", "65535x8191"])).is_ok());
+        assert!(parse_options(args(&["--gif-seconds", "-1"])).is_err());
+        assert!(parse_options(args(&["--gif-dither", "maybe"])).is_err());
+        assert_eq!(
+            parse_options(args(&["--loop", "8"])),
+            Ok(Options {
+                loop_seconds: Some(8.0),
+                ..Options::default()
+            })
+        );
+        assert!(parse_options(args(&["--loop", "0"])).is_err());
+        assert_eq!(
+            parse_options(args(&["--y4m", "-", "--loop", "4"])),
+            Ok(Options {
+                video: Some((VideoFormat::Y4m, "-".to_string())),
+                loop_seconds: Some(4.0),
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            parse_options(args(&["--terminal", "--loop", "4", "--long-exposure"])),
+            Ok(Options {
+                terminal: true,
+                long_exposure: true,
+                loop_seconds: Some(4.0),
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            parse_options(args(&["--raw", "a.bgra", "--frames", "1800"])),
+            Ok(Options {
+                video: Some((VideoFormat::RawBgra, "a.bgra".to_string())),
+                frames: Some(1800),
+                ..Options::default()
+            })
+        );
+        assert!(parse_options(args(&["--frames", "0"])).is_err());
+        assert!(parse_options(args(&["--screenshot-scale", "0"])).is_err());
+        assert!(parse_options(args(&["--screenshot-scale", "17"])).is_err());
+        assert!(parse_options(args(&["--screenshot-scale", "99999999999"])).is_err());
+        assert!(parse_options(args(&["--record"])).is_err());
+        assert!(parse_options(args(&["--fullscreen"])).is_err());
+    }
+
+    #[test]
+    fn terminal_buffers_have_the_terminal_shape() {
+        assert_eq!(terminal_buffer_size(80, 24), (TERMINAL_BUFFER_WIDTH, 384));
+        assert_eq!(terminal_buffer_size(0, 0), (TERMINAL_BUFFER_WIDTH, 1));
+        assert_eq!(
+            terminal_buffer_size(1, 500),
+            (TERMINAL_BUFFER_WIDTH, TERMINAL_BUFFER_WIDTH * 4)
+        );
+    }
+
+    // NOTE(Fermin): Moves the mouse around, cycles the palette and turns on
+    // the overlay, with a dt that isn't the same every frame.
+    #[test]
+    fn replays_render_the_same_frames() {
+        let (width, height, seed) = (128, 72, 43);
+        let mut recorder = InputRecorder::new(Vec::new(), seed, width, height).unwrap();
+        let mut game = game_init(width, height, seed);
+        let mut buffer = PixelBuffer::new(width, height, PixelFormat::Bgra8);
+        let mut input = Input::default();
+        let mut frames = Vec::new();
+        for frame in 0..30 {
+            input.begin_frame();
+            input.mouse.pos = Some(V2 {
+                x: frame as f32,
+                y: 45.0,
+            });
+            input.set_key(Key::Tab, frame == 8);
+            input.set_key(Key::F1, frame == 20);
+            let dt_for_frame = 1.0 / 60.0 + (frame % 3) as f32 * 0.001;
+
+            game_update_and_render(&mut game, &mut buffer, &input, dt_for_frame);
+            game.dirty_region.clear();
+            recorder
+                .record_frame(width, height, dt_for_frame, &input)
+                .unwrap();
+            frames.push(buffer.bits.clone());
+        }
+
+        let mut playback = InputPlayback::from_bytes(recorder.finish().unwrap()).unwrap();
+        let mut game = game_init(playback.width, playback.height, playback.seed);
+        let mut buffer = PixelBuffer::new(playback.width, playback.height, PixelFormat::Bgra8);
+        let mut replayed = 0;
+        while let Some(dt_for_frame) = playback.next_frame().unwrap() {
+            game_update_and_render(&mut game, &mut buffer, &playback.input, dt_for_frame);
+            game.dirty_region.clear();
+            assert!(buffer.bits == frames[replayed], "frame {} differs", replayed);
+            replayed += 1;
+        }
+        assert_eq!(replayed, frames.len());
+    }
+
+    #[test]
+    fn paused_games_keep_rendering() {
+        let mut game = game_init(128, 72, 44);
+        let mut buffer = PixelBuffer::new(128, 72, PixelFormat::Bgra8);
+        let mut input = Input::default();
+        let mut frame = |game: &mut GameState, buffer: &mut PixelBuffer, pressed: Option<Key>| {
+            input.begin_frame();
+            for key in [Key::Space, Key::Tab, Key::Period] {
+                input.set_key(key, Some(key) == pressed);
+            }
+            game_update_and_render(game, buffer, &input, 0.1);
+            game.dirty_region.clear();
+            game.sky.stars.iter().map(|star| star.origin).collect::<Vec<V2>>()
+        };
+
+        frame(&mut game, &mut buffer, None);
+        let paused_at = frame(&mut game, &mut buffer, Some(Key::Space));
+        let before = buffer.bits.clone();
+        assert_eq!(frame(&mut game, &mut buffer, None), paused_at);
+        assert_eq!(frame(&mut game, &mut buffer, Some(Key::Tab)), paused_at);
+        assert!(buffer.bits != before);
+
+        let stepped = frame(&mut game, &mut buffer, Some(Key::Period));
+        for (star, (from, to)) in game.sky.stars.iter().zip(paused_at.iter().zip(&stepped)) {
+            let fall = to.y - from.y;
+            // NOTE(Fermin): Unless it respawned at the top
+            assert!(
+                fall < 0.0
+                    || (fall - STAR_SPEED_PER_RADIUS * star.radius as f32 * FRAME_STEP_SECONDS)
+                        .abs()
+                        < 1e-3
+            );
+        }
+    }
+
+    // NOTE(Fermin): The export's own frames are checked in gif.rs, this is
+    // about stars coming back and the file hanging together.
+    #[test]
+    fn looping_skies_come_back() {
+        let (width, height) = (96, 54);
+        let mut game = game_init(width, height, 45);
+        start_sky_loop(&mut game.sky, width, height, 1.0);
+        let start: Vec<V2> = game.sky.stars.iter().map(|star| star.origin).collect();
+        for star in &game.sky.stars {
+            assert!(star.speed * 1.0 >= (height + 2 * star.radius) as f32 - 1e-3);
+        }
+
+        let mut buffer = PixelBuffer::new(width, height, PixelFormat::Bgra8);
+        let input = Input::default();
+        for frame in 1..=20 {
+            game_update_and_render(&mut game, &mut buffer, &input, 0.05);
+            game.dirty_region.clear();
+            let now: Vec<V2> = game.sky.stars.iter().map(|star| star.origin).collect();
+            assert_eq!(now == start, frame == 20, "frame {}", frame);
+        }
+        assert!(game.sky.meteors.is_empty());
+
+        let export = GifExport {
+            width: 32,
+            height: 18,
+            seconds: 0.2,
+            dither: true,
+        };
+        let gif = export_gif(Vec::new(), &export, 46).unwrap();
+        assert_eq!(&gif[..6], b"GIF89a");
+        assert_eq!(*gif.last().unwrap(), 0x3b);
+    }
+
+    // NOTE(Fermin): After a lap to settle, the frame a loop ends on is the
+    // one it started with, also after a resize starts it over.
+    #[test]
+    fn looping_frames_match() {
+        let mut game = game_init(64, 36, 47);
+        start_sky_loop(&mut game.sky, 64, 36, 4.0);
+        assert!(!game.sky.looping.as_ref().unwrap().meteors.is_empty());
+
+        let input = Input::default();
+        for (width, height) in [(64, 36), (40, 48)] {
+            if width != game.compositor.width {
+                game_resize(&mut game, width, height);
+            }
+            let mut buffer = PixelBuffer::new(width, height, PixelFormat::Bgra8);
+            let mut frames = Vec::new();
+            for _frame in 0..=40 {
+                game_update_and_render(&mut game, &mut buffer, &input, 0.2);
+                game.dirty_region.clear();
+                frames.push(buffer.bits.clone());
+            }
+            assert!(frames[20] == frames[40]);
+            assert!(frames[20..40].iter().skip(1).all(|frame| *frame != frames[20]));
+        }
+    }
+}
@@ -0,0 +1,590 @@
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+pub fn lerp(a: f32, t: f32, b: f32) -> f32 {
+    // TODO(Fermin): Deal with multiple types
+    (1.0 - t) * a + t * b
+}
+
+// --------------------------------------------------------------------
+// NOTE(Fermin): V2
+// --------------------------------------------------------------------
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct V2 {
+    pub x: f32,
+    pub y: f32,
+}
+impl Add<V2> for V2 {
+    type Output = V2;
+
+    fn add(self, a: V2) -> V2 {
+        V2 {
+            x: self.x + a.x,
+            y: self.y + a.y,
+        }
+    }
+}
+impl Sub<V2> for V2 {
+    type Output = V2;
+
+    fn sub(self, a: V2) -> V2 {
+        V2 {
+            x: self.x - a.x,
+            y: self.y - a.y,
+        }
+    }
+}
+impl Mul<f32> for V2 {
+    type Output = V2;
+
+    fn mul(self, a: f32) -> V2 {
+        V2 {
+            x: self.x * a,
+            y: self.y * a,
+        }
+    }
+}
+impl Mul<V2> for f32 {
+    type Output = V2;
+
+    fn mul(self, a: V2) -> V2 {
+        a * self
+    }
+}
+impl Div<f32> for V2 {
+    type Output = V2;
+
+    fn div(self, a: f32) -> V2 {
+        V2 {
+            x: self.x / a,
+            y: self.y / a,
+        }
+    }
+}
+impl Neg for V2 {
+    type Output = V2;
+
+    fn neg(self) -> V2 {
+        V2 {
+            x: -self.x,
+            y: -self.y,
+        }
+    }
+}
+pub fn v2_dot(a: V2, b: V2) -> f32 {
+    a.x * b.x + a.y * b.y
+}
+pub fn v2_length_sq(a: V2) -> f32 {
+    v2_dot(a, a)
+}
+pub fn v2_length(a: V2) -> f32 {
+    v2_length_sq(a).sqrt()
+}
+// NOTE(Fermin): Rotated 90 degrees counter-clockwise, in screen space (y
+// pointing down) that looks clockwise.
+pub fn v2_perp(a: V2) -> V2 {
+    V2 { x: -a.y, y: a.x }
+}
+// NOTE(Fermin): Zero length vectors stay zero instead of becoming NaN
+pub fn v2_normalize(a: V2) -> V2 {
+    let length = v2_length(a);
+    if length > 0.0 {
+        a / length
+    } else {
+        V2 { x: 0.0, y: 0.0 }
+    }
+}
+// NOTE(Fermin): Nothing moves along a straight V2 path yet
+#[allow(dead_code)]
+pub fn v2_lerp(a: V2, t: f32, b: V2) -> V2 {
+    V2 {
+        x: lerp(a.x, t, b.x),
+        y: lerp(a.y, t, b.y),
+    }
+}
+pub fn v2_rotate(a: V2, angle: f32) -> V2 {
+    let (sin, cos) = angle.sin_cos();
+    V2 {
+        x: a.x * cos - a.y * sin,
+        y: a.x * sin + a.y * cos,
+    }
+}
+
+// --------------------------------------------------------------------
+// NOTE(Fermin): V3, waiting for the 3D star projection
+// --------------------------------------------------------------------
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct V3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+impl Add<V3> for V3 {
+    type Output = V3;
+
+    fn add(self, a: V3) -> V3 {
+        V3 {
+            x: self.x + a.x,
+            y: self.y + a.y,
+            z: self.z + a.z,
+        }
+    }
+}
+impl Sub<V3> for V3 {
+    type Output = V3;
+
+    fn sub(self, a: V3) -> V3 {
+        V3 {
+            x: self.x - a.x,
+            y: self.y - a.y,
+            z: self.z - a.z,
+        }
+    }
+}
+impl Mul<f32> for V3 {
+    type Output = V3;
+
+    fn mul(self, a: f32) -> V3 {
+        V3 {
+            x: self.x * a,
+            y: self.y * a,
+            z: self.z * a,
+        }
+    }
+}
+impl Mul<V3> for f32 {
+    type Output = V3;
+
+    fn mul(self, a: V3) -> V3 {
+        a * self
+    }
+}
+impl Div<f32> for V3 {
+    type Output = V3;
+
+    fn div(self, a: f32) -> V3 {
+        V3 {
+            x: self.x / a,
+            y: self.y / a,
+            z: self.z / a,
+        }
+    }
+}
+impl Neg for V3 {
+    type Output = V3;
+
+    fn neg(self) -> V3 {
+        V3 {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+#[allow(dead_code)]
+pub fn v3_dot(a: V3, b: V3) -> f32 {
+    a.x * b.x + a.y * b.y + a.z * b.z
+}
+#[allow(dead_code)]
+pub fn v3_cross(a: V3, b: V3) -> V3 {
+    V3 {
+        x: a.y * b.z - a.z * b.y,
+        y: a.z * b.x - a.x * b.z,
+        z: a.x * b.y - a.y * b.x,
+    }
+}
+#[allow(dead_code)]
+pub fn v3_length_sq(a: V3) -> f32 {
+    v3_dot(a, a)
+}
+#[allow(dead_code)]
+pub fn v3_length(a: V3) -> f32 {
+    v3_length_sq(a).sqrt()
+}
+#[allow(dead_code)]
+pub fn v3_normalize(a: V3) -> V3 {
+    let length = v3_length(a);
+    if length > 0.0 {
+        a / length
+    } else {
+        V3 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+}
+#[allow(dead_code)]
+pub fn v3_lerp(a: V3, t: f32, b: V3) -> V3 {
+    V3 {
+        x: lerp(a.x, t, b.x),
+        y: lerp(a.y, t, b.y),
+        z: lerp(a.z, t, b.z),
+    }
+}
+
+// --------------------------------------------------------------------
+// NOTE(Fermin): M3, row major 2D affine transform. Points are column
+// vectors (x, y, 1), so a * b applies b first and then a. Kept for the
+// camera and the render_bmp axes, nothing builds one yet.
+// --------------------------------------------------------------------
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct M3 {
+    pub e: [[f32; 3]; 3],
+}
+impl Mul<M3> for M3 {
+    type Output = M3;
+
+    fn mul(self, a: M3) -> M3 {
+        let mut result = M3 { e: [[0.0; 3]; 3] };
+        for row in 0..3 {
+            for column in 0..3 {
+                result.e[row][column] = (0..3)
+                    .map(|k| self.e[row][k] * a.e[k][column])
+                    .sum();
+            }
+        }
+        result
+    }
+}
+#[allow(dead_code)]
+pub fn m3_identity() -> M3 {
+    M3 {
+        e: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+    }
+}
+#[allow(dead_code)]
+pub fn m3_translation(offset: V2) -> M3 {
+    M3 {
+        e: [[1.0, 0.0, offset.x], [0.0, 1.0, offset.y], [0.0, 0.0, 1.0]],
+    }
+}
+// NOTE(Fermin): Same direction as v2_rotate
+#[allow(dead_code)]
+pub fn m3_rotation(angle: f32) -> M3 {
+    let (sin, cos) = angle.sin_cos();
+    M3 {
+        e: [[cos, -sin, 0.0], [sin, cos, 0.0], [0.0, 0.0, 1.0]],
+    }
+}
+#[allow(dead_code)]
+pub fn m3_scale(scale: V2) -> M3 {
+    M3 {
+        e: [[scale.x, 0.0, 0.0], [0.0, scale.y, 0.0], [0.0, 0.0, 1.0]],
+    }
+}
+// NOTE(Fermin): Returns None when the transform collapses space, like a
+// scale by zero.
+#[allow(dead_code)]
+pub fn m3_invert(m: M3) -> Option<M3> {
+    let e = &m.e;
+    let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+        e[r0][c0] * e[r1][c1] - e[r0][c1] * e[r1][c0]
+    };
+
+    let det = e[0][0] * cofactor(1, 2, 1, 2) - e[0][1] * cofactor(1, 2, 0, 2)
+        + e[0][2] * cofactor(1, 2, 0, 1);
+    if det.abs() <= f32::EPSILON {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    Some(M3 {
+        e: [
+            [
+                cofactor(1, 2, 1, 2) * inv_det,
+                -cofactor(0, 2, 1, 2) * inv_det,
+                cofactor(0, 1, 1, 2) * inv_det,
+            ],
+            [
+                -cofactor(1, 2, 0, 2) * inv_det,
+                cofactor(0, 2, 0, 2) * inv_det,
+                -cofactor(0, 1, 0, 2) * inv_det,
+            ],
+            [
+                cofactor(1, 2, 0, 1) * inv_det,
+                -cofactor(0, 2, 0, 1) * inv_det,
+                cofactor(0, 1, 0, 1) * inv_det,
+            ],
+        ],
+    })
+}
+#[allow(dead_code)]
+pub fn m3_transform_point(m: M3, p: V2) -> V2 {
+    V2 {
+        x: m.e[0][0] * p.x + m.e[0][1] * p.y + m.e[0][2],
+        y: m.e[1][0] * p.x + m.e[1][1] * p.y + m.e[1][2],
+    }
+}
+// NOTE(Fermin): Ignores translation, for axes and directions
+#[allow(dead_code)]
+pub fn m3_transform_vector(m: M3, v: V2) -> V2 {
+    V2 {
+        x: m.e[0][0] * v.x + m.e[0][1] * v.y,
+        y: m.e[1][0] * v.x + m.e[1][1] * v.y,
+    }
+}
+
+// --------------------------------------------------------------------
+// NOTE(Fermin): Rect
+// --------------------------------------------------------------------
+// NOTE(Fermin): Pixel rect, min is inclusive and max is exclusive
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub min_x: i32,
+    pub min_y: i32,
+    pub max_x: i32,
+    pub max_y: i32,
+}
+
+impl Rect {
+    // NOTE(Fermin): Rects reaching past i32::MAX stop there
+    pub fn from_pos_size(x: i32, y: i32, width: i32, height: i32) -> Rect {
+        Rect {
+            min_x: x,
+            min_y: y,
+            max_x: x.saturating_add(width),
+            max_y: y.saturating_add(height),
+        }
+    }
+
+    pub fn width(&self) -> i32 {
+        self.max_x.saturating_sub(self.min_x).max(0)
+    }
+
+    pub fn height(&self) -> i32 {
+        self.max_y.saturating_sub(self.min_y).max(0)
+    }
+
+    pub fn area(&self) -> i64 {
+        self.width() as i64 * self.height() as i64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width() == 0 || self.height() == 0
+    }
+
+    pub fn union(&self, b: &Rect) -> Rect {
+        Rect {
+            min_x: self.min_x.min(b.min_x),
+            min_y: self.min_y.min(b.min_y),
+            max_x: self.max_x.max(b.max_x),
+            max_y: self.max_y.max(b.max_y),
+        }
+    }
+
+    // NOTE(Fermin): Result might be empty, check with is_empty
+    pub fn intersection(&self, b: &Rect) -> Rect {
+        Rect {
+            min_x: self.min_x.max(b.min_x),
+            min_y: self.min_y.max(b.min_y),
+            max_x: self.max_x.min(b.max_x),
+            max_y: self.max_y.min(b.max_y),
+        }
+    }
+
+    pub fn overlaps(&self, b: &Rect) -> bool {
+        !self.intersection(b).is_empty()
+    }
+
+    // NOTE(Fermin): Drawing clips whole rects, only tests check pixels
+    #[allow(dead_code)]
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.min_x && x < self.max_x && y >= self.min_y && y < self.max_y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::{FRAC_PI_2, PI};
+
+    const TOLERANCE: f32 = 1.0e-5;
+
+    fn assert_v2_eq(a: V2, b: V2) {
+        assert!(
+            (a.x - b.x).abs() < TOLERANCE && (a.y - b.y).abs() < TOLERANCE,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    fn assert_m3_eq(a: M3, b: M3) {
+        for row in 0..3 {
+            for column in 0..3 {
+                assert!(
+                    (a.e[row][column] - b.e[row][column]).abs() < TOLERANCE,
+                    "{:?} != {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn scalar_lerp() {
+        assert_eq!(lerp(2.0, 0.0, 10.0), 2.0);
+        assert_eq!(lerp(2.0, 1.0, 10.0), 10.0);
+        assert_eq!(lerp(2.0, 0.25, 10.0), 4.0);
+    }
+
+    #[test]
+    fn v2_operators() {
+        let a = V2 { x: 1.0, y: -2.0 };
+        let b = V2 { x: 3.0, y: 4.0 };
+        assert_eq!(a + b, V2 { x: 4.0, y: 2.0 });
+        assert_eq!(a - b, V2 { x: -2.0, y: -6.0 });
+        assert_eq!(a * 2.0, V2 { x: 2.0, y: -4.0 });
+        assert_eq!(2.0 * a, a * 2.0);
+        assert_eq!(b / 2.0, V2 { x: 1.5, y: 2.0 });
+        assert_eq!(-a, V2 { x: -1.0, y: 2.0 });
+    }
+
+    #[test]
+    fn v2_products_and_length() {
+        let a = V2 { x: 3.0, y: 4.0 };
+        assert_eq!(v2_dot(a, V2 { x: 2.0, y: -1.0 }), 2.0);
+        assert_eq!(v2_length_sq(a), 25.0);
+        assert_eq!(v2_length(a), 5.0);
+        assert_eq!(v2_perp(a), V2 { x: -4.0, y: 3.0 });
+        assert_eq!(v2_dot(a, v2_perp(a)), 0.0);
+    }
+
+    #[test]
+    fn v2_normalize_handles_zero() {
+        assert_v2_eq(v2_normalize(V2 { x: 3.0, y: 4.0 }), V2 { x: 0.6, y: 0.8 });
+        assert_eq!(v2_normalize(V2 { x: 0.0, y: 0.0 }), V2 { x: 0.0, y: 0.0 });
+    }
+
+    #[test]
+    fn v2_lerp_and_rotate() {
+        let a = V2 { x: 0.0, y: 10.0 };
+        let b = V2 { x: 10.0, y: 20.0 };
+        assert_eq!(v2_lerp(a, 0.5, b), V2 { x: 5.0, y: 15.0 });
+
+        let x_axis = V2 { x: 1.0, y: 0.0 };
+        assert_v2_eq(v2_rotate(x_axis, FRAC_PI_2), V2 { x: 0.0, y: 1.0 });
+        assert_v2_eq(v2_rotate(x_axis, PI), V2 { x: -1.0, y: 0.0 });
+        assert_v2_eq(v2_rotate(x_axis, FRAC_PI_2), v2_perp(x_axis));
+        assert!((v2_length(v2_rotate(b, 1.234)) - v2_length(b)).abs() < TOLERANCE);
+    }
+
+    #[test]
+    fn v3_operators_and_products() {
+        let a = V3 { x: 1.0, y: 2.0, z: 3.0 };
+        let b = V3 { x: -1.0, y: 0.5, z: 2.0 };
+        assert_eq!(a + b, V3 { x: 0.0, y: 2.5, z: 5.0 });
+        assert_eq!(a - b, V3 { x: 2.0, y: 1.5, z: 1.0 });
+        assert_eq!(a * 2.0, V3 { x: 2.0, y: 4.0, z: 6.0 });
+        assert_eq!(2.0 * a, a * 2.0);
+        assert_eq!(a / 2.0, V3 { x: 0.5, y: 1.0, z: 1.5 });
+        assert_eq!(-a, V3 { x: -1.0, y: -2.0, z: -3.0 });
+        assert_eq!(v3_dot(a, b), 6.0);
+        assert_eq!(v3_length_sq(a), 14.0);
+        assert_eq!(v3_lerp(a, 0.5, b), V3 { x: 0.0, y: 1.25, z: 2.5 });
+
+        let x = V3 { x: 1.0, y: 0.0, z: 0.0 };
+        let y = V3 { x: 0.0, y: 1.0, z: 0.0 };
+        assert_eq!(v3_cross(x, y), V3 { x: 0.0, y: 0.0, z: 1.0 });
+        assert_eq!(v3_dot(v3_cross(a, b), a), 0.0);
+    }
+
+    #[test]
+    fn v3_normalize_handles_zero() {
+        let n = v3_normalize(V3 { x: 0.0, y: 3.0, z: 4.0 });
+        assert!((v3_length(n) - 1.0).abs() < TOLERANCE);
+        let zero = V3 { x: 0.0, y: 0.0, z: 0.0 };
+        assert_eq!(v3_normalize(zero), zero);
+    }
+
+    #[test]
+    fn m3_basic_transforms() {
+        let p = V2 { x: 2.0, y: 3.0 };
+        assert_eq!(m3_transform_point(m3_identity(), p), p);
+        assert_eq!(
+            m3_transform_point(m3_translation(V2 { x: 10.0, y: -1.0 }), p),
+            V2 { x: 12.0, y: 2.0 }
+        );
+        assert_eq!(
+            m3_transform_point(m3_scale(V2 { x: 2.0, y: 0.5 }), p),
+            V2 { x: 4.0, y: 1.5 }
+        );
+        assert_v2_eq(m3_transform_point(m3_rotation(0.7), p), v2_rotate(p, 0.7));
+
+        // NOTE(Fermin): Vectors don't move with translation
+        assert_eq!(
+            m3_transform_vector(m3_translation(V2 { x: 10.0, y: -1.0 }), p),
+            p
+        );
+    }
+
+    #[test]
+    fn m3_compose_applies_right_first() {
+        let p = V2 { x: 1.0, y: 0.0 };
+        let translate = m3_translation(V2 { x: 5.0, y: 0.0 });
+        let rotate = m3_rotation(FRAC_PI_2);
+
+        assert_v2_eq(m3_transform_point(translate * rotate, p), V2 { x: 5.0, y: 1.0 });
+        assert_v2_eq(m3_transform_point(rotate * translate, p), V2 { x: 0.0, y: 6.0 });
+        assert_m3_eq(m3_identity() * rotate, rotate);
+        assert_m3_eq(rotate * m3_identity(), rotate);
+    }
+
+    #[test]
+    fn m3_invert_undoes_transform() {
+        let m = m3_translation(V2 { x: 3.0, y: -7.0 })
+            * m3_rotation(0.4)
+            * m3_scale(V2 { x: 2.0, y: 0.25 });
+        let inverse = m3_invert(m).expect("transform should be invertible");
+
+        assert_m3_eq(m * inverse, m3_identity());
+        assert_m3_eq(inverse * m, m3_identity());
+        let p = V2 { x: -4.0, y: 9.0 };
+        assert_v2_eq(m3_transform_point(inverse, m3_transform_point(m, p)), p);
+    }
+
+    #[test]
+    fn m3_invert_rejects_degenerate() {
+        assert!(m3_invert(m3_scale(V2 { x: 0.0, y: 1.0 })).is_none());
+    }
+
+    #[test]
+    fn rect_size_and_emptiness() {
+        let r = Rect::from_pos_size(-2, 3, 5, 4);
+        assert_eq!(r.width(), 5);
+        assert_eq!(r.height(), 4);
+        assert_eq!(r.area(), 20);
+        assert!(!r.is_empty());
+        assert!(Rect::from_pos_size(0, 0, 0, 10).is_empty());
+        assert!(Rect::from_pos_size(0, 0, -3, 10).is_empty());
+        assert_eq!(Rect::from_pos_size(0, 0, -3, 10).area(), 0);
+
+        let huge = Rect::from_pos_size(i32::MAX - 5, -10, 100, i32::MAX);
+        assert_eq!((huge.max_x, huge.max_y), (i32::MAX, i32::MAX - 10));
+        assert_eq!(huge.width(), 5);
+        let everything = Rect::from_pos_size(i32::MIN, i32::MIN, i32::MAX, i32::MAX);
+        assert_eq!(everything.width(), i32::MAX);
+        assert_eq!(Rect::from_pos_size(i32::MIN, 0, 0, 0).union(&huge).width(), i32::MAX);
+    }
+
+    #[test]
+    fn rect_union_intersection_and_contains() {
+        let a = Rect::from_pos_size(0, 0, 10, 10);
+        let b = Rect::from_pos_size(5, -5, 10, 10);
+        assert_eq!(a.union(&b), Rect::from_pos_size(0, -5, 15, 15));
+        assert_eq!(a.intersection(&b), Rect::from_pos_size(5, 0, 5, 5));
+        assert!(a.overlaps(&b));
+
+        // NOTE(Fermin): Max is exclusive, so rects sharing an edge don't overlap
+        let c = Rect::from_pos_size(10, 0, 5, 5);
+        assert!(!a.overlaps(&c));
+        assert!(a.intersection(&c).is_empty());
+
+        assert!(a.contains(0, 0));
+        assert!(a.contains(9, 9));
+        assert!(!a.contains(10, 9));
+        assert!(!a.contains(-1, 5));
+    }
+}
@@ -0,0 +1,501 @@
+use crate::color::Color;
+use crate::compositor::BlendMode;
+use crate::dirty_rects::DirtyRegion;
+use crate::lines::{draw_polyline, line_bounds, LineJoin, LineStyle, LineVertex};
+use crate::math::{lerp, v2_length, v2_rotate, Rect, V2};
+use crate::render::{clip_to_target, RenderTarget};
+use rand::Rng;
+use std::f32::consts::TAU;
+
+// NOTE(Fermin): The head only leaves a new point behind after moving this
+// many pixels, slow comets would pile up thousands of them otherwise.
+const MIN_PATH_SPACING: f32 = 4.0;
+// NOTE(Fermin): Fractions of the lifetime spent fading in and out
+const FADE_IN: f32 = 0.1;
+const FADE_OUT: f32 = 0.3;
+// NOTE(Fermin): How far past its radius a comet head glows
+const GLOW_SCALE: f32 = 3.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MeteorKind {
+    // NOTE(Fermin): Fast and short lived, straight tapered tail
+    ShootingStar,
+    // NOTE(Fermin): Slow, glowing head and a long tail that bends with its
+    // path.
+    Comet,
+}
+
+pub struct MeteorSettings {
+    // NOTE(Fermin): Average spawns per second, 0 turns them off
+    pub shooting_stars_per_second: f32,
+    pub comets_per_second: f32,
+    pub shooting_star_color: Color,
+    pub comet_color: Color,
+    // NOTE(Fermin): How much brighter than white heads get in hdr targets
+    pub head_intensity: f32,
+}
+
+#[derive(Clone)]
+pub struct Meteor {
+    pub kind: MeteorKind,
+    pub head: V2,
+    pub velocity: V2,
+    // NOTE(Fermin): Radians per second the path bends
+    pub turn_rate: f32,
+    pub age: f32,
+    pub lifetime: f32,
+    pub color: Color,
+    pub head_radius: f32,
+    // NOTE(Fermin): Seconds of path the tail covers
+    pub tail_seconds: f32,
+    // NOTE(Fermin): Where the head has been and the age it was there at,
+    // oldest first.
+    pub path: Vec<(V2, f32)>,
+}
+
+fn random_direction<R: Rng>(rng: &mut R) -> V2 {
+    v2_rotate(V2 { x: 1.0, y: 0.0 }, rng.gen_range(0.0..TAU))
+}
+
+pub fn spawn_shooting_star<R: Rng>(
+    settings: &MeteorSettings,
+    width: i32,
+    height: i32,
+    rng: &mut R,
+) -> Meteor {
+    let head = V2 {
+        x: rng.gen_range(0.0..width.max(1) as f32),
+        y: rng.gen_range(0.0..(height.max(2) as f32 * 0.6)),
+    };
+    Meteor {
+        kind: MeteorKind::ShootingStar,
+        head,
+        velocity: random_direction(rng) * rng.gen_range(900.0..1600.0),
+        turn_rate: 0.0,
+        age: 0.0,
+        lifetime: rng.gen_range(0.5..1.1),
+        color: settings.shooting_star_color,
+        head_radius: rng.gen_range(1.5..2.5),
+        tail_seconds: 0.18,
+        path: vec![(head, 0.0)],
+    }
+}
+
+// NOTE(Fermin): Comets start off screen aimed at a random point on it and
+// live until they (and their tail) are gone on the other side.
+pub fn spawn_comet<R: Rng>(
+    settings: &MeteorSettings,
+    width: i32,
+    height: i32,
+    rng: &mut R,
+) -> Meteor {
+    let target = V2 {
+        x: rng.gen_range(0.0..width.max(1) as f32),
+        y: rng.gen_range(0.0..height.max(1) as f32),
+    };
+    let diagonal = v2_length(V2 {
+        x: width as f32,
+        y: height as f32,
+    });
+    let direction = random_direction(rng);
+    let speed = rng.gen_range(40.0..90.0);
+    let tail_seconds = rng.gen_range(6.0..10.0);
+    let head_radius = rng.gen_range(4.0..7.0);
+
+    let head = target - direction * (diagonal * 0.5 + head_radius * GLOW_SCALE);
+    Meteor {
+        kind: MeteorKind::Comet,
+        head,
+        velocity: direction * speed,
+        turn_rate: rng.gen_range(0.03..0.08) * if rng.gen_bool(0.5) { 1.0 } else { -1.0 },
+        age: 0.0,
+        lifetime: (diagonal + head_radius * GLOW_SCALE * 2.0) / speed + tail_seconds,
+        color: settings.comet_color,
+        head_radius,
+        tail_seconds,
+        path: vec![(head, 0.0)],
+    }
+}
+
+fn smoothstep01(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// NOTE(Fermin): Overall opacity at the meteor's current age
+fn meteor_fade(meteor: &Meteor) -> f32 {
+    let life = meteor.age / meteor.lifetime;
+    smoothstep01(life / FADE_IN) * smoothstep01((1.0 - life) / FADE_OUT)
+}
+
+// NOTE(Fermin): As thick as the head, vertex widths taper it from there
+fn meteor_style(meteor: &Meteor) -> LineStyle {
+    LineStyle {
+        thickness: meteor.head_radius * 2.0,
+        join: LineJoin::Round,
+        blend_mode: BlendMode::Normal,
+    }
+}
+
+// NOTE(Fermin): Head first. Shooting star tails taper to nothing, comet tails
+// widen as they fade.
+pub fn meteor_trail(meteor: &Meteor, head_intensity: f32) -> Vec<LineVertex> {
+    let fade = meteor_fade(meteor);
+    let mut vertices: Vec<LineVertex> = Vec::with_capacity(meteor.path.len() + 1);
+    let head = std::iter::once((meteor.head, meteor.age));
+    for (pos, age) in head.chain(meteor.path.iter().rev().copied()) {
+        let along = ((meteor.age - age) / meteor.tail_seconds).clamp(0.0, 1.0);
+        let (width, alpha) = match meteor.kind {
+            MeteorKind::ShootingStar => (1.0 - along, 1.0 - along),
+            MeteorKind::Comet => (lerp(0.6, along, 2.0), (1.0 - along) * (1.0 - along) * 0.8),
+        };
+        vertices.push(LineVertex {
+            pos,
+            width,
+            color: meteor.color,
+            alpha: alpha * fade,
+            intensity: lerp(head_intensity, along, 1.0),
+        });
+    }
+    vertices
+}
+
+fn glow_bounds(meteor: &Meteor) -> Rect {
+    let reach = meteor.head_radius * GLOW_SCALE;
+    Rect {
+        min_x: (meteor.head.x - reach).floor() as i32,
+        min_y: (meteor.head.y - reach).floor() as i32,
+        max_x: (meteor.head.x + reach).ceil() as i32 + 1,
+        max_y: (meteor.head.y + reach).ceil() as i32 + 1,
+    }
+}
+
+pub fn meteor_bounds(meteor: &Meteor, head_intensity: f32) -> Rect {
+    let bounds = line_bounds(&meteor_trail(meteor, head_intensity), &meteor_style(meteor));
+    match meteor.kind {
+        MeteorKind::ShootingStar => bounds,
+        MeteorKind::Comet => bounds.union(&glow_bounds(meteor)),
+    }
+}
+
+// NOTE(Fermin): Soft round glow that goes above 1.0 in the middle when
+// drawing into an hdr target.
+fn draw_glow<T: RenderTarget>(meteor: &Meteor, intensity: f32, buffer: &mut T) {
+    let clipped = clip_to_target(&glow_bounds(meteor), buffer);
+    if clipped.is_empty() {
+        return;
+    }
+
+    let fade = meteor_fade(meteor);
+    let reach = meteor.head_radius * GLOW_SCALE;
+    let buffer_pitch = buffer.pitch();
+    for y in clipped.min_y..clipped.max_y {
+        let row = (y * buffer_pitch) as usize;
+        for x in clipped.min_x..clipped.max_x {
+            let pixel = V2 {
+                x: x as f32 + 0.5,
+                y: y as f32 + 0.5,
+            };
+            let falloff = (1.0 - v2_length(pixel - meteor.head) / reach).max(0.0);
+            if falloff <= 0.0 {
+                continue;
+            }
+            let alpha = falloff * falloff * fade;
+            buffer.blend_pixel(
+                row + x as usize,
+                &meteor.color,
+                lerp(1.0, falloff, intensity),
+                alpha,
+            );
+        }
+    }
+}
+
+pub fn draw_meteor<T: RenderTarget>(meteor: &Meteor, head_intensity: f32, buffer: &mut T) {
+    draw_polyline(
+        &meteor_trail(meteor, head_intensity),
+        &meteor_style(meteor),
+        buffer,
+    );
+    if meteor.kind == MeteorKind::Comet {
+        draw_glow(meteor, head_intensity, buffer);
+    }
+}
+
+// NOTE(Fermin): Adds where meteors were to dirty_region before moving them
+fn move_meteors(
+    meteors: &mut Vec<Meteor>,
+    settings: &MeteorSettings,
+    dirty_region: &mut DirtyRegion,
+    dt_for_frame: f32,
+) {
+    for meteor in meteors.iter_mut() {
+        dirty_region.add(meteor_bounds(meteor, settings.head_intensity));
+
+        meteor.age += dt_for_frame;
+        meteor.velocity = v2_rotate(meteor.velocity, meteor.turn_rate * dt_for_frame);
+        meteor.head = meteor.head + meteor.velocity * dt_for_frame;
+
+        let last = meteor.path[meteor.path.len() - 1].0;
+        if v2_length(meteor.head - last) >= MIN_PATH_SPACING {
+            meteor.path.push((meteor.head, meteor.age));
+        }
+        // NOTE(Fermin): Keep one point past the end of the tail so it doesn't
+        // pop when the oldest one expires.
+        let expired = meteor
+            .path
+            .iter()
+            .take_while(|&&(_, age)| meteor.age - age > meteor.tail_seconds)
+            .count();
+        meteor.path.drain(..expired.saturating_sub(1));
+    }
+    meteors.retain(|meteor| meteor.age < meteor.lifetime);
+}
+
+// NOTE(Fermin): Moves, spawns and retires meteors, adding everything they
+// covered before and after to dirty_region.
+pub fn update_meteors<R: Rng>(
+    meteors: &mut Vec<Meteor>,
+    settings: &MeteorSettings,
+    dirty_region: &mut DirtyRegion,
+    width: i32,
+    height: i32,
+    dt_for_frame: f32,
+    rng: &mut R,
+) {
+    move_meteors(meteors, settings, dirty_region, dt_for_frame);
+
+    if rng.gen::<f32>() < settings.shooting_stars_per_second * dt_for_frame {
+        meteors.push(spawn_shooting_star(settings, width, height, rng));
+    }
+    if rng.gen::<f32>() < settings.comets_per_second * dt_for_frame {
+        meteors.push(spawn_comet(settings, width, height, rng));
+    }
+
+    for meteor in meteors.iter() {
+        dirty_region.add(meteor_bounds(meteor, settings.head_intensity));
+    }
+}
+
+// NOTE(Fermin): Meteors for a sky that repeats every seconds, each one
+// spawning at its own time into every loop. About as many as the rates
+// give on average, leaving out the ones that would outlive a loop and run
+// into themselves. Earliest first.
+pub fn schedule_meteors<R: Rng>(
+    settings: &MeteorSettings,
+    width: i32,
+    height: i32,
+    seconds: f32,
+    rng: &mut R,
+) -> Vec<(f32, Meteor)> {
+    let mut schedule = Vec::new();
+    let kinds = [
+        (settings.shooting_stars_per_second, MeteorKind::ShootingStar),
+        (settings.comets_per_second, MeteorKind::Comet),
+    ];
+    for (per_second, kind) in kinds {
+        let expected = per_second * seconds;
+        let mut count = expected.floor() as usize;
+        if rng.gen::<f32>() < expected.fract() {
+            count += 1;
+        }
+        for _meteor in 0..count {
+            let time = rng.gen_range(0.0..seconds);
+            let meteor = match kind {
+                MeteorKind::ShootingStar => spawn_shooting_star(settings, width, height, rng),
+                MeteorKind::Comet => spawn_comet(settings, width, height, rng),
+            };
+            if meteor.lifetime < seconds {
+                schedule.push((time, meteor));
+            }
+        }
+    }
+    schedule.sort_by(|a, b| a.0.total_cmp(&b.0));
+    schedule
+}
+
+// NOTE(Fermin): update_meteors for looping skies, spawns the scheduled
+// meteors that fall between from and to instead of random ones. A loop
+// that just ended goes up to its length.
+pub fn update_scheduled_meteors(
+    meteors: &mut Vec<Meteor>,
+    settings: &MeteorSettings,
+    schedule: &[(f32, Meteor)],
+    dirty_region: &mut DirtyRegion,
+    from: f32,
+    to: f32,
+    dt_for_frame: f32,
+) {
+    move_meteors(meteors, settings, dirty_region, dt_for_frame);
+
+    for (time, meteor) in schedule {
+        if (from..to).contains(time) {
+            meteors.push(meteor.clone());
+        }
+    }
+
+    for meteor in meteors.iter() {
+        dirty_region.add(meteor_bounds(meteor, settings.head_intensity));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hdr::{HdrBuffer, HDR_CHANNELS};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    const SETTINGS: MeteorSettings = MeteorSettings {
+        shooting_stars_per_second: 2.0,
+        comets_per_second: 0.5,
+        shooting_star_color: Color::rgba(255, 255, 255, 255),
+        comet_color: Color::rgba(160, 230, 255, 255),
+        head_intensity: 3.0,
+    };
+
+    #[test]
+    fn meteors_stay_inside_their_dirty_rects() {
+        let mut rng = StdRng::seed_from_u64(37);
+        let mut meteors: Vec<Meteor> = Vec::new();
+        let mut dirty_region = DirtyRegion::new(320, 240);
+        let mut spawned_comet = false;
+
+        for _frame in 0..300 {
+            dirty_region.clear();
+            update_meteors(
+                &mut meteors,
+                &SETTINGS,
+                &mut dirty_region,
+                320,
+                240,
+                1.0 / 15.0,
+                &mut rng,
+            );
+            spawned_comet |= meteors
+                .iter()
+                .any(|meteor| meteor.kind == MeteorKind::Comet);
+
+            let mut buffer = HdrBuffer::new(320, 240);
+            for meteor in &meteors {
+                draw_meteor(meteor, SETTINGS.head_intensity, &mut buffer);
+            }
+            for (index, pixel) in buffer.bits.chunks_exact(HDR_CHANNELS).enumerate() {
+                if pixel.iter().any(|&channel| channel > 0.0) {
+                    let (x, y) = (index as i32 % 320, index as i32 / 320);
+                    assert!(dirty_region.rects.iter().any(|rect| rect.contains(x, y)));
+                }
+            }
+        }
+        assert!(spawned_comet);
+    }
+
+    #[test]
+    fn meteors_fade_and_retire() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut meteors = vec![
+            spawn_shooting_star(&SETTINGS, 320, 240, &mut rng),
+            spawn_comet(&SETTINGS, 320, 240, &mut rng),
+        ];
+        assert_eq!(meteor_fade(&meteors[0]), 0.0);
+        meteors[1].tail_seconds = 1.0;
+        meteors[1].lifetime = 100.0;
+
+        let quiet = MeteorSettings {
+            shooting_stars_per_second: 0.0,
+            comets_per_second: 0.0,
+            ..SETTINGS
+        };
+        let mut dirty_region = DirtyRegion::new(320, 240);
+        update_meteors(
+            &mut meteors,
+            &quiet,
+            &mut dirty_region,
+            320,
+            240,
+            0.3,
+            &mut rng,
+        );
+        assert!(meteor_fade(&meteors[0]) > 0.0);
+
+        // NOTE(Fermin): Comet tails only keep what they need
+        for _frame in 0..200 {
+            update_meteors(
+                &mut meteors,
+                &quiet,
+                &mut dirty_region,
+                320,
+                240,
+                0.1,
+                &mut rng,
+            );
+        }
+        assert_eq!(meteors.len(), 1);
+        let comet = &meteors[0];
+        let needed =
+            (comet.tail_seconds * v2_length(comet.velocity) / MIN_PATH_SPACING) as usize + 2;
+        assert!(
+            comet.path.len() <= needed,
+            "{} > {}",
+            comet.path.len(),
+            needed
+        );
+
+        for _frame in 0..2000 {
+            update_meteors(
+                &mut meteors,
+                &quiet,
+                &mut dirty_region,
+                320,
+                240,
+                0.1,
+                &mut rng,
+            );
+        }
+        assert!(meteors.is_empty());
+    }
+
+    // NOTE(Fermin): Frames a whole number of loops apart look the same once
+    // the meteors left over from the first loop are there.
+    #[test]
+    fn scheduled_meteors_repeat() {
+        let mut rng = StdRng::seed_from_u64(38);
+        let seconds = 4.0;
+        let schedule = schedule_meteors(&SETTINGS, 320, 240, seconds, &mut rng);
+        assert!(schedule.len() >= 4);
+        assert!(schedule
+            .iter()
+            .all(|(time, meteor)| { (0.0..seconds).contains(time) && meteor.lifetime < seconds }));
+
+        let mut meteors: Vec<Meteor> = Vec::new();
+        let mut dirty_region = DirtyRegion::new(320, 240);
+        let mut loop_starts = Vec::new();
+        let frames_per_loop = 40;
+        for frame in 0..3 * frames_per_loop {
+            let into_loop = frame % frames_per_loop;
+            let from = into_loop as f32 * 0.1;
+            let to = (into_loop + 1) as f32 * 0.1;
+            update_scheduled_meteors(
+                &mut meteors,
+                &SETTINGS,
+                &schedule,
+                &mut dirty_region,
+                from,
+                if into_loop + 1 == frames_per_loop {
+                    seconds
+                } else {
+                    to
+                },
+                0.1,
+            );
+            if into_loop + 1 == frames_per_loop {
+                let heads: Vec<(V2, f32)> = meteors
+                    .iter()
+                    .map(|meteor| (meteor.head, meteor.age))
+                    .collect();
+                loop_starts.push(heads);
+            }
+        }
+        assert!(!loop_starts[1].is_empty());
+        assert_eq!(loop_starts[1], loop_starts[2]);
+    }
+}
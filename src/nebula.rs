@@ -0,0 +1,360 @@
+use crate::color::{color_lerp, Color, Gradient};
+use crate::math::{v2_dot, Rect, V2};
+use crate::pixel_buffer::{PixelBuffer, PixelFormat};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+const PERMUTATION_SIZE: usize = 256;
+// NOTE(Fermin): Each octave doubles the frequency and halves the amplitude
+const LACUNARITY: f32 = 2.0;
+const GAIN: f32 = 0.5;
+// NOTE(Fermin): Width of the soft edge between the nebula and the plain
+// background, in noise units.
+const EDGE_SOFTNESS: f32 = 0.15;
+
+pub struct NebulaSettings {
+    pub seed: u64,
+    // NOTE(Fermin): 0 leaves the plain background, 1 covers all of it
+    pub density: f32,
+    // NOTE(Fermin): Size in pixels of the biggest clouds
+    pub scale: f32,
+    pub octaves: u32,
+    // NOTE(Fermin): How far domain warping drags the clouds around, in units
+    // of scale. 0 turns it off.
+    pub warp: f32,
+    // NOTE(Fermin): Units of scale per second the clouds drift, 0 keeps the
+    // nebula still so it only gets rendered once. Drifting slides the
+    // clouds without changing them, see NebulaField.
+    pub drift_speed: f32,
+    // NOTE(Fermin): Noise is smooth, rendering it at a fraction of the
+    // resolution and upscaling looks the same and is way cheaper.
+    pub downscale: i32,
+}
+
+// NOTE(Fermin): The downscaled nebula as of the last render. Drifting only
+// slides the clouds around, so the field slides along with them and only
+// the strips that come into view get sampled.
+struct NebulaField {
+    small: PixelBuffer,
+    // NOTE(Fermin): Where the top left of small is in the nebula at time 0,
+    // in small pixels.
+    origin: (i32, i32),
+    gradient: Gradient,
+    background: Color,
+}
+
+pub struct Nebula {
+    pub settings: NebulaSettings,
+    permutation: Vec<u8>,
+    field: Option<NebulaField>,
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+impl Nebula {
+    pub fn new(settings: NebulaSettings) -> Nebula {
+        let mut rng = StdRng::seed_from_u64(settings.seed);
+        let mut permutation: Vec<u8> = (0..PERMUTATION_SIZE).map(|i| i as u8).collect();
+        permutation.shuffle(&mut rng);
+        // NOTE(Fermin): Doubled so lookups never have to wrap
+        permutation.extend_from_within(..);
+        Nebula {
+            settings,
+            permutation,
+            field: None,
+        }
+    }
+
+    fn gradient(&self, x: i32, y: i32) -> V2 {
+        let x = (x & (PERMUTATION_SIZE as i32 - 1)) as usize;
+        let y = (y & (PERMUTATION_SIZE as i32 - 1)) as usize;
+        let hash = self.permutation[self.permutation[x] as usize + y];
+        // NOTE(Fermin): 8 directions around the unit circle are plenty for
+        // clouds.
+        let angle = (hash & 7) as f32 * std::f32::consts::FRAC_PI_4;
+        V2 {
+            x: angle.cos(),
+            y: angle.sin(),
+        }
+    }
+
+    // NOTE(Fermin): Perlin gradient noise, roughly in [-0.7, 0.7]
+    fn noise(&self, p: V2) -> f32 {
+        let cell_x = p.x.floor();
+        let cell_y = p.y.floor();
+        let (x0, y0) = (cell_x as i32, cell_y as i32);
+        let local = V2 {
+            x: p.x - cell_x,
+            y: p.y - cell_y,
+        };
+
+        let corner = |dx: i32, dy: i32| {
+            let offset = V2 {
+                x: local.x - dx as f32,
+                y: local.y - dy as f32,
+            };
+            v2_dot(self.gradient(x0 + dx, y0 + dy), offset)
+        };
+        let (u, v) = (fade(local.x), fade(local.y));
+        let top = corner(0, 0) + u * (corner(1, 0) - corner(0, 0));
+        let bottom = corner(0, 1) + u * (corner(1, 1) - corner(0, 1));
+        top + v * (bottom - top)
+    }
+
+    // NOTE(Fermin): Fractal sum of octaves remapped to [0, 1]
+    fn fbm(&self, p: V2) -> f32 {
+        let mut sum = 0.0;
+        let mut amplitude = 1.0;
+        let mut total_amplitude = 0.0;
+        let mut frequency = 1.0;
+        for _octave in 0..self.settings.octaves.max(1) {
+            sum += amplitude * self.noise(p * frequency);
+            total_amplitude += amplitude;
+            amplitude *= GAIN;
+            frequency *= LACUNARITY;
+        }
+        (sum / total_amplitude * 0.5 / 0.7 + 0.5).clamp(0.0, 1.0)
+    }
+
+    // NOTE(Fermin): Nebula value at a pixel, 0 is empty space and 1 is the
+    // thickest part of a cloud.
+    pub fn sample(&self, x: f32, y: f32, time: f32) -> f32 {
+        let drift = self.settings.drift_speed * time;
+        let mut p = V2 {
+            x: x / self.settings.scale + drift,
+            y: y / self.settings.scale + drift * 0.5,
+        };
+
+        if self.settings.warp > 0.0 {
+            // NOTE(Fermin): Offsets just decorrelate the two warp axes
+            let warp = V2 {
+                x: self.fbm(p + V2 { x: 5.2, y: 1.3 }),
+                y: self.fbm(p + V2 { x: 1.7, y: 9.2 }),
+            };
+            p = p + (warp * 2.0 - V2 { x: 1.0, y: 1.0 }) * self.settings.warp;
+        }
+
+        self.fbm(p)
+    }
+}
+
+// NOTE(Fermin): Pixels the clouds have slid by at time, rounded. Same
+// direction as in Nebula::sample.
+fn drift_offset(settings: &NebulaSettings, time: f32) -> (i32, i32) {
+    let drift = settings.drift_speed * time * settings.scale;
+    ((drift).round() as i32, (drift * 0.5).round() as i32)
+}
+
+// NOTE(Fermin): Where the nebula as it was at time 0 lands in a width x
+// height buffer at time. Renders only change when this does.
+pub fn nebula_rect(settings: &NebulaSettings, width: i32, height: i32, time: f32) -> Rect {
+    let (offset_x, offset_y) = drift_offset(settings, time);
+    Rect::from_pos_size(-offset_x, -offset_y, width, height)
+}
+
+fn nebula_color(nebula: &Nebula, gradient: &Gradient, background: &Color, x: f32, y: f32) -> Color {
+    // NOTE(Fermin): density moves the cutoff through the noise so it covers
+    // the matching fraction of the sky, give or take.
+    let cutoff = 1.0 - nebula.settings.density.clamp(0.0, 1.0);
+    let value = nebula.sample(x, y, 0.0);
+    let coverage = smoothstep(cutoff - EDGE_SOFTNESS, cutoff + EDGE_SOFTNESS, value);
+    let color = color_lerp(background, coverage, &gradient.sample(value));
+    Color { a: 255, ..color }
+}
+
+// NOTE(Fermin): Fills the whole buffer. Where there's no nebula the
+// background color shows, clouds take their color from the gradient.
+pub fn render_nebula(
+    nebula: &mut Nebula,
+    gradient: &Gradient,
+    background: &Color,
+    buffer: &mut PixelBuffer,
+    time: f32,
+) {
+    if nebula.settings.density <= 0.0 {
+        buffer.fill(background);
+        return;
+    }
+
+    // NOTE(Fermin): One small pixel more than the buffer needs on every
+    // side, so the filtering never reaches the clamped edges of the field.
+    let downscale = nebula.settings.downscale.max(1);
+    let (offset_x, offset_y) = drift_offset(&nebula.settings, time);
+    let origin = (
+        offset_x.div_euclid(downscale) - 1,
+        offset_y.div_euclid(downscale) - 1,
+    );
+    let mut small = PixelBuffer::new(
+        (buffer.width + downscale - 1) / downscale + 3,
+        (buffer.height + downscale - 1) / downscale + 3,
+        PixelFormat::Bgra8,
+    );
+
+    // NOTE(Fermin): Whatever the last field has of this one is kept
+    let mut kept = Rect::from_pos_size(0, 0, 0, 0);
+    if let Some(field) = nebula.field.take() {
+        if field.gradient == *gradient && field.background == *background {
+            let (x, y) = (field.origin.0 - origin.0, field.origin.1 - origin.1);
+            small.blit(&field.small.view(&field.small.bounds()), x, y);
+            kept = Rect::from_pos_size(x, y, field.small.width, field.small.height)
+                .intersection(&small.bounds());
+        }
+    }
+
+    for y in 0..small.height {
+        let kept_row = y >= kept.min_y && y < kept.max_y;
+        for x in 0..small.width {
+            if kept_row && x >= kept.min_x && x < kept.max_x {
+                continue;
+            }
+            let color = nebula_color(
+                nebula,
+                gradient,
+                background,
+                ((origin.0 + x) as f32 + 0.5) * downscale as f32,
+                ((origin.1 + y) as f32 + 0.5) * downscale as f32,
+            );
+            small.set(x, y, &color);
+        }
+    }
+
+    let rect = Rect::from_pos_size(
+        origin.0 * downscale - offset_x,
+        origin.1 * downscale - offset_y,
+        small.width * downscale,
+        small.height * downscale,
+    );
+    buffer.blit_scaled(&small.view(&small.bounds()), &rect);
+    nebula.field = Some(NebulaField {
+        small,
+        origin,
+        gradient: gradient.clone(),
+        background: *background,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(seed: u64) -> NebulaSettings {
+        NebulaSettings {
+            seed,
+            density: 0.5,
+            scale: 64.0,
+            octaves: 5,
+            warp: 1.0,
+            drift_speed: 0.0,
+            downscale: 2,
+        }
+    }
+
+    #[test]
+    fn same_seed_same_nebula() {
+        let a = Nebula::new(settings(7));
+        let b = Nebula::new(settings(7));
+        let c = Nebula::new(settings(8));
+
+        let points = [(0.0, 0.0), (13.5, 200.25), (1000.0, -40.0), (-321.0, 77.0)];
+        for (x, y) in points {
+            assert_eq!(a.sample(x, y, 0.0), b.sample(x, y, 0.0));
+        }
+        assert!(points
+            .iter()
+            .any(|&(x, y)| a.sample(x, y, 0.0) != c.sample(x, y, 0.0)));
+    }
+
+    #[test]
+    fn noise_is_continuous_and_in_range() {
+        let nebula = Nebula::new(settings(1));
+        let mut previous = nebula.sample(0.0, 10.0, 0.0);
+        for step in 1..2000 {
+            let value = nebula.sample(step as f32 * 0.5, 10.0, 0.0);
+            assert!((0.0..=1.0).contains(&value));
+            assert!((value - previous).abs() < 0.05, "Jump at step {}", step);
+            previous = value;
+        }
+
+        // NOTE(Fermin): Noise is 0 on lattice points, without octaves and
+        // warping that lands right in the middle.
+        let flat = Nebula::new(NebulaSettings {
+            octaves: 1,
+            warp: 0.0,
+            ..settings(1)
+        });
+        assert_eq!(flat.sample(128.0, 64.0, 0.0), 0.5);
+    }
+
+    #[test]
+    fn density_controls_coverage() {
+        let background = Color::rgba(10, 10, 40, 255);
+        let gradient = Gradient::from_hex(&[(0.0, "#ff0000"), (1.0, "#ffffff")]);
+        let coverage = |density: f32| {
+            let mut nebula = Nebula::new(NebulaSettings {
+                density,
+                ..settings(3)
+            });
+            let mut buffer = PixelBuffer::new(64, 64, PixelFormat::Bgra8);
+            render_nebula(&mut nebula, &gradient, &background, &mut buffer, 0.0);
+            (0..64 * 64)
+                .filter(|i| buffer.get(i % 64, i / 64) != Some(background))
+                .count()
+        };
+
+        assert_eq!(coverage(0.0), 0);
+        assert!(coverage(0.3) < coverage(0.7));
+        assert_eq!(coverage(1.0), 64 * 64);
+    }
+
+    // NOTE(Fermin): 1 unit of scale per second is 64 pixels, at 0.125
+    // seconds the clouds moved 8 pixels right and 4 down.
+    #[test]
+    fn drifting_slides_the_field() {
+        let background = Color::rgba(10, 10, 40, 255);
+        let gradient = Gradient::from_hex(&[(0.0, "#ff0000"), (1.0, "#ffffff")]);
+        let drifting = || {
+            Nebula::new(NebulaSettings {
+                drift_speed: 1.0,
+                ..settings(5)
+            })
+        };
+        let render = |nebula: &mut Nebula, time: f32| {
+            let mut buffer = PixelBuffer::new(48, 32, PixelFormat::Bgra8);
+            render_nebula(nebula, &gradient, &background, &mut buffer, time);
+            buffer
+        };
+
+        let mut nebula = drifting();
+        let start = render(&mut nebula, 0.0);
+        for time in [0.01, 0.05, -0.2, 0.1] {
+            render(&mut nebula, time);
+        }
+        let moved = render(&mut nebula, 0.125);
+        assert_eq!(moved.bits, render(&mut drifting(), 0.125).bits);
+        for y in 0..28 {
+            for x in 0..40 {
+                assert_eq!(moved.get(x, y), start.get(x + 8, y + 4), "{}, {}", x, y);
+            }
+        }
+
+        assert_eq!(
+            nebula_rect(&nebula.settings, 48, 32, 0.125),
+            Rect::from_pos_size(-8, -4, 48, 32)
+        );
+        // NOTE(Fermin): Other colors can't reuse what was sampled
+        let gradient = Gradient::from_hex(&[(0.0, "#00ff00"), (1.0, "#ffffff")]);
+        let mut recolored = PixelBuffer::new(48, 32, PixelFormat::Bgra8);
+        render_nebula(&mut nebula, &gradient, &background, &mut recolored, 0.125);
+        let mut fresh = PixelBuffer::new(48, 32, PixelFormat::Bgra8);
+        render_nebula(&mut drifting(), &gradient, &background, &mut fresh, 0.125);
+        assert_eq!(recolored.bits, fresh.bits);
+    }
+}
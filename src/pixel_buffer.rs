@@ -0,0 +1,459 @@
+use crate::color::Color;
+use crate::math::Rect;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    // NOTE(Fermin): Pixel -> BB GG RR AA, what Win32 DIBs want
+    Bgra8,
+    // NOTE(Fermin): Pixel -> RR GG BB AA, what most image files want. The
+    // decoders swizzle into Bgra8 as they go, so only tests build these.
+    #[allow(dead_code)]
+    Rgba8,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Bgra8 | PixelFormat::Rgba8 => 4,
+        }
+    }
+
+    pub fn encode(self, color: &Color) -> [u8; 4] {
+        match self {
+            PixelFormat::Bgra8 => color.to_bgra(),
+            PixelFormat::Rgba8 => [color.r, color.g, color.b, color.a],
+        }
+    }
+
+    pub fn decode(self, bytes: &[u8]) -> Color {
+        match self {
+            PixelFormat::Bgra8 => Color::from_bgra(bytes),
+            PixelFormat::Rgba8 => Color::rgba(bytes[0], bytes[1], bytes[2], bytes[3]),
+        }
+    }
+}
+
+// NOTE(Fermin): Top-down pixel storage, pitch is in bytes and might be bigger
+// than width * bytes_per_pixel.
+pub struct PixelBuffer {
+    pub bits: Vec<u8>,
+    pub width: i32,
+    pub height: i32,
+    pub pitch: i32,
+    pub format: PixelFormat,
+}
+
+// NOTE(Fermin): Borrowed rect of some buffer. bits starts at the view's top
+// left pixel and keeps the pitch of the buffer it came from.
+pub struct PixelView<'a> {
+    pub bits: &'a [u8],
+    pub width: i32,
+    pub height: i32,
+    pub pitch: i32,
+    pub format: PixelFormat,
+}
+
+pub struct PixelViewMut<'a> {
+    pub bits: &'a mut [u8],
+    pub width: i32,
+    pub height: i32,
+    pub pitch: i32,
+    pub format: PixelFormat,
+}
+
+fn pixel_offset(x: i32, y: i32, pitch: i32, format: PixelFormat) -> usize {
+    y as usize * pitch as usize + x as usize * format.bytes_per_pixel()
+}
+
+// NOTE(Fermin): Byte range a width x height rect starting at offset spans,
+// the last row doesn't need the padding at the end of the pitch.
+fn view_range(rect: &Rect, pitch: i32, format: PixelFormat) -> std::ops::Range<usize> {
+    if rect.is_empty() {
+        return 0..0;
+    }
+    let start = pixel_offset(rect.min_x, rect.min_y, pitch, format);
+    let end = start
+        + (rect.height() as usize - 1) * pitch as usize
+        + rect.width() as usize * format.bytes_per_pixel();
+    start..end
+}
+
+impl PixelBuffer {
+    pub fn new(width: i32, height: i32, format: PixelFormat) -> PixelBuffer {
+        PixelBuffer::try_new(width, height, format)
+            .unwrap_or_else(|| panic!("{}x{} doesn't fit in a pixel buffer", width, height))
+    }
+
+    // NOTE(Fermin): None when the size is negative or the bytes don't fit
+    // in the i32 offsets everything else uses.
+    pub fn try_new(width: i32, height: i32, format: PixelFormat) -> Option<PixelBuffer> {
+        let len = PixelBuffer::byte_len(width, height, format)?;
+        Some(PixelBuffer {
+            bits: vec![0; len],
+            width,
+            height,
+            pitch: width * format.bytes_per_pixel() as i32,
+            format,
+        })
+    }
+
+    pub fn byte_len(width: i32, height: i32, format: PixelFormat) -> Option<usize> {
+        if width < 0 || height < 0 {
+            return None;
+        }
+        let pitch = width.checked_mul(format.bytes_per_pixel() as i32)?;
+        pitch.checked_mul(height).map(|len| len as usize)
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::from_pos_size(0, 0, self.width, self.height)
+    }
+
+    // NOTE(Fermin): Drawing goes through rows, reading single pixels is
+    // for tests.
+    #[allow(dead_code)]
+    pub fn get(&self, x: i32, y: i32) -> Option<Color> {
+        self.view(&self.bounds()).get(x, y)
+    }
+
+    // NOTE(Fermin): Returns false and leaves the buffer alone when x, y is
+    // outside of it.
+    pub fn set(&mut self, x: i32, y: i32, color: &Color) -> bool {
+        let bounds = self.bounds();
+        self.view_mut(&bounds).set(x, y, color)
+    }
+
+    pub fn fill(&mut self, color: &Color) {
+        let bounds = self.bounds();
+        self.view_mut(&bounds).fill(color);
+    }
+
+    // NOTE(Fermin): Only the width * bytes_per_pixel part of every row
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        let row_len = self.width as usize * self.format.bytes_per_pixel();
+        self.bits
+            .chunks(self.pitch as usize)
+            .take(self.height as usize)
+            .map(move |row| &row[..row_len])
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [u8]> {
+        let row_len = self.width as usize * self.format.bytes_per_pixel();
+        self.bits
+            .chunks_mut(self.pitch as usize)
+            .take(self.height as usize)
+            .map(move |row| &mut row[..row_len])
+    }
+
+    // NOTE(Fermin): rect is clipped to the buffer, so the view might be
+    // smaller than asked for or even empty.
+    pub fn view(&self, rect: &Rect) -> PixelView<'_> {
+        let clipped = rect.intersection(&self.bounds());
+        let range = view_range(&clipped, self.pitch, self.format);
+        PixelView {
+            bits: &self.bits[range],
+            width: clipped.width(),
+            height: clipped.height(),
+            pitch: self.pitch,
+            format: self.format,
+        }
+    }
+
+    pub fn view_mut(&mut self, rect: &Rect) -> PixelViewMut<'_> {
+        let clipped = rect.intersection(&self.bounds());
+        let range = view_range(&clipped, self.pitch, self.format);
+        PixelViewMut {
+            bits: &mut self.bits[range],
+            width: clipped.width(),
+            height: clipped.height(),
+            pitch: self.pitch,
+            format: self.format,
+        }
+    }
+
+    pub fn blit(&mut self, src: &PixelView, dest_x: i32, dest_y: i32) {
+        let bounds = self.bounds();
+        self.view_mut(&bounds).blit(src, dest_x, dest_y);
+    }
+
+    pub fn blit_scaled(&mut self, src: &PixelView, dest_rect: &Rect) {
+        let bounds = self.bounds();
+        self.view_mut(&bounds).blit_scaled(src, dest_rect);
+    }
+}
+
+impl PixelView<'_> {
+    #[allow(dead_code)]
+    pub fn get(&self, x: i32, y: i32) -> Option<Color> {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return None;
+        }
+        let offset = pixel_offset(x, y, self.pitch, self.format);
+        Some(self.format.decode(&self.bits[offset..]))
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        let row_len = self.width as usize * self.format.bytes_per_pixel();
+        (0..self.height as usize).map(move |y| {
+            let start = y * self.pitch as usize;
+            &self.bits[start..start + row_len]
+        })
+    }
+}
+
+impl PixelViewMut<'_> {
+    pub fn set(&mut self, x: i32, y: i32, color: &Color) -> bool {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return false;
+        }
+        let offset = pixel_offset(x, y, self.pitch, self.format);
+        let bytes_per_pixel = self.format.bytes_per_pixel();
+        self.bits[offset..offset + bytes_per_pixel].copy_from_slice(&self.format.encode(color));
+        true
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [u8]> {
+        let row_len = self.width as usize * self.format.bytes_per_pixel();
+        let height = self.height as usize;
+        self.bits
+            .chunks_mut(self.pitch as usize)
+            .take(height)
+            .map(move |row| &mut row[..row_len])
+    }
+
+    pub fn fill(&mut self, color: &Color) {
+        let pixel = self.format.encode(color);
+        let bytes_per_pixel = self.format.bytes_per_pixel();
+        for row in self.rows_mut() {
+            for dest in row.chunks_exact_mut(bytes_per_pixel) {
+                dest.copy_from_slice(&pixel);
+            }
+        }
+    }
+
+    // NOTE(Fermin): Copies src with its top left at dest_x, dest_y. Whatever
+    // falls outside of this view is clipped and formats are converted.
+    pub fn blit(&mut self, src: &PixelView, dest_x: i32, dest_y: i32) {
+        let dest_rect = Rect::from_pos_size(dest_x, dest_y, src.width, src.height)
+            .intersection(&Rect::from_pos_size(0, 0, self.width, self.height));
+        if dest_rect.is_empty() {
+            return;
+        }
+
+        let (src_format, dest_format) = (src.format, self.format);
+        let src_bytes_per_pixel = src_format.bytes_per_pixel();
+        let dest_bytes_per_pixel = dest_format.bytes_per_pixel();
+        for y in dest_rect.min_y..dest_rect.max_y {
+            let src_start =
+                pixel_offset(dest_rect.min_x - dest_x, y - dest_y, src.pitch, src.format);
+            let dest_start = pixel_offset(dest_rect.min_x, y, self.pitch, self.format);
+            let src_row =
+                &src.bits[src_start..src_start + dest_rect.width() as usize * src_bytes_per_pixel];
+            let dest_row = &mut self.bits
+                [dest_start..dest_start + dest_rect.width() as usize * dest_bytes_per_pixel];
+
+            if src_format == dest_format {
+                dest_row.copy_from_slice(src_row);
+            } else {
+                for (dest_pixel, src_pixel) in dest_row
+                    .chunks_exact_mut(dest_bytes_per_pixel)
+                    .zip(src_row.chunks_exact(src_bytes_per_pixel))
+                {
+                    dest_pixel.copy_from_slice(&dest_format.encode(&src_format.decode(src_pixel)));
+                }
+            }
+        }
+    }
+
+    // NOTE(Fermin): Stretches src over dest_rect with bilinear filtering,
+    // dest_rect can reach outside of this view and gets clipped.
+    pub fn blit_scaled(&mut self, src: &PixelView, dest_rect: &Rect) {
+        let clipped = dest_rect.intersection(&Rect::from_pos_size(0, 0, self.width, self.height));
+        if clipped.is_empty() || src.width == 0 || src.height == 0 {
+            return;
+        }
+
+        // NOTE(Fermin): Sample positions go through pixel centers and the
+        // edges are clamped, so a 1x1 source fills the rect with its color.
+        // Weights of the second tap are in 256ths.
+        let taps = |dest: i32, dest_min: i32, dest_size: i32, src_size: i32| {
+            let scale = src_size as f32 / dest_size as f32;
+            let src_pos =
+                (((dest - dest_min) as f32 + 0.5) * scale - 0.5).clamp(0.0, (src_size - 1) as f32);
+            let first = src_pos.floor() as i32;
+            let weight = ((src_pos - first as f32) * 256.0).round() as u64;
+            (first as usize, (first + 1).min(src_size - 1) as usize, weight)
+        };
+
+        // NOTE(Fermin): Every row samples the same columns
+        let src_bytes_per_pixel = src.format.bytes_per_pixel();
+        let columns: Vec<(usize, usize, u64)> = (clipped.min_x..clipped.max_x)
+            .map(|x| {
+                let (x0, x1, weight) = taps(x, dest_rect.min_x, dest_rect.width(), src.width);
+                (x0 * src_bytes_per_pixel, x1 * src_bytes_per_pixel, weight)
+            })
+            .collect();
+
+        let dest_format = self.format;
+        let dest_bytes_per_pixel = dest_format.bytes_per_pixel();
+        let src_row = |y: usize| &src.bits[y * src.pitch as usize..];
+        for y in clipped.min_y..clipped.max_y {
+            let (y0, y1, weight_y) = taps(y, dest_rect.min_y, dest_rect.height(), src.height);
+            let (top_row, bottom_row) = (src_row(y0), src_row(y1));
+            let dest_start = pixel_offset(clipped.min_x, y, self.pitch, dest_format);
+            let dest_row = &mut self.bits
+                [dest_start..dest_start + clipped.width() as usize * dest_bytes_per_pixel];
+
+            for (dest, &(x0, x1, weight_x)) in
+                dest_row.chunks_exact_mut(dest_bytes_per_pixel).zip(&columns)
+            {
+                let top = filter(spread(&top_row[x0..]), spread(&top_row[x1..]), weight_x);
+                let bottom =
+                    filter(spread(&bottom_row[x0..]), spread(&bottom_row[x1..]), weight_x);
+                let pixel = gather(filter(top, bottom, weight_y));
+                if src.format == dest_format {
+                    dest.copy_from_slice(&pixel);
+                } else {
+                    dest.copy_from_slice(&dest_format.encode(&src.format.decode(&pixel)));
+                }
+            }
+        }
+    }
+}
+
+// NOTE(Fermin): The 4 bytes of a pixel 16 bits apart, room enough to filter
+// all of them with one multiply.
+fn spread(pixel: &[u8]) -> u64 {
+    let [a, b, c, d] = [pixel[0], pixel[1], pixel[2], pixel[3]].map(u64::from);
+    a | b << 16 | c << 32 | d << 48
+}
+
+fn gather(spread: u64) -> [u8; 4] {
+    [0, 16, 32, 48].map(|shift| (spread >> shift) as u8)
+}
+
+// NOTE(Fermin): Lerps spread pixels by weight in 256ths. Every channel stays
+// under 65536, so nothing carries into the next one.
+fn filter(a: u64, b: u64, weight: u64) -> u64 {
+    let sum = a * (256 - weight) + b * weight + 0x0080_0080_0080_0080;
+    (sum >> 8) & 0x00ff_00ff_00ff_00ff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Color = Color::rgba(255, 0, 0, 255);
+    const BLUE: Color = Color::rgba(0, 0, 255, 128);
+
+    #[test]
+    fn get_and_set_are_bounds_checked() {
+        let mut buffer = PixelBuffer::new(4, 3, PixelFormat::Bgra8);
+        assert!(buffer.set(3, 2, &RED));
+        assert!(!buffer.set(4, 0, &RED));
+        assert!(!buffer.set(0, -1, &RED));
+
+        assert_eq!(buffer.get(3, 2), Some(RED));
+        assert_eq!(buffer.get(0, 0), Some(Color::rgba(0, 0, 0, 0)));
+        assert_eq!(buffer.get(0, 3), None);
+        assert_eq!(&buffer.bits[buffer.bits.len() - 4..], &[0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn sizes_have_to_fit_in_i32() {
+        let format = PixelFormat::Bgra8;
+        assert_eq!(PixelBuffer::byte_len(3, 2, format), Some(24));
+        assert_eq!(PixelBuffer::byte_len(0, 0, format), Some(0));
+        assert_eq!(
+            PixelBuffer::byte_len(65535, 8191, format),
+            Some(65535 * 8191 * 4)
+        );
+        assert_eq!(PixelBuffer::byte_len(65535, 65535, format), None);
+        assert_eq!(PixelBuffer::byte_len(i32::MAX, 1, format), None);
+        assert_eq!(PixelBuffer::byte_len(-1, 1, format), None);
+        assert!(PixelBuffer::try_new(70_000, 70_000, format).is_none());
+        assert_eq!(PixelBuffer::try_new(5, 7, format).unwrap().pitch, 20);
+    }
+
+    #[test]
+    fn formats_store_channels_in_their_own_order() {
+        let mut bgra = PixelBuffer::new(1, 1, PixelFormat::Bgra8);
+        let mut rgba = PixelBuffer::new(1, 1, PixelFormat::Rgba8);
+        bgra.set(0, 0, &BLUE);
+        rgba.set(0, 0, &BLUE);
+
+        assert_eq!(bgra.bits, vec![255, 0, 0, 128]);
+        assert_eq!(rgba.bits, vec![0, 0, 255, 128]);
+        assert_eq!(bgra.get(0, 0), rgba.get(0, 0));
+    }
+
+    #[test]
+    fn views_are_clipped_and_offset() {
+        let mut buffer = PixelBuffer::new(8, 8, PixelFormat::Rgba8);
+        buffer.set(6, 7, &RED);
+
+        let view = buffer.view(&Rect::from_pos_size(5, 5, 10, 10));
+        assert_eq!((view.width, view.height), (3, 3));
+        assert_eq!(view.get(1, 2), Some(RED));
+        assert_eq!(view.get(3, 0), None);
+        assert_eq!(view.rows().count(), 3);
+        assert!(view.rows().all(|row| row.len() == 3 * 4));
+
+        let empty = buffer.view(&Rect::from_pos_size(20, 20, 4, 4));
+        assert_eq!((empty.width, empty.height), (0, 0));
+        assert_eq!(empty.get(0, 0), None);
+    }
+
+    #[test]
+    fn writing_through_a_view_only_touches_its_rect() {
+        let mut buffer = PixelBuffer::new(6, 4, PixelFormat::Bgra8);
+        buffer.view_mut(&Rect::from_pos_size(2, 1, 2, 2)).fill(&RED);
+
+        for y in 0..4 {
+            for x in 0..6 {
+                let inside = (2..4).contains(&x) && (1..3).contains(&y);
+                assert_eq!(buffer.get(x, y) == Some(RED), inside, "{}, {}", x, y);
+            }
+        }
+        assert_eq!(buffer.rows().count(), 4);
+        assert_eq!(buffer.rows_mut().count(), 4);
+    }
+
+    #[test]
+    fn blit_clips_and_converts_formats() {
+        let mut src = PixelBuffer::new(3, 3, PixelFormat::Rgba8);
+        src.fill(&BLUE);
+        src.set(0, 0, &RED);
+
+        let mut dest = PixelBuffer::new(4, 4, PixelFormat::Bgra8);
+        dest.blit(&src.view(&src.bounds()), -1, 2);
+
+        // NOTE(Fermin): Only src's (1..3, 0..2) lands inside dest
+        assert_eq!(dest.get(0, 2), Some(BLUE));
+        assert_eq!(dest.get(1, 3), Some(BLUE));
+        assert_eq!(dest.get(2, 2), Some(Color::rgba(0, 0, 0, 0)));
+        assert_eq!(dest.get(0, 1), Some(Color::rgba(0, 0, 0, 0)));
+
+        dest.blit(&src.view(&Rect::from_pos_size(0, 0, 1, 1)), 3, 0);
+        assert_eq!(dest.get(3, 0), Some(RED));
+    }
+
+    #[test]
+    fn blit_scaled_filters_and_clips() {
+        let mut src = PixelBuffer::new(2, 1, PixelFormat::Bgra8);
+        src.set(0, 0, &Color::rgba(0, 0, 0, 255));
+        src.set(1, 0, &Color::rgba(200, 100, 0, 255));
+
+        let mut dest = PixelBuffer::new(4, 2, PixelFormat::Rgba8);
+        dest.blit_scaled(&src.view(&src.bounds()), &Rect::from_pos_size(0, 0, 4, 2));
+        assert_eq!(dest.get(0, 0), Some(Color::rgba(0, 0, 0, 255)));
+        assert_eq!(dest.get(1, 1), Some(Color::rgba(50, 25, 0, 255)));
+        assert_eq!(dest.get(2, 0), Some(Color::rgba(150, 75, 0, 255)));
+        assert_eq!(dest.get(3, 1), Some(Color::rgba(200, 100, 0, 255)));
+
+        let mut clipped = PixelBuffer::new(2, 2, PixelFormat::Bgra8);
+        clipped.blit_scaled(&src.view(&src.bounds()), &Rect::from_pos_size(-2, 1, 4, 4));
+        assert_eq!(clipped.get(0, 0), Some(Color::rgba(0, 0, 0, 0)));
+        assert_eq!(clipped.get(1, 1), Some(Color::rgba(200, 100, 0, 255)));
+    }
+}
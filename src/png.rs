@@ -0,0 +1,715 @@
+use crate::color::Color;
+use crate::deflate::{zlib_compress, zlib_decompress};
+use crate::image::{check_image_size, ImageError};
+use crate::pixel_buffer::{PixelBuffer, PixelFormat};
+
+// --------------------------------------------------------------------
+// NOTE(Fermin): Png reading and writing. The decoder takes every color type
+// and bit depth the spec allows, interlaced or not, and hands back 8 bit
+// straight alpha like the bmp one does. 16 bit samples keep their high
+// byte. The encoder always writes 8 bit RGBA.
+// --------------------------------------------------------------------
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+const COLOR_TYPE_GRAY: u8 = 0;
+const COLOR_TYPE_RGB: u8 = 2;
+const COLOR_TYPE_PALETTE: u8 = 3;
+const COLOR_TYPE_GRAY_ALPHA: u8 = 4;
+const COLOR_TYPE_RGBA: u8 = 6;
+
+const FILTER_NONE: u8 = 0;
+const FILTER_SUB: u8 = 1;
+const FILTER_UP: u8 = 2;
+const FILTER_AVERAGE: u8 = 3;
+const FILTER_PAETH: u8 = 4;
+
+// NOTE(Fermin): Adam7 passes as x start, y start, x step, y step
+const ADAM7_PASSES: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+const fn make_crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+const CRC_TABLE: [u32; 256] = make_crc_table();
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff;
+    for &byte in bytes {
+        crc = CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc ^ 0xffff_ffff
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let crc_start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[crc_start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// NOTE(Fermin): a is the byte one pixel to the left, b the one above and c
+// the one above a. Bytes off the image count as 0.
+fn predict(filter: u8, a: u8, b: u8, c: u8) -> u8 {
+    match filter {
+        FILTER_SUB => a,
+        FILTER_UP => b,
+        FILTER_AVERAGE => ((a as u16 + b as u16) / 2) as u8,
+        FILTER_PAETH => paeth(a, b, c),
+        _ => 0,
+    }
+}
+
+fn filter_row(filter: u8, row: &[u8], prior: &[u8], bytes_per_pixel: usize, out: &mut Vec<u8>) {
+    out.push(filter);
+    for i in 0..row.len() {
+        let a = if i >= bytes_per_pixel {
+            row[i - bytes_per_pixel]
+        } else {
+            0
+        };
+        let c = if i >= bytes_per_pixel {
+            prior[i - bytes_per_pixel]
+        } else {
+            0
+        };
+        out.push(row[i].wrapping_sub(predict(filter, a, prior[i], c)));
+    }
+}
+
+// NOTE(Fermin): row comes in filtered and leaves unfiltered, prior is the
+// previous row of the same pass, already unfiltered.
+fn unfilter_row(
+    filter: u8,
+    row: &mut [u8],
+    prior: &[u8],
+    bytes_per_pixel: usize,
+) -> Result<(), ImageError> {
+    if filter > FILTER_PAETH {
+        return Err(ImageError::Corrupt("unknown png filter type"));
+    }
+    for i in 0..row.len() {
+        let a = if i >= bytes_per_pixel {
+            row[i - bytes_per_pixel]
+        } else {
+            0
+        };
+        let c = if i >= bytes_per_pixel {
+            prior[i - bytes_per_pixel]
+        } else {
+            0
+        };
+        row[i] = row[i].wrapping_add(predict(filter, a, prior[i], c));
+    }
+    Ok(())
+}
+
+// NOTE(Fermin): rgba is width * height pixels, top row first, 4 bytes each.
+// Every row gets the filter whose output adds up to the smallest bytes as
+// signed values, the usual guess at what deflate will like best.
+pub fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let row_len = width as usize * 4;
+    assert_eq!(rgba.len(), row_len * height as usize);
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // NOTE(Fermin): 8 bits per channel, deflate, adaptive filters, no interlace
+    header.extend_from_slice(&[8, COLOR_TYPE_RGBA, 0, 0, 0]);
+
+    let mut filtered = Vec::with_capacity((row_len + 1) * height as usize);
+    let mut candidate = Vec::with_capacity(row_len + 1);
+    let mut best = Vec::with_capacity(row_len + 1);
+    let empty_row = vec![0; row_len];
+    let mut prior = empty_row.as_slice();
+    for row in rgba.chunks_exact(row_len.max(1)).take(height as usize) {
+        let mut best_cost = u64::MAX;
+        for filter in FILTER_NONE..=FILTER_PAETH {
+            candidate.clear();
+            filter_row(filter, row, prior, 4, &mut candidate);
+            let cost: u64 = candidate[1..]
+                .iter()
+                .map(|&byte| (byte as i8).unsigned_abs() as u64)
+                .sum();
+            if cost < best_cost {
+                best_cost = cost;
+                std::mem::swap(&mut best, &mut candidate);
+            }
+        }
+        filtered.extend_from_slice(&best);
+        prior = row;
+    }
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&SIGNATURE);
+    write_chunk(&mut out, b"IHDR", &header);
+    write_chunk(&mut out, b"IDAT", &zlib_compress(&filtered));
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Header, ImageError> {
+        if data.len() != 13 {
+            return Err(ImageError::Corrupt("bad png header size"));
+        }
+        let width = u32::from_be_bytes(data[0..4].try_into().unwrap());
+        let height = u32::from_be_bytes(data[4..8].try_into().unwrap());
+        let (bit_depth, color_type) = (data[8], data[9]);
+        let (compression, filter_method, interlace) = (data[10], data[11], data[12]);
+        if width == 0 || height == 0 {
+            return Err(ImageError::Corrupt("bad png size"));
+        }
+        check_image_size(width as usize, height as usize)?;
+        let allowed_depths: &[u8] = match color_type {
+            COLOR_TYPE_GRAY => &[1, 2, 4, 8, 16],
+            COLOR_TYPE_PALETTE => &[1, 2, 4, 8],
+            COLOR_TYPE_RGB | COLOR_TYPE_GRAY_ALPHA | COLOR_TYPE_RGBA => &[8, 16],
+            _ => return Err(ImageError::Corrupt("unknown png color type")),
+        };
+        if !allowed_depths.contains(&bit_depth) {
+            return Err(ImageError::Corrupt("bad png bit depth for its color type"));
+        }
+        if compression != 0 || filter_method != 0 || interlace > 1 {
+            return Err(ImageError::Unsupported(format!(
+                "png compression {}, filter method {}, interlace {}",
+                compression, filter_method, interlace
+            )));
+        }
+        Ok(Header {
+            width: width as usize,
+            height: height as usize,
+            bit_depth,
+            color_type,
+            interlaced: interlace == 1,
+        })
+    }
+
+    fn channels(&self) -> usize {
+        match self.color_type {
+            COLOR_TYPE_RGB => 3,
+            COLOR_TYPE_GRAY_ALPHA => 2,
+            COLOR_TYPE_RGBA => 4,
+            _ => 1,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    // NOTE(Fermin): Filters look this many bytes back, at least one
+    fn filter_distance(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    fn row_bytes(&self, width: usize) -> usize {
+        (width * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    // NOTE(Fermin): Size and placement of every pass with pixels in it,
+    // a plain image is one pass covering everything.
+    fn passes(&self) -> Vec<(usize, usize, usize, usize, usize, usize)> {
+        let passes: &[(usize, usize, usize, usize)] = if self.interlaced {
+            &ADAM7_PASSES
+        } else {
+            &[(0, 0, 1, 1)]
+        };
+        passes
+            .iter()
+            .map(|&(x0, y0, dx, dy)| {
+                let width = self.width.saturating_sub(x0).div_ceil(dx);
+                let height = self.height.saturating_sub(y0).div_ceil(dy);
+                (x0, y0, dx, dy, width, height)
+            })
+            .filter(|&(_, _, _, _, width, height)| width > 0 && height > 0)
+            .collect()
+    }
+}
+
+fn read_sample(row: &[u8], index: usize, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+        8 => row[index] as u16,
+        _ => {
+            let bit = index * bit_depth as usize;
+            let shift = 8 - bit_depth as usize - bit % 8;
+            (row[bit / 8] >> shift) as u16 & ((1 << bit_depth) - 1)
+        }
+    }
+}
+
+fn sample_to_u8(sample: u16, bit_depth: u8) -> u8 {
+    match bit_depth {
+        16 => (sample >> 8) as u8,
+        8 => sample as u8,
+        _ => (sample as u32 * 255 / ((1 << bit_depth) - 1)) as u8,
+    }
+}
+
+// NOTE(Fermin): Chunks after IEND are ignored, so are ancillary chunks
+// (lowercase first letter) other than tRNS. Any other critical chunk means
+// the image can't be decoded right.
+pub fn decode_png(bytes: &[u8]) -> Result<PixelBuffer, ImageError> {
+    if !bytes.starts_with(&SIGNATURE) {
+        return Err(ImageError::Corrupt("missing png signature"));
+    }
+
+    let mut header: Option<Header> = None;
+    let mut palette: Vec<Color> = Vec::new();
+    let mut transparency: Vec<u8> = Vec::new();
+    let mut compressed = Vec::new();
+    let mut at = SIGNATURE.len();
+    loop {
+        let length = bytes
+            .get(at..at + 4)
+            .ok_or(ImageError::Corrupt("truncated png chunk"))?;
+        let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+        let chunk = bytes
+            .get(at + 4..at + 8 + length)
+            .ok_or(ImageError::Corrupt("truncated png chunk"))?;
+        let crc = bytes
+            .get(at + 8 + length..at + 12 + length)
+            .ok_or(ImageError::Corrupt("truncated png chunk"))?;
+        if crc32(chunk) != u32::from_be_bytes(crc.try_into().unwrap()) {
+            return Err(ImageError::Corrupt("png chunk checksum doesn't match"));
+        }
+        at += 12 + length;
+
+        let (kind, data) = (&chunk[..4], &chunk[4..]);
+        if header.is_none() && kind != b"IHDR" {
+            return Err(ImageError::Corrupt("png doesn't start with a header"));
+        }
+        match kind {
+            b"IHDR" => {
+                if header.is_some() {
+                    return Err(ImageError::Corrupt("png has two headers"));
+                }
+                header = Some(Header::parse(data)?);
+            }
+            b"PLTE" => {
+                if data.len() % 3 != 0 || data.len() > 256 * 3 {
+                    return Err(ImageError::Corrupt("bad png palette size"));
+                }
+                palette = data
+                    .chunks_exact(3)
+                    .map(|entry| Color::rgba(entry[0], entry[1], entry[2], 255))
+                    .collect();
+            }
+            b"tRNS" => transparency = data.to_vec(),
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ if kind[0].is_ascii_lowercase() => {}
+            _ => {
+                return Err(ImageError::Unsupported(format!(
+                    "png chunk {}",
+                    String::from_utf8_lossy(kind)
+                )))
+            }
+        }
+    }
+    let header = header.unwrap();
+    if compressed.is_empty() {
+        return Err(ImageError::Corrupt("png has no image data"));
+    }
+    if header.color_type == COLOR_TYPE_PALETTE {
+        if palette.is_empty() {
+            return Err(ImageError::Corrupt("png is missing its palette"));
+        }
+        for (entry, &alpha) in palette.iter_mut().zip(&transparency) {
+            entry.a = alpha;
+        }
+    }
+
+    // NOTE(Fermin): Gray and RGB images can name one color as transparent,
+    // matched against the samples before they get scaled to 8 bits.
+    let key: Option<Vec<u16>> = match header.color_type {
+        COLOR_TYPE_GRAY | COLOR_TYPE_RGB if !transparency.is_empty() => {
+            if transparency.len() != header.channels() * 2 {
+                return Err(ImageError::Corrupt("bad png transparency size"));
+            }
+            Some(
+                transparency
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                    .collect(),
+            )
+        }
+        _ => None,
+    };
+
+    let passes = header.passes();
+    let expected_size: usize = passes
+        .iter()
+        .map(|&(_, _, _, _, width, height)| (header.row_bytes(width) + 1) * height)
+        .sum();
+    // NOTE(Fermin): Small streams can inflate to a lot, only what the rows
+    // need comes out.
+    let mut data = zlib_decompress(&compressed, expected_size).map_err(ImageError::Corrupt)?;
+    if data.len() < expected_size {
+        return Err(ImageError::Corrupt("not enough png image data"));
+    }
+
+    let mut buffer = PixelBuffer::new(
+        header.width as i32,
+        header.height as i32,
+        PixelFormat::Bgra8,
+    );
+    let channels = header.channels();
+    let depth = header.bit_depth;
+    let mut samples = [0u16; 4];
+    let mut at = 0;
+    for (x0, y0, dx, dy, width, height) in passes {
+        let row_bytes = header.row_bytes(width);
+        let mut prior = vec![0; row_bytes];
+        for pass_y in 0..height {
+            let filter = data[at];
+            let row = &mut data[at + 1..at + 1 + row_bytes];
+            unfilter_row(filter, row, &prior, header.filter_distance())?;
+            at += 1 + row_bytes;
+
+            for pass_x in 0..width {
+                for (channel, sample) in samples[..channels].iter_mut().enumerate() {
+                    *sample = read_sample(row, pass_x * channels + channel, depth);
+                }
+                let value = |channel: usize| sample_to_u8(samples[channel], depth);
+                let is_key = key.as_deref() == Some(&samples[..channels]);
+                let opaque_unless_key = if is_key { 0 } else { 255 };
+                let color = match header.color_type {
+                    COLOR_TYPE_GRAY => Color::rgba(value(0), value(0), value(0), opaque_unless_key),
+                    COLOR_TYPE_RGB => Color::rgba(value(0), value(1), value(2), opaque_unless_key),
+                    COLOR_TYPE_PALETTE => *palette
+                        .get(samples[0] as usize)
+                        .ok_or(ImageError::Corrupt("png palette index out of range"))?,
+                    COLOR_TYPE_GRAY_ALPHA => Color::rgba(value(0), value(0), value(0), value(1)),
+                    _ => Color::rgba(value(0), value(1), value(2), value(3)),
+                };
+                buffer.set((x0 + pass_x * dx) as i32, (y0 + pass_y * dy) as i32, &color);
+            }
+            prior.copy_from_slice(row);
+        }
+    }
+
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::adler32;
+
+    fn chunks(png: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut chunks = Vec::new();
+        let mut at = 8;
+        while at < png.len() {
+            let len = u32::from_be_bytes(png[at..at + 4].try_into().unwrap()) as usize;
+            let kind = &png[at + 4..at + 8];
+            let data = &png[at + 8..at + 8 + len];
+            let crc = u32::from_be_bytes(png[at + 8 + len..at + 12 + len].try_into().unwrap());
+            assert_eq!(crc32(&png[at + 4..at + 8 + len]), crc);
+            chunks.push((kind.to_vec(), data.to_vec()));
+            at += 12 + len;
+        }
+        chunks
+    }
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+    }
+
+    // NOTE(Fermin): Walks the chunks and undoes the filters by hand
+    #[test]
+    fn writes_valid_chunks_around_the_rows() {
+        let (width, height) = (300, 60);
+        let rgba: Vec<u8> = (0..width * height * 4).map(|i| (i * 7) as u8).collect();
+        let png = encode_png(width, height, &rgba);
+        assert_eq!(png[..8], SIGNATURE);
+
+        let chunks = chunks(&png);
+        let kinds: Vec<&[u8]> = chunks.iter().map(|(kind, _)| kind.as_slice()).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1[..8], [0, 0, 1, 44, 0, 0, 0, 60]);
+
+        let zlib = &chunks[1].1;
+        let filtered = zlib_decompress(zlib, usize::MAX).unwrap();
+        assert_eq!(zlib[zlib.len() - 4..], adler32(&filtered).to_be_bytes());
+        // NOTE(Fermin): The pattern repeats, it had better compress
+        assert!(zlib.len() < rgba.len() / 10);
+
+        let row_len = width as usize * 4;
+        let mut prior = vec![0; row_len];
+        for (row, expected) in filtered
+            .chunks_exact(row_len + 1)
+            .zip(rgba.chunks_exact(row_len))
+        {
+            let mut unfiltered = row[1..].to_vec();
+            unfilter_row(row[0], &mut unfiltered, &prior, 4).unwrap();
+            assert_eq!(unfiltered, expected);
+            prior = unfiltered;
+        }
+    }
+
+    #[test]
+    fn encoded_images_decode_the_same() {
+        let (width, height) = (45, 31);
+        let rgba: Vec<u8> = (0..width * height)
+            .flat_map(|i| {
+                let (x, y) = (i % width, i / width);
+                [
+                    (x * 5) as u8,
+                    (y * 8) as u8,
+                    (x * y) as u8,
+                    (255 - x - y) as u8,
+                ]
+            })
+            .collect();
+        let image = decode_png(&encode_png(width, height, &rgba)).unwrap();
+        assert_eq!((image.width, image.height), (width as i32, height as i32));
+        for (i, expected) in rgba.chunks_exact(4).enumerate() {
+            let (x, y) = (i as i32 % image.width, i as i32 / image.width);
+            let expected = Color::rgba(expected[0], expected[1], expected[2], expected[3]);
+            assert_eq!(image.get(x, y), Some(expected));
+        }
+    }
+
+    // --------------------------------------------------------------------
+    // NOTE(Fermin): tests/png/generate.py writes these with Python's zlib,
+    // except handmade_* that come from its own fixed Huffman encoder. Every
+    // sample comes from the same formula as here.
+    // --------------------------------------------------------------------
+    fn sample(x: u64, y: u64, channel: u64, depth: u8) -> u16 {
+        ((x * 2741 + y * 6521 + channel * 12345 + x * y * 17) % (1 << depth)) as u16
+    }
+
+    fn expected_color(color_type: u8, depth: u8, transparent: bool, x: u64, y: u64) -> Color {
+        let raw = |channel: u64| sample(x, y, channel, depth);
+        let value = |channel: u64| sample_to_u8(raw(channel), depth);
+        let is_key =
+            |channels: u64| transparent && (0..channels).all(|c| raw(c) == sample(1, 0, c, depth));
+        match color_type {
+            COLOR_TYPE_GRAY => Color::rgba(
+                value(0),
+                value(0),
+                value(0),
+                if is_key(1) { 0 } else { 255 },
+            ),
+            COLOR_TYPE_RGB => Color::rgba(
+                value(0),
+                value(1),
+                value(2),
+                if is_key(3) { 0 } else { 255 },
+            ),
+            COLOR_TYPE_PALETTE => {
+                let index = raw(0) as u64;
+                let has_alpha = transparent && index < (1 << depth) / 2 + 1;
+                Color::rgba(
+                    (index * 37 % 256) as u8,
+                    (index * 91 % 256) as u8,
+                    (index * 53 % 256) as u8,
+                    if has_alpha {
+                        (index * 71 % 256) as u8
+                    } else {
+                        255
+                    },
+                )
+            }
+            COLOR_TYPE_GRAY_ALPHA => Color::rgba(value(0), value(0), value(0), value(1)),
+            _ => Color::rgba(value(0), value(1), value(2), value(3)),
+        }
+    }
+
+    macro_rules! corpus {
+        ($(($name:literal, $color_type:expr, $depth:literal, $transparent:literal)),* $(,)?) => {
+            [$(
+                (
+                    $name,
+                    &include_bytes!(concat!("../tests/png/", $name, ".png"))[..],
+                    $color_type,
+                    $depth,
+                    $transparent,
+                ),
+            )*]
+        };
+    }
+
+    #[test]
+    fn decodes_every_color_type_and_depth() {
+        let corpus = corpus![
+            ("gray1", COLOR_TYPE_GRAY, 1, false),
+            ("gray1_interlaced", COLOR_TYPE_GRAY, 1, false),
+            ("gray2", COLOR_TYPE_GRAY, 2, false),
+            ("gray2_interlaced", COLOR_TYPE_GRAY, 2, false),
+            ("gray4", COLOR_TYPE_GRAY, 4, false),
+            ("gray4_interlaced", COLOR_TYPE_GRAY, 4, false),
+            ("gray8", COLOR_TYPE_GRAY, 8, false),
+            ("gray8_interlaced", COLOR_TYPE_GRAY, 8, false),
+            ("gray16", COLOR_TYPE_GRAY, 16, false),
+            ("gray16_interlaced", COLOR_TYPE_GRAY, 16, false),
+            ("rgb8", COLOR_TYPE_RGB, 8, false),
+            ("rgb8_interlaced", COLOR_TYPE_RGB, 8, false),
+            ("rgb16", COLOR_TYPE_RGB, 16, false),
+            ("rgb16_interlaced", COLOR_TYPE_RGB, 16, false),
+            ("palette1", COLOR_TYPE_PALETTE, 1, false),
+            ("palette1_interlaced", COLOR_TYPE_PALETTE, 1, false),
+            ("palette2", COLOR_TYPE_PALETTE, 2, false),
+            ("palette2_interlaced", COLOR_TYPE_PALETTE, 2, false),
+            ("palette4", COLOR_TYPE_PALETTE, 4, false),
+            ("palette4_interlaced", COLOR_TYPE_PALETTE, 4, false),
+            ("palette8", COLOR_TYPE_PALETTE, 8, false),
+            ("palette8_interlaced", COLOR_TYPE_PALETTE, 8, false),
+            ("gray_alpha8", COLOR_TYPE_GRAY_ALPHA, 8, false),
+            ("gray_alpha8_interlaced", COLOR_TYPE_GRAY_ALPHA, 8, false),
+            ("gray_alpha16", COLOR_TYPE_GRAY_ALPHA, 16, false),
+            ("gray_alpha16_interlaced", COLOR_TYPE_GRAY_ALPHA, 16, false),
+            ("rgba8", COLOR_TYPE_RGBA, 8, false),
+            ("rgba8_interlaced", COLOR_TYPE_RGBA, 8, false),
+            ("rgba16", COLOR_TYPE_RGBA, 16, false),
+            ("rgba16_interlaced", COLOR_TYPE_RGBA, 16, false),
+            ("gray2_transparent", COLOR_TYPE_GRAY, 2, true),
+            ("gray16_transparent", COLOR_TYPE_GRAY, 16, true),
+            ("rgb8_transparent", COLOR_TYPE_RGB, 8, true),
+            ("rgb16_transparent", COLOR_TYPE_RGB, 16, true),
+            ("palette4_transparent", COLOR_TYPE_PALETTE, 4, true),
+            ("palette8_transparent", COLOR_TYPE_PALETTE, 8, true),
+            ("rgba8_1x1_interlaced", COLOR_TYPE_RGBA, 8, false),
+            ("gray1_3x2_interlaced", COLOR_TYPE_GRAY, 1, false),
+            ("palette2_5x9_interlaced", COLOR_TYPE_PALETTE, 2, false),
+            ("handmade_rgb16_interlaced", COLOR_TYPE_RGB, 16, false),
+            ("handmade_rgba16", COLOR_TYPE_RGBA, 16, false),
+            ("handmade_palette8_transparent", COLOR_TYPE_PALETTE, 8, true),
+            ("handmade_gray4", COLOR_TYPE_GRAY, 4, false),
+        ];
+        for (name, bytes, color_type, depth, transparent) in corpus {
+            let image = decode_png(bytes).unwrap_or_else(|error| panic!("{}: {}", name, error));
+            let size = match name {
+                "rgba8_1x1_interlaced" => (1, 1),
+                "gray1_3x2_interlaced" => (3, 2),
+                "palette2_5x9_interlaced" => (5, 9),
+                _ => (37, 23),
+            };
+            assert_eq!((image.width, image.height), size, "{}", name);
+            for y in 0..image.height {
+                for x in 0..image.width {
+                    let expected =
+                        expected_color(color_type, depth, transparent, x as u64, y as u64);
+                    assert_eq!(image.get(x, y), Some(expected), "{} at {}, {}", name, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bad_pngs_are_reported() {
+        let png = include_bytes!("../tests/png/rgb8.png");
+        assert!(matches!(decode_png(b"BM"), Err(ImageError::Corrupt(_))));
+
+        // NOTE(Fermin): A byte inside IHDR's width
+        let mut bad_crc = png.to_vec();
+        bad_crc[18] ^= 1;
+        assert!(matches!(decode_png(&bad_crc), Err(ImageError::Corrupt(_))));
+
+        for length in [8, 20, 40, png.len() / 2, png.len() - 1] {
+            assert!(decode_png(&png[..length]).is_err());
+        }
+
+        let rgba = [1, 2, 3, 4];
+        let mut bad_zlib = encode_png(1, 1, &rgba);
+        let idat = 8 + 25;
+        bad_zlib[idat + 8] = 0x77;
+        let crc = crc32(&bad_zlib[idat + 4..bad_zlib.len() - 16]);
+        let crc_at = bad_zlib.len() - 16;
+        bad_zlib[crc_at..crc_at + 4].copy_from_slice(&crc.to_be_bytes());
+        assert!(matches!(decode_png(&bad_zlib), Err(ImageError::Corrupt(_))));
+
+        let mut unknown_chunk = SIGNATURE.to_vec();
+        write_chunk(&mut unknown_chunk, b"IHDR", &chunks(png)[0].1);
+        write_chunk(&mut unknown_chunk, b"ZZZZ", &[]);
+        assert!(matches!(
+            decode_png(&unknown_chunk),
+            Err(ImageError::Unsupported(_))
+        ));
+
+        // NOTE(Fermin): 20000x30000 1 bit gray claims a 2.4GB buffer
+        let mut huge = SIGNATURE.to_vec();
+        let mut header = chunks(png)[0].1.clone();
+        header[0..8].copy_from_slice(&[0, 0, 0x4e, 0x20, 0, 0, 0x75, 0x30]);
+        header[8..10].copy_from_slice(&[1, COLOR_TYPE_GRAY]);
+        write_chunk(&mut huge, b"IHDR", &header);
+        write_chunk(&mut huge, b"IDAT", &zlib_compress(&vec![0; 1 << 16]));
+        write_chunk(&mut huge, b"IEND", &[]);
+        assert!(matches!(decode_png(&huge), Err(ImageError::Unsupported(_))));
+
+        // NOTE(Fermin): Data past the last row never gets inflated
+        let mut too_much = SIGNATURE.to_vec();
+        write_chunk(
+            &mut too_much,
+            b"IHDR",
+            &chunks(&encode_png(1, 1, &rgba))[0].1,
+        );
+        let mut filtered = vec![0, 1, 2, 3, 4];
+        filtered.resize(1 << 20, 7);
+        write_chunk(&mut too_much, b"IDAT", &zlib_compress(&filtered));
+        write_chunk(&mut too_much, b"IEND", &[]);
+        let image = decode_png(&too_much).unwrap();
+        assert_eq!(image.get(0, 0), Some(Color::rgba(1, 2, 3, 4)));
+
+        let mut bad_depth = chunks(png)[0].1.clone();
+        bad_depth[8] = 4;
+        assert!(matches!(
+            Header::parse(&bad_depth),
+            Err(ImageError::Corrupt(_))
+        ));
+    }
+}
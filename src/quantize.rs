@@ -0,0 +1,313 @@
+use crate::pixel_buffer::PixelBuffer;
+
+// --------------------------------------------------------------------
+// NOTE(Fermin): Median cut down to a palette of at most 256 colors, and
+// mapping pixels onto it with optional ordered dithering.
+// --------------------------------------------------------------------
+
+// NOTE(Fermin): Colors get counted in bins of 5 bits per channel. Bins also
+// add up the real colors that land in them, so a palette entry is the
+// average of what it stands for and images with few colors keep them.
+const BIN_BITS: u32 = 5;
+const BIN_COUNT: usize = 1 << (3 * BIN_BITS);
+
+// NOTE(Fermin): Nearest palette entries are remembered per cell of 6 bits
+// per channel, looked up from the middle of the cell.
+const CACHE_BITS: u32 = 6;
+const CACHE_EMPTY: u16 = u16::MAX;
+
+// NOTE(Fermin): Ordered dithering nudges each pixel by a threshold that only
+// depends on where it is, so a pixel that doesn't change between frames
+// keeps its index. Error diffusion would carry every change across the rest
+// of the frame and make still areas of an animation shimmer.
+const BAYER: [[i32; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+fn bin_index(rgb: [u8; 3]) -> usize {
+    let shift = 8 - BIN_BITS;
+    ((rgb[0] as usize >> shift) << (2 * BIN_BITS))
+        | ((rgb[1] as usize >> shift) << BIN_BITS)
+        | (rgb[2] as usize >> shift)
+}
+
+fn bin_channel(bin: usize, channel: usize) -> u32 {
+    let mask = (1 << BIN_BITS) - 1;
+    (bin >> (BIN_BITS as usize * (2 - channel))) as u32 & mask
+}
+
+pub struct ColorHistogram {
+    counts: Vec<u32>,
+    sums: Vec<[u64; 3]>,
+}
+
+impl ColorHistogram {
+    pub fn new() -> ColorHistogram {
+        ColorHistogram {
+            counts: vec![0; BIN_COUNT],
+            sums: vec![[0; 3]; BIN_COUNT],
+        }
+    }
+
+    pub fn add(&mut self, rgb: [u8; 3]) {
+        let bin = bin_index(rgb);
+        self.counts[bin] += 1;
+        for (sum, value) in self.sums[bin].iter_mut().zip(rgb) {
+            *sum += value as u64;
+        }
+    }
+
+    // NOTE(Fermin): Alpha is ignored
+    pub fn add_buffer(&mut self, buffer: &PixelBuffer) {
+        let bytes_per_pixel = buffer.format.bytes_per_pixel();
+        for row in buffer.rows() {
+            for pixel in row.chunks_exact(bytes_per_pixel) {
+                let color = buffer.format.decode(pixel);
+                self.add([color.r, color.g, color.b]);
+            }
+        }
+    }
+}
+
+struct ColorBox {
+    bins: Vec<usize>,
+    population: u64,
+}
+
+impl ColorBox {
+    // NOTE(Fermin): The channel the box is widest in and how wide, in bins
+    fn widest_channel(&self) -> (usize, u32) {
+        (0..3)
+            .map(|channel| {
+                let values = self.bins.iter().map(|&bin| bin_channel(bin, channel));
+                let (min, max) = values.fold((u32::MAX, 0), |(min, max), value| {
+                    (min.min(value), max.max(value))
+                });
+                (channel, max - min)
+            })
+            .max_by_key(|&(channel, range)| (range, std::cmp::Reverse(channel)))
+            .unwrap()
+    }
+}
+
+// NOTE(Fermin): Keeps splitting the box with the most pixels times width at
+// its median, along its widest channel. An empty histogram gives black.
+pub fn median_cut(histogram: &ColorHistogram, max_colors: usize) -> Vec<[u8; 3]> {
+    let bins: Vec<usize> = (0..BIN_COUNT)
+        .filter(|&bin| histogram.counts[bin] > 0)
+        .collect();
+    if bins.is_empty() {
+        return vec![[0, 0, 0]];
+    }
+    let population =
+        |bins: &[usize]| -> u64 { bins.iter().map(|&bin| histogram.counts[bin] as u64).sum() };
+
+    let mut boxes = vec![ColorBox {
+        population: population(&bins),
+        bins,
+    }];
+    while boxes.len() < max_colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, color_box)| color_box.bins.len() > 1)
+            .max_by_key(|(_, color_box)| {
+                color_box.population * color_box.widest_channel().1 as u64
+            });
+        let Some((index, _)) = widest else {
+            break;
+        };
+
+        let mut color_box = boxes.swap_remove(index);
+        let (channel, _) = color_box.widest_channel();
+        color_box
+            .bins
+            .sort_by_key(|&bin| (bin_channel(bin, channel), bin));
+        let mut below = 0;
+        let mut split = 1;
+        for (at, &bin) in color_box.bins.iter().enumerate() {
+            below += histogram.counts[bin] as u64;
+            if below * 2 >= color_box.population {
+                split = (at + 1).clamp(1, color_box.bins.len() - 1);
+                break;
+            }
+        }
+        let upper = color_box.bins.split_off(split);
+        boxes.push(ColorBox {
+            population: population(&upper),
+            bins: upper,
+        });
+        boxes.push(ColorBox {
+            population: population(&color_box.bins),
+            bins: color_box.bins,
+        });
+    }
+
+    boxes
+        .iter()
+        .map(|color_box| {
+            let mut sums = [0u64; 3];
+            for &bin in &color_box.bins {
+                for (sum, bin_sum) in sums.iter_mut().zip(histogram.sums[bin]) {
+                    *sum += bin_sum;
+                }
+            }
+            let half = color_box.population / 2;
+            sums.map(|sum| ((sum + half) / color_box.population) as u8)
+        })
+        .collect()
+}
+
+pub struct PaletteMapper {
+    pub palette: Vec<[u8; 3]>,
+    cache: Vec<u16>,
+    // NOTE(Fermin): How far dithering can nudge a channel, about the gap
+    // between entries if the palette were spread evenly over the cube
+    spread: i32,
+}
+
+impl PaletteMapper {
+    pub fn new(palette: Vec<[u8; 3]>) -> PaletteMapper {
+        assert!(!palette.is_empty() && palette.len() <= 256);
+        let levels = (palette.len() as f32).cbrt();
+        PaletteMapper {
+            spread: (255.0 / (levels - 1.0).max(1.0)) as i32,
+            palette,
+            cache: vec![CACHE_EMPTY; 1 << (3 * CACHE_BITS)],
+        }
+    }
+
+    pub fn nearest(&mut self, rgb: [u8; 3]) -> u8 {
+        let shift = 8 - CACHE_BITS;
+        let cell = rgb.map(|value| value as usize >> shift);
+        let key = (cell[0] << (2 * CACHE_BITS)) | (cell[1] << CACHE_BITS) | cell[2];
+        if self.cache[key] == CACHE_EMPTY {
+            let center = cell.map(|value| ((value << shift) + (1 << (shift - 1))) as i32);
+            let distance = |entry: &[u8; 3]| -> i32 {
+                (0..3)
+                    .map(|channel| (entry[channel] as i32 - center[channel]).pow(2))
+                    .sum()
+            };
+            let (index, _) = self
+                .palette
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, entry)| distance(entry))
+                .unwrap();
+            self.cache[key] = index as u16;
+        }
+        self.cache[key] as u8
+    }
+
+    // NOTE(Fermin): One palette index per pixel, top row first
+    pub fn map_buffer(&mut self, buffer: &PixelBuffer, dither: bool) -> Vec<u8> {
+        let width = buffer.width as usize;
+        let bytes_per_pixel = buffer.format.bytes_per_pixel();
+        let mut indices = Vec::with_capacity(width * buffer.height as usize);
+        for (y, row) in buffer.rows().enumerate() {
+            for (x, pixel) in row.chunks_exact(bytes_per_pixel).enumerate() {
+                let color = buffer.format.decode(pixel);
+                let rgb = [color.r, color.g, color.b];
+                let mut index = self.nearest(rgb);
+                // NOTE(Fermin): Colors the palette has exactly stay flat
+                if dither && self.palette[index as usize] != rgb {
+                    // NOTE(Fermin): Thresholds centered on zero, in 32ths of the spread
+                    let offset = (2 * BAYER[y % 4][x % 4] - 15) * self.spread / 32;
+                    index =
+                        self.nearest(rgb.map(|value| (value as i32 + offset).clamp(0, 255) as u8));
+                }
+                indices.push(index);
+            }
+        }
+        indices
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::pixel_buffer::PixelFormat;
+
+    #[test]
+    fn few_colors_come_back_exactly() {
+        let colors = [[0, 0, 0], [255, 255, 255], [200, 30, 90], [12, 140, 250]];
+        let mut buffer = PixelBuffer::new(16, 4, PixelFormat::Bgra8);
+        for y in 0..4 {
+            for x in 0..16 {
+                let [r, g, b] = colors[(x as usize + y as usize) % 4];
+                buffer.set(x, y, &Color::rgba(r, g, b, 255));
+            }
+        }
+        let mut histogram = ColorHistogram::new();
+        histogram.add_buffer(&buffer);
+        let mut palette = median_cut(&histogram, 256);
+        palette.sort();
+        let mut expected = colors.to_vec();
+        expected.sort();
+        assert_eq!(palette, expected);
+
+        let mut mapper = PaletteMapper::new(palette);
+        for dither in [false, true] {
+            let indices = mapper.map_buffer(&buffer, dither);
+            for (i, &index) in indices.iter().enumerate() {
+                let (x, y) = (i % 16, i / 16);
+                assert_eq!(mapper.palette[index as usize], colors[(x + y) % 4]);
+            }
+        }
+    }
+
+    #[test]
+    fn palettes_stay_within_the_limit() {
+        let mut histogram = ColorHistogram::new();
+        for r in (0..=255).step_by(5) {
+            for g in (0..=255).step_by(5) {
+                for b in (0..=255).step_by(15) {
+                    histogram.add([r as u8, g as u8, b as u8]);
+                }
+            }
+        }
+        assert_eq!(median_cut(&histogram, 256).len(), 256);
+        assert_eq!(median_cut(&histogram, 7).len(), 7);
+        assert_eq!(median_cut(&ColorHistogram::new(), 256), [[0, 0, 0]]);
+    }
+
+    // NOTE(Fermin): A gray between the only two entries comes out as a mix of
+    // both that averages close to it, without dithering it's all one.
+    #[test]
+    fn dithering_keeps_the_average() {
+        let mut buffer = PixelBuffer::new(32, 32, PixelFormat::Bgra8);
+        buffer.fill(&Color::rgba(64, 64, 64, 255));
+        let mut mapper = PaletteMapper::new(vec![[0, 0, 0], [255, 255, 255]]);
+
+        let flat = mapper.map_buffer(&buffer, false);
+        assert!(flat.iter().all(|&index| index == 0));
+
+        let dithered = mapper.map_buffer(&buffer, true);
+        let average = dithered
+            .iter()
+            .map(|&index| mapper.palette[index as usize][0] as f32)
+            .sum::<f32>()
+            / dithered.len() as f32;
+        assert!((average - 64.0).abs() < 4.0, "{}", average);
+    }
+
+    #[test]
+    fn dithering_only_changes_the_pixels_that_changed() {
+        let mut buffer = PixelBuffer::new(32, 32, PixelFormat::Bgra8);
+        for y in 0..32 {
+            for x in 0..32 {
+                let value = (x * 8) as u8;
+                buffer.set(x, y, &Color::rgba(value, value, 255 - value, 255));
+            }
+        }
+        let palette = (0..16u8).map(|i| [i * 17, i * 17, 255 - i * 17]).collect();
+        let mut mapper = PaletteMapper::new(palette);
+        let before = mapper.map_buffer(&buffer, true);
+
+        buffer.set(3, 3, &Color::rgba(255, 0, 0, 255));
+        let after = mapper.map_buffer(&buffer, true);
+        let changed: Vec<usize> = (0..before.len())
+            .filter(|&i| before[i] != after[i])
+            .collect();
+        assert_eq!(changed, [3 * 32 + 3]);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::color::Color;
+use crate::compositor::BlendMode;
+use crate::math::{lerp, Rect};
+use crate::pixel_buffer::PixelBuffer;
+
+// NOTE(Fermin): Anything the draw routines can rasterize into. Indices and
+// pitch are in pixels, not bytes: index = x + y * pitch.
+pub trait RenderTarget {
+    fn width(&self) -> i32;
+    fn height(&self) -> i32;
+    fn pitch(&self) -> i32;
+    fn fill_pixel(&mut self, index: usize, color: &Color);
+    // NOTE(Fermin): Lerps the pixel towards color * intensity by t. Targets
+    // that can't go above 1.0 treat anything brighter as 1.0.
+    fn blend_pixel(&mut self, index: usize, color: &Color, intensity: f32, t: f32) {
+        self.blend_pixel_with(index, color, intensity, t, BlendMode::Normal);
+    }
+    // NOTE(Fermin): Same as blend_pixel, but lerps towards color blended
+    // over the pixel with blend_mode instead of towards color itself.
+    fn blend_pixel_with(
+        &mut self,
+        index: usize,
+        color: &Color,
+        intensity: f32,
+        t: f32,
+        blend_mode: BlendMode,
+    );
+    // NOTE(Fermin): Moves every pixel towards color, keeping factor of the
+    // difference. 0 erases the whole target, 1 leaves it untouched.
+    fn decay_towards(&mut self, color: &Color, factor: f32);
+}
+
+// NOTE(Fermin): Every rasterizer clips its bounds with this before touching
+// any pixel, the result might be empty.
+pub fn clip_to_target<T: RenderTarget>(rect: &Rect, target: &T) -> Rect {
+    rect.intersection(&Rect::from_pos_size(0, 0, target.width(), target.height()))
+}
+
+// NOTE(Fermin): What a fully covered pixel ends up as. max is the value of
+// white in whatever units dest and src come in.
+pub fn blend_channel(dest: f32, src: f32, max: f32, blend_mode: BlendMode) -> f32 {
+    match blend_mode {
+        BlendMode::Normal => src,
+        BlendMode::Add => dest + src,
+        BlendMode::Multiply => dest * src / max,
+        BlendMode::Screen => dest + src - dest * src / max,
+    }
+}
+
+impl RenderTarget for PixelBuffer {
+    fn width(&self) -> i32 {
+        self.width
+    }
+
+    fn height(&self) -> i32 {
+        self.height
+    }
+
+    fn pitch(&self) -> i32 {
+        self.pitch / self.format.bytes_per_pixel() as i32
+    }
+
+    fn fill_pixel(&mut self, index: usize, color: &Color) {
+        let bytes_per_pixel = self.format.bytes_per_pixel();
+        let dest_index = index * bytes_per_pixel;
+        self.bits[dest_index..dest_index + bytes_per_pixel]
+            .copy_from_slice(&self.format.encode(color));
+    }
+
+    fn blend_pixel_with(
+        &mut self,
+        index: usize,
+        color: &Color,
+        intensity: f32,
+        t: f32,
+        blend_mode: BlendMode,
+    ) {
+        let bytes_per_pixel = self.format.bytes_per_pixel();
+        let dest_index = index * bytes_per_pixel;
+        let dest = &mut self.bits[dest_index..dest_index + bytes_per_pixel];
+        let current = self.format.decode(dest);
+        // NOTE(Fermin): Brighter than the color itself would only clip, this
+        // way stars that glow in hdr look the same as they always did here.
+        let intensity = intensity.min(1.0);
+        let blend = |dest: u8, src: u8| {
+            let src = src as f32 * intensity;
+            let blended = blend_channel(dest as f32, src, 255.0, blend_mode).min(255.0);
+            lerp(dest as f32, t, blended) as u8
+        };
+
+        let blended = Color::rgba(
+            blend(current.r, color.r),
+            blend(current.g, color.g),
+            blend(current.b, color.b),
+            // NOTE(Fermin): Coverage accumulates like alpha blending over
+            // a transparent layer would.
+            lerp(current.a as f32, t, 255.0).round() as u8,
+        );
+        dest.copy_from_slice(&self.format.encode(&blended));
+    }
+
+    fn decay_towards(&mut self, color: &Color, factor: f32) {
+        let target = self.format.encode(color);
+        let bytes_per_pixel = self.format.bytes_per_pixel();
+        for row in self.rows_mut() {
+            for pixel in row.chunks_exact_mut(bytes_per_pixel) {
+                for (channel, target) in pixel.iter_mut().zip(target) {
+                    // NOTE(Fermin): Truncating the difference towards zero makes
+                    // sure every channel reaches the target instead of getting
+                    // stuck one step away from it.
+                    let difference = *channel as i32 - target as i32;
+                    *channel = (target as i32 + (difference as f32 * factor) as i32) as u8;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel_buffer::PixelFormat;
+
+    // NOTE(Fermin): What drawing a star into the window buffer did before
+    // there were intensities and blend modes.
+    #[test]
+    fn ldr_blending_matches_plain_lerp() {
+        let background = Color::rgba(20, 40, 200, 255);
+        let color = Color::rgba(219, 232, 255, 255);
+        for intensity in [1.0, 2.5] {
+            for src_a in 0..=255 {
+                let t = src_a as f32 / 255.0;
+                let mut buffer = PixelBuffer::new(1, 1, PixelFormat::Bgra8);
+                buffer.fill(&background);
+                buffer.blend_pixel(0, &color, intensity, t);
+
+                let expected = |dest: u8, src: u8| lerp(dest as f32, t, src as f32) as u8;
+                assert_eq!(
+                    &buffer.bits[..3],
+                    [
+                        expected(background.b, color.b),
+                        expected(background.g, color.g),
+                        expected(background.r, color.r),
+                    ]
+                );
+            }
+        }
+
+        let mut buffer = PixelBuffer::new(1, 1, PixelFormat::Bgra8);
+        buffer.fill(&background);
+        buffer.blend_pixel(0, &color, 0.5, 1.0);
+        assert_eq!(&buffer.bits[..3], [127, 116, 109]);
+    }
+}
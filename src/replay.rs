@@ -0,0 +1,369 @@
+use crate::image::check_image_size;
+use crate::input::{ButtonState, Input, BUTTON_COUNT};
+use crate::math::V2;
+use std::fmt;
+use std::fs::{read, File};
+use std::io::{BufWriter, Write};
+
+// --------------------------------------------------------------------
+// NOTE(Fermin): A recording is everything a frame depends on that doesn't
+// come from the game itself, so feeding it back gives the same frames.
+//
+// Header: "SDRP", version u8, seed u64, width u32, height u32
+// Frame:  flags u8, dt f32, [mouse x f32, y f32], [width u32, height u32],
+//         change count u8, (button index u8, state u8) * change count
+//
+// Everything is little endian. Buttons are only stored when they aren't
+// what begin_frame() leaves from the previous frame, so frames where
+// nothing happens take 6 bytes, 14 with the mouse over the window.
+// --------------------------------------------------------------------
+const MAGIC: &[u8; 4] = b"SDRP";
+const VERSION: u8 = 1;
+
+const FLAG_MOUSE_POS: u8 = 1 << 0;
+const FLAG_RESIZED: u8 = 1 << 1;
+
+const STATE_IS_DOWN: u8 = 1 << 0;
+const STATE_PRESSED: u8 = 1 << 1;
+const STATE_RELEASED: u8 = 1 << 2;
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(error) => write!(f, "Couldn't read recording: {}", error),
+            ReplayError::Corrupt(what) => write!(f, "Corrupt recording: {}", what),
+        }
+    }
+}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(error: std::io::Error) -> ReplayError {
+        ReplayError::Io(error)
+    }
+}
+
+fn encode_state(state: ButtonState) -> u8 {
+    let mut bits = 0;
+    if state.is_down {
+        bits |= STATE_IS_DOWN;
+    }
+    if state.pressed {
+        bits |= STATE_PRESSED;
+    }
+    if state.released {
+        bits |= STATE_RELEASED;
+    }
+    bits
+}
+
+fn decode_state(bits: u8) -> ButtonState {
+    ButtonState {
+        is_down: bits & STATE_IS_DOWN != 0,
+        pressed: bits & STATE_PRESSED != 0,
+        released: bits & STATE_RELEASED != 0,
+    }
+}
+
+// NOTE(Fermin): Frames go out as they are recorded, a session that crashes
+// still leaves everything up to its last flush behind.
+pub struct InputRecorder<W: Write> {
+    writer: W,
+    // NOTE(Fermin): Input of the last frame after begin_frame(), what
+    // buttons get compared against.
+    previous: Input,
+    width: i32,
+    height: i32,
+}
+
+impl<W: Write> InputRecorder<W> {
+    pub fn new(
+        mut writer: W,
+        seed: u64,
+        width: i32,
+        height: i32,
+    ) -> std::io::Result<InputRecorder<W>> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&seed.to_le_bytes())?;
+        writer.write_all(&(width as u32).to_le_bytes())?;
+        writer.write_all(&(height as u32).to_le_bytes())?;
+        Ok(InputRecorder {
+            writer,
+            previous: Input::default(),
+            width,
+            height,
+        })
+    }
+
+    // NOTE(Fermin): width and height are the buffer size the frame was
+    // rendered at, dt_for_frame what the game was handed.
+    pub fn record_frame(
+        &mut self,
+        width: i32,
+        height: i32,
+        dt_for_frame: f32,
+        input: &Input,
+    ) -> std::io::Result<()> {
+        let mut flags = 0;
+        if input.mouse.pos.is_some() {
+            flags |= FLAG_MOUSE_POS;
+        }
+        let resized = width != self.width || height != self.height;
+        if resized {
+            flags |= FLAG_RESIZED;
+        }
+
+        let mut frame = vec![flags];
+        frame.extend_from_slice(&dt_for_frame.to_le_bytes());
+        if let Some(pos) = input.mouse.pos {
+            frame.extend_from_slice(&pos.x.to_le_bytes());
+            frame.extend_from_slice(&pos.y.to_le_bytes());
+        }
+        if resized {
+            frame.extend_from_slice(&(width as u32).to_le_bytes());
+            frame.extend_from_slice(&(height as u32).to_le_bytes());
+        }
+
+        let changes: Vec<usize> = (0..BUTTON_COUNT)
+            .filter(|&index| input.button_state(index) != self.previous.button_state(index))
+            .collect();
+        frame.push(changes.len() as u8);
+        for index in changes {
+            frame.push(index as u8);
+            frame.push(encode_state(input.button_state(index)));
+        }
+        self.writer.write_all(&frame)?;
+
+        self.previous = input.clone();
+        self.previous.begin_frame();
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+pub fn create_recording(
+    path: &str,
+    seed: u64,
+    width: i32,
+    height: i32,
+) -> std::io::Result<InputRecorder<BufWriter<File>>> {
+    InputRecorder::new(BufWriter::new(File::create(path)?), seed, width, height)
+}
+
+// NOTE(Fermin): Steps through a recording one frame at a time, input,
+// width and height always hold what the last frame was recorded with.
+pub struct InputPlayback {
+    bytes: Vec<u8>,
+    at: usize,
+    pub seed: u64,
+    pub width: i32,
+    pub height: i32,
+    pub input: Input,
+}
+
+impl InputPlayback {
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<InputPlayback, ReplayError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(ReplayError::Corrupt("missing recording signature"));
+        }
+        let mut playback = InputPlayback {
+            bytes,
+            at: MAGIC.len(),
+            seed: 0,
+            width: 0,
+            height: 0,
+            input: Input::default(),
+        };
+        if playback.take(1)?[0] != VERSION {
+            return Err(ReplayError::Corrupt("unknown recording version"));
+        }
+        playback.seed = u64::from_le_bytes(playback.take(8)?.try_into().unwrap());
+        (playback.width, playback.height) = playback.read_size()?;
+        Ok(playback)
+    }
+
+    fn take(&mut self, count: usize) -> Result<&[u8], ReplayError> {
+        let bytes = self
+            .bytes
+            .get(self.at..self.at + count)
+            .ok_or(ReplayError::Corrupt("truncated recording"))?;
+        self.at += count;
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, ReplayError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    // NOTE(Fermin): Sizes end up as pixel buffers, they get the same limit
+    // as images loaded from disk.
+    fn read_size(&mut self) -> Result<(i32, i32), ReplayError> {
+        let (width, height) = (self.read_u32()?, self.read_u32()?);
+        if width == 0 || height == 0 || check_image_size(width as usize, height as usize).is_err() {
+            return Err(ReplayError::Corrupt("bad recording size"));
+        }
+        Ok((width as i32, height as i32))
+    }
+
+    fn read_f32(&mut self) -> Result<f32, ReplayError> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    // NOTE(Fermin): Returns the frame's dt, None once the recording is over
+    pub fn next_frame(&mut self) -> Result<Option<f32>, ReplayError> {
+        if self.at == self.bytes.len() {
+            return Ok(None);
+        }
+
+        let flags = self.take(1)?[0];
+        let dt_for_frame = self.read_f32()?;
+        self.input.begin_frame();
+        self.input.mouse.pos = None;
+        if flags & FLAG_MOUSE_POS != 0 {
+            self.input.mouse.pos = Some(V2 {
+                x: self.read_f32()?,
+                y: self.read_f32()?,
+            });
+        }
+        if flags & FLAG_RESIZED != 0 {
+            (self.width, self.height) = self.read_size()?;
+        }
+
+        let change_count = self.take(1)?[0] as usize;
+        for _change in 0..change_count {
+            let change = self.take(2)?;
+            let (index, state) = (change[0] as usize, decode_state(change[1]));
+            if index >= BUTTON_COUNT {
+                return Err(ReplayError::Corrupt("unknown button"));
+            }
+            self.input.set_button_state(index, state);
+        }
+        Ok(Some(dt_for_frame))
+    }
+}
+
+pub fn load_recording(path: &str) -> Result<InputPlayback, ReplayError> {
+    InputPlayback::from_bytes(read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{Key, MouseButton};
+
+    #[test]
+    fn frames_come_back_the_way_they_were_recorded() {
+        let mut frames = Vec::new();
+        let mut input = Input::default();
+        input.set_key(Key::Space, true);
+        frames.push((320, 200, 0.016, input.clone()));
+
+        input.begin_frame();
+        input.mouse.pos = Some(V2 { x: 12.5, y: -3.0 });
+        input.mouse.set_down(MouseButton::Left, true);
+        input.set_key(Key::Tab, true);
+        input.set_key(Key::Tab, false);
+        frames.push((320, 200, 0.017, input.clone()));
+
+        input.begin_frame();
+        input.set_key(Key::Space, false);
+        frames.push((640, 400, 0.25, input.clone()));
+
+        // NOTE(Fermin): Nothing changes
+        input.begin_frame();
+        frames.push((640, 400, 0.016, input.clone()));
+
+        let mut recorder = InputRecorder::new(Vec::new(), 43, 320, 200).unwrap();
+        for (width, height, dt_for_frame, input) in &frames {
+            recorder
+                .record_frame(*width, *height, *dt_for_frame, input)
+                .unwrap();
+        }
+        let bytes = recorder.finish().unwrap();
+        assert_eq!(bytes.len(), 21 + 8 + 18 + 24 + 14);
+
+        let mut playback = InputPlayback::from_bytes(bytes).unwrap();
+        assert_eq!(
+            (playback.seed, playback.width, playback.height),
+            (43, 320, 200)
+        );
+        for (width, height, dt_for_frame, input) in &frames {
+            assert_eq!(playback.next_frame().unwrap(), Some(*dt_for_frame));
+            assert_eq!((playback.width, playback.height), (*width, *height));
+            assert_eq!(playback.input.mouse.pos, input.mouse.pos);
+            for index in 0..BUTTON_COUNT {
+                assert_eq!(
+                    playback.input.button_state(index),
+                    input.button_state(index)
+                );
+            }
+        }
+        assert_eq!(playback.next_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn bad_recordings_are_reported() {
+        let mut recorder = InputRecorder::new(Vec::new(), 1, 8, 8).unwrap();
+        let mut input = Input::default();
+        input.set_key(Key::A, true);
+        recorder.record_frame(8, 8, 0.016, &input).unwrap();
+        let bytes = recorder.finish().unwrap();
+
+        assert!(matches!(
+            InputPlayback::from_bytes(b"BM".to_vec()),
+            Err(ReplayError::Corrupt(_))
+        ));
+        let mut newer = bytes.clone();
+        newer[4] = VERSION + 1;
+        assert!(InputPlayback::from_bytes(newer).is_err());
+
+        let mut truncated = InputPlayback::from_bytes(bytes[..bytes.len() - 1].to_vec()).unwrap();
+        assert!(matches!(
+            truncated.next_frame(),
+            Err(ReplayError::Corrupt("truncated recording"))
+        ));
+
+        let mut unknown_button = bytes.clone();
+        let index_at = unknown_button.len() - 2;
+        unknown_button[index_at] = BUTTON_COUNT as u8;
+        let mut playback = InputPlayback::from_bytes(unknown_button).unwrap();
+        assert!(playback.next_frame().is_err());
+
+        // NOTE(Fermin): Header sizes and the ones frames resize to
+        for (width, height) in [(0u32, 8u32), (8, 0), (u32::MAX, 8), (70_000, 70_000)] {
+            let mut bad_size = bytes.clone();
+            bad_size[13..17].copy_from_slice(&width.to_le_bytes());
+            bad_size[17..21].copy_from_slice(&height.to_le_bytes());
+            assert!(matches!(
+                InputPlayback::from_bytes(bad_size),
+                Err(ReplayError::Corrupt("bad recording size"))
+            ));
+
+            let mut recorder = InputRecorder::new(Vec::new(), 1, 8, 8).unwrap();
+            recorder.record_frame(8, 8, 0.016, &input).unwrap();
+            recorder.record_frame(16, 16, 0.016, &input).unwrap();
+            let mut bad_resize = recorder.finish().unwrap();
+            let size_at = 21 + 8 + 5;
+            bad_resize[size_at..size_at + 4].copy_from_slice(&width.to_le_bytes());
+            bad_resize[size_at + 4..size_at + 8].copy_from_slice(&height.to_le_bytes());
+            let mut playback = InputPlayback::from_bytes(bad_resize).unwrap();
+            playback.next_frame().unwrap();
+            assert!(matches!(
+                playback.next_frame(),
+                Err(ReplayError::Corrupt("bad recording size"))
+            ));
+        }
+    }
+}
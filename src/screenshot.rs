@@ -0,0 +1,201 @@
+use crate::bmp::encode_bmp;
+use crate::pixel_buffer::PixelBuffer;
+use crate::png::encode_png;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ScreenshotFormat {
+    Png,
+    Bmp,
+}
+
+impl ScreenshotFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ScreenshotFormat::Png => "png",
+            ScreenshotFormat::Bmp => "bmp",
+        }
+    }
+
+    // NOTE(Fermin): Anything that doesn't end in .bmp is saved as a png
+    pub fn from_path(path: &Path) -> ScreenshotFormat {
+        match path.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("bmp") => ScreenshotFormat::Bmp,
+            _ => ScreenshotFormat::Png,
+        }
+    }
+}
+
+// NOTE(Fermin): A 1080p shot at the biggest scale still fits in a buffer
+pub const MAX_SCREENSHOT_SCALE: i32 = 16;
+
+// NOTE(Fermin): Every pixel turns into a scale x scale block, so shots of
+// small windows stay crisp when blown up.
+pub fn upscale(buffer: &PixelBuffer, scale: i32) -> PixelBuffer {
+    let scale = scale.clamp(1, MAX_SCREENSHOT_SCALE);
+    let bytes_per_pixel = buffer.format.bytes_per_pixel();
+    let mut scaled = PixelBuffer::new(buffer.width * scale, buffer.height * scale, buffer.format);
+    let mut scaled_rows = scaled.rows_mut();
+    for row in buffer.rows() {
+        let mut scaled_row = Vec::with_capacity(row.len() * scale as usize);
+        for pixel in row.chunks_exact(bytes_per_pixel) {
+            for _ in 0..scale {
+                scaled_row.extend_from_slice(pixel);
+            }
+        }
+        for _ in 0..scale {
+            scaled_rows.next().unwrap().copy_from_slice(&scaled_row);
+        }
+    }
+    drop(scaled_rows);
+    scaled
+}
+
+// NOTE(Fermin): Window buffers don't keep alpha around, screenshots always
+// come out opaque.
+pub fn encode_screenshot(buffer: &PixelBuffer, format: ScreenshotFormat, scale: i32) -> Vec<u8> {
+    let scaled;
+    let buffer = if scale > 1 {
+        scaled = upscale(buffer, scale);
+        &scaled
+    } else {
+        buffer
+    };
+
+    match format {
+        ScreenshotFormat::Png => {
+            let mut rgba = Vec::with_capacity((buffer.width * buffer.height * 4) as usize);
+            for row in buffer.rows() {
+                for pixel in row.chunks_exact(buffer.format.bytes_per_pixel()) {
+                    let color = buffer.format.decode(pixel);
+                    rgba.extend_from_slice(&[color.r, color.g, color.b, 255]);
+                }
+            }
+            encode_png(buffer.width as u32, buffer.height as u32, &rgba)
+        }
+        ScreenshotFormat::Bmp => encode_bmp(buffer),
+    }
+}
+
+// NOTE(Fermin): The format comes from the file extension. Scales that make
+// more pixels than a buffer can hold are an error.
+pub fn save_screenshot(buffer: &PixelBuffer, path: &Path, scale: i32) -> std::io::Result<()> {
+    let scale = scale.clamp(1, MAX_SCREENSHOT_SCALE);
+    let bytes = (buffer.width * 4)
+        .checked_mul(scale * scale)
+        .and_then(|row_bytes| row_bytes.checked_mul(buffer.height));
+    if bytes.is_none() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "a {}x{} screenshot is too big to scale by {}",
+                buffer.width, buffer.height, scale
+            ),
+        ));
+    }
+    let format = ScreenshotFormat::from_path(path);
+    std::fs::write(path, encode_screenshot(buffer, format, scale))
+}
+
+// NOTE(Fermin): Howard Hinnant's days_from_civil backwards, days are counted
+// from 1970-01-01. Returns year, month and day.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+// NOTE(Fermin): UTC down to the millisecond, so shots taken in a row don't
+// overwrite each other, and nothing Windows doesn't allow in a file name.
+pub fn screenshot_file_name(time: SystemTime, format: ScreenshotFormat) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs() as i64;
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let second_of_day = seconds.rem_euclid(86_400);
+    format!(
+        "space_drift_{:04}{:02}{:02}_{:02}{:02}{:02}_{:03}.{}",
+        year,
+        month,
+        day,
+        second_of_day / 3600,
+        second_of_day / 60 % 60,
+        second_of_day % 60,
+        since_epoch.subsec_millis(),
+        format.extension()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::pixel_buffer::PixelFormat;
+    use std::time::Duration;
+
+    #[test]
+    fn file_names_are_timestamped() {
+        let at = |seconds: u64, millis: u64| {
+            UNIX_EPOCH + Duration::from_secs(seconds) + Duration::from_millis(millis)
+        };
+        assert_eq!(
+            screenshot_file_name(at(0, 0), ScreenshotFormat::Png),
+            "space_drift_19700101_000000_000.png"
+        );
+        assert_eq!(
+            screenshot_file_name(at(951_782_400, 7), ScreenshotFormat::Bmp),
+            "space_drift_20000229_000000_007.bmp"
+        );
+        assert_eq!(
+            screenshot_file_name(at(1_700_000_000, 999), ScreenshotFormat::Png),
+            "space_drift_20231114_221320_999.png"
+        );
+        assert_eq!(
+            ScreenshotFormat::from_path(Path::new("shots/a.BMP")),
+            ScreenshotFormat::Bmp
+        );
+        assert_eq!(
+            ScreenshotFormat::from_path(Path::new("a")),
+            ScreenshotFormat::Png
+        );
+    }
+
+    #[test]
+    fn upscaling_repeats_pixels() {
+        let mut buffer = PixelBuffer::new(2, 1, PixelFormat::Bgra8);
+        let red = Color::rgba(255, 0, 0, 255);
+        let blue = Color::rgba(0, 0, 255, 255);
+        buffer.set(0, 0, &red);
+        buffer.set(1, 0, &blue);
+
+        let scaled = upscale(&buffer, 3);
+        assert_eq!((scaled.width, scaled.height), (6, 3));
+        for y in 0..3 {
+            for x in 0..6 {
+                let expected = if x < 3 { red } else { blue };
+                assert_eq!(scaled.get(x, y), Some(expected));
+            }
+        }
+
+        let scaled = upscale(&buffer, i32::MAX);
+        assert_eq!(scaled.width, 2 * MAX_SCREENSHOT_SCALE);
+
+        // NOTE(Fermin): 3000x3000 is 2.3GB at a scale of 8
+        let tall = PixelBuffer::new(3000, 3000, PixelFormat::Bgra8);
+        let path = std::env::temp_dir().join("space_drift_too_big.png");
+        assert!(save_screenshot(&tall, &path, 8).is_err());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,122 @@
+// NOTE(Fermin): Turns wall clock seconds into simulation seconds. The game
+// keeps rendering every frame no matter what, only how far the simulation
+// moves changes.
+pub struct SimClock {
+    // NOTE(Fermin): Speeds speed_up and speed_down go through, slowest first
+    scales: &'static [f32],
+    scale_index: usize,
+    paused: bool,
+    // NOTE(Fermin): Frames to step while paused, each one step_seconds long
+    pending_steps: u32,
+    step_seconds: f32,
+    // NOTE(Fermin): Simulation seconds since the clock started
+    pub time: f32,
+}
+
+impl SimClock {
+    // NOTE(Fermin): Starts at the scale closest to 1
+    pub fn new(scales: &'static [f32], step_seconds: f32) -> SimClock {
+        let scale_index = (0..scales.len())
+            .min_by(|&a, &b| (scales[a] - 1.0).abs().total_cmp(&(scales[b] - 1.0).abs()))
+            .expect("A clock needs at least one time scale");
+        SimClock {
+            scales,
+            scale_index,
+            paused: false,
+            pending_steps: 0,
+            step_seconds,
+            time: 0.0,
+        }
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scales[self.scale_index]
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.pending_steps = 0;
+    }
+
+    pub fn speed_up(&mut self) {
+        self.scale_index = (self.scale_index + 1).min(self.scales.len() - 1);
+    }
+
+    pub fn speed_down(&mut self) {
+        self.scale_index = self.scale_index.saturating_sub(1);
+    }
+
+    // NOTE(Fermin): Stepping a running clock pauses it first
+    pub fn step(&mut self) {
+        if self.paused {
+            self.pending_steps += 1;
+        } else {
+            self.paused = true;
+        }
+    }
+
+    // NOTE(Fermin): Call once per rendered frame. Steps ignore the scale,
+    // they are always exactly one frame of simulation.
+    pub fn advance(&mut self, wall_seconds: f32) -> f32 {
+        let dt_for_frame = if !self.paused {
+            wall_seconds * self.scale()
+        } else if self.pending_steps > 0 {
+            self.pending_steps -= 1;
+            self.step_seconds
+        } else {
+            0.0
+        };
+        self.time += dt_for_frame;
+        dt_for_frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCALES: &[f32] = &[0.25, 0.5, 1.0, 2.0];
+
+    #[test]
+    fn scales_stay_in_range() {
+        let mut clock = SimClock::new(SCALES, 0.1);
+        assert_eq!(clock.advance(0.5), 0.5);
+
+        clock.speed_down();
+        clock.speed_down();
+        clock.speed_down();
+        assert_eq!(clock.advance(1.0), 0.25);
+
+        for _ in 0..10 {
+            clock.speed_up();
+        }
+        assert_eq!(clock.advance(1.0), 2.0);
+        assert_eq!(clock.time, 2.75);
+    }
+
+    #[test]
+    fn paused_clocks_only_move_on_steps() {
+        let mut clock = SimClock::new(SCALES, 0.1);
+        clock.speed_up();
+        clock.step();
+        assert!(clock.is_paused());
+        assert_eq!(clock.advance(1.0), 0.0);
+
+        clock.step();
+        clock.step();
+        assert_eq!(clock.advance(1.0), 0.1);
+        assert_eq!(clock.advance(1.0), 0.1);
+        assert_eq!(clock.advance(1.0), 0.0);
+
+        // NOTE(Fermin): Steps left over when unpausing are dropped
+        clock.step();
+        clock.toggle_pause();
+        assert_eq!(clock.advance(1.0), 2.0);
+        clock.toggle_pause();
+        assert_eq!(clock.advance(1.0), 0.0);
+    }
+}
@@ -0,0 +1,462 @@
+use crate::math::{v2_length_sq, Rect, V2};
+
+// NOTE(Fermin): Points spread far apart would need millions of cells, past
+// this many per axis the cells get bigger instead.
+const MAX_CELLS_PER_AXIS: i32 = 1024;
+
+// NOTE(Fermin): Uniform grid over a set of points for neighbor queries.
+// Items are the indices of the positions it was built from. The grid
+// covers the positions it was last rebuilt with, update rebuilds it as
+// soon as one of them moves outside of that.
+pub struct SpatialGrid {
+    pub cell_size: f32,
+    origin: V2,
+    // NOTE(Fermin): Corner across from origin, inside the grid too
+    far_corner: V2,
+    // NOTE(Fermin): Can be bigger than cell_size, see MAX_CELLS_PER_AXIS
+    cell_width: f32,
+    cell_height: f32,
+    columns: i32,
+    rows: i32,
+    cells: Vec<Vec<usize>>,
+    positions: Vec<V2>,
+    item_cells: Vec<usize>,
+}
+
+// NOTE(Fermin): Cells needed to cover extent and how big they end up
+fn axis_cells(extent: f32, cell_size: f32) -> (i32, f32) {
+    // NOTE(Fermin): Infinite and NaN extents end up as one capped axis
+    let extent = if extent.is_finite() {
+        extent.max(0.0)
+    } else {
+        f32::MAX
+    };
+    let cells = ((extent / cell_size).floor() as i32).saturating_add(1);
+    if cells > MAX_CELLS_PER_AXIS {
+        (MAX_CELLS_PER_AXIS, extent / (MAX_CELLS_PER_AXIS - 1) as f32)
+    } else {
+        (cells.max(1), cell_size)
+    }
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32) -> SpatialGrid {
+        assert!(cell_size > 0.0);
+        SpatialGrid {
+            cell_size,
+            origin: V2 { x: 0.0, y: 0.0 },
+            far_corner: V2 { x: 0.0, y: 0.0 },
+            cell_width: cell_size,
+            cell_height: cell_size,
+            columns: 1,
+            rows: 1,
+            cells: vec![Vec::new()],
+            positions: Vec::new(),
+            item_cells: Vec::new(),
+        }
+    }
+
+    // NOTE(Fermin): Clamped to the grid, see the note on SpatialGrid
+    fn cell_coords(&self, pos: V2) -> (i32, i32) {
+        let column = ((pos.x - self.origin.x) / self.cell_width).floor() as i32;
+        let row = ((pos.y - self.origin.y) / self.cell_height).floor() as i32;
+        (
+            column.clamp(0, self.columns - 1),
+            row.clamp(0, self.rows - 1),
+        )
+    }
+
+    fn cell_index(&self, pos: V2) -> usize {
+        let (column, row) = self.cell_coords(pos);
+        (row * self.columns + column) as usize
+    }
+
+    // NOTE(Fermin): Resizes the grid around positions and puts every one of
+    // them in its cell. Cells keep their allocations between rebuilds.
+    pub fn rebuild(&mut self, positions: &[V2]) {
+        let mut min = V2 { x: 0.0, y: 0.0 };
+        let mut max = V2 { x: 0.0, y: 0.0 };
+        for (index, pos) in positions.iter().enumerate() {
+            if index == 0 {
+                (min, max) = (*pos, *pos);
+            }
+            min = V2 {
+                x: min.x.min(pos.x),
+                y: min.y.min(pos.y),
+            };
+            max = V2 {
+                x: max.x.max(pos.x),
+                y: max.y.max(pos.y),
+            };
+        }
+        self.origin = min;
+        self.far_corner = max;
+        (self.columns, self.cell_width) = axis_cells(max.x - min.x, self.cell_size);
+        (self.rows, self.cell_height) = axis_cells(max.y - min.y, self.cell_size);
+
+        let cell_count = (self.columns * self.rows) as usize;
+        self.cells.resize_with(cell_count, Vec::new);
+        for cell in &mut self.cells {
+            cell.clear();
+        }
+
+        self.positions.clear();
+        self.positions.extend_from_slice(positions);
+        self.item_cells.clear();
+        for (index, pos) in positions.iter().enumerate() {
+            let cell = self.cell_index(*pos);
+            self.cells[cell].push(index);
+            self.item_cells.push(cell);
+        }
+    }
+
+    fn covers(&self, pos: V2) -> bool {
+        pos.x >= self.origin.x
+            && pos.y >= self.origin.y
+            && pos.x <= self.far_corner.x
+            && pos.y <= self.far_corner.y
+    }
+
+    // NOTE(Fermin): Only items that changed cells get moved. A different
+    // number of positions than last time, or any of them outside of the
+    // grid, means a rebuild.
+    pub fn update(&mut self, positions: &[V2]) {
+        if positions.len() != self.positions.len() || !positions.iter().all(|pos| self.covers(*pos))
+        {
+            self.rebuild(positions);
+            return;
+        }
+
+        for (index, pos) in positions.iter().enumerate() {
+            self.positions[index] = *pos;
+            let cell = self.cell_index(*pos);
+            let old_cell = self.item_cells[index];
+            if cell != old_cell {
+                let items = &mut self.cells[old_cell];
+                let slot = items.iter().position(|&item| item == index).unwrap();
+                items.swap_remove(slot);
+                self.cells[cell].push(index);
+                self.item_cells[index] = cell;
+            }
+        }
+    }
+
+    // NOTE(Fermin): Calls visit with every item in the cells between min
+    // and max, some of them might be outside of that area.
+    fn visit_cells(&self, min: V2, max: V2, mut visit: impl FnMut(usize)) {
+        let (min_column, min_row) = self.cell_coords(min);
+        let (max_column, max_row) = self.cell_coords(max);
+        for row in min_row..=max_row {
+            let row_start = row * self.columns;
+            for column in min_column..=max_column {
+                for &item in &self.cells[(row_start + column) as usize] {
+                    visit(item);
+                }
+            }
+        }
+    }
+
+    // NOTE(Fermin): Appends every item inside rect to results, in no
+    // particular order. Same as Rect::contains, max edges are outside.
+    #[allow(dead_code)] // NOTE(Fermin): For culling, nothing culls by it yet
+    pub fn query_rect(&self, rect: &Rect, results: &mut Vec<usize>) {
+        if rect.is_empty() {
+            return;
+        }
+        let min = V2 {
+            x: rect.min_x as f32,
+            y: rect.min_y as f32,
+        };
+        let max = V2 {
+            x: rect.max_x as f32,
+            y: rect.max_y as f32,
+        };
+        self.visit_cells(min, max, |item| {
+            let pos = self.positions[item];
+            if pos.x >= min.x && pos.x < max.x && pos.y >= min.y && pos.y < max.y {
+                results.push(item);
+            }
+        });
+    }
+
+    // NOTE(Fermin): Appends every item at most radius away from center to
+    // results, in no particular order.
+    pub fn query_radius(&self, center: V2, radius: f32, results: &mut Vec<usize>) {
+        let reach = V2 {
+            x: radius,
+            y: radius,
+        };
+        self.visit_cells(center - reach, center + reach, |item| {
+            if v2_length_sq(self.positions[item] - center) <= radius * radius {
+                results.push(item);
+            }
+        });
+    }
+
+    // NOTE(Fermin): Appends every pair of items at most distance apart to
+    // pairs, lowest index first.
+    pub fn pairs_within(&self, distance: f32, pairs: &mut Vec<(usize, usize)>) {
+        let reach = V2 {
+            x: distance,
+            y: distance,
+        };
+        for (a, &pos) in self.positions.iter().enumerate() {
+            self.visit_cells(pos - reach, pos + reach, |b| {
+                if b > a && v2_length_sq(self.positions[b] - pos) <= distance * distance {
+                    pairs.push((a, b));
+                }
+            });
+        }
+    }
+}
+
+// NOTE(Fermin): Shared with the tests of whatever builds grids
+#[cfg(test)]
+pub fn random_positions(
+    rng: &mut rand::rngs::StdRng,
+    count: usize,
+    width: f32,
+    height: f32,
+) -> Vec<V2> {
+    use rand::Rng;
+    (0..count)
+        .map(|_| V2 {
+            x: rng.gen_range(0.0..width),
+            y: rng.gen_range(0.0..height),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::time::Instant;
+
+    fn brute_force_radius(positions: &[V2], center: V2, radius: f32) -> Vec<usize> {
+        (0..positions.len())
+            .filter(|&item| v2_length_sq(positions[item] - center) <= radius * radius)
+            .collect()
+    }
+
+    fn brute_force_pairs(positions: &[V2], distance: f32) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        for a in 0..positions.len() {
+            for b in a + 1..positions.len() {
+                if v2_length_sq(positions[b] - positions[a]) <= distance * distance {
+                    pairs.push((a, b));
+                }
+            }
+        }
+        pairs
+    }
+
+    // NOTE(Fermin): Runs every query against the grid and against a plain
+    // loop over positions.
+    fn assert_matches_brute_force(grid: &SpatialGrid, positions: &[V2], rng: &mut StdRng) {
+        for _ in 0..50 {
+            let center = V2 {
+                x: rng.gen_range(-100.0..500.0),
+                y: rng.gen_range(-100.0..400.0),
+            };
+            let radius = rng.gen_range(0.0..120.0);
+            let mut found = Vec::new();
+            grid.query_radius(center, radius, &mut found);
+            found.sort_unstable();
+            assert_eq!(found, brute_force_radius(positions, center, radius));
+
+            let rect = Rect::from_pos_size(
+                center.x as i32,
+                center.y as i32,
+                rng.gen_range(0..200),
+                rng.gen_range(0..200),
+            );
+            let mut found = Vec::new();
+            grid.query_rect(&rect, &mut found);
+            found.sort_unstable();
+            let expected: Vec<usize> = (0..positions.len())
+                .filter(|&item| {
+                    let pos = positions[item];
+                    pos.x >= rect.min_x as f32
+                        && pos.x < rect.max_x as f32
+                        && pos.y >= rect.min_y as f32
+                        && pos.y < rect.max_y as f32
+                })
+                .collect();
+            assert_eq!(found, expected);
+        }
+
+        for distance in [0.0, 5.0, 30.0, 200.0] {
+            let mut pairs = Vec::new();
+            grid.pairs_within(distance, &mut pairs);
+            pairs.sort_unstable();
+            assert_eq!(pairs, brute_force_pairs(positions, distance));
+        }
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let mut rng = StdRng::seed_from_u64(40);
+        for count in [0, 1, 7, 300] {
+            let positions = random_positions(&mut rng, count, 400.0, 300.0);
+            let mut grid = SpatialGrid::new(25.0);
+            grid.rebuild(&positions);
+            assert_matches_brute_force(&grid, &positions, &mut rng);
+        }
+    }
+
+    #[test]
+    fn incremental_updates_match_brute_force() {
+        let mut rng = StdRng::seed_from_u64(40);
+        let mut positions = random_positions(&mut rng, 300, 400.0, 300.0);
+        let mut grid = SpatialGrid::new(25.0);
+        grid.update(&positions);
+
+        for _frame in 0..20 {
+            // NOTE(Fermin): Some of them end up outside of the grid
+            for pos in &mut positions {
+                *pos = *pos
+                    + V2 {
+                        x: rng.gen_range(-30.0..30.0),
+                        y: rng.gen_range(-30.0..30.0),
+                    };
+            }
+            grid.update(&positions);
+            assert_matches_brute_force(&grid, &positions, &mut rng);
+        }
+    }
+
+    #[test]
+    fn rect_queries_leave_out_the_max_edges() {
+        let positions = [
+            V2 { x: 10.0, y: 10.0 },
+            V2 { x: 19.5, y: 15.0 },
+            V2 { x: 20.0, y: 15.0 },
+            V2 { x: 15.0, y: 20.0 },
+            V2 { x: 9.9, y: 12.0 },
+        ];
+        let mut grid = SpatialGrid::new(4.0);
+        grid.rebuild(&positions);
+
+        let mut found = Vec::new();
+        grid.query_rect(&Rect::from_pos_size(10, 10, 10, 10), &mut found);
+        found.sort_unstable();
+        assert_eq!(found, [0, 1]);
+
+        found.clear();
+        grid.query_rect(&Rect::from_pos_size(10, 10, 0, 10), &mut found);
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn positions_outside_the_grid_rebuild_it() {
+        let mut positions = vec![V2 { x: 0.0, y: 0.0 }, V2 { x: 100.0, y: 100.0 }];
+        let mut grid = SpatialGrid::new(10.0);
+        grid.update(&positions);
+        assert_eq!((grid.columns, grid.rows), (11, 11));
+
+        positions[1] = V2 { x: 50.0, y: 50.0 };
+        grid.update(&positions);
+        assert_eq!((grid.columns, grid.rows), (11, 11));
+
+        positions[1] = V2 {
+            x: 300.0,
+            y: -100.0,
+        };
+        grid.update(&positions);
+        assert_eq!((grid.columns, grid.rows), (31, 11));
+        assert!(positions.iter().all(|pos| grid.covers(*pos)));
+    }
+
+    #[test]
+    fn far_apart_positions_cap_the_cell_count() {
+        let positions = [
+            V2 {
+                x: -1.0e6,
+                y: -1.0e6,
+            },
+            V2 { x: 1.0e6, y: 1.0e6 },
+            V2 { x: 10.0, y: 10.0 },
+            V2 { x: 12.0, y: 10.0 },
+        ];
+        let mut grid = SpatialGrid::new(1.0);
+        grid.rebuild(&positions);
+        assert!(grid.cells.len() <= (MAX_CELLS_PER_AXIS * MAX_CELLS_PER_AXIS) as usize);
+
+        let mut pairs = Vec::new();
+        grid.pairs_within(5.0, &mut pairs);
+        assert_eq!(pairs, vec![(2, 3)]);
+
+        for extent in [f32::MAX, f32::INFINITY, f32::NAN] {
+            let (cells, cell_size) = axis_cells(extent, 1.0);
+            assert!((1..=MAX_CELLS_PER_AXIS).contains(&cells));
+            assert!(cell_size >= 1.0 || cells == 1);
+        }
+    }
+
+    // NOTE(Fermin): cargo test --release benchmark -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn benchmark() {
+        let mut rng = StdRng::seed_from_u64(40);
+        let milliseconds = |start: Instant| start.elapsed().as_secs_f32() * 1000.0;
+        for count in [1_000, 10_000, 100_000] {
+            let mut positions = random_positions(&mut rng, count, 1920.0, 1080.0);
+            let mut grid = SpatialGrid::new(20.0);
+
+            let start = Instant::now();
+            grid.rebuild(&positions);
+            let rebuild = milliseconds(start);
+
+            for pos in &mut positions {
+                pos.y += rng.gen_range(0.0..8.0);
+            }
+            let start = Instant::now();
+            grid.update(&positions);
+            let update = milliseconds(start);
+
+            let start = Instant::now();
+            let mut found = Vec::new();
+            for pos in &positions {
+                found.clear();
+                grid.query_radius(*pos, 20.0, &mut found);
+            }
+            let radius_queries = milliseconds(start);
+
+            let start = Instant::now();
+            for pos in &positions {
+                found.clear();
+                let rect = Rect::from_pos_size(pos.x as i32 - 20, pos.y as i32 - 20, 40, 40);
+                grid.query_rect(&rect, &mut found);
+            }
+            let rect_queries = milliseconds(start);
+
+            let start = Instant::now();
+            let mut pairs = Vec::new();
+            grid.pairs_within(20.0, &mut pairs);
+            let pair_search = milliseconds(start);
+
+            // NOTE(Fermin): 100k would take minutes
+            let brute_force = if count <= 10_000 {
+                let start = Instant::now();
+                assert_eq!(brute_force_pairs(&positions, 20.0).len(), pairs.len());
+                format!("{:.2} ms", milliseconds(start))
+            } else {
+                "skipped".to_string()
+            };
+
+            println!(
+                "{} stars: rebuild {:.2} ms, update {:.2} ms, radius queries {:.2} ms, \
+                 rect queries {:.2} ms, {} pairs {:.2} ms, brute force pairs {}",
+                count,
+                rebuild,
+                update,
+                radius_queries,
+                rect_queries,
+                pairs.len(),
+                pair_search,
+                brute_force
+            );
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use crate::pixel_buffer::PixelBuffer;
+use std::io::Write;
+
+// --------------------------------------------------------------------
+// NOTE(Fermin): Draws buffers in a terminal with 24-bit color escape codes.
+// Every cell is an upper half block, the top pixel is the foreground color
+// and the bottom one the background, so rows of cells are two pixels tall.
+// Cells are about twice as tall as they are wide, pixels come out square.
+// --------------------------------------------------------------------
+
+// NOTE(Fermin): Switches to the alternate screen and hides the cursor,
+// TERMINAL_LEAVE puts both back the way they were.
+pub const TERMINAL_ENTER: &str = "\x1b[?1049h\x1b[?25l";
+pub const TERMINAL_LEAVE: &str = "\x1b[0m\x1b[?25h\x1b[?1049l";
+
+const UPPER_HALF_BLOCK: &str = "\u{2580}";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Cell {
+    top: [u8; 3],
+    bottom: [u8; 3],
+}
+
+// NOTE(Fermin): Average of the pixels every target pixel covers, top row
+// first. Targets bigger than the buffer repeat pixels instead.
+pub fn downsample(buffer: &PixelBuffer, width: i32, height: i32) -> Vec<[u8; 3]> {
+    let bytes_per_pixel = buffer.format.bytes_per_pixel();
+    let span = |at: i32, size: i32, buffer_size: i32| -> (usize, usize) {
+        let from = (at as i64 * buffer_size as i64 / size as i64) as usize;
+        let to = ((at + 1) as i64 * buffer_size as i64 / size as i64) as usize;
+        (from, to.max(from + 1))
+    };
+
+    let mut pixels = Vec::with_capacity((width * height).max(0) as usize);
+    for y in 0..height {
+        let (from_y, to_y) = span(y, height, buffer.height);
+        for x in 0..width {
+            let (from_x, to_x) = span(x, width, buffer.width);
+            let mut sum = [0u32; 3];
+            for row in buffer.rows().skip(from_y).take(to_y - from_y) {
+                let pixels = &row[from_x * bytes_per_pixel..to_x * bytes_per_pixel];
+                for pixel in pixels.chunks_exact(bytes_per_pixel) {
+                    let color = buffer.format.decode(pixel);
+                    for (channel, value) in sum.iter_mut().zip([color.r, color.g, color.b]) {
+                        *channel += value as u32;
+                    }
+                }
+            }
+            let count = ((to_x - from_x) * (to_y - from_y)) as u32;
+            pixels.push(sum.map(|channel| ((channel + count / 2) / count) as u8));
+        }
+    }
+    pixels
+}
+
+pub struct TerminalRenderer {
+    columns: i32,
+    rows: i32,
+    // NOTE(Fermin): What the terminal shows, empty before the first frame
+    cells: Vec<Cell>,
+    out: Vec<u8>,
+}
+
+impl TerminalRenderer {
+    pub fn new() -> TerminalRenderer {
+        TerminalRenderer {
+            columns: 0,
+            rows: 0,
+            cells: Vec::new(),
+            out: Vec::new(),
+        }
+    }
+
+    // NOTE(Fermin): What gets the terminal from the last frame to this one.
+    // Only cells that changed are in it, unless the terminal changed size,
+    // then the screen gets cleared and everything drawn again.
+    pub fn render(&mut self, buffer: &PixelBuffer, columns: i32, rows: i32) -> &[u8] {
+        let pixels = downsample(buffer, columns, rows * 2);
+        let columns_usize = columns as usize;
+        let cells: Vec<Cell> = (0..(columns * rows) as usize)
+            .map(|index| {
+                let (x, row) = (index % columns_usize, index / columns_usize);
+                Cell {
+                    top: pixels[2 * row * columns_usize + x],
+                    bottom: pixels[(2 * row + 1) * columns_usize + x],
+                }
+            })
+            .collect();
+
+        self.out.clear();
+        let redraw_all = columns != self.columns || rows != self.rows || self.cells.is_empty();
+        if redraw_all {
+            self.out.extend_from_slice(b"\x1b[0m\x1b[2J");
+        }
+
+        // NOTE(Fermin): Where the cursor and colors are, None when unknown.
+        // Moves and colors only get written when they don't already match.
+        let mut cursor: Option<usize> = None;
+        let mut foreground: Option<[u8; 3]> = None;
+        let mut background: Option<[u8; 3]> = None;
+        for (index, cell) in cells.iter().enumerate() {
+            if !redraw_all && self.cells[index] == *cell {
+                continue;
+            }
+            let (x, row) = (index % columns_usize, index / columns_usize);
+            if cursor != Some(index) {
+                write!(self.out, "\x1b[{};{}H", row + 1, x + 1).unwrap();
+            }
+            if background != Some(cell.bottom) {
+                let [r, g, b] = cell.bottom;
+                write!(self.out, "\x1b[48;2;{};{};{}m", r, g, b).unwrap();
+                background = Some(cell.bottom);
+            }
+            // NOTE(Fermin): Cells that are one color are just background
+            if cell.top == cell.bottom {
+                self.out.push(b' ');
+            } else {
+                if foreground != Some(cell.top) {
+                    let [r, g, b] = cell.top;
+                    write!(self.out, "\x1b[38;2;{};{};{}m", r, g, b).unwrap();
+                    foreground = Some(cell.top);
+                }
+                self.out.extend_from_slice(UPPER_HALF_BLOCK.as_bytes());
+            }
+            // NOTE(Fermin): Past the last column terminals don't agree on
+            // where the cursor is.
+            cursor = (x + 1 < columns_usize).then_some(index + 1);
+        }
+
+        self.columns = columns;
+        self.rows = rows;
+        self.cells = cells;
+        &self.out
+    }
+}
+
+// NOTE(Fermin): Columns and rows of the terminal stdout goes to, None when
+// it isn't one.
+#[cfg(unix)]
+pub fn terminal_size() -> Option<(i32, i32)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    (result == 0 && size.ws_col > 0 && size.ws_row > 0)
+        .then_some((size.ws_col as i32, size.ws_row as i32))
+}
+
+// NOTE(Fermin): Elsewhere only the shell can say, and only at startup
+#[cfg(not(unix))]
+pub fn terminal_size() -> Option<(i32, i32)> {
+    let variable = |name: &str| std::env::var(name).ok()?.parse::<i32>().ok();
+    Some((variable("COLUMNS")?, variable("LINES")?))
+        .filter(|&(columns, rows)| columns > 0 && rows > 0)
+}
+
+// --------------------------------------------------------------------
+// NOTE(Fermin): Ctrl+C only sets a flag, so whoever draws gets to put the
+// terminal back before exiting.
+// --------------------------------------------------------------------
+#[cfg(unix)]
+static INTERRUPTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    INTERRUPTED.store(true, std::sync::atomic::Ordering::Relaxed);
+}
+
+#[cfg(unix)]
+pub fn catch_interrupts() {
+    let handler = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+#[cfg(unix)]
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+#[cfg(not(unix))]
+pub fn catch_interrupts() {}
+
+#[cfg(not(unix))]
+pub fn interrupted() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::pixel_buffer::PixelFormat;
+
+    #[test]
+    fn downsampling_averages_what_it_covers() {
+        let mut buffer = PixelBuffer::new(4, 4, PixelFormat::Bgra8);
+        for y in 0..4 {
+            for x in 0..4 {
+                let value = if x < 2 { 200 } else { 0 };
+                buffer.set(x, y, &Color::rgba(value, y as u8 * 10, 50, 255));
+            }
+        }
+        assert_eq!(
+            downsample(&buffer, 2, 2),
+            [[200, 5, 50], [0, 5, 50], [200, 25, 50], [0, 25, 50]]
+        );
+        assert_eq!(downsample(&buffer, 1, 1), [[100, 15, 50]]);
+        assert_eq!(downsample(&buffer, 8, 8).len(), 64);
+        assert_eq!(downsample(&buffer, 8, 8)[7], [0, 0, 50]);
+    }
+
+    // NOTE(Fermin): Top and bottom pixels differ, so every written cell is a
+    // half block.
+    #[test]
+    fn only_changed_cells_get_written() {
+        let mut buffer = PixelBuffer::new(4, 4, PixelFormat::Bgra8);
+        for y in 0..4 {
+            for x in 0..4 {
+                buffer.set(x, y, &Color::rgba(y as u8 * 60, 0, 0, 255));
+            }
+        }
+        let blocks = |out: &[u8]| {
+            String::from_utf8_lossy(out)
+                .matches(UPPER_HALF_BLOCK)
+                .count()
+        };
+        let mut renderer = TerminalRenderer::new();
+
+        let first = renderer.render(&buffer, 4, 2).to_vec();
+        assert!(first.starts_with(b"\x1b[0m\x1b[2J"));
+        assert_eq!(blocks(&first), 8);
+        assert!(renderer.render(&buffer, 4, 2).is_empty());
+
+        buffer.set(2, 3, &Color::rgba(0, 255, 0, 255));
+        let changed = String::from_utf8(renderer.render(&buffer, 4, 2).to_vec()).unwrap();
+        assert_eq!(
+            changed,
+            "\x1b[2;3H\x1b[48;2;0;255;0m\x1b[38;2;120;0;0m\u{2580}"
+        );
+
+        let resized = renderer.render(&buffer, 2, 2).to_vec();
+        assert!(resized.starts_with(b"\x1b[0m\x1b[2J"));
+        assert_eq!(blocks(&resized), 4);
+    }
+}
@@ -0,0 +1,71 @@
+// NOTE(Fermin): Long exposure decay is expressed as how much of a pixel is
+// left after one second, so trails look the same at any frame rate.
+
+// NOTE(Fermin): Below this a trail can't be told apart from the background
+// in the 8 bit presentation buffer.
+const VISIBLE_THRESHOLD: f32 = 1.0 / 255.0;
+
+pub fn trail_decay_for_frame(decay_per_second: f32, dt_for_frame: f32) -> f32 {
+    decay_per_second.clamp(0.0, 1.0).powf(dt_for_frame)
+}
+
+// NOTE(Fermin): Seconds until a trail is gone. Times the speed of a star
+// that's how long its trail is in pixels.
+pub fn trail_length_in_seconds(decay_per_second: f32) -> f32 {
+    if decay_per_second <= 0.0 {
+        0.0
+    } else if decay_per_second >= 1.0 {
+        f32::INFINITY
+    } else {
+        VISIBLE_THRESHOLD.ln() / decay_per_second.ln()
+    }
+}
+
+// NOTE(Fermin): How long the trails left by the slowest and fastest stars
+// will be, handy to tune the decay without watching the whole thing.
+pub fn trail_preview(decay_per_second: f32, min_speed: f32, max_speed: f32) -> String {
+    let seconds = trail_length_in_seconds(decay_per_second);
+    format!(
+        "Long exposure: trails fade in {:.2} s, {:.0} px to {:.0} px long",
+        seconds,
+        seconds * min_speed,
+        seconds * max_speed
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decay_is_the_same_at_any_frame_rate() {
+        for frames_per_second in [30, 60, 144] {
+            let dt_for_frame = 1.0 / frames_per_second as f32;
+            let decay = trail_decay_for_frame(0.1, dt_for_frame);
+            let after_a_second = decay.powi(frames_per_second);
+            assert!((after_a_second - 0.1).abs() < 1e-4, "{}", frames_per_second);
+        }
+
+        assert_eq!(trail_decay_for_frame(0.25, 0.0), 1.0);
+        assert_eq!(trail_decay_for_frame(0.25, 2.0), 0.0625);
+        // NOTE(Fermin): Out of range decays don't make pixels grow or flip
+        assert_eq!(trail_decay_for_frame(-1.0, 0.5), 0.0);
+        assert_eq!(trail_decay_for_frame(3.0, 0.5), 1.0);
+    }
+
+    #[test]
+    fn trails_last_until_they_are_invisible() {
+        assert_eq!(trail_length_in_seconds(0.0), 0.0);
+        assert_eq!(trail_length_in_seconds(1.0), f32::INFINITY);
+
+        let seconds = trail_length_in_seconds(0.1);
+        let left = trail_decay_for_frame(0.1, seconds);
+        assert!((left - VISIBLE_THRESHOLD).abs() < 1e-6);
+        assert!(trail_length_in_seconds(0.01) < seconds);
+
+        assert_eq!(
+            trail_preview(0.1, 8.0, 48.0),
+            "Long exposure: trails fade in 2.41 s, 19 px to 116 px long"
+        );
+    }
+}
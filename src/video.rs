@@ -0,0 +1,227 @@
+use crate::pixel_buffer::PixelBuffer;
+use std::io::{Error, ErrorKind, Write};
+
+// --------------------------------------------------------------------
+// NOTE(Fermin): Frames streamed out for external encoders, one after the
+// other with nothing in between to seek by. Y4m carries its own size and
+// frame rate, raw frames are just the pixels and whoever reads them has
+// to be told both.
+//
+// Y4m has no standard way to say which color matrix it uses and ffmpeg
+// assumes BT.601 when nobody says, so tell it this is BT.709:
+//   ffmpeg -i sky.y4m -colorspace bt709 -color_primaries bt709 \
+//          -color_trc bt709 -color_range tv sky.mp4
+// --------------------------------------------------------------------
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VideoFormat {
+    // NOTE(Fermin): YUV4MPEG2, 4:2:0 with BT.709 colors in limited range
+    Y4m,
+    // NOTE(Fermin): BGRA, 4 bytes per pixel, top row first and always opaque
+    RawBgra,
+}
+
+// NOTE(Fermin): BT.709 weights in 65536ths. Luma spans 16 to 235 and
+// chroma 16 to 240, what encoders expect unless told otherwise. The header
+// says so in XCOLORMATRIX for readers that look at it.
+const FIXED_ONE: f32 = 65536.0;
+const Y_FROM_RGB: [i32; 3] = [
+    (0.2126 * 219.0 / 255.0 * FIXED_ONE) as i32,
+    (0.7152 * 219.0 / 255.0 * FIXED_ONE) as i32,
+    (0.0722 * 219.0 / 255.0 * FIXED_ONE) as i32,
+];
+const U_FROM_RGB: [i32; 3] = [
+    (-0.2126 / 1.8556 * 224.0 / 255.0 * FIXED_ONE) as i32,
+    (-0.7152 / 1.8556 * 224.0 / 255.0 * FIXED_ONE) as i32,
+    (0.5 * 224.0 / 255.0 * FIXED_ONE) as i32,
+];
+const V_FROM_RGB: [i32; 3] = [
+    (0.5 * 224.0 / 255.0 * FIXED_ONE) as i32,
+    (-0.7152 / 1.5748 * 224.0 / 255.0 * FIXED_ONE) as i32,
+    (-0.0722 / 1.5748 * 224.0 / 255.0 * FIXED_ONE) as i32,
+];
+
+fn to_fixed(weights: &[i32; 3], rgb: [i32; 3], offset: i32) -> u8 {
+    let sum: i32 = weights
+        .iter()
+        .zip(rgb)
+        .map(|(weight, value)| weight * value)
+        .sum();
+    (((sum + (1 << 15)) >> 16) + offset).clamp(0, 255) as u8
+}
+
+// NOTE(Fermin): Planes of y, u and v one after the other into out. Chroma
+// is the average of each 2x2 block, half the size rounded up, so it sits in
+// the middle of the block like C420jpeg says.
+pub fn bgra_to_yuv420(buffer: &PixelBuffer, out: &mut Vec<u8>) {
+    let (width, height) = (buffer.width as usize, buffer.height as usize);
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+    let bytes_per_pixel = buffer.format.bytes_per_pixel();
+    out.clear();
+    out.reserve(width * height + 2 * chroma_width * chroma_height);
+
+    for row in buffer.rows() {
+        for pixel in row.chunks_exact(bytes_per_pixel) {
+            let color = buffer.format.decode(pixel);
+            let rgb = [color.r as i32, color.g as i32, color.b as i32];
+            out.push(to_fixed(&Y_FROM_RGB, rgb, 16));
+        }
+    }
+
+    // NOTE(Fermin): Sums of up to 4 pixels, blocks on odd edges have less
+    let mut sums = vec![([0i32; 3], 0i32); chroma_width * chroma_height];
+    for (y, row) in buffer.rows().enumerate() {
+        for (x, pixel) in row.chunks_exact(bytes_per_pixel).enumerate() {
+            let color = buffer.format.decode(pixel);
+            let (sum, count) = &mut sums[(y / 2) * chroma_width + x / 2];
+            for (channel, value) in sum.iter_mut().zip([color.r, color.g, color.b]) {
+                *channel += value as i32;
+            }
+            *count += 1;
+        }
+    }
+    for weights in [&U_FROM_RGB, &V_FROM_RGB] {
+        for (sum, count) in &sums {
+            let average = sum.map(|channel| (channel + count / 2) / count);
+            out.push(to_fixed(weights, average, 128));
+        }
+    }
+}
+
+pub struct VideoWriter<W: Write> {
+    writer: W,
+    format: VideoFormat,
+    width: i32,
+    height: i32,
+    // NOTE(Fermin): Reused for every frame
+    frame: Vec<u8>,
+}
+
+impl<W: Write> VideoWriter<W> {
+    // NOTE(Fermin): Every frame has to be width x height, streams can't
+    // change size halfway.
+    pub fn new(
+        mut writer: W,
+        format: VideoFormat,
+        width: i32,
+        height: i32,
+        frames_per_second: u32,
+    ) -> std::io::Result<VideoWriter<W>> {
+        if format == VideoFormat::Y4m {
+            writeln!(
+                writer,
+                "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C420jpeg XCOLORRANGE=LIMITED XCOLORMATRIX=BT709",
+                width, height, frames_per_second
+            )?;
+        }
+        Ok(VideoWriter {
+            writer,
+            format,
+            width,
+            height,
+            frame: Vec::new(),
+        })
+    }
+
+    pub fn write_frame(&mut self, buffer: &PixelBuffer) -> std::io::Result<()> {
+        if buffer.width != self.width || buffer.height != self.height {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{}x{} frame in a {}x{} video",
+                    buffer.width, buffer.height, self.width, self.height
+                ),
+            ));
+        }
+        match self.format {
+            VideoFormat::Y4m => {
+                bgra_to_yuv420(buffer, &mut self.frame);
+                self.writer.write_all(b"FRAME\n")?;
+            }
+            VideoFormat::RawBgra => {
+                let bytes_per_pixel = buffer.format.bytes_per_pixel();
+                self.frame.clear();
+                for row in buffer.rows() {
+                    for pixel in row.chunks_exact(bytes_per_pixel) {
+                        let color = buffer.format.decode(pixel);
+                        self.frame
+                            .extend_from_slice(&[color.b, color.g, color.r, 255]);
+                    }
+                }
+            }
+        }
+        self.writer.write_all(&self.frame)
+    }
+
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::pixel_buffer::PixelFormat;
+
+    #[test]
+    fn colors_convert_like_bt709() {
+        let expected = [
+            (Color::rgba(0, 0, 0, 255), [16, 128, 128]),
+            (Color::rgba(255, 255, 255, 255), [235, 128, 128]),
+            (Color::rgba(255, 0, 0, 255), [63, 102, 240]),
+            (Color::rgba(0, 255, 0, 255), [173, 42, 26]),
+            (Color::rgba(0, 0, 255, 255), [32, 240, 118]),
+        ];
+        let mut out = Vec::new();
+        for (color, yuv) in expected {
+            let mut buffer = PixelBuffer::new(2, 2, PixelFormat::Bgra8);
+            buffer.fill(&color);
+            bgra_to_yuv420(&buffer, &mut out);
+            assert_eq!(
+                out,
+                [yuv[0], yuv[0], yuv[0], yuv[0], yuv[1], yuv[2]],
+                "{:?}",
+                color
+            );
+        }
+
+        // NOTE(Fermin): Odd sizes get chroma for the half blocks too
+        let mut buffer = PixelBuffer::new(3, 3, PixelFormat::Bgra8);
+        buffer.fill(&Color::rgba(0, 0, 0, 255));
+        buffer.set(2, 2, &Color::rgba(255, 255, 255, 255));
+        bgra_to_yuv420(&buffer, &mut out);
+        assert_eq!(out.len(), 9 + 2 * 4);
+        assert_eq!(out[8], 235);
+        assert_eq!(&out[9..], [128; 8]);
+    }
+
+    #[test]
+    fn streams_have_a_header_and_whole_frames() {
+        let mut buffer = PixelBuffer::new(4, 2, PixelFormat::Bgra8);
+        buffer.fill(&Color::rgba(10, 20, 30, 0));
+
+        let mut video = VideoWriter::new(Vec::new(), VideoFormat::Y4m, 4, 2, 60).unwrap();
+        video.write_frame(&buffer).unwrap();
+        video.write_frame(&buffer).unwrap();
+        assert!(video
+            .write_frame(&PixelBuffer::new(2, 2, PixelFormat::Bgra8))
+            .is_err());
+        let bytes = video.finish().unwrap();
+        let header =
+            b"YUV4MPEG2 W4 H2 F60:1 Ip A1:1 C420jpeg XCOLORRANGE=LIMITED XCOLORMATRIX=BT709\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let frame_len = b"FRAME\n".len() + 4 * 2 + 2 * 2;
+        assert_eq!(bytes.len(), header.len() + 2 * frame_len);
+        assert_eq!(&bytes[header.len()..header.len() + 6], b"FRAME\n");
+
+        let mut video = VideoWriter::new(Vec::new(), VideoFormat::RawBgra, 4, 2, 60).unwrap();
+        video.write_frame(&buffer).unwrap();
+        let bytes = video.finish().unwrap();
+        assert_eq!(bytes.len(), 4 * 2 * 4);
+        assert!(bytes
+            .chunks_exact(4)
+            .all(|pixel| pixel == [30, 20, 10, 255]));
+    }
+}
@@ -1,10 +1,14 @@
+use crate::dirty_rects::DirtyRegion;
+use crate::input::{Input, Key, MouseButton};
+use crate::math::V2;
+use crate::pixel_buffer::{PixelBuffer, PixelFormat};
 use windows::{
     core::{Error, Result, PCSTR},
     s,
     Win32::{
         Foundation::{HINSTANCE, HWND, LPARAM, LRESULT, RECT, WPARAM},
         Graphics::Gdi::*,
-        UI::Input::KeyboardAndMouse::VK_F4,
+        UI::Input::KeyboardAndMouse::*,
         UI::WindowsAndMessaging::*,
         System::LibraryLoader::GetModuleHandleA,
         Media::timeBeginPeriod,
@@ -12,14 +16,18 @@ use windows::{
 };
 
 pub const WINDOW_CLASS_NAME: PCSTR = s!("win32.Window");
+// NOTE(Fermin): Above this much dirty area, or this many rects, the whole
+// buffer is presented in one go instead.
+const FULL_PRESENT_DIRTY_FRACTION: f32 = 0.5;
+const FULL_PRESENT_MAX_RECTS: usize = 32;
+// NOTE(Fermin): Lives in Win32_UI_Controls, not worth the feature for one
+// constant.
+const WM_MOUSELEAVE: u32 = 0x02a3;
 
 pub struct Win32OffscreenBuffer {
     // Pixels always are 32-bits wide, Memory Order BB GG RR XX
     pub info: BITMAPINFO,
-    pub bits: Vec<u8>,
-    pub width: i32,
-    pub height: i32,
-    pub pitch: i32,
+    pub pixels: PixelBuffer,
 }
 
 pub struct Window {
@@ -27,6 +35,10 @@ pub struct Window {
     pub buffer: Win32OffscreenBuffer,
     pub window_running: bool,
     pub refresh_rate: i32,
+    pub input: Input,
+    // NOTE(Fermin): Windows only sends WM_MOUSELEAVE once per
+    // TrackMouseEvent call.
+    tracking_mouse: bool,
 }
 pub trait CheckHandle: Sized {
     fn ok(self) -> Result<Self>;
@@ -61,10 +73,25 @@ pub unsafe extern "system" fn win32_main_window_callback(
                 this.window_running = false;
             }
         }
-        WM_SYSKEYDOWN => println!("Keyboard input came in through a non-dispatch message"),
-        WM_SYSKEYUP => println!("Keyboard input came in through a non-dispatch message"),
-        WM_KEYDOWN => println!("Keyboard input came in through a non-dispatch message"),
-        WM_KEYUP => println!("Keyboard input came in through a non-dispatch message"),
+        WM_SIZE => {
+            // NOTE(Fermin): The buffer follows the client area, the game
+            // notices the new size on its next frame.
+            let this = GetWindowLongPtrA(window, GWLP_USERDATA) as *mut Window;
+            let width = (lparam.0 & 0xffff) as i32;
+            let height = ((lparam.0 >> 16) & 0xffff) as i32;
+            if let Some(this) = this.as_mut() {
+                if width > 0 && height > 0 {
+                    win32_resize_buffer(&mut this.buffer, width, height);
+                }
+            }
+        }
+        WM_KILLFOCUS => {
+            // NOTE(Fermin): Key ups go to whoever has focus now
+            let this = GetWindowLongPtrA(window, GWLP_USERDATA) as *mut Window;
+            if let Some(this) = this.as_mut() {
+                this.input.release_all();
+            }
+        }
         WM_PAINT => {
             let this = GetWindowLongPtrA(window, GWLP_USERDATA) as *mut Window;
             if let Some(this) = this.as_mut() {
@@ -79,26 +106,114 @@ pub unsafe extern "system" fn win32_main_window_callback(
     DefWindowProcA(window, message, wparam, lparam)
 }
 
+// NOTE(Fermin): Keys we don't have a Key for are None. Key messages carry
+// the generic Shift, Control and Alt codes, not the left/right ones.
+fn win32_key_from_virtual_key(virtual_key: u16) -> Option<Key> {
+    // NOTE(Fermin): Letters and digits use their ASCII codes
+    if (b'A' as u16..=b'Z' as u16).contains(&virtual_key) {
+        return Key::A.offset_by((virtual_key - b'A' as u16) as usize);
+    }
+    if (b'0' as u16..=b'9' as u16).contains(&virtual_key) {
+        return Key::Digit0.offset_by((virtual_key - b'0' as u16) as usize);
+    }
+    if (VK_F1.0..=VK_F12.0).contains(&virtual_key) {
+        return Key::F1.offset_by((virtual_key - VK_F1.0) as usize);
+    }
+
+    let key = match VIRTUAL_KEY(virtual_key) {
+        VK_SPACE => Key::Space,
+        VK_RETURN => Key::Enter,
+        VK_ESCAPE => Key::Escape,
+        VK_TAB => Key::Tab,
+        VK_BACK => Key::Backspace,
+        VK_LEFT => Key::Left,
+        VK_RIGHT => Key::Right,
+        VK_UP => Key::Up,
+        VK_DOWN => Key::Down,
+        VK_PRIOR => Key::PageUp,
+        VK_NEXT => Key::PageDown,
+        VK_HOME => Key::Home,
+        VK_END => Key::End,
+        VK_INSERT => Key::Insert,
+        VK_DELETE => Key::Delete,
+        VK_OEM_MINUS => Key::Minus,
+        VK_OEM_PLUS => Key::Plus,
+        VK_OEM_COMMA => Key::Comma,
+        VK_OEM_PERIOD => Key::Period,
+        VK_SHIFT => Key::Shift,
+        VK_CONTROL => Key::Control,
+        VK_MENU => Key::Alt,
+        _ => return None,
+    };
+    Some(key)
+}
+
+// NOTE(Fermin): Fills in window.input for the next frame, transitions from
+// the last one are cleared first.
 pub fn win32_process_pending_messages(window: &mut Window) {
+    window.input.begin_frame();
+
     let mut message: MSG = Default::default();
     unsafe {
         while PeekMessageA(&mut message, HWND(0), 0, 0, PM_REMOVE).into() {
             match message.message {
+                // NOTE(Fermin): Handled here instead of dispatched, so the
+                // window callback never sees them. Repeats come in as downs
+                // on keys that are already down, Input ignores those.
                 WM_SYSKEYDOWN | WM_SYSKEYUP | WM_KEYDOWN | WM_KEYUP => {
-                    let v_k_code: char =
-                        char::from_u32(message.wParam.0 as u32).expect("Failed to parse VKCode");
-
-                    let was_down = message.lParam.0 & (1 << 30) != 0;
-                    let is_down = (message.lParam.0 & (1 << 31)) == 0;
-                    let alt_key_was_down = message.lParam.0 & (1 << 29) != 0;
-
-                    if was_down != is_down {
-                        if is_down {
-                            if (v_k_code as u16 == VK_F4.0) && alt_key_was_down {
-                                println!("Alt+F4");
-                                window.window_running = false;
-                            }
-                        }
+                    let is_down = message.message == WM_SYSKEYDOWN || message.message == WM_KEYDOWN;
+                    if let Some(key) = win32_key_from_virtual_key(message.wParam.0 as u16) {
+                        window.input.set_key(key, is_down);
+                    }
+
+                    // NOTE(Fermin): Not dispatching means DefWindowProc never
+                    // gets to close the window on Alt+F4.
+                    if window.input.key(Key::F4).pressed && window.input.modifiers().alt {
+                        window.window_running = false;
+                    }
+                }
+                WM_MOUSEMOVE => {
+                    // NOTE(Fermin): Client area coordinates, signed because
+                    // they go negative while dragging outside of it.
+                    let x = (message.lParam.0 & 0xffff) as i16;
+                    let y = ((message.lParam.0 >> 16) & 0xffff) as i16;
+                    window.input.mouse.pos = Some(V2 {
+                        x: x as f32,
+                        y: y as f32,
+                    });
+
+                    if !window.tracking_mouse {
+                        let mut track = TRACKMOUSEEVENT {
+                            cbSize: std::mem::size_of::<TRACKMOUSEEVENT>() as u32,
+                            dwFlags: TME_LEAVE,
+                            hwndTrack: window.handle,
+                            dwHoverTime: 0,
+                        };
+                        window.tracking_mouse = TrackMouseEvent(&mut track).as_bool();
+                    }
+                }
+                WM_MOUSELEAVE => {
+                    window.input.mouse.pos = None;
+                    window.tracking_mouse = false;
+                }
+                WM_LBUTTONDOWN | WM_LBUTTONUP | WM_RBUTTONDOWN | WM_RBUTTONUP | WM_MBUTTONDOWN
+                | WM_MBUTTONUP => {
+                    let (button, is_down) = match message.message {
+                        WM_LBUTTONDOWN => (MouseButton::Left, true),
+                        WM_LBUTTONUP => (MouseButton::Left, false),
+                        WM_RBUTTONDOWN => (MouseButton::Right, true),
+                        WM_RBUTTONUP => (MouseButton::Right, false),
+                        WM_MBUTTONDOWN => (MouseButton::Middle, true),
+                        _ => (MouseButton::Middle, false),
+                    };
+                    window.input.mouse.set_down(button, is_down);
+
+                    // NOTE(Fermin): Capturing makes sure the button up comes
+                    // back to us even if it happens outside of the window.
+                    if is_down {
+                        SetCapture(window.handle);
+                    } else if !window.input.mouse.any_down() {
+                        ReleaseCapture();
                     }
                 }
                 _ => {
@@ -107,8 +222,39 @@ pub fn win32_process_pending_messages(window: &mut Window) {
                 }
             }
         }
+    }
+}
 
-        win32_display_buffer_in_window(GetDC(window.handle), window);
+pub fn win32_present_buffer(window: &mut Window, dirty_region: &DirtyRegion) {
+    if dirty_region.is_empty() {
+        return;
+    }
+
+    unsafe {
+        let device_context = GetDC(window.handle);
+        if dirty_region.should_present_all(FULL_PRESENT_DIRTY_FRACTION, FULL_PRESENT_MAX_RECTS) {
+            win32_display_buffer_in_window(device_context, window);
+        } else {
+            for rect in &dirty_region.rects {
+                // NOTE(Fermin): Even though the buffer is top-down, GDI measures
+                // the source rect's y from the bottom of the bitmap.
+                StretchDIBits(
+                    device_context,
+                    rect.min_x,
+                    rect.min_y,
+                    rect.width(),
+                    rect.height(),
+                    rect.min_x,
+                    window.buffer.pixels.height - rect.max_y,
+                    rect.width(),
+                    rect.height(),
+                    Some(window.buffer.pixels.bits.as_mut_ptr() as _),
+                    &window.buffer.info,
+                    DIB_RGB_COLORS,
+                    SRCCOPY,
+                );
+            }
+        }
     }
 }
 
@@ -124,7 +270,7 @@ fn win32_display_buffer_in_window(device_context: HDC, window: &mut Window) {
         PatBlt(device_context, 0, 0, 0, window_height, WHITENESS);
         PatBlt(
             device_context,
-            window.buffer.width,
+            window.buffer.pixels.width,
             0,
             window_width,
             window_height,
@@ -133,7 +279,7 @@ fn win32_display_buffer_in_window(device_context: HDC, window: &mut Window) {
         PatBlt(
             device_context,
             0,
-            window.buffer.height,
+            window.buffer.pixels.height,
             window_width,
             window_height,
             WHITENESS,
@@ -143,13 +289,13 @@ fn win32_display_buffer_in_window(device_context: HDC, window: &mut Window) {
             device_context,
             0,
             0,
-            window.buffer.width,
-            window.buffer.height,
+            window.buffer.pixels.width,
+            window.buffer.pixels.height,
             0,
             0,
-            window.buffer.width,
-            window.buffer.height,
-            Some(window.buffer.bits.as_mut_ptr() as _),
+            window.buffer.pixels.width,
+            window.buffer.pixels.height,
+            Some(window.buffer.pixels.bits.as_mut_ptr() as _),
             &window.buffer.info,
             DIB_RGB_COLORS,
             SRCCOPY,
@@ -157,17 +303,24 @@ fn win32_display_buffer_in_window(device_context: HDC, window: &mut Window) {
     }
 }
 
+fn win32_resize_buffer(buffer: &mut Win32OffscreenBuffer, width: i32, height: i32) {
+    if buffer.pixels.width == width && buffer.pixels.height == height {
+        return;
+    }
+    buffer.pixels = PixelBuffer::new(width, height, PixelFormat::Bgra8);
+    buffer.info.bmiHeader.biWidth = width;
+    buffer.info.bmiHeader.biHeight = -height; // - sign so origin is top left
+}
+
+// NOTE(Fermin): The buffer starts at buffer_width x buffer_height and
+// follows the client area once the window is up.
 pub fn get_window(buffer_width: i32, buffer_height: i32, name: &PCSTR) -> Result<Box<Window>> {
     // --------------------------------------------------------------------
     // NOTE(Fermin): Create buffer
     // --------------------------------------------------------------------
-    let num_of_pixels = buffer_width * buffer_height * crate::BYTES_PER_PIXEL;
     let mut buffer = Win32OffscreenBuffer {
         info: Default::default(),
-        bits: vec![0; num_of_pixels as usize], //NOTE(Fermin): Fill bg with dif color?
-        width: buffer_width,
-        height: buffer_height,
-        pitch: buffer_width * crate::BYTES_PER_PIXEL,
+        pixels: PixelBuffer::new(buffer_width, buffer_height, PixelFormat::Bgra8),
     };
     buffer.info.bmiHeader.biWidth = buffer_width;
     buffer.info.bmiHeader.biHeight = -buffer_height; // - sign so origin is top left
@@ -197,6 +350,8 @@ pub fn get_window(buffer_width: i32, buffer_height: i32, name: &PCSTR) -> Result
         buffer,
         window_running: true,
         refresh_rate: 60,
+        input: Input::default(),
+        tracking_mouse: false,
     });
 
     unsafe {
@@ -0,0 +1,272 @@
+#!/usr/bin/env python3
+# Writes the pngs the decoder tests run against, using Python's own zlib so
+# inflate gets checked against streams it didn't make. zlib only ever writes
+# dynamic Huffman or stored blocks, so the handmade_* files go through the
+# small encoder below instead: fixed Huffman blocks with matches reaching
+# back into stored ones, IDATs split at odd sizes (empty ones too) and
+# ancillary chunks on both sides of the data. Every sample comes from
+# sample() and the png.rs tests compute the same values to compare.
+#
+# Usage: python3 tests/png/generate.py (from the repo root)
+import os
+import struct
+import zlib
+
+WIDTH, HEIGHT = 37, 23
+OUT = os.path.dirname(os.path.abspath(__file__))
+
+GRAY, RGB, PALETTE, GRAY_ALPHA, RGBA = 0, 2, 3, 4, 6
+CHANNELS = {GRAY: 1, RGB: 3, PALETTE: 1, GRAY_ALPHA: 2, RGBA: 4}
+ADAM7 = [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4),
+         (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)]
+
+
+def sample(x, y, channel, depth):
+    return (x * 2741 + y * 6521 + channel * 12345 + x * y * 17) % (1 << depth)
+
+
+def palette_entry(index):
+    return (index * 37 % 256, index * 91 % 256, index * 53 % 256)
+
+
+def palette_alpha(index):
+    return index * 71 % 256
+
+
+def chunk(kind, data):
+    body = kind + data
+    return struct.pack(">I", len(data)) + body + struct.pack(">I", zlib.crc32(body))
+
+
+def pack_row(samples, depth):
+    if depth == 16:
+        return b"".join(struct.pack(">H", s) for s in samples)
+    if depth == 8:
+        return bytes(samples)
+    out = bytearray()
+    per_byte = 8 // depth
+    for start in range(0, len(samples), per_byte):
+        byte = 0
+        group = samples[start:start + per_byte]
+        for i, s in enumerate(group):
+            byte |= s << (8 - depth * (i + 1))
+        out.append(byte)
+    return bytes(out)
+
+
+def paeth(a, b, c):
+    p = a + b - c
+    pa, pb, pc = abs(p - a), abs(p - b), abs(p - c)
+    if pa <= pb and pa <= pc:
+        return a
+    return b if pb <= pc else c
+
+
+def filter_row(kind, row, prior, bpp):
+    out = bytearray()
+    for i, byte in enumerate(row):
+        a = row[i - bpp] if i >= bpp else 0
+        b = prior[i] if prior else 0
+        c = prior[i - bpp] if prior and i >= bpp else 0
+        predictor = [0, a, b, (a + b) // 2, paeth(a, b, c)][kind]
+        out.append((byte - predictor) % 256)
+    return bytes([kind]) + bytes(out)
+
+
+def image_data(color_type, depth, width, height, interlaced):
+    channels = CHANNELS[color_type]
+    bpp = max(1, channels * depth // 8)
+    passes = ADAM7 if interlaced else [(0, 0, 1, 1)]
+    raw = bytearray()
+    row_number = 0
+    for x0, y0, dx, dy in passes:
+        xs = range(x0, width, dx)
+        ys = range(y0, height, dy)
+        if not xs or not ys:
+            continue
+        prior = None
+        for y in ys:
+            samples = [sample(x, y, c, depth) for x in xs for c in range(channels)]
+            row = pack_row(samples, depth)
+            # Every filter type gets used, in a different order per pass
+            raw += filter_row(row_number % 5, row, prior, bpp)
+            row_number += 1
+            prior = row
+    return bytes(raw)
+
+
+class BitWriter:
+    def __init__(self):
+        self.out = bytearray()
+        self.buffer = 0
+        self.count = 0
+
+    def bits(self, value, count):
+        self.buffer |= value << self.count
+        self.count += count
+        while self.count >= 8:
+            self.out.append(self.buffer & 0xFF)
+            self.buffer >>= 8
+            self.count -= 8
+
+    # Huffman codes go out most significant bit first
+    def code(self, code, length):
+        self.bits(int(format(code, "0%db" % length)[::-1], 2), length)
+
+    def align(self):
+        if self.count:
+            self.bits(0, 8 - self.count)
+
+
+LENGTH_BASES = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43,
+                51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258]
+LENGTH_EXTRA = [0] * 8 + [1] * 4 + [2] * 4 + [3] * 4 + [4] * 4 + [5] * 4 + [0]
+DISTANCE_BASES = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257,
+                  385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289,
+                  16385, 24577]
+DISTANCE_EXTRA = [0, 0, 0, 0] + [n // 2 for n in range(2, 28)]
+
+
+def fixed_literal(out, symbol):
+    if symbol < 144:
+        out.code(0x30 + symbol, 8)
+    elif symbol < 256:
+        out.code(0x190 + symbol - 144, 9)
+    elif symbol < 280:
+        out.code(symbol - 256, 7)
+    else:
+        out.code(0xC0 + symbol - 280, 8)
+
+
+def fixed_match(out, length, distance):
+    code = max(i for i, base in enumerate(LENGTH_BASES) if base <= length)
+    fixed_literal(out, 257 + code)
+    out.bits(length - LENGTH_BASES[code], LENGTH_EXTRA[code])
+    code = max(i for i, base in enumerate(DISTANCE_BASES) if base <= distance)
+    out.code(code, 5)
+    out.bits(distance - DISTANCE_BASES[code], DISTANCE_EXTRA[code])
+
+
+def handmade_deflate(data):
+    # Seven blocks taking turns being stored and fixed, matches look back
+    # over everything before them no matter which kind of block it was in
+    block_size = len(data) // 7 + 1
+    out = BitWriter()
+    out.bits(0x78, 8)
+    out.bits(0x01, 8)
+    recent = {}
+    starts = list(range(0, len(data), block_size)) or [0]
+    for block, start in enumerate(starts):
+        end = min(start + block_size, len(data))
+        final = int(block == len(starts) - 1)
+        if block % 2 == 0:
+            out.bits(final, 1)
+            out.bits(0, 2)
+            out.align()
+            out.bits(end - start, 16)
+            out.bits((end - start) ^ 0xFFFF, 16)
+            for byte in data[start:end]:
+                out.bits(byte, 8)
+            for at in range(start, end):
+                recent.setdefault(bytes(data[at:at + 3]), []).append(at)
+            continue
+        out.bits(final, 1)
+        out.bits(1, 2)
+        at = start
+        while at < end:
+            best_length, best_distance = 0, 0
+            for candidate in reversed(recent.get(bytes(data[at:at + 3]), [])[-32:]):
+                if at - candidate > 32768:
+                    break
+                length = 0
+                while (length < 258 and at + length < end
+                       and data[candidate + length] == data[at + length]):
+                    length += 1
+                if length > best_length:
+                    best_length, best_distance = length, at - candidate
+            if best_length >= 3:
+                fixed_match(out, best_length, best_distance)
+            else:
+                best_length = 1
+                fixed_literal(out, data[at])
+            for skipped in range(at, at + best_length):
+                recent.setdefault(bytes(data[skipped:skipped + 3]), []).append(skipped)
+            at += best_length
+        fixed_literal(out, 256)
+    out.align()
+    out.out += struct.pack(">I", zlib.adler32(data))
+    compressed = bytes(out.out)
+    assert zlib.decompress(compressed) == data
+    return compressed
+
+
+def write(name, color_type, depth, interlaced=False, transparency=False,
+          width=WIDTH, height=HEIGHT, level=9, idat_chunks=1, text=False,
+          handmade=False):
+    png = b"\x89PNG\r\n\x1a\n"
+    png += chunk(b"IHDR", struct.pack(">IIBBBBB", width, height, depth,
+                                      color_type, 0, 0, int(interlaced)))
+    if text:
+        png += chunk(b"tEXt", b"Comment\x00Skipped by the decoder")
+    if handmade:
+        png += chunk(b"gAMA", struct.pack(">I", 45455))
+        png += chunk(b"pHYs", struct.pack(">IIB", 2835, 2835, 1))
+    if color_type == PALETTE:
+        size = 1 << depth
+        png += chunk(b"PLTE", b"".join(bytes(palette_entry(i)) for i in range(size)))
+        if transparency:
+            png += chunk(b"tRNS", bytes(palette_alpha(i) for i in range(size // 2 + 1)))
+    elif transparency:
+        # The key is whatever the second pixel of the first row is
+        key = [sample(1, 0, c, depth) for c in range(CHANNELS[color_type])]
+        png += chunk(b"tRNS", b"".join(struct.pack(">H", k) for k in key))
+    raw = image_data(color_type, depth, width, height, interlaced)
+    if handmade:
+        data = handmade_deflate(raw)
+        start = 0
+        for size in [2, 0, 1, 5, 0, 311, 64]:
+            png += chunk(b"IDAT", data[start:start + size])
+            start += size
+        while start < len(data):
+            png += chunk(b"IDAT", data[start:start + 997])
+            start += 997
+        png += chunk(b"tIME", struct.pack(">HBBBBB", 2017, 7, 19, 12, 0, 0))
+    else:
+        data = zlib.compress(raw, level)
+        step = len(data) // idat_chunks + 1
+        for start in range(0, len(data), step):
+            png += chunk(b"IDAT", data[start:start + step])
+    png += chunk(b"IEND", b"")
+    with open(os.path.join(OUT, name + ".png"), "wb") as f:
+        f.write(png)
+
+
+KINDS = [("gray", GRAY, [1, 2, 4, 8, 16]), ("rgb", RGB, [8, 16]),
+         ("palette", PALETTE, [1, 2, 4, 8]), ("gray_alpha", GRAY_ALPHA, [8, 16]),
+         ("rgba", RGBA, [8, 16])]
+
+count = 0
+for kind, color_type, depths in KINDS:
+    for depth in depths:
+        for interlaced in (False, True):
+            name = "%s%d%s" % (kind, depth, "_interlaced" if interlaced else "")
+            # Stored, fast and best compression all show up
+            write(name, color_type, depth, interlaced, level=[9, 1, 0][count % 3],
+                  idat_chunks=1 + count % 3, text=count % 4 == 0)
+            count += 1
+
+write("gray2_transparent", GRAY, 2, transparency=True)
+write("gray16_transparent", GRAY, 16, transparency=True)
+write("rgb8_transparent", RGB, 8, transparency=True)
+write("rgb16_transparent", RGB, 16, True, transparency=True)
+write("palette4_transparent", PALETTE, 4, transparency=True)
+write("palette8_transparent", PALETTE, 8, True, transparency=True)
+# Interlaced images this small leave some passes empty
+write("rgba8_1x1_interlaced", RGBA, 8, True, width=1, height=1)
+write("gray1_3x2_interlaced", GRAY, 1, True, width=3, height=2)
+write("palette2_5x9_interlaced", PALETTE, 2, True, width=5, height=9)
+# Not from zlib, see handmade_deflate()
+write("handmade_rgb16_interlaced", RGB, 16, True, handmade=True)
+write("handmade_rgba16", RGBA, 16, handmade=True)
+write("handmade_palette8_transparent", PALETTE, 8, True, transparency=True, handmade=True)
+write("handmade_gray4", GRAY, 4, handmade=True)